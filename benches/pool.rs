@@ -0,0 +1,365 @@
+// Repeatable timings for the pool, run with `cargo bench`. Each benchmark
+// is timed over several samples after a warm-up run, and the median is
+// compared with the last run's so a change shows up as a percentage rather
+// than a hunch. `cargo bench -- submit` runs just the benchmarks whose
+// names contain "submit", and `cargo bench --features spin-lock` times
+// the pool with its other kind of lock
+use rust_concurrent_processor::{
+    self as rcp, Scheduler, ShutdownMode, TaskContext, TaskError, TaskOutput, ThreadPool,
+    WaitStrategy,
+};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::hint::black_box;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::atomic::{self, AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const SAMPLES: usize = 10;
+// Tasks per sample in the throughput benchmarks
+const BATCH: u64 = 2_000;
+// Round trips per sample in the latency benchmarks
+const ROUND_TRIPS: u64 = 200;
+const UPDATES_PER_THREAD: u64 = 100_000;
+const THREADS: usize = 4;
+
+// A task that finishes almost immediately, so the pool's own overhead is
+// what gets measured
+struct Tiny {
+    id: u32,
+}
+
+impl rcp::Task for Tiny {
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn execute(&self, _ctx: &TaskContext) -> Result<TaskOutput, TaskError> {
+        Ok(TaskOutput::new(tiny_work().to_string()))
+    }
+}
+
+fn tiny_work() -> u64 {
+    (0..200u64).map(black_box).sum()
+}
+
+// 256KB of u64s: about a core's L2 cache
+const SWEEP_WORDS: usize = 32 * 1024;
+
+// Reads and writes every word of its worker's buffer
+struct Sweep {
+    id: u32,
+}
+
+impl rcp::Task for Sweep {
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn execute(&self, ctx: &TaskContext) -> Result<TaskOutput, TaskError> {
+        let sum = ctx.with_worker_state(|buffer: &mut Vec<u64>| {
+            buffer.iter_mut().fold(0u64, |sum, word| {
+                *word = word.wrapping_mul(3).wrapping_add(1);
+                sum.wrapping_add(*word)
+            })
+        });
+        Ok(TaskOutput::new(black_box(sum).unwrap_or_default().to_string()))
+    }
+}
+
+fn main() {
+    let mut bench = Bench::new();
+
+    // Submission throughput through each kind of queue: Channel is an mpsc
+    // channel, SharedQueue and Fifo a mutex and condvar
+    for scheduler in [Scheduler::SharedQueue, Scheduler::WorkStealing, Scheduler::Channel, Scheduler::Fifo] {
+        let pool = ThreadPool::builder().workers(THREADS).scheduler(scheduler).build();
+        bench.run(&format!("submit/{:?}", scheduler), BATCH, || {
+            pool.submit_batch((0..BATCH as u32).map(|id| Tiny { id })).wait_all();
+        });
+        pool.shutdown(ShutdownMode::Drain);
+    }
+
+    // The same with every thread submitting at once, so producers and
+    // workers fight over the queue's lock. Run once without and once with
+    // `--features spin-lock` to compare the pool's two kinds of lock
+    for scheduler in [Scheduler::SharedQueue, Scheduler::Fifo] {
+        let pool = ThreadPool::builder().workers(THREADS).scheduler(scheduler).build();
+        bench.run(&format!("contended/{:?}", scheduler), BATCH, || {
+            thread::scope(|scope| {
+                for producer in 0..THREADS as u32 {
+                    let pool = &pool;
+                    scope.spawn(move || {
+                        let ids = producer * BATCH as u32 / THREADS as u32..(producer + 1) * BATCH as u32 / THREADS as u32;
+                        pool.submit_batch(ids.map(|id| Tiny { id })).wait_all();
+                    });
+                }
+            });
+        });
+        pool.shutdown(ShutdownMode::Drain);
+    }
+
+    // From submitting one task to having its result, with the pool
+    // otherwise idle, as the number of workers waiting for it grows
+    for workers in [1, 2, 4, 8] {
+        let pool = ThreadPool::new(workers);
+        bench.run(&format!("latency/{}_workers", workers), ROUND_TRIPS, || {
+            for id in 0..ROUND_TRIPS as u32 {
+                black_box(pool.submit(Tiny { id }).wait());
+            }
+        });
+        pool.shutdown(ShutdownMode::Drain);
+    }
+
+    // The same round trip with the worker spinning for a while before it
+    // parks: a task submitted in the meantime needs no wake-up
+    let strategies = [("park", WaitStrategy::Park), ("spin_then_park", WaitStrategy::SpinThenPark(Duration::from_micros(50)))];
+    for (name, strategy) in strategies {
+        let pool = ThreadPool::builder().workers(1).wait_strategy(strategy).build();
+        bench.run(&format!("latency/{}", name), ROUND_TRIPS, || {
+            for id in 0..ROUND_TRIPS as u32 {
+                black_box(pool.submit(Tiny { id }).wait());
+            }
+        });
+        pool.shutdown(ShutdownMode::Drain);
+    }
+
+    // Compute-heavy tasks that each sweep their worker's own buffer, sized
+    // to fit a core's cache. Pinned, a worker's buffer is still warm in
+    // its core's cache when the next task comes along; unpinned, the OS
+    // may have moved the thread to another core in between
+    for pin in [false, true] {
+        let pool = ThreadPool::builder()
+            .workers(THREADS)
+            .pin_workers(pin)
+            .worker_init(|| vec![1u64; SWEEP_WORDS])
+            .build();
+        let name = if pin { "affinity/pinned" } else { "affinity/unpinned" };
+        bench.run(name, BATCH, || {
+            pool.submit_batch((0..BATCH as u32).map(|id| Sweep { id })).wait_all();
+        });
+        pool.shutdown(ShutdownMode::Drain);
+    }
+
+    // Every finished task bumps a couple of counters: under one Mutex, as
+    // separate atomics, under a Mutex in a shard per thread, or as atomics
+    // in a seqlocked shard per thread the way the pool keeps its stats
+    let locked = Mutex::new((0u64, 0u64));
+    bench.run("stats/mutex", UPDATES_PER_THREAD * THREADS as u64, || {
+        hammer(|_| {
+            let mut counters = locked.lock().unwrap();
+            counters.0 += 1;
+            counters.1 += 1;
+        });
+    });
+    let atomic = (AtomicU64::new(0), AtomicU64::new(0));
+    bench.run("stats/atomic", UPDATES_PER_THREAD * THREADS as u64, || {
+        hammer(|_| {
+            atomic.0.fetch_add(1, Ordering::Relaxed);
+            atomic.1.fetch_add(1, Ordering::Relaxed);
+        });
+    });
+    let locked_shards: Vec<LockedShard> = (0..THREADS).map(|_| LockedShard::default()).collect();
+    let locked_update = |thread: usize| {
+        let mut counts = locked_shards[thread].0.lock().unwrap();
+        counts.0 += 1;
+        counts.1 += 1;
+    };
+    bench.run("stats/sharded-mutex", UPDATES_PER_THREAD * THREADS as u64, || {
+        hammer(locked_update);
+    });
+    let shards: Vec<Shard> = (0..THREADS).map(|_| Shard::default()).collect();
+    let update = |thread: usize| shards[thread].add();
+    bench.run("stats/sharded", UPDATES_PER_THREAD * THREADS as u64, || {
+        hammer(update);
+    });
+    // The same while something reads the stats every millisecond, as the
+    // dashboard or a metrics scrape would. Locked shards are all held at
+    // once, holding up the threads updating them; seqlocked ones are only
+    // read again if an update got in the way
+    bench.run("stats/sharded-mutex/snapshotted", UPDATES_PER_THREAD * THREADS as u64, || {
+        snapshotted(|| locked_snapshot(&locked_shards), locked_update);
+    });
+    bench.run("stats/sharded/snapshotted", UPDATES_PER_THREAD * THREADS as u64, || {
+        snapshotted(|| snapshot(&shards), update);
+    });
+
+    bench.save();
+}
+
+// Two counters under a lock and on a cache line of their own
+#[derive(Default)]
+#[repr(align(128))]
+struct LockedShard(Mutex<(u64, u64)>);
+
+// Both counters summed across the shards, all locked at once so they
+// agree
+fn locked_snapshot(shards: &[LockedShard]) -> (u64, u64) {
+    let locked: Vec<_> = shards.iter().map(|shard| shard.0.lock().unwrap()).collect();
+    locked.iter().fold((0, 0), |(a, b), counts| (a + counts.0, b + counts.1))
+}
+
+// Two counters on a cache line of their own behind a seqlock, like one of
+// the pool's stats shards: odd while a thread is updating it
+#[derive(Default)]
+#[repr(align(128))]
+struct Shard {
+    sequence: AtomicU64,
+    counts: (AtomicU64, AtomicU64),
+}
+
+impl Shard {
+    fn add(&self) {
+        let mut sequence = self.sequence.load(Ordering::Relaxed);
+        loop {
+            if sequence % 2 == 1 {
+                thread::yield_now();
+                sequence = self.sequence.load(Ordering::Relaxed);
+                continue;
+            }
+            match self.sequence.compare_exchange_weak(sequence, sequence + 1, Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => break,
+                Err(now) => sequence = now,
+            }
+        }
+        atomic::fence(Ordering::Release);
+        let add = |count: &AtomicU64| count.store(count.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+        add(&self.counts.0);
+        add(&self.counts.1);
+        self.sequence.store(sequence + 2, Ordering::Release);
+    }
+
+    fn read(&self) -> (u64, u64) {
+        loop {
+            let sequence = self.sequence.load(Ordering::Acquire);
+            if sequence.is_multiple_of(2) {
+                let counts = (self.counts.0.load(Ordering::Relaxed), self.counts.1.load(Ordering::Relaxed));
+                atomic::fence(Ordering::Acquire);
+                if self.sequence.load(Ordering::Relaxed) == sequence {
+                    return counts;
+                }
+            }
+            thread::yield_now();
+        }
+    }
+}
+
+// Both counters summed across the shards, each shard read whole, as the
+// pool's stats snapshot does
+fn snapshot(shards: &[Shard]) -> (u64, u64) {
+    shards.iter().map(Shard::read).fold((0, 0), |(a, b), counts| (a + counts.0, b + counts.1))
+}
+
+// Runs `update` on every thread while `snapshot` is taken every millisecond
+fn snapshotted<S>(snapshot: impl Fn() -> S + Sync, update: impl Fn(usize) + Sync) {
+    let done = AtomicBool::new(false);
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            while !done.load(Ordering::Relaxed) {
+                black_box(snapshot());
+                thread::sleep(Duration::from_millis(1));
+            }
+        });
+        hammer(update);
+        done.store(true, Ordering::Relaxed);
+    });
+}
+
+fn hammer(update: impl Fn(usize) + Sync) {
+    thread::scope(|scope| {
+        for thread in 0..THREADS {
+            let update = &update;
+            scope.spawn(move || {
+                for _ in 0..UPDATES_PER_THREAD {
+                    update(thread);
+                }
+            });
+        }
+    });
+}
+
+struct Bench {
+    // Names to run, from the command line; all of them when empty
+    filters: Vec<String>,
+    // Last run's median nanoseconds per element, by benchmark
+    baseline: HashMap<String, f64>,
+    measured: Vec<(String, f64)>,
+}
+
+impl Bench {
+    fn new() -> Bench {
+        // cargo passes --bench along with anything after `--`
+        let filters = env::args().skip(1).filter(|arg| !arg.starts_with('-')).collect();
+        let baseline = fs::read_to_string(baseline_path())
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| {
+                let (name, nanos) = line.rsplit_once(' ')?;
+                Some((name.to_string(), nanos.parse().ok()?))
+            })
+            .collect();
+        Bench { filters, baseline, measured: Vec::new() }
+    }
+
+    // Times `routine`, which handles `elements` items each run
+    fn run(&mut self, name: &str, elements: u64, mut routine: impl FnMut()) {
+        if !self.filters.is_empty() && !self.filters.iter().any(|filter| name.contains(filter.as_str())) {
+            return;
+        }
+        routine();
+        let mut samples: Vec<f64> = (0..SAMPLES)
+            .map(|_| {
+                let start = Instant::now();
+                routine();
+                start.elapsed().as_nanos() as f64 / elements as f64
+            })
+            .collect();
+        samples.sort_by(f64::total_cmp);
+        let median = samples[SAMPLES / 2];
+        let change = match self.baseline.get(name) {
+            Some(before) => format!("{:+.1}%", (median - before) / before * 100.0),
+            None => "new".to_string(),
+        };
+        println!(
+            "{:<22} {:>10}/elem  [{} .. {}]  {:>12.0} elem/s  {}",
+            name,
+            nanos(median),
+            nanos(samples[0]),
+            nanos(samples[SAMPLES - 1]),
+            1e9 / median,
+            change
+        );
+        self.measured.push((name.to_string(), median));
+    }
+
+    // Keeps this run's medians for the next run to compare against.
+    // Benchmarks skipped this time keep their old numbers
+    fn save(mut self) {
+        for (name, median) in self.measured {
+            self.baseline.insert(name, median);
+        }
+        let mut lines: Vec<String> = self.baseline.iter().map(|(name, nanos)| format!("{} {}", name, nanos)).collect();
+        lines.sort();
+        let path = baseline_path();
+        let saved = fs::create_dir_all(path.parent().unwrap()).and_then(|_| fs::write(&path, lines.join("\n") + "\n"));
+        if let Err(err) = saved {
+            eprintln!("warning: can't write {}: {}", path.display(), err);
+        }
+    }
+}
+
+fn baseline_path() -> PathBuf {
+    let target = env::var_os("CARGO_TARGET_DIR").map_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target"), PathBuf::from);
+    target.join("bench").join("pool.txt")
+}
+
+fn nanos(nanos: f64) -> String {
+    match Duration::from_nanos(nanos as u64) {
+        time if time >= Duration::from_millis(1) => format!("{:.2}ms", nanos / 1e6),
+        time if time >= Duration::from_micros(1) => format!("{:.2}µs", nanos / 1e3),
+        _ => format!("{:.1}ns", nanos),
+    }
+}
@@ -0,0 +1,93 @@
+/// The cores the process may run on, as the OS numbers them. std has no
+/// call for it, so this asks Linux or Windows directly; anywhere else
+/// there are none and nothing gets pinned.
+pub(crate) fn cpus() -> Vec<usize> {
+    imp::cpus()
+}
+
+/// Keeps the calling thread on `cpu` from now on. Whether that worked.
+pub(crate) fn pin(cpu: usize) -> bool {
+    imp::pin(cpu)
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::os::raw::c_int;
+
+    /// A `cpu_set_t`: a bit for each of up to 1024 CPUs.
+    type CpuSet = [u64; 16];
+
+    // std already links against libc.
+    unsafe extern "C" {
+        fn sched_getaffinity(pid: c_int, size: usize, mask: *mut u64) -> c_int;
+        fn sched_setaffinity(pid: c_int, size: usize, mask: *const u64) -> c_int;
+    }
+
+    pub(super) fn cpus() -> Vec<usize> {
+        let mut allowed: CpuSet = [0; 16];
+        // A pid of 0 is the calling thread.
+        let got = unsafe { sched_getaffinity(0, size_of::<CpuSet>(), allowed.as_mut_ptr()) };
+        if got != 0 {
+            return Vec::new();
+        }
+        (0..allowed.len() * 64)
+            .filter(|cpu| allowed[cpu / 64] & (1 << (cpu % 64)) != 0)
+            .collect()
+    }
+
+    pub(super) fn pin(cpu: usize) -> bool {
+        let mut mask: CpuSet = [0; 16];
+        let Some(word) = mask.get_mut(cpu / 64) else {
+            return false;
+        };
+        *word = 1 << (cpu % 64);
+        unsafe { sched_setaffinity(0, size_of::<CpuSet>(), mask.as_ptr()) == 0 }
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::ffi::c_void;
+
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        fn GetCurrentProcess() -> *mut c_void;
+        fn GetCurrentThread() -> *mut c_void;
+        fn GetProcessAffinityMask(
+            process: *mut c_void,
+            process_mask: *mut usize,
+            system_mask: *mut usize,
+        ) -> i32;
+        fn SetThreadAffinityMask(thread: *mut c_void, mask: usize) -> usize;
+    }
+
+    pub(super) fn cpus() -> Vec<usize> {
+        let (mut process, mut system) = (0, 0);
+        let got = unsafe { GetProcessAffinityMask(GetCurrentProcess(), &mut process, &mut system) };
+        if got == 0 {
+            return Vec::new();
+        }
+        (0..usize::BITS as usize)
+            .filter(|cpu| process & (1 << cpu) != 0)
+            .collect()
+    }
+
+    pub(super) fn pin(cpu: usize) -> bool {
+        if cpu >= usize::BITS as usize {
+            return false;
+        }
+        // The old mask on success, zero on failure.
+        unsafe { SetThreadAffinityMask(GetCurrentThread(), 1 << cpu) != 0 }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+mod imp {
+    pub(super) fn cpus() -> Vec<usize> {
+        Vec::new()
+    }
+
+    pub(super) fn pin(_cpu: usize) -> bool {
+        false
+    }
+}
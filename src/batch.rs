@@ -0,0 +1,120 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver};
+
+use crate::pool::{SubmitOptions, ThreadPool};
+use crate::task::{Task, TaskResult};
+
+/// Running totals for a batch, kept up to date by the workers.
+#[derive(Debug, Default)]
+struct Counts {
+    succeeded: AtomicUsize,
+    failed: AtomicUsize,
+}
+
+/// Receives the outcomes of a batch of tasks submitted together with
+/// [`ThreadPool::submit_batch`].
+pub struct BatchHandle {
+    receiver: Receiver<(usize, TaskResult)>,
+    counts: Arc<Counts>,
+    len: usize,
+}
+
+impl BatchHandle {
+    /// Blocks until every task in the batch has finished and returns the
+    /// results not already handed out by [`wait_any`](Self::wait_any), in
+    /// the order the tasks were submitted. Tasks the pool dropped without
+    /// running are left out.
+    pub fn wait_all(self) -> Vec<TaskResult> {
+        let mut results: Vec<_> = self.receiver.iter().collect();
+        results.sort_unstable_by_key(|&(index, _)| index);
+        results.into_iter().map(|(_, result)| result).collect()
+    }
+
+    /// Blocks until the next task in the batch finishes and returns its
+    /// result, or `None` once every result has been handed out.
+    pub fn wait_any(&mut self) -> Option<TaskResult> {
+        self.receiver.recv().ok().map(|(_, result)| result)
+    }
+
+    /// Tasks in the batch.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Tasks that have finished and succeeded so far.
+    pub fn succeeded(&self) -> usize {
+        self.counts.succeeded.load(Ordering::Acquire)
+    }
+
+    /// Tasks that have finished without succeeding so far.
+    pub fn failed(&self) -> usize {
+        self.counts.failed.load(Ordering::Acquire)
+    }
+
+    /// Tasks that have finished so far, whether or not they succeeded.
+    pub fn finished(&self) -> usize {
+        self.succeeded() + self.failed()
+    }
+
+    /// Tasks still queued or running.
+    pub fn pending(&self) -> usize {
+        self.len - self.finished()
+    }
+}
+
+impl ThreadPool {
+    /// Queues every task in `tasks` at [`Priority::Normal`](crate::Priority)
+    /// and returns one handle for the lot.
+    pub fn submit_batch<T>(&self, tasks: impl IntoIterator<Item = T>) -> BatchHandle
+    where
+        T: Task + 'static,
+    {
+        self.submit_batch_with(tasks, SubmitOptions::new())
+    }
+
+    /// Like [`submit_batch`](Self::submit_batch), with the same
+    /// [`SubmitOptions`] for every task. Blocks while a bounded queue is
+    /// full.
+    pub fn submit_batch_with<T>(
+        &self,
+        tasks: impl IntoIterator<Item = T>,
+        options: SubmitOptions,
+    ) -> BatchHandle
+    where
+        T: Task + 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+        let counts = Arc::new(Counts::default());
+        let mut len = 0;
+        for (index, task) in tasks.into_iter().enumerate() {
+            let sender = sender.clone();
+            let counts = Arc::clone(&counts);
+            let lane = Arc::clone(self.lane(task.kind()));
+            let info = options.job_info(&task);
+            let job = self
+                .shared
+                .job(Arc::new(task), options.clone(), None, move |result| {
+                    let count = if result.is_success() {
+                        &counts.succeeded
+                    } else {
+                        &counts.failed
+                    };
+                    count.fetch_add(1, Ordering::AcqRel);
+                    // The caller may have dropped the handle; that's fine.
+                    let _ = sender.send((index, result));
+                });
+            lane.push(job, info);
+            len += 1;
+        }
+        BatchHandle {
+            receiver,
+            counts,
+            len,
+        }
+    }
+}
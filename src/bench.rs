@@ -0,0 +1,444 @@
+use crate::cli::{self, Args};
+use rust_concurrent_processor::{
+    self as rcp, BoundedQueue, FifoQueue, Scheduler, ShutdownMode, TaskContext, TaskError, TaskOutput, ThreadPool,
+};
+use std::hint::black_box;
+use std::fs;
+use std::sync::Mutex;
+use std::sync::atomic::{self, AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const TASKS: u32 = 20_000;
+const WORKERS: usize = 4;
+// Queue capacity for the bounded comparisons
+const BOUND: usize = 64;
+// Sleeping tasks in the shortest-job-first comparison, at most
+const SJF_TASKS: u32 = 40;
+// Compute tasks flooding the queue in the fair queuing comparison
+const FLOOD: u32 = 200;
+const UPDATES_PER_WORKER: u32 = 250_000;
+// CPU-bound items in the pool overhead comparison
+const OVERHEAD_ITEMS: u32 = 5_000;
+
+// A task that finishes almost immediately, so queue overhead dominates
+struct Tiny {
+    id: u32,
+}
+
+impl rcp::Task for Tiny {
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn execute(&self, _ctx: &TaskContext) -> Result<TaskOutput, TaskError> {
+        Ok(TaskOutput::new(tiny_work().to_string()))
+    }
+}
+
+fn tiny_work() -> u64 {
+    (0..200u64).map(black_box).sum()
+}
+
+pub fn run(args: &Args) {
+    let tasks = args.tasks_or(TASKS);
+    let workers = args.workers_or(WORKERS);
+    let schedulers = [Scheduler::SharedQueue, Scheduler::WorkStealing, Scheduler::Channel, Scheduler::Fifo]
+        .map(|scheduler| (format!("{:?}", scheduler), ThreadPool::builder().scheduler(scheduler)));
+    // mpsc against the mutex and condvar queue once more with a bound, where
+    // submitters have to wait for room, and the same bound put on from
+    // outside the queue
+    let bounded = [Scheduler::Channel, Scheduler::Fifo].map(|scheduler| {
+        let builder = ThreadPool::builder().scheduler(scheduler).queue_capacity(BOUND);
+        (format!("{:?}, bounded({})", scheduler, BOUND), builder)
+    });
+    let wrapped = ("BoundedQueue(Fifo)".to_string(), ThreadPool::builder().queue(|| BoundedQueue::new(FifoQueue::new(), BOUND)));
+    for (name, builder) in schedulers.into_iter().chain(bounded).chain([wrapped]) {
+        let pool = builder.workers(workers).build();
+
+        let start = Instant::now();
+        pool.submit_batch((0..tasks).map(|id| Tiny { id })).wait_all();
+        report(&name, tasks, workers, start.elapsed());
+        pool.shutdown(ShutdownMode::Drain);
+    }
+
+    // The same work as plain closures, skipping the Task/TaskResult plumbing
+    let pool = ThreadPool::new(workers);
+    let start = Instant::now();
+    let handles: Vec<_> = (0..tasks).map(|_| pool.spawn(tiny_work)).collect();
+    for handle in handles {
+        handle.wait();
+    }
+    report("Closures", tasks, workers, start.elapsed());
+    pool.shutdown(ShutdownMode::Drain);
+
+    // Chunked up front by map(), a handful of jobs instead of one per item
+    let pool = ThreadPool::new(workers);
+    let start = Instant::now();
+    pool.map(0..tasks, |_| tiny_work());
+    report("Map", tasks, workers, start.elapsed());
+    pool.shutdown(ShutdownMode::Drain);
+}
+
+// Sleeps for as long as it says it will, so a cost-aware scheduler knows
+// exactly what it's in for
+struct Sleep {
+    id: u32,
+    kind: &'static str,
+    millis: u64,
+}
+
+impl rcp::Task for Sleep {
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn kind(&self) -> &str {
+        self.kind
+    }
+
+    fn cost(&self) -> Option<Duration> {
+        Some(Duration::from_millis(self.millis))
+    }
+
+    fn execute(&self, _ctx: &TaskContext) -> Result<TaskOutput, TaskError> {
+        thread::sleep(Duration::from_millis(self.millis));
+        Ok(TaskOutput::new(format!("Slept {}ms", self.millis)))
+    }
+}
+
+// The same mix of short and long tasks in the order they came and
+// shortest first, timing each from submission to its result. Running the
+// quick ones first leaves fewer tasks waiting behind slow ones, so the
+// average comes down even though the last result is no sooner
+pub fn run_sjf(args: &Args) {
+    let tasks = args.tasks_or(SJF_TASKS).min(SJF_TASKS);
+    let workers = args.workers_or(2);
+    for scheduler in [Scheduler::Fifo, Scheduler::ShortestJobFirst] {
+        let pool = ThreadPool::builder().workers(workers).scheduler(scheduler).build();
+        let start = Instant::now();
+        let mut results = pool.results();
+        for id in 1..=tasks {
+            results.submit(Sleep { id, kind: "sleep", millis: cli::work_duration(id) / 5 });
+        }
+        let finished: Vec<Duration> = results.map(|_| start.elapsed()).collect();
+        let mean = finished.iter().sum::<Duration>() / finished.len().max(1) as u32;
+        println!(
+            "{:?}: {} tasks on {} workers, {}ms to a result on average, all done in {}ms",
+            scheduler,
+            tasks,
+            workers,
+            mean.as_millis(),
+            start.elapsed().as_millis()
+        );
+        pool.shutdown(ShutdownMode::Drain);
+    }
+}
+
+// A flood of compute tasks with a few downloads queued right behind them.
+// Shared, the downloads wait for the whole flood to clear; taking turns
+// between task types, they start straight away
+pub fn run_fair(args: &Args) {
+    let workers = args.workers_or(2);
+    let runs = [
+        ("SharedQueue", ThreadPool::builder().scheduler(Scheduler::SharedQueue)),
+        ("WeightedFair", ThreadPool::builder().scheduler(Scheduler::WeightedFair)),
+        ("WeightedFair, download x3", ThreadPool::builder().scheduler(Scheduler::WeightedFair).weight("download", 3)),
+    ];
+    for (name, builder) in runs {
+        let pool = builder.workers(workers).build();
+        let flood = (0..FLOOD).map(|id| Sleep { id, kind: "compute", millis: 2 });
+        let trickle = (FLOOD..FLOOD + FLOOD / 10).map(|id| Sleep { id, kind: "download", millis: 2 });
+        pool.submit_batch(flood.chain(trickle)).wait_all();
+        let stats = pool.shutdown(ShutdownMode::Drain);
+        let waits: Vec<String> = stats.queue_wait.iter()
+            .map(|(task_type, wait)| {
+                let mean = wait.sum_ms() / wait.count().max(1);
+                format!("{} {}ms (p95 {}ms)", task_type, mean, wait.percentile_ms(95.0))
+            })
+            .collect();
+        println!("{}: average wait {}", name, waits.join(", "));
+    }
+}
+
+// Tens to hundreds of microseconds of arithmetic, varying by id
+struct Spin {
+    id: u32,
+}
+
+impl rcp::Task for Spin {
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn execute(&self, _ctx: &TaskContext) -> Result<TaskOutput, TaskError> {
+        Ok(TaskOutput::new(spin(self.id).to_string()))
+    }
+}
+
+fn spin(id: u32) -> u64 {
+    (0..cli::work_duration(id) * 1000).map(black_box).sum()
+}
+
+// The same CPU-bound items on plain scoped threads that each take the
+// next item as they free up, which is about all a data-parallel library
+// like rayon does for a flat loop, and through the pool as tasks and as
+// closures. What the pool costs on top shows as lost throughput and extra
+// CPU time. Latency runs from the start to each item's result
+pub fn run_overhead(args: &Args) {
+    let items = args.tasks_or(OVERHEAD_ITEMS);
+    let workers = args.workers_or(WORKERS);
+    println!("{:<14} {:>8} {:>10} {:>9} {:>9}", "", "wall", "items/s", "p99", "CPU");
+
+    let (start, cpu) = (Instant::now(), cpu_time());
+    let next = AtomicU32::new(0);
+    let latencies = thread::scope(|scope| {
+        let threads: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut latencies = Vec::new();
+                    loop {
+                        let id = next.fetch_add(1, Ordering::Relaxed);
+                        if id >= items {
+                            break;
+                        }
+                        black_box(spin(id));
+                        latencies.push(start.elapsed());
+                    }
+                    latencies
+                })
+            })
+            .collect();
+        threads.into_iter().flat_map(|thread| thread.join().unwrap()).collect()
+    });
+    report_overhead("Scoped threads", items, start, cpu, latencies);
+
+    let pool = ThreadPool::new(workers);
+    let (start, cpu) = (Instant::now(), cpu_time());
+    let mut results = pool.results();
+    for id in 0..items {
+        results.submit(Spin { id });
+    }
+    let latencies = results.map(|_| start.elapsed()).collect();
+    report_overhead("Pool tasks", items, start, cpu, latencies);
+
+    let (start, cpu) = (Instant::now(), cpu_time());
+    let handles: Vec<_> = (0..items)
+        .map(|id| {
+            pool.spawn(move || {
+                black_box(spin(id));
+                start.elapsed()
+            })
+        })
+        .collect();
+    let latencies = handles.into_iter().map(|handle| handle.wait().unwrap_or_default()).collect();
+    report_overhead("Pool spawn", items, start, cpu, latencies);
+    pool.shutdown(ShutdownMode::Drain);
+}
+
+fn report_overhead(label: &str, items: u32, start: Instant, cpu: Option<Duration>, mut latencies: Vec<Duration>) {
+    let elapsed = start.elapsed();
+    let cpu = cpu.zip(cpu_time()).map_or("n/a".to_string(), |(before, after)| format!("{}ms", (after - before).as_millis()));
+    latencies.sort_unstable();
+    let p99 = latencies.get((latencies.len() * 99 / 100).min(latencies.len().saturating_sub(1))).copied().unwrap_or_default();
+    println!(
+        "{:<14} {:>6}ms {:>10.0} {:>7}ms {:>9}",
+        label,
+        elapsed.as_millis(),
+        items as f64 / elapsed.as_secs_f64(),
+        p99.as_millis(),
+        cpu
+    );
+}
+
+// User plus system CPU time the process has used so far, from
+// /proc/self/stat in clock ticks of 10ms. None where there's no /proc
+fn cpu_time() -> Option<Duration> {
+    let stat = fs::read_to_string("/proc/self/stat").ok()?;
+    // The command name can hold spaces, so count from the ')' closing it:
+    // utime and stime are the 12th and 13th fields after it
+    let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+    let ticks = fields.get(11)?.parse::<u64>().ok()? + fields.get(12)?.parse::<u64>().ok()?;
+    Some(Duration::from_millis(ticks * 10))
+}
+
+// Every task bumps a couple of counters; compare doing that under one
+// Mutex with plain atomics, with a Mutex in a shard per thread, and with
+// atomics in a seqlocked shard per thread the way the pool keeps its
+// stats, when the tasks themselves are tiny
+#[derive(Default)]
+struct LockedCounters {
+    completed: u64,
+    total_time: u64,
+}
+
+#[derive(Default)]
+struct AtomicCounters {
+    completed: AtomicU64,
+    total_time: AtomicU64,
+}
+
+// Each shard under a lock and on a cache line of its own
+#[derive(Default)]
+#[repr(align(128))]
+struct CounterShard(Mutex<LockedCounters>);
+
+// Every shard locked at once so the totals agree
+fn snapshot(shards: &[CounterShard]) -> u64 {
+    let locked: Vec<_> = shards.iter().map(|shard| shard.0.lock().unwrap()).collect();
+    locked.iter().map(|counters| counters.completed + counters.total_time).sum()
+}
+
+// Atomics on a cache line of their own behind a seqlock, like one of the
+// pool's stats shards: odd while a thread is updating it
+#[derive(Default)]
+#[repr(align(128))]
+struct SeqlockShard {
+    sequence: AtomicU64,
+    counters: AtomicCounters,
+}
+
+impl SeqlockShard {
+    fn add(&self) {
+        let mut sequence = self.sequence.load(Ordering::Relaxed);
+        loop {
+            if sequence % 2 == 1 {
+                thread::yield_now();
+                sequence = self.sequence.load(Ordering::Relaxed);
+                continue;
+            }
+            match self.sequence.compare_exchange_weak(sequence, sequence + 1, Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => break,
+                Err(now) => sequence = now,
+            }
+        }
+        atomic::fence(Ordering::Release);
+        let add = |counter: &AtomicU64| counter.store(counter.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+        add(&self.counters.completed);
+        add(&self.counters.total_time);
+        self.sequence.store(sequence + 2, Ordering::Release);
+    }
+
+    // Read again if an update got in the way, so the two counters agree
+    fn read(&self) -> u64 {
+        loop {
+            let sequence = self.sequence.load(Ordering::Acquire);
+            if sequence.is_multiple_of(2) {
+                let sum = self.counters.completed.load(Ordering::Relaxed) + self.counters.total_time.load(Ordering::Relaxed);
+                atomic::fence(Ordering::Acquire);
+                if self.sequence.load(Ordering::Relaxed) == sequence {
+                    return sum;
+                }
+            }
+            thread::yield_now();
+        }
+    }
+}
+
+// Every shard read whole, as the pool's stats snapshot does
+fn seqlock_snapshot(shards: &[SeqlockShard]) -> u64 {
+    shards.iter().map(SeqlockShard::read).sum()
+}
+
+pub fn run_stats(args: &Args) {
+    let workers = args.workers_or(WORKERS);
+    let locked = Mutex::new(LockedCounters::default());
+    let start = Instant::now();
+    hammer(workers, |_| {
+        let mut counters = locked.lock().unwrap();
+        counters.completed += 1;
+        counters.total_time += 1;
+    });
+    report_updates("Mutex", workers, start.elapsed());
+
+    let atomic = AtomicCounters::default();
+    let start = Instant::now();
+    hammer(workers, |_| {
+        atomic.completed.fetch_add(1, Ordering::Relaxed);
+        atomic.total_time.fetch_add(1, Ordering::Relaxed);
+    });
+    report_updates("Atomics", workers, start.elapsed());
+
+    // Summed once at the end, as a snapshot would
+    let shards: Vec<CounterShard> = (0..workers).map(|_| CounterShard::default()).collect();
+    let update = |worker: usize| {
+        let mut counters = shards[worker].0.lock().unwrap();
+        counters.completed += 1;
+        counters.total_time += 1;
+    };
+    let start = Instant::now();
+    hammer(workers, update);
+    black_box(snapshot(&shards));
+    report_updates("Sharded Mutex", workers, start.elapsed());
+
+    let seqlocked: Vec<SeqlockShard> = (0..workers).map(|_| SeqlockShard::default()).collect();
+    let seqlocked_update = |worker: usize| seqlocked[worker].add();
+    let start = Instant::now();
+    hammer(workers, seqlocked_update);
+    black_box(seqlock_snapshot(&seqlocked));
+    report_updates("Sharded seqlock", workers, start.elapsed());
+
+    // Again with a snapshot every millisecond, as the dashboard or a
+    // metrics scrape would take. Locked shards are all held at once,
+    // holding up the threads updating them; seqlocked ones are only read
+    // again if an update got in the way
+    let start = Instant::now();
+    snapshotted(workers, || snapshot(&shards), update);
+    report_updates("Sharded Mutex, snapshotted", workers, start.elapsed());
+
+    let start = Instant::now();
+    snapshotted(workers, || seqlock_snapshot(&seqlocked), seqlocked_update);
+    report_updates("Sharded seqlock, snapshotted", workers, start.elapsed());
+}
+
+// Runs `update` on every worker while `snapshot` is taken every millisecond
+fn snapshotted(workers: usize, snapshot: impl Fn() -> u64 + Sync, update: impl Fn(usize) + Sync) {
+    let done = AtomicBool::new(false);
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            while !done.load(Ordering::Relaxed) {
+                black_box(snapshot());
+                thread::sleep(Duration::from_millis(1));
+            }
+        });
+        hammer(workers, update);
+        done.store(true, Ordering::Relaxed);
+    });
+}
+
+// Runs `update` over and over on `workers` threads, passing each its index
+fn hammer(workers: usize, update: impl Fn(usize) + Sync) {
+    thread::scope(|scope| {
+        for worker in 0..workers {
+            let update = &update;
+            scope.spawn(move || {
+                for _ in 0..UPDATES_PER_WORKER {
+                    update(worker);
+                }
+            });
+        }
+    });
+}
+
+fn report_updates(label: &str, workers: usize, elapsed: Duration) {
+    let updates = UPDATES_PER_WORKER * workers as u32;
+    println!(
+        "{}: {} stat updates on {} threads in {}ms ({:.0} updates/s)",
+        label,
+        updates,
+        workers,
+        elapsed.as_millis(),
+        updates as f64 / elapsed.as_secs_f64()
+    );
+}
+
+fn report(label: &str, tasks: u32, workers: usize, elapsed: Duration) {
+    println!(
+        "{}: {} tasks on {} workers in {}ms ({:.0} tasks/s)",
+        label,
+        tasks,
+        workers,
+        elapsed.as_millis(),
+        tasks as f64 / elapsed.as_secs_f64()
+    );
+}
@@ -0,0 +1,319 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::chaos::Chaos;
+use crate::circuit::CircuitBreaker;
+use crate::clock::{Clock, SharedClock};
+use crate::hooks::{Listeners, TaskListener};
+use crate::local::WorkerInit;
+use crate::pool::{Job, ShutdownMode, ThreadPool};
+use crate::queue::{QueueFactory, Scheduler, TaskQueue};
+use crate::rate_limit::RateLimit;
+use crate::retry::RetryPolicy;
+use crate::sync::PoisonPolicy;
+use crate::worker::WaitStrategy;
+
+/// Configures a [`ThreadPool`] before any worker is spawned.
+#[derive(Clone, Debug)]
+pub struct ThreadPoolBuilder {
+    pub(crate) workers: usize,
+    pub(crate) max_workers: usize,
+    pub(crate) scale_up_threshold: usize,
+    pub(crate) keep_alive: Duration,
+    pub(crate) queue_capacity: Option<usize>,
+    pub(crate) scheduler: Scheduler,
+    pub(crate) queue: Option<QueueFactory>,
+    pub(crate) default_timeout: Option<Duration>,
+    pub(crate) retry_policy: RetryPolicy,
+    pub(crate) rate_limits: HashMap<String, RateLimit>,
+    pub(crate) max_concurrent: HashMap<String, usize>,
+    pub(crate) circuit_breakers: HashMap<String, CircuitBreaker>,
+    pub(crate) dedicated_workers: HashMap<String, usize>,
+    pub(crate) weights: HashMap<String, u32>,
+    pub(crate) listeners: Listeners,
+    pub(crate) poison_policy: PoisonPolicy,
+    pub(crate) clock: SharedClock,
+    pub(crate) chaos: Option<Chaos>,
+    pub(crate) worker_init: Option<WorkerInit>,
+    pub(crate) pin_workers: bool,
+    pub(crate) thread_name_prefix: String,
+    pub(crate) stack_size: Option<usize>,
+    pub(crate) wait_strategy: WaitStrategy,
+    pub(crate) on_drop: ShutdownMode,
+}
+
+impl Default for ThreadPoolBuilder {
+    fn default() -> Self {
+        ThreadPoolBuilder {
+            workers: thread::available_parallelism().map_or(4, |n| n.get()),
+            max_workers: 0,
+            scale_up_threshold: 0,
+            keep_alive: Duration::from_secs(1),
+            queue_capacity: None,
+            scheduler: Scheduler::SharedQueue,
+            queue: None,
+            default_timeout: None,
+            retry_policy: RetryPolicy::none(),
+            rate_limits: HashMap::new(),
+            max_concurrent: HashMap::new(),
+            circuit_breakers: HashMap::new(),
+            dedicated_workers: HashMap::new(),
+            weights: HashMap::new(),
+            listeners: Listeners::default(),
+            poison_policy: PoisonPolicy::Recover,
+            clock: SharedClock::default(),
+            chaos: None,
+            worker_init: None,
+            pin_workers: false,
+            thread_name_prefix: "rcp-worker".to_string(),
+            stack_size: None,
+            wait_strategy: WaitStrategy::Park,
+            on_drop: ShutdownMode::Drain,
+        }
+    }
+}
+
+impl ThreadPoolBuilder {
+    pub fn new() -> Self {
+        ThreadPoolBuilder::default()
+    }
+
+    /// Number of worker threads kept alive at all times. Defaults to the
+    /// available parallelism.
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers;
+        self
+    }
+
+    /// Lets the pool grow past [`workers`](Self::workers) up to `max`
+    /// threads while tasks are piling up. Extra workers exit again after
+    /// sitting idle for the [`keep_alive`](Self::keep_alive) period.
+    pub fn max_workers(mut self, max: usize) -> Self {
+        self.max_workers = max;
+        self
+    }
+
+    /// Queue depth above which a submission spawns another worker, as long
+    /// as the pool is below [`max_workers`](Self::max_workers). Defaults to 0,
+    /// i.e. grow whenever anything is waiting.
+    pub fn scale_up_threshold(mut self, depth: usize) -> Self {
+        self.scale_up_threshold = depth;
+        self
+    }
+
+    /// How long an extra worker may sit idle before it exits. Defaults to
+    /// one second.
+    pub fn keep_alive(mut self, keep_alive: Duration) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// How idle workers wait for tasks. Defaults to
+    /// [`WaitStrategy::Park`]. An extra worker only starts on its
+    /// [`keep_alive`](Self::keep_alive) period once it has stopped
+    /// spinning.
+    pub fn wait_strategy(mut self, strategy: WaitStrategy) -> Self {
+        self.wait_strategy = strategy;
+        self
+    }
+
+    /// Caps how many tasks may wait in the queue. Once it is full,
+    /// [`ThreadPool::submit`] blocks and [`ThreadPool::try_submit`] returns
+    /// [`QueueFull`](crate::QueueFull). Unbounded by default.
+    pub fn queue_capacity(mut self, capacity: usize) -> Self {
+        self.queue_capacity = Some(capacity);
+        self
+    }
+
+    /// How tasks are handed to workers. Defaults to
+    /// [`Scheduler::SharedQueue`].
+    pub fn scheduler(mut self, scheduler: Scheduler) -> Self {
+        self.scheduler = scheduler;
+        self
+    }
+
+    /// Uses the queues `make` returns instead of the one the
+    /// [`scheduler`](Self::scheduler) calls for, e.g.
+    /// `|| BoundedQueue::new(FifoQueue::new(), 100)`. It's called once for
+    /// the pool and once per task type with [dedicated
+    /// workers](Self::dedicated_workers). Overrides both the scheduler and
+    /// the [`queue_capacity`](Self::queue_capacity).
+    pub fn queue<Q, F>(mut self, make: F) -> Self
+    where
+        Q: TaskQueue<Job> + 'static,
+        F: Fn() -> Q + Send + Sync + 'static,
+    {
+        self.queue = Some(QueueFactory::new(make));
+        self
+    }
+
+    /// Timeout applied to every task that doesn't set its own through
+    /// [`SubmitOptions::timeout`](crate::SubmitOptions::timeout).
+    ///
+    /// Tasks run on their worker either way, so a timeout costs nothing
+    /// extra, but it only cuts a task short if the task checks
+    /// [`TaskContext::is_cancelled`](crate::TaskContext::is_cancelled).
+    /// One that doesn't holds its worker until it returns, and keeps its
+    /// output if it succeeds late; a [`Watchdog`](crate::Watchdog) can
+    /// start another worker in its place.
+    pub fn default_timeout(mut self, timeout: Duration) -> Self {
+        self.default_timeout = Some(timeout);
+        self
+    }
+
+    /// Retry policy applied to every task that doesn't set its own through
+    /// [`SubmitOptions::retry`](crate::SubmitOptions::retry).
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Throttles tasks whose [`Task::kind`](crate::Task::kind) is
+    /// `task_type`, however many workers are free. Other task types are
+    /// unaffected.
+    pub fn rate_limit(mut self, task_type: impl Into<String>, limit: RateLimit) -> Self {
+        self.rate_limits.insert(task_type.into(), limit);
+        self
+    }
+
+    /// Lets at most `max` tasks whose [`Task::kind`](crate::Task::kind) is
+    /// `task_type` run at once, however many workers are free. A worker
+    /// that picks up one more waits for a slot, so give the type
+    /// [dedicated workers](Self::dedicated_workers) if the others shouldn't
+    /// wait on it.
+    pub fn max_concurrent(mut self, task_type: impl Into<String>, max: usize) -> Self {
+        self.max_concurrent.insert(task_type.into(), max);
+        self
+    }
+
+    /// Fails tasks whose [`Task::kind`](crate::Task::kind) is `task_type`
+    /// fast while they keep failing, then lets them run again once a trial
+    /// task succeeds. See [`CircuitBreaker`].
+    pub fn circuit_breaker(
+        mut self,
+        task_type: impl Into<String>,
+        breaker: CircuitBreaker,
+    ) -> Self {
+        self.circuit_breakers.insert(task_type.into(), breaker);
+        self
+    }
+
+    /// Gives tasks whose [`Task::kind`](crate::Task::kind) is `task_type` a
+    /// queue and `workers` threads of their own, so they neither wait behind
+    /// nor hold up other task types. Dedicated workers don't scale; the
+    /// other settings apply to them as to the rest of the pool.
+    pub fn dedicated_workers(mut self, task_type: impl Into<String>, workers: usize) -> Self {
+        self.dedicated_workers.insert(task_type.into(), workers);
+        self
+    }
+
+    /// Under [`Scheduler::WeightedFair`], gives tasks whose
+    /// [`Task::kind`](crate::Task::kind) is `task_type` `weight` turns at a
+    /// free worker for every one a type of weight 1 gets. Types without a
+    /// weight count as 1.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `weight` is zero.
+    pub fn weight(mut self, task_type: impl Into<String>, weight: u32) -> Self {
+        assert!(weight > 0, "a task type needs a weight of at least 1");
+        self.weights.insert(task_type.into(), weight);
+        self
+    }
+
+    /// Calls `listener` as tasks are submitted, started and finished. Can
+    /// be called more than once to add several.
+    pub fn listener(mut self, listener: impl TaskListener + 'static) -> Self {
+        self.listeners.add(Arc::new(listener));
+        self
+    }
+
+    /// What to do when a listener, or the fold given to
+    /// [`ThreadPool::reduce`], panics. Defaults to
+    /// [`PoisonPolicy::Recover`].
+    pub fn poison_policy(mut self, policy: PoisonPolicy) -> Self {
+        self.poison_policy = policy;
+        self
+    }
+
+    /// Takes the time from `clock` rather than the system, e.g. a
+    /// [`VirtualClock`](crate::VirtualClock) so retry backoff and tasks
+    /// that wait through [`TaskContext::sleep`](crate::TaskContext::sleep)
+    /// cost no real time. Delayed and recurring tasks and circuit breaker
+    /// cool-downs follow it too; idle workers' keep-alive, the watchdog
+    /// and rate limits keep to the system clock.
+    pub fn clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = SharedClock::new(clock);
+        self
+    }
+
+    /// Injects the faults `chaos` describes into every task the pool runs.
+    /// Only meant for testing how the rest of the setup copes.
+    pub fn chaos(mut self, chaos: Chaos) -> Self {
+        self.chaos = Some(chaos);
+        self
+    }
+
+    /// Gives every worker a state of its own, made by `init` the first
+    /// time one of its tasks asks for it with
+    /// [`TaskContext::with_worker_state`](crate::TaskContext::with_worker_state).
+    /// Somewhere to keep a connection or a buffer that's costly to set up
+    /// for every task, without sharing it behind a lock.
+    pub fn worker_init<S, F>(mut self, init: F) -> Self
+    where
+        S: Send + 'static,
+        F: Fn() -> S + Send + Sync + 'static,
+    {
+        self.worker_init = Some(WorkerInit::new(init));
+        self
+    }
+
+    /// Keeps each worker on a CPU core of its own, taking the cores in
+    /// turn once there are more workers than cores. A compute-heavy task
+    /// then finds its worker's data still in that core's cache rather than
+    /// following the thread from core to core. Only on Linux and Windows;
+    /// elsewhere it does nothing. Off by default.
+    pub fn pin_workers(mut self, pin: bool) -> Self {
+        self.pin_workers = pin;
+        self
+    }
+
+    /// Names worker threads `<prefix>-<id>`, with the same id the worker
+    /// has in [`TaskStatus::Running`](crate::TaskStatus::Running), so they
+    /// can be told apart in a debugger or profiler. Defaults to
+    /// `rcp-worker`.
+    pub fn thread_name_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.thread_name_prefix = prefix.into();
+        self
+    }
+
+    /// Gives each worker thread a stack of `bytes`, for tasks that recurse
+    /// deeper than the default stack allows. Defaults to std's, which is
+    /// 2MiB unless `RUST_MIN_STACK` says otherwise.
+    pub fn stack_size(mut self, bytes: usize) -> Self {
+        self.stack_size = Some(bytes);
+        self
+    }
+
+    /// How a pool dropped without being [shut
+    /// down](ThreadPool::shutdown) deals with its queued tasks before it
+    /// joins its workers. Defaults to [`ShutdownMode::Drain`], so dropping
+    /// a pool waits for everything submitted to it.
+    pub fn on_drop(mut self, mode: ShutdownMode) -> Self {
+        self.on_drop = mode;
+        self
+    }
+
+    /// Spawns the workers.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any worker count, the queue capacity or a
+    /// [`max_concurrent`](Self::max_concurrent) is zero, or if a rate limit
+    /// allows no tasks per second.
+    pub fn build(self) -> ThreadPool {
+        ThreadPool::from_builder(self)
+    }
+}
@@ -0,0 +1,76 @@
+// Keeps downloaded bodies by URL so a URL fetched once in a run comes
+// straight back the next time it's asked for. Bodies live in memory, and
+// also in a directory if one is given, where later runs find them too
+use crate::log::{self, Tags};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+
+pub struct DownloadCache {
+    dir: Option<PathBuf>,
+    memory: Mutex<HashMap<String, Arc<[u8]>>>,
+    hits: AtomicU32,
+    misses: AtomicU32,
+}
+
+impl DownloadCache {
+    pub fn new(dir: Option<PathBuf>) -> io::Result<DownloadCache> {
+        if let Some(dir) = &dir {
+            fs::create_dir_all(dir)?;
+        }
+        Ok(DownloadCache { dir, memory: Mutex::new(HashMap::new()), hits: AtomicU32::new(0), misses: AtomicU32::new(0) })
+    }
+
+    // The body cached for `url` and true, or else whatever `fetch` gets and
+    // false. Only successful fetches are kept. Two workers missing the same
+    // URL at once both fetch it
+    pub fn get_or_fetch<E>(&self, url: &str, fetch: impl FnOnce() -> Result<Vec<u8>, E>) -> Result<(Arc<[u8]>, bool), E> {
+        if let Some(body) = self.lookup(url) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok((body, true));
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let body: Arc<[u8]> = fetch()?.into();
+        if let Some(path) = self.path(url)
+            && let Err(err) = fs::write(&path, &body)
+        {
+            log::warn(&Tags::default(), format_args!("can't cache {} in {}: {}", url, path.display(), err));
+        }
+        self.memory.lock().unwrap().insert(url.to_string(), Arc::clone(&body));
+        Ok((body, false))
+    }
+
+    pub fn hits(&self) -> u32 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u32 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    fn lookup(&self, url: &str) -> Option<Arc<[u8]>> {
+        if let Some(body) = self.memory.lock().unwrap().get(url) {
+            return Some(Arc::clone(body));
+        }
+        // Found on disk, so keep it in memory from now on
+        let body: Arc<[u8]> = fs::read(self.path(url)?).ok()?.into();
+        self.memory.lock().unwrap().insert(url.to_string(), Arc::clone(&body));
+        Some(body)
+    }
+
+    // One file per URL, named after it with anything that isn't safe in a
+    // file name escaped as %XX
+    fn path(&self, url: &str) -> Option<PathBuf> {
+        let name: String = url
+            .bytes()
+            .map(|byte| match byte {
+                b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'.' | b'-' | b'_' => (byte as char).to_string(),
+                _ => format!("%{:02X}", byte),
+            })
+            .collect();
+        Some(self.dir.as_ref()?.join(name))
+    }
+}
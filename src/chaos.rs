@@ -0,0 +1,95 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::task::{TaskContext, TaskError};
+
+/// Faults to inject into a pool on purpose, set with
+/// [`ThreadPoolBuilder::chaos`](crate::ThreadPoolBuilder::chaos), to check
+/// that retries, timeouts, the watchdog and whatever handles failed tasks
+/// hold up. Every rate is a chance per attempt, from 0.0 to 1.0, and all
+/// are 0.0 to begin with.
+///
+/// Draws come from one sequence seeded by `seed`, shared by every worker,
+/// so a single-worker pool gets the same faults every run.
+#[derive(Clone, Debug, Default)]
+pub struct Chaos {
+    panics: f64,
+    failures: f64,
+    drops: f64,
+    slowdowns: f64,
+    max_latency: Duration,
+    state: Arc<AtomicU64>,
+}
+
+impl Chaos {
+    pub fn new(seed: u64) -> Self {
+        Chaos {
+            state: Arc::new(AtomicU64::new(seed)),
+            ..Chaos::default()
+        }
+    }
+
+    /// Makes attempts panic, as a bug in the task would.
+    pub fn panics(mut self, rate: f64) -> Self {
+        self.panics = rate;
+        self
+    }
+
+    /// Makes attempts fail with a retryable error without running.
+    pub fn failures(mut self, rate: f64) -> Self {
+        self.failures = rate;
+        self
+    }
+
+    /// Drops tasks from the queue before they run. They're reported as
+    /// [`TaskResult::Dropped`](crate::TaskResult::Dropped).
+    pub fn drops(mut self, rate: f64) -> Self {
+        self.drops = rate;
+        self
+    }
+
+    /// Holds attempts up for up to `max` before they run. The wait counts
+    /// against the task's timeout.
+    pub fn latency(mut self, rate: f64, max: Duration) -> Self {
+        self.slowdowns = rate;
+        self.max_latency = max;
+        self
+    }
+
+    /// Whether the next task to run should be dropped instead.
+    pub(crate) fn drop_next(&self) -> bool {
+        self.roll(self.drops)
+    }
+
+    /// Called at the start of every attempt, where a panic is caught like
+    /// the task's own.
+    pub(crate) fn before_attempt(&self, ctx: &TaskContext) -> Result<(), TaskError> {
+        if self.roll(self.slowdowns) {
+            ctx.sleep(self.max_latency.mul_f64(self.next()));
+        }
+        if self.roll(self.panics) {
+            panic!("chaos: injected panic");
+        }
+        if self.roll(self.failures) {
+            return Err(TaskError::new("chaos: injected failure"));
+        }
+        Ok(())
+    }
+
+    fn roll(&self, rate: f64) -> bool {
+        rate > 0.0 && self.next() < rate
+    }
+
+    /// The next draw, from 0.0 up to 1.0 (SplitMix64).
+    fn next(&self) -> f64 {
+        let mut z = self
+            .state
+            .fetch_add(0x9e3779b97f4a7c15, Ordering::Relaxed)
+            .wrapping_add(0x9e3779b97f4a7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
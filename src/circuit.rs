@@ -0,0 +1,236 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::clock::SharedClock;
+use crate::sync::Recover;
+use crate::task::TaskResult;
+
+/// When to stop running tasks of one type that keep failing, set through
+/// [`ThreadPoolBuilder::circuit_breaker`](crate::ThreadPoolBuilder::circuit_breaker).
+///
+/// After `failure_threshold` failures in a row the circuit opens and tasks
+/// of that type fail fast as [`TaskResult::CircuitOpen`] without running.
+/// Once `cool_down` has passed, one task is let through as a trial: if it
+/// succeeds the circuit closes again, otherwise it reopens for another
+/// `cool_down`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CircuitBreaker {
+    pub failure_threshold: u32,
+    pub cool_down: Duration,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, cool_down: Duration) -> Self {
+        CircuitBreaker {
+            failure_threshold,
+            cool_down,
+        }
+    }
+}
+
+/// Where a [`CircuitBreaker`] stands, as reported in
+/// [`SystemStats::circuits`](crate::SystemStats::circuits).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Tasks run as usual.
+    Closed,
+    /// Tasks fail fast until the cool-down is over.
+    Open,
+    /// A trial task is running; the rest fail fast until it's done.
+    HalfOpen,
+}
+
+#[derive(Debug)]
+enum State {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    HalfOpen,
+}
+
+/// Whether a task may run, from [`Breaker::try_pass`].
+pub(crate) enum Pass {
+    Run,
+    /// Runs as the half-open trial; its result decides the circuit.
+    Trial,
+    Reject,
+}
+
+/// The live state behind one task type's [`CircuitBreaker`], shared by
+/// every queue. The cool-down runs on the pool's clock.
+#[derive(Debug)]
+pub(crate) struct Breaker {
+    config: CircuitBreaker,
+    state: Mutex<State>,
+    clock: SharedClock,
+}
+
+impl Breaker {
+    pub(crate) fn new(config: CircuitBreaker, clock: SharedClock) -> Self {
+        assert!(
+            config.failure_threshold > 0,
+            "a circuit breaker needs a failure threshold of at least 1"
+        );
+        Breaker {
+            config,
+            state: Mutex::new(State::Closed { failures: 0 }),
+            clock,
+        }
+    }
+
+    pub(crate) fn try_pass(&self) -> Pass {
+        let mut state = self.state.lock().recover();
+        match *state {
+            State::Closed { .. } => Pass::Run,
+            State::Open { until } if self.clock.now() >= until => {
+                *state = State::HalfOpen;
+                Pass::Trial
+            }
+            State::Open { .. } | State::HalfOpen => Pass::Reject,
+        }
+    }
+
+    /// Counts the result of a task that was let through. Cancelled tasks
+    /// say nothing about the health of what they call, so they don't count.
+    pub(crate) fn record(&self, pass: &Pass, result: &TaskResult) {
+        let failed = match result {
+            TaskResult::Success { .. } => false,
+            TaskResult::Error { .. }
+            | TaskResult::TimedOut { .. }
+            | TaskResult::Panicked { .. } => true,
+            _ => {
+                // Don't leave the circuit stuck half-open without a trial.
+                if matches!(pass, Pass::Trial) {
+                    *self.state.lock().recover() = State::Open {
+                        until: self.clock.now(),
+                    };
+                }
+                return;
+            }
+        };
+        let mut state = self.state.lock().recover();
+        match (&*state, pass) {
+            (State::HalfOpen, Pass::Trial) | (State::Closed { .. }, _) if !failed => {
+                *state = State::Closed { failures: 0 };
+            }
+            (State::HalfOpen, Pass::Trial) => self.open(&mut state),
+            (State::Closed { failures }, _) if failures + 1 >= self.config.failure_threshold => {
+                self.open(&mut state)
+            }
+            (State::Closed { failures }, _) => {
+                *state = State::Closed {
+                    failures: failures + 1,
+                }
+            }
+            // Tasks that started before the circuit opened don't move it.
+            _ => {}
+        }
+    }
+
+    fn open(&self, state: &mut State) {
+        *state = State::Open {
+            until: self.clock.now() + self.config.cool_down,
+        };
+    }
+
+    pub(crate) fn state(&self) -> CircuitState {
+        match *self.state.lock().recover() {
+            State::Closed { .. } => CircuitState::Closed,
+            State::Open { .. } => CircuitState::Open,
+            State::HalfOpen => CircuitState::HalfOpen,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::VirtualClock;
+    use crate::task::{TaskError, TaskOutput};
+    use std::sync::Arc;
+
+    fn succeeded() -> TaskResult {
+        TaskResult::Success {
+            id: 1,
+            task_type: "fetch".to_string(),
+            correlation_id: None,
+            output: TaskOutput::new("done"),
+            duration_ms: 0,
+            queued_ms: 0,
+            attempts: 1,
+        }
+    }
+
+    fn failed() -> TaskResult {
+        TaskResult::Error {
+            id: 1,
+            task_type: "fetch".to_string(),
+            correlation_id: None,
+            error: TaskError::new("down"),
+            attempts: 1,
+        }
+    }
+
+    fn breaker(clock: &Arc<VirtualClock>) -> Breaker {
+        let config = CircuitBreaker::new(2, Duration::from_secs(5));
+        Breaker::new(config, SharedClock::new(Arc::clone(clock)))
+    }
+
+    #[test]
+    fn opens_after_failures_in_a_row_and_closes_on_a_good_trial() {
+        let clock = Arc::new(VirtualClock::new());
+        let breaker = breaker(&clock);
+        breaker.record(&Pass::Run, &failed());
+        breaker.record(&Pass::Run, &succeeded());
+        breaker.record(&Pass::Run, &failed());
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        breaker.record(&Pass::Run, &failed());
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(matches!(breaker.try_pass(), Pass::Reject));
+
+        clock.advance(Duration::from_secs(5));
+        let trial = breaker.try_pass();
+        assert!(matches!(trial, Pass::Trial));
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        // One trial at a time.
+        assert!(matches!(breaker.try_pass(), Pass::Reject));
+        breaker.record(&trial, &succeeded());
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert!(matches!(breaker.try_pass(), Pass::Run));
+    }
+
+    #[test]
+    fn a_failed_trial_reopens_for_another_cool_down() {
+        let clock = Arc::new(VirtualClock::new());
+        let breaker = breaker(&clock);
+        breaker.record(&Pass::Run, &failed());
+        breaker.record(&Pass::Run, &failed());
+        clock.advance(Duration::from_secs(5));
+        let trial = breaker.try_pass();
+        breaker.record(&trial, &failed());
+        assert_eq!(breaker.state(), CircuitState::Open);
+        clock.advance(Duration::from_secs(4));
+        assert!(matches!(breaker.try_pass(), Pass::Reject));
+        // A task let through before the circuit opened doesn't move it.
+        breaker.record(&Pass::Run, &succeeded());
+        assert_eq!(breaker.state(), CircuitState::Open);
+        clock.advance(Duration::from_secs(1));
+        assert!(matches!(breaker.try_pass(), Pass::Trial));
+    }
+
+    #[test]
+    fn a_cancelled_trial_lets_the_next_task_try() {
+        let clock = Arc::new(VirtualClock::new());
+        let breaker = breaker(&clock);
+        breaker.record(&Pass::Run, &failed());
+        breaker.record(&Pass::Run, &failed());
+        clock.advance(Duration::from_secs(5));
+        let trial = breaker.try_pass();
+        let cancelled = TaskResult::Cancelled {
+            id: 1,
+            task_type: "fetch".to_string(),
+            correlation_id: None,
+        };
+        breaker.record(&trial, &cancelled);
+        assert!(matches!(breaker.try_pass(), Pass::Trial));
+    }
+}
@@ -0,0 +1,549 @@
+use crate::config;
+use crate::console::Verbosity;
+use crate::log::{Level, LogFormat};
+use crate::outcome::FailureLimit;
+use crate::rng::Rng;
+use crate::workload::{Arrivals, Durations};
+use rust_concurrent_processor::{Chaos, PoisonPolicy, RetryPolicy, Scheduler, ThreadPoolBuilder};
+use std::fmt;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const USAGE: &str = "\
+Usage: rust-concurrent-processor [DEMO] [OPTIONS]
+
+Demos (the parts and the project run when none is given):
+  part1, part2a, part2b, part3, part4, project
+  pipeline, bench   Only when named
+
+Options:
+  --config <PATH>         Settings file (default: processor.toml, if present)
+  --workers <N>           Worker threads for demos that use a pool
+  --tasks <N>             How many tasks to generate
+  --tasks-file <PATH>     Run the tasks listed in a JSON file instead
+  --failure-rate <R>      Fraction of tasks that fail, between 0 and 1
+  --fail-rate <TYPE=R>    Fraction of one task type's tasks that fail, e.g. download=0.2,
+                          compute=0.1 (may be given more than once)
+  --seed <N>              Pick failures, work durations and the project's task mix at random,
+                          the same way every time for the same N
+  --chaos <RATE>          Inject panics, failures, queue drops and up to 50ms of latency into
+                          that fraction of pool tasks each (see [chaos] in processor.toml)
+  --arrivals <SPEC>       When the project's generated tasks come in: all (at once), poisson:RATE,
+                          bursty:SIZE/MS or ramp:FROM-TO, rates in tasks a second
+  --durations <SPEC>      How long tasks take: uniform:MIN-MAX, lognormal:MEDIAN,SIGMA or
+                          pareto:MIN,ALPHA in ms (work in the part demos, simulated downloads)
+  --load-test             Submit tasks at a steady --rate for --duration instead of running demos,
+                          then report throughput, queue wait and service time
+  --rate <N>              Tasks a second for --load-test (default: 100)
+  --duration <TIME>       How long --load-test submits for, e.g. 60s, 500ms or 2m (default: 10s)
+  --warm-up <TIME>        Report the first TIME of a --load-test apart, leaving it out of the
+                          steady-state figures
+  --queue-capacity <N>    Bound the pool's queue to N waiting tasks
+  --scheduler <NAME>      shared, work-stealing, channel, fifo, sjf or fair
+  --pin-workers           Keep each pool worker on a CPU core of its own (Linux and Windows)
+  --poison-policy <NAME>  recover (default) or fail-fast when a listener or reducer panics
+  --executor <NAME>       Run the project's tasks on a pool or thread-per-task
+  --timeout-ms <MS>       Default task timeout
+  --chunk-size <N>        Split process payloads bigger than N items across workers
+  --cache                 Download each URL only once per run
+  --cache-dir <PATH>      Keep downloads in PATH too, for later runs (implies --cache)
+  --max-bandwidth <RATE>  Share RATE bytes a second (e.g. 500K or 2M) among all downloads
+  --journal <PATH>        Record the project's tasks and results as they happen
+  --resume                Rerun whatever an interrupted project run left unfinished
+                          (reads --journal, or project.journal)
+  --listen <PORT>         Run the project as a job server, taking JSON tasks over TCP on PORT
+  --output <FORMAT>       text or json (JSON lines per result, then the stats)
+  --results-csv <PATH>    Also write the project's results to PATH, a row each
+  --results-jsonl <PATH>  Also write the project's results to PATH, a JSON object per line
+  -q, --quiet             Print only the summaries, not each result
+  -v, --verbose           Print how each task went under its result
+  --log-level <LEVEL>     error, warn, info or debug: how much the workers log (default info,
+                          warn with --quiet and debug with --verbose)
+  --log-format <FORMAT>   text or json log lines, on stderr
+  --summary-out <PATH>    Write the project's counts, throughput and latency percentiles to
+                          PATH as JSON
+  --baseline <PATH>       Compare the project's throughput and latency with a summary an earlier
+                          run wrote with --summary-out
+  --max-failures <N|P%>   Exit with status 3 if more than N, or P percent, of the project's
+                          tasks fail or time out
+  --tui                   Follow the project on a full-screen dashboard instead of a log
+  --gantt                 Chart which worker ran which task when, after part2b and the project
+  --trace-out <PATH>      Write when and where each project task ran, in Chrome's trace format
+  --metrics-port <PORT>   Serve Prometheus metrics at :PORT/metrics while the project runs
+  --status-port <PORT>    Serve the project's state as JSON at :PORT/status and :PORT/tasks/<id>
+  -h, --help              Print this message
+
+Flags override the settings file. While the project runs in a terminal, type pause, resume,
+stats or stop and Enter to steer it.
+
+Exit status: 0 when the run went through, 1 when it couldn't, 2 for bad options or settings,
+3 when the project had more failures than --max-failures allows.";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Demo {
+    Part1,
+    Part2a,
+    Part2b,
+    Part3,
+    Part4,
+    Project,
+    Pipeline,
+    Bench,
+}
+
+impl Demo {
+    fn from_name(name: &str) -> Option<Demo> {
+        match name {
+            "part1" => Some(Demo::Part1),
+            "part2a" => Some(Demo::Part2a),
+            "part2b" => Some(Demo::Part2b),
+            "part3" => Some(Demo::Part3),
+            "part4" => Some(Demo::Part4),
+            "project" => Some(Demo::Project),
+            "pipeline" => Some(Demo::Pipeline),
+            "bench" => Some(Demo::Bench),
+            _ => None,
+        }
+    }
+
+    // The lab's parts and the project. The rest take a while, so they only
+    // run when asked for
+    fn by_default(self) -> bool {
+        !matches!(self, Demo::Pipeline | Demo::Bench)
+    }
+}
+
+// What runs the project's tasks. Only the pool has the extras: task
+// dependencies, the server poll, the watchdog and the live views
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    #[default]
+    Pool,
+    ThreadPerTask,
+}
+
+impl Backend {
+    pub fn from_name(name: &str) -> Option<Backend> {
+        match name {
+            "pool" => Some(Backend::Pool),
+            "thread-per-task" => Some(Backend::ThreadPerTask),
+            _ => None,
+        }
+    }
+}
+
+// How the project demo reports results and stats
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Output {
+    #[default]
+    Text,
+    Json,
+}
+
+impl Output {
+    pub fn from_name(name: &str) -> Option<Output> {
+        match name {
+            "text" => Some(Output::Text),
+            "json" => Some(Output::Json),
+            _ => None,
+        }
+    }
+}
+
+// How often --chaos or [chaos] injects each fault, from 0 to 1
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChaosRates {
+    pub panics: f64,
+    pub failures: f64,
+    pub drops: f64,
+    pub latency: f64,
+    pub max_latency: Duration,
+}
+
+impl ChaosRates {
+    pub const MAX_LATENCY: Duration = Duration::from_millis(50);
+
+    pub fn all(rate: f64) -> ChaosRates {
+        ChaosRates { panics: rate, failures: rate, drops: rate, latency: rate, max_latency: ChaosRates::MAX_LATENCY }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Args {
+    pub demo: Option<Demo>,
+    pub workers: Option<usize>,
+    pub tasks: Option<u32>,
+    pub tasks_file: Option<PathBuf>,
+    pub failure_rate: Option<f64>,
+    pub fail_rates: Vec<(String, f64)>,
+    pub seed: Option<u64>,
+    pub chaos: Option<ChaosRates>,
+    pub arrivals: Option<Arrivals>,
+    pub durations: Option<Durations>,
+    pub load_test: bool,
+    pub rate: Option<f64>,
+    pub duration: Option<Duration>,
+    pub warm_up: Option<Duration>,
+    pub config: Option<PathBuf>,
+    pub queue_capacity: Option<usize>,
+    pub scheduler: Option<Scheduler>,
+    pub pin_workers: bool,
+    pub poison_policy: Option<PoisonPolicy>,
+    pub executor: Option<Backend>,
+    pub timeout: Option<Duration>,
+    pub chunk_size: Option<usize>,
+    pub cache: bool,
+    pub cache_dir: Option<PathBuf>,
+    pub max_bandwidth: Option<u64>,
+    pub output: Option<Output>,
+    pub results_csv: Option<PathBuf>,
+    pub results_jsonl: Option<PathBuf>,
+    pub summary_out: Option<PathBuf>,
+    pub baseline: Option<PathBuf>,
+    pub max_failures: Option<FailureLimit>,
+    pub verbosity: Option<Verbosity>,
+    pub log_level: Option<Level>,
+    pub log_format: Option<LogFormat>,
+    pub journal: Option<PathBuf>,
+    pub resume: bool,
+    pub listen: Option<u16>,
+    pub metrics_port: Option<u16>,
+    pub status_port: Option<u16>,
+    pub trace_out: Option<PathBuf>,
+    pub gantt: bool,
+    pub tui: bool,
+    // Only settable from the config file
+    pub retry: Option<RetryPolicy>,
+    pub weights: Vec<(String, u32)>,
+    pub max_concurrent: Vec<(String, usize)>,
+    pub help: bool,
+}
+
+#[derive(Debug)]
+pub struct ArgsError(String);
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Args {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Args, ArgsError> {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => parsed.help = true,
+                "--workers" => {
+                    let workers = value(&mut args, &arg)?;
+                    if workers == 0 {
+                        return Err(ArgsError("--workers must be at least 1".to_string()));
+                    }
+                    parsed.workers = Some(workers);
+                }
+                "--tasks" => parsed.tasks = Some(value(&mut args, &arg)?),
+                "--tasks-file" => parsed.tasks_file = Some(value::<String>(&mut args, &arg)?.into()),
+                "--failure-rate" => {
+                    let rate: f64 = value(&mut args, &arg)?;
+                    if !(0.0..=1.0).contains(&rate) {
+                        return Err(ArgsError("--failure-rate must be between 0 and 1".to_string()));
+                    }
+                    parsed.failure_rate = Some(rate);
+                }
+                "--fail-rate" => {
+                    let setting: String = value(&mut args, &arg)?;
+                    let rate = setting
+                        .split_once('=')
+                        .and_then(|(task_type, rate)| Some((task_type.trim().to_string(), rate.trim().parse::<f64>().ok()?)))
+                        .filter(|(task_type, rate)| !task_type.is_empty() && (0.0..=1.0).contains(rate))
+                        .ok_or_else(|| ArgsError(format!("--fail-rate takes TYPE=RATE with a rate between 0 and 1, not '{}'", setting)))?;
+                    parsed.fail_rates.push(rate);
+                }
+                "--seed" => parsed.seed = Some(value(&mut args, &arg)?),
+                "--chaos" => {
+                    let rate: f64 = value(&mut args, &arg)?;
+                    if !(0.0..=1.0).contains(&rate) {
+                        return Err(ArgsError("--chaos must be between 0 and 1".to_string()));
+                    }
+                    parsed.chaos = Some(ChaosRates::all(rate));
+                }
+                "--arrivals" => {
+                    let spec: String = value(&mut args, &arg)?;
+                    let arrivals = Arrivals::parse(&spec)
+                        .ok_or_else(|| ArgsError(format!("--arrivals must be all, poisson:RATE, bursty:SIZE/MS or ramp:FROM-TO, not '{}'", spec)))?;
+                    parsed.arrivals = Some(arrivals);
+                }
+                "--durations" => {
+                    let spec: String = value(&mut args, &arg)?;
+                    let durations = Durations::parse(&spec)
+                        .ok_or_else(|| ArgsError(format!("--durations must be uniform:MIN-MAX, lognormal:MEDIAN,SIGMA or pareto:MIN,ALPHA, not '{}'", spec)))?;
+                    parsed.durations = Some(durations);
+                }
+                "--load-test" => parsed.load_test = true,
+                "--rate" => {
+                    let rate: f64 = value(&mut args, &arg)?;
+                    if !rate.is_finite() || rate <= 0.0 {
+                        return Err(ArgsError("--rate must be more than 0".to_string()));
+                    }
+                    parsed.rate = Some(rate);
+                }
+                "--duration" => {
+                    let text: String = value(&mut args, &arg)?;
+                    let duration = config::parse_duration(&text)
+                        .ok_or_else(|| ArgsError(format!("--duration must be a time like 60s, 500ms or 2m, not '{}'", text)))?;
+                    parsed.duration = Some(duration);
+                }
+                "--warm-up" => {
+                    let text: String = value(&mut args, &arg)?;
+                    let warm_up = config::parse_duration(&text)
+                        .ok_or_else(|| ArgsError(format!("--warm-up must be a time like 5s or 500ms, not '{}'", text)))?;
+                    parsed.warm_up = Some(warm_up);
+                }
+                "--config" => parsed.config = Some(value::<String>(&mut args, &arg)?.into()),
+                "--queue-capacity" => {
+                    let capacity = value(&mut args, &arg)?;
+                    if capacity == 0 {
+                        return Err(ArgsError("--queue-capacity must be at least 1".to_string()));
+                    }
+                    parsed.queue_capacity = Some(capacity);
+                }
+                "--pin-workers" => parsed.pin_workers = true,
+                "--poison-policy" => {
+                    let name: String = value(&mut args, &arg)?;
+                    let policy = config::parse_poison_policy(&name)
+                        .ok_or_else(|| ArgsError(format!("unknown poison policy '{}'", name)))?;
+                    parsed.poison_policy = Some(policy);
+                }
+                "--scheduler" => {
+                    let name: String = value(&mut args, &arg)?;
+                    let scheduler = config::parse_scheduler(&name)
+                        .ok_or_else(|| ArgsError(format!("unknown scheduler '{}'", name)))?;
+                    parsed.scheduler = Some(scheduler);
+                }
+                "--executor" => {
+                    let name: String = value(&mut args, &arg)?;
+                    let executor = Backend::from_name(&name)
+                        .ok_or_else(|| ArgsError(format!("unknown executor '{}'", name)))?;
+                    parsed.executor = Some(executor);
+                }
+                "--chunk-size" => {
+                    let size = value(&mut args, &arg)?;
+                    if size == 0 {
+                        return Err(ArgsError("--chunk-size must be at least 1".to_string()));
+                    }
+                    parsed.chunk_size = Some(size);
+                }
+                "--output" => {
+                    let name: String = value(&mut args, &arg)?;
+                    let output = Output::from_name(&name)
+                        .ok_or_else(|| ArgsError(format!("unknown output format '{}'", name)))?;
+                    parsed.output = Some(output);
+                }
+                "--results-csv" => parsed.results_csv = Some(value::<String>(&mut args, &arg)?.into()),
+                "--results-jsonl" => parsed.results_jsonl = Some(value::<String>(&mut args, &arg)?.into()),
+                "--summary-out" => parsed.summary_out = Some(value::<String>(&mut args, &arg)?.into()),
+                "--baseline" => parsed.baseline = Some(value::<String>(&mut args, &arg)?.into()),
+                "--max-failures" => {
+                    let spec: String = value(&mut args, &arg)?;
+                    let limit = FailureLimit::parse(&spec)
+                        .ok_or_else(|| ArgsError(format!("--max-failures takes a count or a percentage like 5%, not '{}'", spec)))?;
+                    parsed.max_failures = Some(limit);
+                }
+                "-q" | "--quiet" => parsed.verbosity = Some(Verbosity::Quiet),
+                "-v" | "--verbose" => parsed.verbosity = Some(Verbosity::Verbose),
+                "--log-level" => {
+                    let name: String = value(&mut args, &arg)?;
+                    let level = Level::from_name(&name)
+                        .ok_or_else(|| ArgsError(format!("unknown log level '{}'", name)))?;
+                    parsed.log_level = Some(level);
+                }
+                "--log-format" => {
+                    let name: String = value(&mut args, &arg)?;
+                    let format = LogFormat::from_name(&name)
+                        .ok_or_else(|| ArgsError(format!("unknown log format '{}'", name)))?;
+                    parsed.log_format = Some(format);
+                }
+                "--cache" => parsed.cache = true,
+                "--cache-dir" => parsed.cache_dir = Some(value::<String>(&mut args, &arg)?.into()),
+                "--max-bandwidth" => {
+                    let rate: String = value(&mut args, &arg)?;
+                    let rate = config::parse_bandwidth(&rate)
+                        .ok_or_else(|| ArgsError(format!("--max-bandwidth must be a positive number of bytes, e.g. 500K, not '{}'", rate)))?;
+                    parsed.max_bandwidth = Some(rate);
+                }
+                "--journal" => parsed.journal = Some(value::<String>(&mut args, &arg)?.into()),
+                "--resume" => parsed.resume = true,
+                "--listen" => parsed.listen = Some(value(&mut args, &arg)?),
+                "--gantt" => parsed.gantt = true,
+                "--tui" => parsed.tui = true,
+                "--trace-out" => parsed.trace_out = Some(value::<String>(&mut args, &arg)?.into()),
+                "--metrics-port" => parsed.metrics_port = Some(value(&mut args, &arg)?),
+                "--status-port" => parsed.status_port = Some(value(&mut args, &arg)?),
+                "--timeout-ms" => parsed.timeout = Some(Duration::from_millis(value(&mut args, &arg)?)),
+                name if !name.starts_with('-') && parsed.demo.is_none() => {
+                    let demo = Demo::from_name(name)
+                        .ok_or_else(|| ArgsError(format!("unknown demo '{}'", name)))?;
+                    parsed.demo = Some(demo);
+                }
+                other => return Err(ArgsError(format!("unexpected argument '{}'", other))),
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    // Whether the given demo was picked, or runs by default and no demo
+    // was named at all
+    pub fn runs(&self, demo: Demo) -> bool {
+        match self.demo {
+            Some(picked) => picked == demo,
+            None => demo.by_default(),
+        }
+    }
+
+    pub fn workers_or(&self, default: usize) -> usize {
+        self.workers.unwrap_or(default)
+    }
+
+    pub fn tasks_or(&self, default: u32) -> u32 {
+        self.tasks.unwrap_or(default)
+    }
+
+    pub fn json(&self) -> bool {
+        self.output == Some(Output::Json)
+    }
+
+    pub fn cache(&self) -> bool {
+        self.cache || self.cache_dir.is_some()
+    }
+
+    pub fn verbosity(&self) -> Verbosity {
+        self.verbosity.unwrap_or_default()
+    }
+
+    // What the workers log unless --log-level says otherwise: less when
+    // only the summaries are wanted, more alongside the per-task detail
+    pub fn log_level(&self) -> Level {
+        self.log_level.unwrap_or(match self.verbosity() {
+            Verbosity::Quiet => Level::Warn,
+            Verbosity::Normal => Level::Info,
+            Verbosity::Verbose => Level::Debug,
+        })
+    }
+
+    // The dashboard only makes sense when people are reading the output
+    pub fn tui(&self) -> bool {
+        self.tui && !self.json()
+    }
+
+    // Applies the pool settings that were given, keeping the demo's own
+    // choices for the rest
+    pub fn configure(&self, mut builder: ThreadPoolBuilder) -> ThreadPoolBuilder {
+        if let Some(capacity) = self.queue_capacity {
+            builder = builder.queue_capacity(capacity);
+        }
+        if let Some(scheduler) = self.scheduler {
+            builder = builder.scheduler(scheduler);
+        }
+        if self.pin_workers {
+            builder = builder.pin_workers(true);
+        }
+        if let Some(policy) = self.poison_policy {
+            builder = builder.poison_policy(policy);
+        }
+        if let Some(timeout) = self.timeout {
+            builder = builder.default_timeout(timeout);
+        }
+        if let Some(retry) = &self.retry {
+            builder = builder.retry_policy(retry.clone());
+        }
+        for (task_type, weight) in &self.weights {
+            builder = builder.weight(task_type.as_str(), *weight);
+        }
+        for (task_type, max) in &self.max_concurrent {
+            builder = builder.max_concurrent(task_type.as_str(), *max);
+        }
+        if let Some(rates) = self.chaos {
+            // The same faults every run with --seed, different ones without
+            let seed = self.seed.unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |t| t.as_nanos() as u64));
+            let chaos = Chaos::new(seed)
+                .panics(rates.panics)
+                .failures(rates.failures)
+                .drops(rates.drops)
+                .latency(rates.latency, rates.max_latency);
+            builder = builder.chaos(chaos);
+        }
+        builder
+    }
+
+    pub fn failures(&self) -> Failures {
+        Failures { rates: self.fail_rates.clone(), rate: self.failure_rate, seed: self.seed }
+    }
+
+    // Whether the task of `task_type` with this id should fail, or every
+    // `default_every`-th one without a rate
+    pub fn should_fail(&self, task_type: &str, id: u32, default_every: u32) -> bool {
+        self.failures().should_fail(task_type, id, Some(default_every))
+    }
+
+    // Between 50 and 250ms, like work_duration(), but at random with --seed
+    // and drawn from --durations if given
+    pub fn work_duration(&self, id: u32) -> u64 {
+        let mut rng = Rng::for_task(self.seed.unwrap_or_default(), id, "duration");
+        match (self.durations, self.seed) {
+            (Some(durations), _) => durations.sample(&mut rng).as_millis() as u64,
+            (None, Some(_)) => 50 + rng.below(200),
+            (None, None) => work_duration(id),
+        }
+    }
+}
+
+// Which tasks fail on purpose. A type given a rate with --fail-rate fails
+// at that rate, the rest at --failure-rate
+#[derive(Clone, Debug, Default)]
+pub struct Failures {
+    rates: Vec<(String, f64)>,
+    rate: Option<f64>,
+    seed: Option<u64>,
+}
+
+impl Failures {
+    // Spreads failures evenly: with a rate of 0.2 every 5th task fails,
+    // and with 0.7 seven in every ten do. Without a rate every `default_every`-th task does, and with no
+    // default either none do. With --seed each task fails with that
+    // chance instead
+    pub fn should_fail(&self, task_type: &str, id: u32, default_every: Option<u32>) -> bool {
+        let rate = match self.rates.iter().find(|(name, _)| name == task_type) {
+            Some(&(_, rate)) => rate,
+            None => match default_every {
+                Some(every) => self.rate.unwrap_or(1.0 / every as f64),
+                None => return false,
+            },
+        };
+        if let Some(seed) = self.seed {
+            return Rng::for_task(seed, id, "fail").chance(rate);
+        }
+        // A task fails when it takes the count of failures so far up by
+        // one. The nudge keeps rates like 1/7 from landing just short
+        let failures = |n: f64| (n * rate + 1e-9).floor();
+        rate > 0.0 && failures(id as f64) > failures(id as f64 - 1.0)
+    }
+}
+
+// Work durations for generated tasks, spread between 50 and 250ms
+pub fn work_duration(id: u32) -> u64 {
+    50 + (id as u64 * 37) % 200
+}
+
+fn value<T: std::str::FromStr>(
+    args: &mut impl Iterator<Item = String>,
+    flag: &str,
+) -> Result<T, ArgsError> {
+    let raw = args
+        .next()
+        .ok_or_else(|| ArgsError(format!("{} needs a value", flag)))?;
+    raw.parse()
+        .map_err(|_| ArgsError(format!("invalid value '{}' for {}", raw, flag)))
+}
@@ -0,0 +1,155 @@
+use std::fmt;
+use std::ops::Deref;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::sync::Recover;
+
+/// Where the pool gets the time and how it waits: for timestamps, task
+/// deadlines and timeouts, retry backoff, delayed and recurring tasks and
+/// circuit breaker cool-downs. Set with
+/// [`ThreadPoolBuilder::clock`](crate::ThreadPoolBuilder::clock);
+/// [`SystemClock`] by default.
+pub trait Clock: Send + Sync + fmt::Debug {
+    fn now(&self) -> Instant;
+
+    fn sleep(&self, duration: Duration);
+
+    /// Whether time only moves when something sleeps.
+    fn is_virtual(&self) -> bool {
+        false
+    }
+}
+
+/// The real time: [`Instant::now`] and [`thread::sleep`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// Time that stands still until something sleeps, which moves it forward
+/// by that much and returns straight away. Tasks that wait through
+/// [`TaskContext::sleep`](crate::TaskContext::sleep) then take no real
+/// time at all, and the same run always sees the same times.
+#[derive(Debug)]
+pub struct VirtualClock {
+    start: Instant,
+    elapsed: Mutex<Duration>,
+}
+
+impl VirtualClock {
+    pub fn new() -> Self {
+        VirtualClock {
+            start: Instant::now(),
+            elapsed: Mutex::new(Duration::ZERO),
+        }
+    }
+
+    /// How far the clock has moved since it was made.
+    pub fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().recover()
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().recover() += duration;
+    }
+
+    /// Moves the clock to `elapsed` since it was made, backwards if need be.
+    pub(crate) fn set(&self, elapsed: Duration) {
+        *self.elapsed.lock().recover() = elapsed;
+    }
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        VirtualClock::new()
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration);
+    }
+
+    fn is_virtual(&self) -> bool {
+        true
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+    fn now(&self) -> Instant {
+        (**self).now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        (**self).sleep(duration);
+    }
+
+    fn is_virtual(&self) -> bool {
+        (**self).is_virtual()
+    }
+}
+
+/// The clock a pool and its tasks share.
+#[derive(Clone, Debug)]
+pub(crate) struct SharedClock(Arc<dyn Clock>);
+
+impl SharedClock {
+    pub(crate) fn new(clock: impl Clock + 'static) -> Self {
+        SharedClock(Arc::new(clock))
+    }
+
+    /// The wall-clock time as this clock has it: the system's, moved by
+    /// however far the clock is ahead of or behind the real time.
+    pub(crate) fn wall(&self) -> SystemTime {
+        let (now, real) = (self.now(), Instant::now());
+        match now.checked_duration_since(real) {
+            Some(ahead) => SystemTime::now() + ahead,
+            None => SystemTime::now() - (real - now),
+        }
+    }
+
+    /// Waits on `condvar` for a change or until `duration` has passed on
+    /// this clock. A virtual clock is moved forward instead, as for any
+    /// other sleep, so the wait takes no real time.
+    pub(crate) fn wait_timeout<'a, T>(
+        &self,
+        condvar: &Condvar,
+        guard: MutexGuard<'a, T>,
+        duration: Duration,
+    ) -> MutexGuard<'a, T> {
+        if self.is_virtual() {
+            self.sleep(duration);
+            guard
+        } else {
+            condvar.wait_timeout(guard, duration).recover().0
+        }
+    }
+}
+
+impl Default for SharedClock {
+    fn default() -> Self {
+        SharedClock::new(SystemClock)
+    }
+}
+
+impl Deref for SharedClock {
+    type Target = dyn Clock;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.0
+    }
+}
@@ -0,0 +1,518 @@
+use crate::cli::{Args, Backend, ChaosRates, Output};
+use crate::console::Verbosity;
+use crate::outcome::FailureLimit;
+use crate::log::{Level, LogFormat};
+use crate::workload::{Arrivals, Durations};
+use rust_concurrent_processor::{PoisonPolicy, RetryPolicy, Scheduler};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub const DEFAULT_PATH: &str = "processor.toml";
+
+#[derive(Debug)]
+pub struct ConfigError(String);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+}
+
+// Reads `path` and fills in every setting the command line left unset
+pub fn apply(args: &mut Args, path: &Path) -> Result<(), ConfigError> {
+    let text = fs::read_to_string(path)
+        .map_err(|err| ConfigError(format!("can't read {}: {}", path.display(), err)))?;
+    apply_text(args, &text)
+}
+
+fn apply_text(args: &mut Args, text: &str) -> Result<(), ConfigError> {
+    let mut values = parse(text)?;
+    let weight_keys: Vec<String> = values.keys().filter(|key| key.starts_with("weights.")).cloned().collect();
+    let limit_keys: Vec<String> = values.keys().filter(|key| key.starts_with("max_concurrent.")).cloned().collect();
+    let fail_keys: Vec<String> = values.keys().filter(|key| key.starts_with("fail_rates.")).cloned().collect();
+    let mut take = |key: &str| values.remove(key);
+
+    if args.workers.is_none() {
+        args.workers = take("workers").map(|v| positive(&v, "workers")).transpose()?;
+    }
+    if args.tasks.is_none() {
+        args.tasks = take("tasks").map(|v| int(&v, "tasks")).transpose()?;
+    }
+    if args.tasks_file.is_none() {
+        args.tasks_file = take("tasks_file").map(|v| file_path(&v, "tasks_file")).transpose()?;
+    }
+    if args.failure_rate.is_none() {
+        args.failure_rate = take("failure_rate").map(|v| rate(&v, "failure_rate")).transpose()?;
+    }
+    if args.seed.is_none() {
+        args.seed = take("seed").map(|v| int(&v, "seed")).transpose()?;
+    }
+    if args.arrivals.is_none() {
+        args.arrivals = take("arrivals").map(|v| spec(&v, "arrivals", Arrivals::parse)).transpose()?;
+    }
+    if args.durations.is_none() {
+        args.durations = take("durations").map(|v| spec(&v, "durations", Durations::parse)).transpose()?;
+    }
+    if args.queue_capacity.is_none() {
+        args.queue_capacity = take("queue_capacity")
+            .map(|v| positive(&v, "queue_capacity"))
+            .transpose()?;
+    }
+    if !args.pin_workers {
+        args.pin_workers = take("pin_workers").map(|v| flag(&v, "pin_workers")).transpose()?.unwrap_or_default();
+    }
+    if args.poison_policy.is_none() {
+        args.poison_policy = take("poison_policy").map(|v| poison_policy(&v)).transpose()?;
+    }
+    if args.scheduler.is_none() {
+        args.scheduler = take("scheduler").map(|v| scheduler(&v)).transpose()?;
+    }
+    if args.executor.is_none() {
+        args.executor = take("executor").map(|v| executor(&v)).transpose()?;
+    }
+    if args.timeout.is_none() {
+        args.timeout = take("timeout_ms").map(|v| millis(&v, "timeout_ms")).transpose()?;
+    }
+    if args.chunk_size.is_none() {
+        args.chunk_size = take("chunk_size").map(|v| positive(&v, "chunk_size")).transpose()?;
+    }
+    if !args.cache {
+        args.cache = take("cache").map(|v| flag(&v, "cache")).transpose()?.unwrap_or_default();
+    }
+    if args.cache_dir.is_none() {
+        args.cache_dir = take("cache_dir").map(|v| file_path(&v, "cache_dir")).transpose()?;
+    }
+    if args.max_bandwidth.is_none() {
+        args.max_bandwidth = take("max_bandwidth").map(|v| bandwidth(&v)).transpose()?;
+    }
+    if args.output.is_none() {
+        args.output = take("output").map(|v| output(&v)).transpose()?;
+    }
+    if args.summary_out.is_none() {
+        args.summary_out = take("summary_out").map(|v| file_path(&v, "summary_out")).transpose()?;
+    }
+    if args.baseline.is_none() {
+        args.baseline = take("baseline").map(|v| file_path(&v, "baseline")).transpose()?;
+    }
+    if args.max_failures.is_none() {
+        args.max_failures = take("max_failures").map(|v| max_failures(&v)).transpose()?;
+    }
+    if args.verbosity.is_none() {
+        let quiet = take("quiet").map(|v| flag(&v, "quiet")).transpose()?.unwrap_or_default();
+        let verbose = take("verbose").map(|v| flag(&v, "verbose")).transpose()?.unwrap_or_default();
+        args.verbosity = match (quiet, verbose) {
+            (true, true) => return Err(ConfigError("quiet and verbose can't both be set".to_string())),
+            (true, false) => Some(Verbosity::Quiet),
+            (false, true) => Some(Verbosity::Verbose),
+            (false, false) => None,
+        };
+    }
+    if args.log_level.is_none() {
+        args.log_level = take("log_level").map(|v| log_level(&v)).transpose()?;
+    }
+    if args.log_format.is_none() {
+        args.log_format = take("log_format").map(|v| log_format(&v)).transpose()?;
+    }
+    if args.results_csv.is_none() {
+        args.results_csv = take("results_csv").map(|v| file_path(&v, "results_csv")).transpose()?;
+    }
+    if args.results_jsonl.is_none() {
+        args.results_jsonl = take("results_jsonl").map(|v| file_path(&v, "results_jsonl")).transpose()?;
+    }
+    if args.trace_out.is_none() {
+        args.trace_out = take("trace_out").map(|v| file_path(&v, "trace_out")).transpose()?;
+    }
+    if !args.gantt {
+        args.gantt = take("gantt").map(|v| flag(&v, "gantt")).transpose()?.unwrap_or_default();
+    }
+    if args.metrics_port.is_none() {
+        args.metrics_port = take("metrics_port").map(|v| int(&v, "metrics_port")).transpose()?;
+    }
+    if args.status_port.is_none() {
+        args.status_port = take("status_port").map(|v| int(&v, "status_port")).transpose()?;
+    }
+
+    let max_attempts = take("retry.max_attempts").map(|v| int(&v, "retry.max_attempts")).transpose()?;
+    let backoff = take("retry.backoff_ms").map(|v| millis(&v, "retry.backoff_ms")).transpose()?;
+    let jitter = take("retry.jitter_ms").map(|v| millis(&v, "retry.jitter_ms")).transpose()?;
+    if max_attempts.is_some() || backoff.is_some() || jitter.is_some() {
+        let policy = RetryPolicy::exponential(max_attempts.unwrap_or(1), backoff.unwrap_or_default())
+            .with_jitter(jitter.unwrap_or_default());
+        args.retry.get_or_insert(policy);
+    }
+
+    // [load_test] only has the rate, duration and warm-up; --load-test
+    // still has to be given to run one
+    if args.rate.is_none() {
+        args.rate = take("load_test.rate").map(|v| load_rate(&v)).transpose()?;
+    }
+    if args.duration.is_none() {
+        args.duration = take("load_test.duration").map(|v| duration(&v, "load_test.duration")).transpose()?;
+    }
+    if args.warm_up.is_none() {
+        args.warm_up = take("load_test.warm_up").map(|v| duration(&v, "load_test.warm_up")).transpose()?;
+    }
+
+    // Each fault under [chaos] is off unless given a rate
+    let panics = take("chaos.panics").map(|v| rate(&v, "chaos.panics")).transpose()?;
+    let failures = take("chaos.failures").map(|v| rate(&v, "chaos.failures")).transpose()?;
+    let drops = take("chaos.drops").map(|v| rate(&v, "chaos.drops")).transpose()?;
+    let latency = take("chaos.latency").map(|v| rate(&v, "chaos.latency")).transpose()?;
+    let max_latency = take("chaos.latency_ms").map(|v| millis(&v, "chaos.latency_ms")).transpose()?;
+    if panics.is_some() || failures.is_some() || drops.is_some() || latency.is_some() {
+        args.chaos.get_or_insert(ChaosRates {
+            panics: panics.unwrap_or_default(),
+            failures: failures.unwrap_or_default(),
+            drops: drops.unwrap_or_default(),
+            latency: latency.unwrap_or_default(),
+            max_latency: max_latency.unwrap_or(ChaosRates::MAX_LATENCY),
+        });
+    }
+
+    // One `task_type = weight` line per task type under [weights]
+    for key in weight_keys {
+        let weight = take(&key).map(|v| positive(&v, &key)).transpose()?.unwrap_or(1);
+        let task_type = key.trim_start_matches("weights.").to_string();
+        args.weights.push((task_type, u32::try_from(weight).unwrap_or(u32::MAX)));
+    }
+
+    // And `task_type = max` under [max_concurrent]
+    for key in limit_keys {
+        let max = take(&key).map(|v| positive(&v, &key)).transpose()?.unwrap_or(1);
+        args.max_concurrent.push((key.trim_start_matches("max_concurrent.").to_string(), max));
+    }
+
+    // And `task_type = rate` under [fail_rates], unless --fail-rate gave any
+    let mut fail_rates = vec![];
+    for key in fail_keys {
+        let rate = take(&key).map(|v| rate(&v, &key)).transpose()?.unwrap_or_default();
+        fail_rates.push((key.trim_start_matches("fail_rates.").to_string(), rate));
+    }
+    if args.fail_rates.is_empty() {
+        args.fail_rates = fail_rates;
+    }
+
+    // Anything left over was set on the command line or isn't a setting at all
+    let known = ["workers", "tasks", "tasks_file", "failure_rate", "seed", "arrivals", "durations", "queue_capacity", "scheduler", "pin_workers", "poison_policy", "executor", "timeout_ms", "chunk_size", "cache", "cache_dir", "max_bandwidth", "output", "results_csv", "results_jsonl", "summary_out", "baseline", "max_failures", "quiet", "verbose", "log_level", "log_format", "gantt", "trace_out", "metrics_port", "status_port"];
+    if let Some(key) = values.keys().find(|key| !known.contains(&key.as_str())) {
+        return Err(ConfigError(format!("unknown setting '{}'", key)));
+    }
+    Ok(())
+}
+
+// Just enough TOML for flat settings: `key = value` lines, `[section]`
+// headers, and string, integer, float and boolean values
+fn parse(text: &str) -> Result<HashMap<String, Value>, ConfigError> {
+    let mut values = HashMap::new();
+    let mut section = String::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let error = |message: &str| ConfigError(format!("line {}: {}", number + 1, message));
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[') {
+            let name = name.strip_suffix(']').ok_or_else(|| error("unclosed section header"))?;
+            section = format!("{}.", name.trim());
+            continue;
+        }
+
+        let (key, raw) = line.split_once('=').ok_or_else(|| error("expected `key = value`"))?;
+        let key = format!("{}{}", section, key.trim());
+        let value = parse_value(raw.trim()).ok_or_else(|| error("invalid value"))?;
+        if values.insert(key, value).is_some() {
+            return Err(error("setting given twice"));
+        }
+    }
+
+    Ok(values)
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_value(raw: &str) -> Option<Value> {
+    if let Some(inner) = raw.strip_prefix('"') {
+        return inner.strip_suffix('"').map(|s| Value::Str(s.to_string()));
+    }
+    match raw {
+        "true" => return Some(Value::Bool(true)),
+        "false" => return Some(Value::Bool(false)),
+        _ => {}
+    }
+    let digits = raw.replace('_', "");
+    if let Ok(n) = digits.parse() {
+        return Some(Value::Int(n));
+    }
+    digits.parse().ok().map(Value::Float)
+}
+
+fn int<T: TryFrom<i64>>(value: &Value, key: &str) -> Result<T, ConfigError> {
+    match value {
+        Value::Int(n) => T::try_from(*n).map_err(|_| ConfigError(format!("{} is out of range", key))),
+        _ => Err(ConfigError(format!("{} must be a whole number", key))),
+    }
+}
+
+fn positive(value: &Value, key: &str) -> Result<usize, ConfigError> {
+    match int(value, key)? {
+        0 => Err(ConfigError(format!("{} must be at least 1", key))),
+        n => Ok(n),
+    }
+}
+
+fn millis(value: &Value, key: &str) -> Result<Duration, ConfigError> {
+    int(value, key).map(Duration::from_millis)
+}
+
+fn flag(value: &Value, key: &str) -> Result<bool, ConfigError> {
+    match value {
+        Value::Bool(b) => Ok(*b),
+        _ => Err(ConfigError(format!("{} must be true or false", key))),
+    }
+}
+
+fn file_path(value: &Value, key: &str) -> Result<PathBuf, ConfigError> {
+    match value {
+        Value::Str(path) => Ok(PathBuf::from(path)),
+        _ => Err(ConfigError(format!("{} must be a string", key))),
+    }
+}
+
+fn rate(value: &Value, key: &str) -> Result<f64, ConfigError> {
+    let rate = match value {
+        Value::Float(rate) => *rate,
+        Value::Int(rate) => *rate as f64,
+        _ => return Err(ConfigError(format!("{} must be a number", key))),
+    };
+    if (0.0..=1.0).contains(&rate) {
+        Ok(rate)
+    } else {
+        Err(ConfigError(format!("{} must be between 0 and 1", key)))
+    }
+}
+
+fn load_rate(value: &Value) -> Result<f64, ConfigError> {
+    let rate = match value {
+        Value::Float(rate) => *rate,
+        Value::Int(rate) => *rate as f64,
+        _ => return Err(ConfigError("load_test.rate must be a number".to_string())),
+    };
+    if rate.is_finite() && rate > 0.0 {
+        Ok(rate)
+    } else {
+        Err(ConfigError("load_test.rate must be more than 0".to_string()))
+    }
+}
+
+// A time like "60s", or a whole number of seconds
+fn duration(value: &Value, key: &str) -> Result<Duration, ConfigError> {
+    let duration = match value {
+        Value::Int(secs) => u64::try_from(*secs).ok().map(Duration::from_secs),
+        Value::Str(text) => parse_duration(text),
+        _ => None,
+    };
+    duration.ok_or_else(|| ConfigError(format!("{} must be a time like \"60s\" or \"500ms\"", key)))
+}
+
+fn scheduler(value: &Value) -> Result<Scheduler, ConfigError> {
+    match value {
+        Value::Str(name) => parse_scheduler(name)
+            .ok_or_else(|| ConfigError(format!("unknown scheduler '{}'", name))),
+        _ => Err(ConfigError("scheduler must be a string".to_string())),
+    }
+}
+
+fn poison_policy(value: &Value) -> Result<PoisonPolicy, ConfigError> {
+    match value {
+        Value::Str(name) => parse_poison_policy(name)
+            .ok_or_else(|| ConfigError(format!("unknown poison policy '{}'", name))),
+        _ => Err(ConfigError("poison_policy must be a string".to_string())),
+    }
+}
+
+fn spec<T>(value: &Value, key: &str, parse: impl Fn(&str) -> Option<T>) -> Result<T, ConfigError> {
+    match value {
+        Value::Str(text) => parse(text).ok_or_else(|| ConfigError(format!("invalid {} '{}'", key, text))),
+        _ => Err(ConfigError(format!("{} must be a string", key))),
+    }
+}
+
+fn executor(value: &Value) -> Result<Backend, ConfigError> {
+    match value {
+        Value::Str(name) => Backend::from_name(name)
+            .ok_or_else(|| ConfigError(format!("unknown executor '{}'", name))),
+        _ => Err(ConfigError("executor must be a string".to_string())),
+    }
+}
+
+fn bandwidth(value: &Value) -> Result<u64, ConfigError> {
+    let rate = match value {
+        Value::Int(bytes) => u64::try_from(*bytes).ok().filter(|&bytes| bytes > 0),
+        Value::Str(text) => parse_bandwidth(text),
+        _ => None,
+    };
+    rate.ok_or_else(|| ConfigError("max_bandwidth must be a positive number of bytes, e.g. 512000 or \"500K\"".to_string()))
+}
+
+fn max_failures(value: &Value) -> Result<FailureLimit, ConfigError> {
+    let limit = match value {
+        Value::Int(count) => u32::try_from(*count).ok().map(FailureLimit::Count),
+        Value::Str(spec) => FailureLimit::parse(spec),
+        _ => None,
+    };
+    limit.ok_or_else(|| ConfigError("max_failures must be a count or a percentage, e.g. 5 or \"2.5%\"".to_string()))
+}
+
+fn output(value: &Value) -> Result<Output, ConfigError> {
+    match value {
+        Value::Str(name) => Output::from_name(name)
+            .ok_or_else(|| ConfigError(format!("unknown output format '{}'", name))),
+        _ => Err(ConfigError("output must be a string".to_string())),
+    }
+}
+
+fn log_level(value: &Value) -> Result<Level, ConfigError> {
+    match value {
+        Value::Str(name) => Level::from_name(name)
+            .ok_or_else(|| ConfigError(format!("unknown log level '{}'", name))),
+        _ => Err(ConfigError("log_level must be a string".to_string())),
+    }
+}
+
+fn log_format(value: &Value) -> Result<LogFormat, ConfigError> {
+    match value {
+        Value::Str(name) => LogFormat::from_name(name)
+            .ok_or_else(|| ConfigError(format!("unknown log format '{}'", name))),
+        _ => Err(ConfigError("log_format must be a string".to_string())),
+    }
+}
+
+// A byte count, optionally in K, M or G (1024, 1024² or 1024³ bytes)
+pub fn parse_bandwidth(text: &str) -> Option<u64> {
+    let text = text.trim();
+    let (digits, scale) = match text.char_indices().last()? {
+        (i, 'K' | 'k') => (&text[..i], 1 << 10),
+        (i, 'M' | 'm') => (&text[..i], 1 << 20),
+        (i, 'G' | 'g') => (&text[..i], 1 << 30),
+        _ => (text, 1),
+    };
+    let bytes: u64 = digits.parse().ok()?;
+    bytes.checked_mul(scale).filter(|&bytes| bytes > 0)
+}
+
+// A number of ms, s or m, e.g. 500ms, 60s or 2m
+pub fn parse_duration(text: &str) -> Option<Duration> {
+    let text = text.trim();
+    let (number, unit) = text.split_at(text.find(|c: char| c.is_ascii_alphabetic())?);
+    let number: f64 = number.parse().ok().filter(|&n: &f64| n >= 0.0 && n.is_finite())?;
+    let secs = match unit {
+        "ms" => number / 1000.0,
+        "s" => number,
+        "m" => number * 60.0,
+        _ => return None,
+    };
+    Some(Duration::from_secs_f64(secs))
+}
+
+pub fn parse_scheduler(name: &str) -> Option<Scheduler> {
+    match name {
+        "shared" | "shared-queue" => Some(Scheduler::SharedQueue),
+        "work-stealing" => Some(Scheduler::WorkStealing),
+        "channel" => Some(Scheduler::Channel),
+        "fifo" => Some(Scheduler::Fifo),
+        "sjf" | "shortest-job-first" => Some(Scheduler::ShortestJobFirst),
+        "fair" | "weighted-fair" => Some(Scheduler::WeightedFair),
+        _ => None,
+    }
+}
+
+pub fn parse_poison_policy(name: &str) -> Option<PoisonPolicy> {
+    match name {
+        "recover" => Some(PoisonPolicy::Recover),
+        "fail-fast" => Some(PoisonPolicy::FailFast),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(text: &str) -> String {
+        apply_text(&mut Args::default(), text).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn settings_fill_in_what_the_command_line_left_unset() {
+        let mut args = Args { workers: Some(2), ..Args::default() };
+        let text = "workers = 8 # ignored, given as a flag\ntasks = 1_000\nscheduler = \"fair\"\ntimeout_ms = 250\n\n[retry]\nmax_attempts = 3\nbackoff_ms = 10\n\n[weights]\ndownload = 2\n";
+        apply_text(&mut args, text).unwrap();
+        assert_eq!(args.workers, Some(2));
+        assert_eq!(args.tasks, Some(1000));
+        assert_eq!(args.scheduler, Some(Scheduler::WeightedFair));
+        assert_eq!(args.timeout, Some(Duration::from_millis(250)));
+        assert_eq!(args.retry, Some(RetryPolicy::exponential(3, Duration::from_millis(10))));
+        assert_eq!(args.weights, [("download".to_string(), 2)]);
+    }
+
+    #[test]
+    fn bad_settings_say_which_and_why() {
+        assert_eq!(error("workers = 0"), "workers must be at least 1");
+        assert_eq!(error("workers = \"four\""), "workers must be a whole number");
+        assert_eq!(error("tasks = -1"), "tasks is out of range");
+        assert_eq!(error("failure_rate = 1.5"), "failure_rate must be between 0 and 1");
+        assert_eq!(error("scheduler = \"random\""), "unknown scheduler 'random'");
+        assert_eq!(error("quiet = true\nverbose = true"), "quiet and verbose can't both be set");
+        assert_eq!(error("[load_test]\nrate = 0"), "load_test.rate must be more than 0");
+        assert_eq!(error("[weights]\ndownload = 0"), "weights.download must be at least 1");
+        assert_eq!(error("wokers = 4"), "unknown setting 'wokers'");
+    }
+
+    #[test]
+    fn malformed_lines_give_their_number() {
+        assert_eq!(error("workers = 4\nworkers = 5"), "line 2: setting given twice");
+        assert_eq!(error("\n[retry\n"), "line 2: unclosed section header");
+        assert_eq!(error("workers"), "line 1: expected `key = value`");
+        assert_eq!(error("output = \"json"), "line 1: invalid value");
+        // A # inside a string isn't a comment
+        let mut args = Args::default();
+        apply_text(&mut args, "tasks_file = \"tasks#1.json\" # the tasks").unwrap();
+        assert_eq!(args.tasks_file, Some(PathBuf::from("tasks#1.json")));
+    }
+
+    #[test]
+    fn durations_and_bandwidths_parse_with_their_units() {
+        assert_eq!(parse_duration("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_duration("1.5s"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("2m"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_duration("60"), None);
+        assert_eq!(parse_duration("-1s"), None);
+        assert_eq!(parse_bandwidth("500K"), Some(500 * 1024));
+        assert_eq!(parse_bandwidth("2m"), Some(2 << 20));
+        assert_eq!(parse_bandwidth("0"), None);
+        assert_eq!(parse_bandwidth("99999999999G"), None);
+    }
+}
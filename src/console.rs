@@ -0,0 +1,126 @@
+// How the demos print their results: a ✓ or ✗ ahead of each, in green or
+// red when stdout is a terminal and plain when it's piped or NO_COLOR is
+// set. --quiet leaves only the summaries and --verbose adds how each task
+// went underneath it
+use rust_concurrent_processor::TaskResult;
+use std::env;
+use std::fmt::Display;
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    Quiet,
+    #[default]
+    Normal,
+    Verbose,
+}
+
+// Set once from the flags before any demo runs
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+pub fn init(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+    COLOR.store(io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none(), Ordering::Relaxed);
+}
+
+pub fn quiet() -> bool {
+    VERBOSITY.load(Ordering::Relaxed) == Verbosity::Quiet as u8
+}
+
+pub fn verbose() -> bool {
+    VERBOSITY.load(Ordering::Relaxed) == Verbosity::Verbose as u8
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mark {
+    Success,
+    Failure,
+    // Didn't run to an outcome of its own: cancelled, skipped, dropped
+    Skipped,
+}
+
+impl Mark {
+    pub fn of(result: &TaskResult) -> Mark {
+        match result {
+            TaskResult::Success { .. } => Mark::Success,
+            TaskResult::Error { .. } | TaskResult::Panicked { .. } | TaskResult::TimedOut { .. } => Mark::Failure,
+            _ => Mark::Skipped,
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            Mark::Success => "✓",
+            Mark::Failure => "✗",
+            Mark::Skipped => "-",
+        }
+    }
+
+    // ANSI foreground colors
+    fn color(self) -> &'static str {
+        match self {
+            Mark::Success => "32",
+            Mark::Failure => "31",
+            Mark::Skipped => "33",
+        }
+    }
+}
+
+// `text` behind its mark, colored if stdout takes color
+pub fn line(mark: Mark, text: impl Display) -> String {
+    if COLOR.load(Ordering::Relaxed) {
+        format!("\x1b[{}m{}\x1b[0m {}", mark.color(), mark.symbol(), text)
+    } else {
+        format!("{} {}", mark.symbol(), text)
+    }
+}
+
+// A task's outcome as "✓ [3] Task 3 completed", unless --quiet
+pub fn task(mark: Mark, id: u32, text: impl Display) {
+    if !quiet() {
+        println!("{}", line(mark, format_args!("[{}] {}", id, text)));
+    }
+}
+
+// A result from the pool, with its type, timings and attempts under it
+// when --verbose
+pub fn result(result: &TaskResult) {
+    match result {
+        TaskResult::Success { id, output, .. } => task(Mark::Success, *id, output),
+        TaskResult::Error { id, error, .. } => task(Mark::Failure, *id, error),
+        other => task(Mark::of(other), other.id(), ended(other)),
+    }
+    if verbose() {
+        println!("    {}", details(result));
+    }
+}
+
+// How a task that neither succeeded nor returned an error ended
+fn ended(result: &TaskResult) -> String {
+    match result {
+        TaskResult::Panicked { message, .. } => format!("Task panicked: {}", message),
+        TaskResult::TimedOut { timeout_ms, .. } => format!("Task timed out after {}ms", timeout_ms),
+        TaskResult::Cancelled { .. } => "Task cancelled".to_string(),
+        TaskResult::DependencyFailed { dependency, .. } => format!("Task skipped, task {} did not succeed", dependency),
+        TaskResult::Expired { late_ms, .. } => format!("Task expired {}ms past its deadline", late_ms),
+        TaskResult::CircuitOpen { task_type, .. } => format!("Task rejected, too many {} tasks failing", task_type),
+        TaskResult::Dropped { .. } => "Task dropped from the queue".to_string(),
+        TaskResult::Success { .. } | TaskResult::Error { .. } => "Task finished".to_string(),
+    }
+}
+
+fn details(result: &TaskResult) -> String {
+    let tries = |attempts: u32| if attempts == 1 { "1 attempt".to_string() } else { format!("{} attempts", attempts) };
+    match result {
+        TaskResult::Success { task_type, duration_ms, queued_ms, attempts, .. } => {
+            format!("{}: ran {}ms after {}ms queued, {}", task_type, duration_ms, queued_ms, tries(*attempts))
+        },
+        TaskResult::Error { task_type, attempts, .. } => format!("{}: {}", task_type, tries(*attempts)),
+        TaskResult::TimedOut { task_type, timeout_ms, attempts, .. } => {
+            format!("{}: timed out after {}ms, {}", task_type, timeout_ms, tries(*attempts))
+        },
+        other => other.task_type().to_string(),
+    }
+}
@@ -0,0 +1,85 @@
+// Steering a project run while it goes. Results and commands come in on
+// one channel as events, so the coordinator waits on both at once instead
+// of blocking on results alone. Commands are typed on stdin, one a line,
+// when it's a terminal; Ctrl-C stops the run the same way `stop` does
+use rust_concurrent_processor::{Subscription, TaskResult};
+use std::io::{self, BufRead, IsTerminal};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    // Workers finish what they're on, then take nothing new
+    Pause,
+    Resume,
+    // Print the counters as they stand
+    Stats,
+    // Drop what's still queued and wrap up, as on Ctrl-C
+    Shutdown,
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Option<Command> {
+        match line.trim() {
+            "pause" | "p" => Some(Command::Pause),
+            "resume" | "r" => Some(Command::Resume),
+            "stats" | "s" => Some(Command::Stats),
+            "stop" | "quit" | "q" => Some(Command::Shutdown),
+            _ => None,
+        }
+    }
+}
+
+pub enum Event {
+    Result(TaskResult),
+    Command(Command),
+}
+
+pub struct Control {
+    sender: Sender<Event>,
+    pub events: Receiver<Event>,
+}
+
+impl Control {
+    pub fn new() -> Control {
+        let (sender, events) = mpsc::channel();
+        Control { sender, events }
+    }
+
+    // Passes on the results `subscription` sees, bar those `skip` turns
+    // down, until the pool shuts down
+    pub fn forward(&self, subscription: Subscription, skip: impl Fn(&TaskResult) -> bool + Send + 'static) {
+        let sender = self.sender.clone();
+        thread::spawn(move || {
+            for result in subscription.filter(|result| !skip(result)) {
+                if sender.send(Event::Result(result)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    // Reads commands from stdin if someone's there to type them. Whether
+    // it is
+    pub fn read_stdin(&self) -> bool {
+        if !io::stdin().is_terminal() {
+            return false;
+        }
+        let sender = self.sender.clone();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines() {
+                let Ok(line) = line else { break };
+                match Command::parse(&line) {
+                    Some(command) => {
+                        if sender.send(Event::Command(command)).is_err() {
+                            break;
+                        }
+                    },
+                    None if line.trim().is_empty() => {},
+                    None => eprintln!("Unknown command '{}': pause, resume, stats or stop", line.trim()),
+                }
+            }
+        });
+        true
+    }
+}
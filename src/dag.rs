@@ -0,0 +1,350 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::handle::TaskHandle;
+use crate::pool::{SubmitOptions, ThreadPool};
+use crate::sync::Recover;
+use crate::task::{Task, TaskResult};
+
+/// How a finished task looks to the tasks that depend on it: `Err` carries
+/// the id of the task that didn't succeed.
+pub(crate) type Outcome = Result<(), u32>;
+
+type Callback = Box<dyn FnOnce(Outcome) + Send + 'static>;
+
+enum State {
+    Pending(Vec<Callback>),
+    Done(Outcome),
+}
+
+/// Tells dependents when a submitted task has finished.
+pub(crate) struct Completion {
+    state: Mutex<State>,
+}
+
+impl Completion {
+    pub(crate) fn new() -> Self {
+        Completion {
+            state: Mutex::new(State::Pending(Vec::new())),
+        }
+    }
+
+    /// Calls `callback` once the task finishes, or right away if it
+    /// already has.
+    pub(crate) fn on_done(&self, callback: Callback) {
+        let mut state = self.state.lock().recover();
+        match &mut *state {
+            State::Pending(callbacks) => callbacks.push(callback),
+            State::Done(outcome) => {
+                let outcome = *outcome;
+                drop(state);
+                callback(outcome);
+            }
+        }
+    }
+
+    pub(crate) fn complete(&self, outcome: Outcome) {
+        let previous = std::mem::replace(&mut *self.state.lock().recover(), State::Done(outcome));
+        if let State::Pending(callbacks) = previous {
+            for callback in callbacks {
+                callback(outcome);
+            }
+        }
+    }
+}
+
+/// Holds a dependent task back until all of its dependencies succeed, or
+/// one of them fails.
+pub(crate) struct Gate {
+    remaining: AtomicUsize,
+    released: AtomicBool,
+    failed: Mutex<Option<u32>>,
+}
+
+impl Gate {
+    pub(crate) fn new(dependencies: usize) -> Self {
+        Gate {
+            remaining: AtomicUsize::new(dependencies),
+            released: AtomicBool::new(false),
+            failed: Mutex::new(None),
+        }
+    }
+
+    /// Records one finished dependency. Returns `true` exactly once: when
+    /// the last dependency succeeds or the first one fails.
+    pub(crate) fn arrive(&self, outcome: Outcome) -> bool {
+        match outcome {
+            Ok(()) => {
+                self.remaining.fetch_sub(1, Ordering::AcqRel) == 1
+                    && !self.released.swap(true, Ordering::AcqRel)
+            }
+            Err(id) => {
+                if self.released.swap(true, Ordering::AcqRel) {
+                    return false;
+                }
+                *self.failed.lock().recover() = Some(id);
+                true
+            }
+        }
+    }
+
+    /// The dependency that failed, if any.
+    pub(crate) fn failed_dependency(&self) -> Option<u32> {
+        *self.failed.lock().recover()
+    }
+}
+
+/// Identifies a task added to a [`TaskGraph`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+impl NodeId {
+    /// Position of the task in the graph, and of its handle in the vector
+    /// returned by [`ThreadPool::submit_graph`].
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+/// Returned by [`ThreadPool::submit_graph`] when the dependencies loop back
+/// on themselves. Nothing is submitted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CycleError {
+    /// Ids of the tasks on or behind the cycle.
+    pub tasks: Vec<u32>,
+}
+
+impl fmt::Display for CycleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "task dependencies form a cycle among {:?}", self.tasks)
+    }
+}
+
+impl std::error::Error for CycleError {}
+
+/// A set of tasks and the order they have to run in, submitted all at once
+/// with [`ThreadPool::submit_graph`].
+pub struct TaskGraph<T> {
+    nodes: Vec<(T, SubmitOptions)>,
+    dependencies: Vec<Vec<usize>>,
+}
+
+impl<T> Default for TaskGraph<T> {
+    fn default() -> Self {
+        TaskGraph {
+            nodes: Vec::new(),
+            dependencies: Vec::new(),
+        }
+    }
+}
+
+impl<T: Task + 'static> TaskGraph<T> {
+    pub fn new() -> Self {
+        TaskGraph::default()
+    }
+
+    pub fn add(&mut self, task: T) -> NodeId {
+        self.add_with(task, SubmitOptions::new())
+    }
+
+    pub fn add_with(&mut self, task: T, options: SubmitOptions) -> NodeId {
+        self.nodes.push((task, options));
+        self.dependencies.push(Vec::new());
+        NodeId(self.nodes.len() - 1)
+    }
+
+    /// Makes `node` wait until `on` has succeeded.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either node belongs to another graph.
+    pub fn add_dependency(&mut self, node: NodeId, on: NodeId) {
+        assert!(
+            node.0 < self.nodes.len() && on.0 < self.nodes.len(),
+            "unknown task graph node"
+        );
+        self.dependencies[node.0].push(on.0);
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Orders the nodes so every dependency comes before its dependents.
+    fn topological_order(&self) -> Result<Vec<usize>, CycleError> {
+        let mut waiting_on: Vec<usize> = self.dependencies.iter().map(Vec::len).collect();
+        let mut dependents = vec![Vec::new(); self.nodes.len()];
+        for (node, dependencies) in self.dependencies.iter().enumerate() {
+            for &on in dependencies {
+                dependents[on].push(node);
+            }
+        }
+
+        let mut ready: VecDeque<usize> = (0..self.nodes.len())
+            .filter(|&node| waiting_on[node] == 0)
+            .collect();
+        let mut order = Vec::with_capacity(self.nodes.len());
+        while let Some(node) = ready.pop_front() {
+            order.push(node);
+            for &dependent in &dependents[node] {
+                waiting_on[dependent] -= 1;
+                if waiting_on[dependent] == 0 {
+                    ready.push_back(dependent);
+                }
+            }
+        }
+
+        if order.len() == self.nodes.len() {
+            Ok(order)
+        } else {
+            let tasks = (0..self.nodes.len())
+                .filter(|&node| waiting_on[node] > 0)
+                .map(|node| self.nodes[node].0.id())
+                .collect();
+            Err(CycleError { tasks })
+        }
+    }
+}
+
+impl<T: Task + 'static> TaskGraph<T> {
+    /// Hands every task to `submit` in dependency order, along with its
+    /// index and the completions of the tasks it waits on. `submit` returns
+    /// the task's own completion for its dependents.
+    pub(crate) fn submit_each<F>(self, mut submit: F) -> Result<(), CycleError>
+    where
+        F: FnMut(usize, T, SubmitOptions, Vec<Arc<Completion>>) -> Arc<Completion>,
+    {
+        let order = self.topological_order()?;
+        let mut nodes: Vec<Option<(T, SubmitOptions)>> = self.nodes.into_iter().map(Some).collect();
+        let mut completions: Vec<Option<Arc<Completion>>> =
+            (0..nodes.len()).map(|_| None).collect();
+
+        for node in order {
+            let (task, options) = nodes[node].take().expect("each node is visited once");
+            let dependencies = self.dependencies[node]
+                .iter()
+                .map(|&on| {
+                    let completion = completions[on].as_ref();
+                    Arc::clone(completion.expect("dependencies are submitted first"))
+                })
+                .collect();
+            completions[node] = Some(submit(node, task, options, dependencies));
+        }
+        Ok(())
+    }
+}
+
+impl ThreadPool {
+    /// Submits every task in `graph`, each one held back until the tasks it
+    /// depends on have succeeded. Handles come back in the order the tasks
+    /// were added.
+    pub fn submit_graph<T>(
+        &self,
+        graph: TaskGraph<T>,
+    ) -> Result<Vec<TaskHandle<TaskResult>>, CycleError>
+    where
+        T: Task + 'static,
+    {
+        let mut handles: Vec<Option<TaskHandle<TaskResult>>> =
+            (0..graph.len()).map(|_| None).collect();
+        graph.submit_each(|node, task, options, dependencies| {
+            let completion = Arc::new(Completion::new());
+            let (result_tx, handle) = TaskHandle::with_completion(Arc::clone(&completion));
+            self.submit_gated(
+                Arc::new(task),
+                dependencies,
+                options,
+                Arc::clone(&completion),
+                move |result| {
+                    let _ = result_tx.send(result);
+                },
+            );
+            handles[node] = Some(handle);
+            completion
+        })?;
+        Ok(handles.into_iter().map(Option::unwrap).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::{TaskContext, TaskError, TaskOutput};
+
+    struct Node(u32);
+
+    impl Task for Node {
+        fn id(&self) -> u32 {
+            self.0
+        }
+
+        fn execute(&self, _ctx: &TaskContext) -> Result<TaskOutput, TaskError> {
+            Ok(TaskOutput::new("done"))
+        }
+    }
+
+    #[test]
+    fn dependencies_come_before_their_dependents() {
+        let mut graph = TaskGraph::new();
+        let nodes: Vec<NodeId> = (0..4).map(|id| graph.add(Node(id))).collect();
+        graph.add_dependency(nodes[0], nodes[3]);
+        graph.add_dependency(nodes[0], nodes[1]);
+        graph.add_dependency(nodes[1], nodes[2]);
+        let order = graph.topological_order().unwrap();
+        let position = |node: NodeId| order.iter().position(|&at| at == node.index());
+        assert!(position(nodes[3]) < position(nodes[0]));
+        assert!(position(nodes[2]) < position(nodes[1]));
+        assert!(position(nodes[1]) < position(nodes[0]));
+    }
+
+    #[test]
+    fn a_cycle_names_the_tasks_on_and_behind_it() {
+        let mut graph = TaskGraph::new();
+        let free = graph.add(Node(10));
+        let a = graph.add(Node(11));
+        let b = graph.add(Node(12));
+        let behind = graph.add(Node(13));
+        graph.add_dependency(a, b);
+        graph.add_dependency(b, a);
+        graph.add_dependency(behind, a);
+        graph.add_dependency(behind, free);
+        assert_eq!(
+            graph.topological_order(),
+            Err(CycleError {
+                tasks: vec![11, 12, 13]
+            })
+        );
+
+        let mut graph = TaskGraph::new();
+        let alone = graph.add(Node(1));
+        graph.add_dependency(alone, alone);
+        let mut submitted = 0;
+        let refused = graph.submit_each(|_, _, _, _| {
+            submitted += 1;
+            Arc::new(Completion::new())
+        });
+        assert!(refused.is_err());
+        assert_eq!(submitted, 0);
+    }
+
+    #[test]
+    fn a_gate_opens_once_on_the_last_success_or_first_failure() {
+        let gate = Gate::new(2);
+        assert!(!gate.arrive(Ok(())));
+        assert!(gate.arrive(Ok(())));
+        assert_eq!(gate.failed_dependency(), None);
+
+        let gate = Gate::new(3);
+        assert!(!gate.arrive(Ok(())));
+        assert!(gate.arrive(Err(7)));
+        assert!(!gate.arrive(Err(8)));
+        assert!(!gate.arrive(Ok(())));
+        assert_eq!(gate.failed_dependency(), Some(7));
+    }
+}
@@ -0,0 +1,111 @@
+// A full-screen view of a running pool for --tui: queue depth, what each
+// busy worker is on, a throughput sparkline and the latest results. Drawn
+// with plain ANSI escapes on the terminal's alternate screen, which is
+// left again on drop so the final stats print as usual
+use rust_concurrent_processor::{TaskStatus, ThreadPool};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+// Results kept on screen, newest last
+const LOG_LINES: usize = 12;
+// Throughput samples in the sparkline, one per draw
+const HISTORY: usize = 60;
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+// How often the screen is redrawn
+const REDRAW: Duration = Duration::from_millis(50);
+
+pub struct Dashboard {
+    started: Instant,
+    last_draw: Instant,
+    // Results logged since the last draw
+    finished: u32,
+    throughput: VecDeque<f64>,
+    log: VecDeque<String>,
+}
+
+impl Dashboard {
+    // `started` is when the tasks started going in
+    pub fn new(started: Instant) -> Dashboard {
+        // Switch to the alternate screen and hide the cursor
+        print!("\x1b[?1049h\x1b[?25l");
+        let _ = io::stdout().flush();
+        Dashboard {
+            started,
+            last_draw: started,
+            finished: 0,
+            throughput: VecDeque::with_capacity(HISTORY),
+            log: VecDeque::with_capacity(LOG_LINES),
+        }
+    }
+
+    pub fn log(&mut self, line: String) {
+        self.finished += 1;
+        if self.log.len() == LOG_LINES {
+            self.log.pop_front();
+        }
+        self.log.push_back(line);
+    }
+
+    // Whether it's time to draw again
+    pub fn due(&self) -> bool {
+        self.last_draw.elapsed() >= REDRAW
+    }
+
+    pub fn draw(&mut self, pool: &ThreadPool) {
+        let now = Instant::now();
+        let rate = self.finished as f64 / (now - self.last_draw).as_secs_f64().max(0.001);
+        self.last_draw = now;
+        self.finished = 0;
+        if self.throughput.len() == HISTORY {
+            self.throughput.pop_front();
+        }
+        self.throughput.push_back(rate);
+
+        let stats = pool.stats();
+        let busy: BTreeMap<usize, String> = pool.in_flight().into_iter()
+            .filter_map(|(id, status)| match status {
+                TaskStatus::Running { worker } => Some((worker, format!("task {}", id))),
+                TaskStatus::Stuck { worker } => Some((worker, format!("task {} (stuck)", id))),
+                _ => None,
+            })
+            .collect();
+
+        // Home the cursor and clear the screen, then draw top to bottom
+        let mut out = String::from("\x1b[H\x1b[2J");
+        out += &format!("Project running for {:.1}s   Ctrl-C to stop\n\n", self.started.elapsed().as_secs_f64());
+        out += &format!("Queued: {:<6} Workers: {} ({} busy)\n", pool.queued(), stats.active_workers, busy.len());
+        out += &format!(
+            "Completed: {:<6} Failed: {:<6} Timed out: {:<6} Retries: {}\n\n",
+            stats.tasks_completed, stats.tasks_failed, stats.tasks_timed_out, stats.retries
+        );
+        out += &format!("Throughput {} {:.1} tasks/s\n\n", self.sparkline(), rate);
+        for (worker, task) in &busy {
+            out += &format!("  worker {:<3} {}\n", worker, task);
+        }
+        out += "\nLatest results:\n";
+        for line in &self.log {
+            out += &format!("  {}\n", line);
+        }
+        print!("{}", out);
+        let _ = io::stdout().flush();
+    }
+
+    fn sparkline(&self) -> String {
+        let peak = self.throughput.iter().copied().fold(0.0, f64::max);
+        self.throughput.iter()
+            .map(|&rate| {
+                let level = if peak > 0.0 { rate / peak * (SPARKS.len() - 1) as f64 } else { 0.0 };
+                SPARKS[level.round() as usize]
+            })
+            .collect()
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        // Show the cursor again and go back to the normal screen
+        print!("\x1b[?25h\x1b[?1049l");
+        let _ = io::stdout().flush();
+    }
+}
@@ -0,0 +1,136 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use crate::pool::ThreadPool;
+use crate::sync::Recover;
+use crate::task::{Task, TaskContext, TaskError, TaskOutput};
+
+type Handler =
+    dyn Fn(&(dyn Any + Send + Sync), &TaskContext) -> Result<TaskOutput, TaskError> + Send + Sync;
+
+/// A registered handler and the payload type it takes.
+#[derive(Clone)]
+struct Registered {
+    payload: TypeId,
+    handler: Arc<Handler>,
+}
+
+/// Handlers registered on a pool with [`ThreadPool::register`], by task
+/// type.
+#[derive(Default)]
+pub(crate) struct Handlers(RwLock<HashMap<String, Registered>>);
+
+/// Why [`ThreadPool::dispatch`] couldn't make a task.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DispatchError {
+    /// No handler is registered for the task type.
+    UnknownTaskType(String),
+    /// The handler for the task type takes a different kind of payload.
+    WrongPayload(String),
+}
+
+impl fmt::Display for DispatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DispatchError::UnknownTaskType(task_type) => {
+                write!(f, "unknown task type '{}'", task_type)
+            }
+            DispatchError::WrongPayload(task_type) => {
+                write!(f, "wrong kind of payload for task type '{}'", task_type)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DispatchError {}
+
+/// A task made by [`ThreadPool::dispatch`]: a payload and the handler
+/// registered for its type. Submitted like any other task.
+pub struct Dispatched {
+    id: u32,
+    task_type: String,
+    payload: Box<dyn Any + Send + Sync>,
+    handler: Arc<Handler>,
+}
+
+impl Task for Dispatched {
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn kind(&self) -> &str {
+        &self.task_type
+    }
+
+    fn execute(&self, ctx: &TaskContext) -> Result<TaskOutput, TaskError> {
+        (self.handler)(&*self.payload, ctx)
+    }
+}
+
+impl fmt::Debug for Dispatched {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Dispatched")
+            .field("id", &self.id)
+            .field("task_type", &self.task_type)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ThreadPool {
+    /// Runs tasks of `task_type` made by [`dispatch`](Self::dispatch) with
+    /// `handler`, which gets the task's payload. Tasks that come in by name,
+    /// such as from a file or over the network, can then have types the
+    /// pool knows nothing else about. Registering a type again replaces its
+    /// handler for tasks dispatched from then on.
+    pub fn register<P, F>(&self, task_type: impl Into<String>, handler: F)
+    where
+        P: Send + Sync + 'static,
+        F: Fn(&P, &TaskContext) -> Result<TaskOutput, TaskError> + Send + Sync + 'static,
+    {
+        let handler: Arc<Handler> = Arc::new(move |payload, ctx| {
+            let payload = payload
+                .downcast_ref::<P>()
+                .expect("payload type checked at dispatch");
+            handler(payload, ctx)
+        });
+        let registered = Registered {
+            payload: TypeId::of::<P>(),
+            handler,
+        };
+        let mut handlers = self.handlers.0.write().recover();
+        handlers.insert(task_type.into(), registered);
+    }
+
+    /// Makes a task of `task_type` that runs its registered handler on
+    /// `payload`.
+    pub fn dispatch<P>(
+        &self,
+        id: u32,
+        task_type: &str,
+        payload: P,
+    ) -> Result<Dispatched, DispatchError>
+    where
+        P: Send + Sync + 'static,
+    {
+        let handlers = self.handlers.0.read().recover();
+        let Some(registered) = handlers.get(task_type) else {
+            return Err(DispatchError::UnknownTaskType(task_type.to_string()));
+        };
+        if registered.payload != TypeId::of::<P>() {
+            return Err(DispatchError::WrongPayload(task_type.to_string()));
+        }
+        Ok(Dispatched {
+            id,
+            task_type: task_type.to_string(),
+            payload: Box::new(payload),
+            handler: Arc::clone(&registered.handler),
+        })
+    }
+
+    /// Whether a handler is registered for `task_type`.
+    pub fn handles(&self, task_type: &str) -> bool {
+        self.handlers.0.read().recover().contains_key(task_type)
+    }
+}
@@ -0,0 +1,125 @@
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::cancel::CancellationToken;
+use crate::clock::SharedClock;
+use crate::handle::TaskHandle;
+use crate::hooks::Listeners;
+use crate::pool::{ShutdownMode, ThreadPool};
+use crate::registry::TaskRegistry;
+use crate::retry::RetryPolicy;
+use crate::runner::{self, RunSpec};
+use crate::stats::{LiveStats, SystemStats};
+use crate::sync::Recover;
+use crate::task::{Task, TaskInfo, TaskResult};
+
+/// Something that runs [`Task`]s, so code that only submits tasks and
+/// reads their results can be handed any backend: a [`ThreadPool`], a
+/// [`ThreadPerTask`], or one of your own.
+pub trait Executor: Send + Sync {
+    /// Starts `task`, now or once there's room, and hands back its result.
+    fn submit(&self, task: Box<dyn Task>) -> TaskHandle<TaskResult>;
+
+    /// Current counters. Safe to call while tasks are running.
+    fn stats(&self) -> SystemStats;
+
+    /// Stops taking tasks, deals with the ones not yet finished as `mode`
+    /// says, and returns the final stats.
+    fn shutdown(self: Box<Self>, mode: ShutdownMode) -> SystemStats;
+}
+
+impl Executor for ThreadPool {
+    fn submit(&self, task: Box<dyn Task>) -> TaskHandle<TaskResult> {
+        ThreadPool::submit(self, task)
+    }
+
+    fn stats(&self) -> SystemStats {
+        ThreadPool::stats(self)
+    }
+
+    fn shutdown(self: Box<Self>, mode: ShutdownMode) -> SystemStats {
+        ThreadPool::shutdown(*self, mode)
+    }
+}
+
+/// Starts a thread for every task as it's submitted: no queue, no limit
+/// on threads and no reuse. No timeouts or retries either. The simplest
+/// backend there is, to hold a [`ThreadPool`] up against.
+#[derive(Default)]
+pub struct ThreadPerTask {
+    stats: Arc<LiveStats>,
+    registry: Arc<TaskRegistry>,
+    threads: Mutex<Vec<JoinHandle<()>>>,
+    shutdown: CancellationToken,
+}
+
+impl ThreadPerTask {
+    pub fn new() -> Self {
+        ThreadPerTask::default()
+    }
+}
+
+impl Executor for ThreadPerTask {
+    fn submit(&self, task: Box<dyn Task>) -> TaskHandle<TaskResult> {
+        let (result_tx, handle) = TaskHandle::new();
+        let stats = Arc::clone(&self.stats);
+        let mut spec = RunSpec {
+            cancellation: CancellationToken::new(),
+            shutdown: self.shutdown.clone(),
+            timeout: None,
+            retry: RetryPolicy::none(),
+            rate_limit: None,
+            registry: Arc::clone(&self.registry),
+            listeners: Listeners::default(),
+            clock: SharedClock::default(),
+            chaos: None,
+            queued: Duration::ZERO,
+            info: TaskInfo::default(),
+        };
+        let submitted = Instant::now();
+        spec.info.task_type = task.kind().into();
+        spec.info.submitted_at = Some(submitted);
+        self.registry.queued(task.id());
+        let thread = thread::spawn(move || {
+            stats.worker_started();
+            // No queue, just the wait for the thread to start
+            spec.queued = submitted.elapsed();
+            stats.waited(task.kind(), spec.queued);
+            let result = runner::run(&Arc::new(task), &spec);
+            spec.registry.finished(&result);
+            stats.record(&result);
+            stats.worker_stopped();
+            let _ = result_tx.send(result);
+        });
+        let mut threads = self.threads.lock().recover();
+        threads.retain(|thread| !thread.is_finished());
+        threads.push(thread);
+        handle
+    }
+
+    fn stats(&self) -> SystemStats {
+        self.stats.snapshot()
+    }
+
+    /// Every task already has its thread, so either mode waits for them
+    /// all to finish; [`ShutdownMode::Immediate`] cancels them first.
+    fn shutdown(self: Box<Self>, mode: ShutdownMode) -> SystemStats {
+        if mode == ShutdownMode::Immediate {
+            self.shutdown.cancel();
+        }
+        for thread in self.threads.lock().recover().drain(..) {
+            let _ = thread.join();
+        }
+        self.stats.snapshot()
+    }
+}
+
+/// Waits for every task's thread, as shutting down would.
+impl Drop for ThreadPerTask {
+    fn drop(&mut self) {
+        for thread in self.threads.lock().recover().drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
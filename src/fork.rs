@@ -0,0 +1,121 @@
+use std::cell::RefCell;
+use std::sync::Arc;
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::time::Duration;
+
+use crate::handle::TaskHandle;
+use crate::local::WorkerState;
+use crate::pool::Shared;
+use crate::queue::{JobInfo, Pop, TaskQueue, TryPushError};
+
+/// How long a waiting worker looks for other work before checking on what
+/// it's waiting for again.
+const HELP_INTERVAL: Duration = Duration::from_millis(1);
+
+/// The queue a pool thread takes its jobs from, and its place in it.
+#[derive(Clone)]
+pub(crate) struct Worker {
+    pub(crate) lane: Arc<Shared>,
+    index: usize,
+    /// Kept for the worker's tasks, and handed along with its place.
+    pub(crate) state: WorkerState,
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Worker>> = const { RefCell::new(None) };
+}
+
+/// Marks the calling thread as working for `lane`, until it exits.
+pub(crate) fn enter(lane: &Arc<Shared>, index: usize) {
+    set_current(Some(Worker {
+        lane: Arc::clone(lane),
+        index,
+        state: WorkerState::default(),
+    }));
+}
+
+/// The pool the calling thread works for, if any.
+pub(crate) fn current() -> Option<Worker> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Hands a pool thread's place to another thread, so a task moved off the
+/// worker can still spawn and wait.
+pub(crate) fn set_current(worker: Option<Worker>) {
+    CURRENT.with(|current| *current.borrow_mut() = worker);
+}
+
+/// Tells the watchdog the calling pool thread is making progress, now on
+/// `task` if given. Does nothing off the pool.
+pub(crate) fn heartbeat(task: Option<u32>) {
+    if let Some(worker) = current() {
+        worker.lane.heartbeats.beat(worker.index, task);
+    }
+}
+
+/// Blocks on `receiver`. On a pool thread, runs other queued jobs in the
+/// meantime, so a task waiting on its own subtasks can't starve the pool.
+pub(crate) fn wait<T>(receiver: &Receiver<T>) -> Option<T> {
+    let Some(worker) = current() else {
+        return receiver.recv().ok();
+    };
+    loop {
+        match receiver.try_recv() {
+            Ok(value) => return Some(value),
+            Err(TryRecvError::Disconnected) => return None,
+            Err(TryRecvError::Empty) => {}
+        }
+        match worker.lane.queue.pop(worker.index, Some(HELP_INTERVAL)) {
+            Pop::Item(job) => {
+                worker.lane.run_job(job);
+                heartbeat(None);
+            }
+            Pop::TimedOut => {}
+            // Whatever we're waiting on has been picked up already.
+            Pop::Closed => return receiver.recv().ok(),
+        }
+    }
+}
+
+/// Runs `f` on the pool the calling task is running on and returns a handle
+/// to its return value. Waiting on the handle from inside the pool helps
+/// with queued work instead of blocking a worker, so tasks can split
+/// themselves up recursively.
+///
+/// Off the pool, or when its queue is full or shutting down, `f` runs
+/// right away on the calling thread.
+pub fn spawn<F, R>(f: F) -> TaskHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let Some(worker) = current() else {
+        let (value_tx, handle) = TaskHandle::new();
+        let _ = value_tx.send(f());
+        return handle;
+    };
+    let (job, handle) = worker.lane.closure(f);
+    match worker.lane.queue.try_push(job, JobInfo::default()) {
+        Ok(()) => worker.lane.scale_up_if_busy(),
+        Err(TryPushError::Full(job) | TryPushError::Closed(job)) => job(),
+    }
+    handle
+}
+
+/// Runs `a` here and `b` on the pool, and returns both results once both
+/// are done. See [`spawn`] for how that works.
+///
+/// # Panics
+///
+/// Panics if `a` or `b` does.
+pub fn join<A, B, RA, RB>(a: A, b: B) -> (RA, RB)
+where
+    A: FnOnce() -> RA,
+    B: FnOnce() -> RB + Send + 'static,
+    RB: Send + 'static,
+{
+    let b = spawn(b);
+    let a = a();
+    let b = b.wait().expect("joined closure panicked");
+    (a, b)
+}
@@ -0,0 +1,128 @@
+use std::fmt;
+use std::sync::Arc;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+
+use crate::dag::Completion;
+use crate::fork;
+use crate::pool::{self, ThreadPool};
+use crate::task::{Task, TaskOutput, TaskResult};
+
+/// Why [`TaskHandle::wait_timeout`] returned without an outcome.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WaitError {
+    /// The task hasn't finished yet; it's fine to wait again.
+    TimedOut,
+    /// The pool dropped the task without running it.
+    Dropped,
+}
+
+impl fmt::Display for WaitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WaitError::TimedOut => f.write_str("task is still running"),
+            WaitError::Dropped => f.write_str("task was dropped without running"),
+        }
+    }
+}
+
+impl std::error::Error for WaitError {}
+
+/// Receives the outcome of one submitted task.
+pub struct TaskHandle<T> {
+    receiver: mpsc::Receiver<T>,
+    completion: Option<Arc<Completion>>,
+}
+
+impl<T> TaskHandle<T> {
+    pub(crate) fn new() -> (mpsc::Sender<T>, TaskHandle<T>) {
+        let (sender, receiver) = mpsc::channel();
+        let handle = TaskHandle {
+            receiver,
+            completion: None,
+        };
+        (sender, handle)
+    }
+
+    /// Like [`new`](Self::new), but other tasks can be made to wait on the
+    /// handle.
+    pub(crate) fn with_completion(completion: Arc<Completion>) -> (mpsc::Sender<T>, TaskHandle<T>) {
+        let (sender, mut handle) = TaskHandle::new();
+        handle.completion = Some(completion);
+        (sender, handle)
+    }
+
+    pub(crate) fn completion(&self) -> Option<&Arc<Completion>> {
+        self.completion.as_ref()
+    }
+
+    /// Blocks until the task finishes. Returns `None` if the pool dropped
+    /// the task without running it.
+    ///
+    /// Called from a task running on the pool, the worker runs other queued
+    /// work while it waits rather than sitting idle, which keeps tasks that
+    /// wait on their own subtasks from deadlocking the pool.
+    pub fn wait(self) -> Option<T> {
+        fork::wait(&self.receiver)
+    }
+
+    /// Blocks for at most `timeout` waiting for the task to finish.
+    pub fn wait_timeout(&mut self, timeout: Duration) -> Result<T, WaitError> {
+        self.receiver
+            .recv_timeout(timeout)
+            .map_err(|err| match err {
+                RecvTimeoutError::Timeout => WaitError::TimedOut,
+                RecvTimeoutError::Disconnected => WaitError::Dropped,
+            })
+    }
+
+    /// Returns the outcome if the task has already finished, without blocking.
+    pub fn try_get(&mut self) -> Option<T> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+impl TaskHandle<TaskResult> {
+    /// Once this task succeeds, queues the task `next` makes from its
+    /// output and returns a handle to that one's result instead. If this
+    /// task doesn't succeed, `next` is never called and the returned handle
+    /// gets this task's result, so a chain stops at its first failure.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the handle didn't come from one of the `submit` methods.
+    pub fn then<T, F>(self, pool: &ThreadPool, next: F) -> TaskHandle<TaskResult>
+    where
+        T: Task + 'static,
+        F: FnOnce(TaskOutput) -> T + Send + 'static,
+    {
+        let previous = Arc::clone(
+            self.completion
+                .as_ref()
+                .expect("handle doesn't belong to a submitted task"),
+        );
+        let receiver = self.receiver;
+        let completion = Arc::new(Completion::new());
+        let (result_tx, handle) = TaskHandle::with_completion(Arc::clone(&completion));
+        let submitter = pool.submitter();
+        previous.on_done(Box::new(move |_| {
+            // The result is sent before the task counts as done.
+            let Ok(result) = receiver.try_recv() else {
+                return;
+            };
+            match result {
+                TaskResult::Success { output, .. } => {
+                    submitter.release(next(output), completion, move |result| {
+                        let _ = result_tx.send(result);
+                    });
+                }
+                failed => {
+                    let outcome = pool::outcome(&failed);
+                    let _ = result_tx.send(failed);
+                    completion.complete(outcome);
+                }
+            }
+        }));
+        handle
+    }
+}
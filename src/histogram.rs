@@ -0,0 +1,156 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Each power of two is split into this many equal buckets, so a recorded
+/// value lands in a bucket at most ~3% wider than itself.
+const SUB_BUCKETS: u64 = 32;
+const SUB_BUCKET_BITS: u32 = SUB_BUCKETS.trailing_zeros();
+const BUCKETS: usize = ((64 - SUB_BUCKET_BITS + 1) as u64 * SUB_BUCKETS) as usize;
+
+fn bucket_of(value: u64) -> usize {
+    if value < SUB_BUCKETS {
+        return value as usize;
+    }
+    let exponent = 63 - value.leading_zeros();
+    let shift = exponent - SUB_BUCKET_BITS;
+    let sub_bucket = (value >> shift) & (SUB_BUCKETS - 1);
+    ((shift as u64 + 1) * SUB_BUCKETS + sub_bucket) as usize
+}
+
+/// Largest value that falls into `bucket`.
+fn upper_bound(bucket: usize) -> u64 {
+    let bucket = bucket as u64;
+    if bucket < SUB_BUCKETS {
+        return bucket;
+    }
+    let shift = bucket / SUB_BUCKETS - 1;
+    let lower = (SUB_BUCKETS + bucket % SUB_BUCKETS) << shift;
+    lower + ((1u64 << shift) - 1)
+}
+
+/// Distribution of task durations in milliseconds, as of one moment.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    /// Counts per bucket, with trailing empty buckets trimmed off.
+    buckets: Vec<u64>,
+    count: u64,
+    sum_ms: u64,
+    max_ms: u64,
+}
+
+impl LatencyHistogram {
+    /// How many durations were recorded.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// All recorded durations added up.
+    pub fn sum_ms(&self) -> u64 {
+        self.sum_ms
+    }
+
+    pub fn max_ms(&self) -> u64 {
+        self.max_ms
+    }
+
+    /// How many recorded durations were at most `ms`. Like the
+    /// percentiles, accurate to within a few percent of `ms`: a duration
+    /// counts once its whole bucket is within `ms`.
+    pub fn count_within_ms(&self, ms: u64) -> u64 {
+        if ms >= self.max_ms {
+            return self.count;
+        }
+        self.buckets
+            .iter()
+            .enumerate()
+            .take_while(|&(bucket, _)| upper_bound(bucket) <= ms)
+            .map(|(_, &count)| count)
+            .sum()
+    }
+
+    /// Adds in the durations `other` recorded.
+    pub(crate) fn merge(&mut self, other: &LatencyHistogram) {
+        if self.buckets.len() < other.buckets.len() {
+            self.buckets.resize(other.buckets.len(), 0);
+        }
+        for (bucket, count) in self.buckets.iter_mut().zip(&other.buckets) {
+            *bucket += count;
+        }
+        self.count += other.count;
+        self.sum_ms += other.sum_ms;
+        self.max_ms = self.max_ms.max(other.max_ms);
+    }
+
+    /// The duration that `percentile` percent of recorded tasks finished
+    /// within, e.g. `percentile_ms(99.0)`. Accurate to within a few percent;
+    /// zero if nothing has been recorded.
+    pub fn percentile_ms(&self, percentile: f64) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+        let rank = ((percentile / 100.0) * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, &count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return upper_bound(bucket).min(self.max_ms);
+            }
+        }
+        self.max_ms
+    }
+}
+
+/// The live histogram behind [`LatencyHistogram`], which a snapshot can
+/// read while it is being recorded into.
+pub(crate) struct AtomicHistogram {
+    buckets: Box<[AtomicU64]>,
+    sum_ms: AtomicU64,
+    max_ms: AtomicU64,
+}
+
+impl AtomicHistogram {
+    pub(crate) fn new() -> Self {
+        AtomicHistogram {
+            buckets: (0..BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+            sum_ms: AtomicU64::new(0),
+            max_ms: AtomicU64::new(0),
+        }
+    }
+
+    /// Only one thread may record at a time, such as the one holding a
+    /// stats shard, though any may take a snapshot.
+    pub(crate) fn record(&self, duration_ms: u64) {
+        let bucket = &self.buckets[bucket_of(duration_ms)];
+        bucket.store(bucket.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+        let sum = self.sum_ms.load(Ordering::Relaxed);
+        self.sum_ms
+            .store(sum.saturating_add(duration_ms), Ordering::Relaxed);
+        if duration_ms > self.max_ms.load(Ordering::Relaxed) {
+            self.max_ms.store(duration_ms, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> LatencyHistogram {
+        let mut buckets: Vec<u64> = self
+            .buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .collect();
+        while buckets.last() == Some(&0) {
+            buckets.pop();
+        }
+        LatencyHistogram {
+            count: buckets.iter().sum(),
+            buckets,
+            sum_ms: self.sum_ms.load(Ordering::Relaxed),
+            max_ms: self.max_ms.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl std::fmt::Debug for AtomicHistogram {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AtomicHistogram")
+            .field("max_ms", &self.max_ms.load(Ordering::Relaxed))
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,122 @@
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::sync::PoisonPolicy;
+use crate::task::TaskResult;
+
+/// Callbacks fired as tasks move through the pool, registered with
+/// [`ThreadPoolBuilder::listener`](crate::ThreadPoolBuilder::listener).
+/// Every method does nothing by default, so a listener only implements
+/// the ones it cares about.
+///
+/// Apart from `on_submit`, the callbacks run on the worker that runs the
+/// task, so they hold that worker up for as long as they take. `on_start`
+/// and the `on_complete` or `on_failure` that follows it run on the same
+/// thread with the task in between, so a listener can open a span or
+/// scope in one and close it in the other.
+pub trait TaskListener: Send + Sync {
+    /// A task has been submitted and is about to be queued.
+    fn on_submit(&self, _task_id: u32, _task_type: &str) {}
+
+    /// A worker has taken the task off the queue.
+    fn on_start(&self, _task_id: u32, _task_type: &str, _worker: usize) {}
+
+    /// An attempt ended in `result` and the task will run again after
+    /// `delay`.
+    fn on_retry(&self, _result: &TaskResult, _delay: Duration) {}
+
+    /// The task succeeded.
+    fn on_complete(&self, _result: &TaskResult) {}
+
+    /// The task finished any other way: it failed, timed out, panicked,
+    /// was cancelled or never ran at all.
+    fn on_failure(&self, _result: &TaskResult) {}
+}
+
+/// Lets the caller keep hold of a listener to read what it gathered.
+impl<L: TaskListener + ?Sized> TaskListener for Arc<L> {
+    fn on_submit(&self, task_id: u32, task_type: &str) {
+        (**self).on_submit(task_id, task_type);
+    }
+
+    fn on_start(&self, task_id: u32, task_type: &str, worker: usize) {
+        (**self).on_start(task_id, task_type, worker);
+    }
+
+    fn on_retry(&self, result: &TaskResult, delay: Duration) {
+        (**self).on_retry(result, delay);
+    }
+
+    fn on_complete(&self, result: &TaskResult) {
+        (**self).on_complete(result);
+    }
+
+    fn on_failure(&self, result: &TaskResult) {
+        (**self).on_failure(result);
+    }
+}
+
+/// The listeners registered on a pool, called in the order they were added.
+#[derive(Clone, Default)]
+pub(crate) struct Listeners {
+    listeners: Vec<Arc<dyn TaskListener>>,
+    policy: PoisonPolicy,
+}
+
+impl Listeners {
+    pub(crate) fn add(&mut self, listener: Arc<dyn TaskListener>) {
+        self.listeners.push(listener);
+    }
+
+    /// The same listeners, with panics in them handled as `policy` says.
+    pub(crate) fn with_policy(&self, policy: PoisonPolicy) -> Listeners {
+        Listeners {
+            listeners: self.listeners.clone(),
+            policy,
+        }
+    }
+
+    pub(crate) fn submitted(&self, task_id: u32, task_type: &str) {
+        self.each(|listener| listener.on_submit(task_id, task_type));
+    }
+
+    pub(crate) fn started(&self, task_id: u32, task_type: &str, worker: usize) {
+        self.each(|listener| listener.on_start(task_id, task_type, worker));
+    }
+
+    pub(crate) fn retrying(&self, result: &TaskResult, delay: Duration) {
+        self.each(|listener| listener.on_retry(result, delay));
+    }
+
+    pub(crate) fn finished(&self, result: &TaskResult) {
+        self.each(|listener| {
+            if result.is_success() {
+                listener.on_complete(result);
+            } else {
+                listener.on_failure(result);
+            }
+        });
+    }
+
+    /// Calls `f` on every listener. One that panics is skipped over when
+    /// recovering, so the rest still hear about the task and the worker
+    /// goes on to report its result.
+    fn each(&self, f: impl Fn(&dyn TaskListener)) {
+        for listener in &self.listeners {
+            match self.policy {
+                PoisonPolicy::Recover => {
+                    let _ = panic::catch_unwind(AssertUnwindSafe(|| f(&**listener)));
+                }
+                PoisonPolicy::FailFast => f(&**listener),
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Listeners {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} listeners", self.listeners.len())
+    }
+}
@@ -0,0 +1,130 @@
+// A bare-bones blocking HTTP/1.1 client, just enough for the download
+// tasks: plain `http://` GET requests, one connection per request, with the
+// body read whole or streamed
+use rust_concurrent_processor::Semaphore;
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+// A response whose body hasn't been read yet; read it from here
+pub struct Response {
+    pub status: u16,
+    body: BufReader<TcpStream>,
+}
+
+impl Read for Response {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.body.read(buf)
+    }
+}
+
+// What one host is held to
+struct Host {
+    connections: Semaphore,
+    // The earliest the next request may start
+    next_start: Mutex<Instant>,
+}
+
+// Keeps requests to each host to a few connections at once and a steady
+// rate, so a slow host only ties up the workers already talking to it and
+// the rest carry on with other hosts
+pub struct HostLimits {
+    max_connections: usize,
+    interval: Duration,
+    hosts: Mutex<HashMap<String, Arc<Host>>>,
+}
+
+impl HostLimits {
+    pub fn new(max_connections: usize, per_second: f64) -> HostLimits {
+        HostLimits {
+            max_connections,
+            interval: Duration::from_secs_f64(1.0 / per_second),
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Waits for a connection to the URL's host and its turn to start, then
+    // runs `request`, which has the connection until it returns
+    pub fn with<T>(&self, url: &str, request: impl FnOnce() -> Result<T, String>) -> Result<T, String> {
+        let (authority, _) = split(url)?;
+        let host = Arc::clone(self.hosts.lock().unwrap().entry(authority.to_string()).or_insert_with(|| {
+            Arc::new(Host { connections: Semaphore::new(self.max_connections), next_start: Mutex::new(Instant::now()) })
+        }));
+        let _connection = host.connections.acquire();
+        let start = {
+            let mut next_start = host.next_start.lock().unwrap();
+            let start = (*next_start).max(Instant::now());
+            *next_start = start + self.interval;
+            start
+        };
+        thread::sleep(start.saturating_duration_since(Instant::now()));
+        request()
+    }
+}
+
+// Splits a URL into its host (with any port) and path
+fn split(url: &str) -> Result<(&str, &str), String> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| format!("only http:// URLs are supported: {}", url))?;
+    Ok(match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    })
+}
+
+// Sends the request and reads the response up to its body, which is left
+// to be read from the response as it arrives. With `Connection: close` the
+// server ends the body by hanging up
+pub fn open(url: &str) -> Result<Response, String> {
+    let (authority, path) = split(url)?;
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().map_err(|_| format!("bad port in {}", url))?),
+        None => (authority, 80),
+    };
+
+    let addr = (host, port)
+        .to_socket_addrs()
+        .map_err(|err| format!("can't resolve {}: {}", host, err))?
+        .next()
+        .ok_or_else(|| format!("no address for {}", host))?;
+    let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT).map_err(|err| err.to_string())?;
+    stream.set_read_timeout(Some(TIMEOUT)).map_err(|err| err.to_string())?;
+    stream.set_write_timeout(Some(TIMEOUT)).map_err(|err| err.to_string())?;
+
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: rust-concurrent-processor\r\nConnection: close\r\n\r\n",
+        path, authority
+    );
+    stream.write_all(request.as_bytes()).map_err(|err| err.to_string())?;
+
+    let mut reader = BufReader::new(stream);
+    let mut status_line = Vec::new();
+    reader.read_until(b'\n', &mut status_line).map_err(|err| err.to_string())?;
+    let status_line = String::from_utf8_lossy(&status_line);
+
+    // e.g. "HTTP/1.1 404 Not Found"
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| format!("malformed status line: {}", status_line.trim_end()))?;
+
+    // Skip the headers, which end at an empty line
+    let mut header = Vec::new();
+    loop {
+        header.clear();
+        if reader.read_until(b'\n', &mut header).map_err(|err| err.to_string())? == 0 {
+            return Err("response ended before its headers did".to_string());
+        }
+        if header == b"\r\n" || header == b"\n" {
+            break;
+        }
+    }
+    Ok(Response { status, body: reader })
+}
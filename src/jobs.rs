@@ -0,0 +1,137 @@
+// Turns the project into a small job server: clients connect over TCP
+// and send one task per line as JSON, e.g.
+//   {"type": "compute", "iterations": 1000}
+// The server picks each task's id and answers {"submitted": <id>}, or
+// {"error": "..."} for a line it can't use. Results follow on the same
+// connection as they finish, one JSON line each as with --output json.
+// Once a client shuts down its side, it gets the rest of its results and
+// the connection closes. A task sent with a "correlation_id" gets it back
+// on its reply and its result, and the task can read it from its context
+use crate::json::{self, ToJson, Value};
+use crate::signal;
+use rust_concurrent_processor::{self as rcp, SubmitOptions, TaskResult, ThreadPool};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+// How often to look for Ctrl-C, new clients and finished results
+const POLL: Duration = Duration::from_millis(50);
+
+// Takes tasks on `port` until Ctrl-C, then stops reading and lets each
+// client collect what it still has running before returning. `make` builds
+// a task from a client's JSON and the id picked for it; `on_result` sees
+// every result before it goes back to its client
+pub fn serve<T, M, R>(port: u16, pool: &ThreadPool, make: M, on_result: R) -> io::Result<()>
+where
+    T: rcp::Task + 'static,
+    M: Fn(&Value, u32) -> Result<(T, SubmitOptions), String> + Sync,
+    R: Fn(&TaskResult) + Sync,
+{
+    let listener = TcpListener::bind(("0.0.0.0", port))?;
+    listener.set_nonblocking(true)?;
+    let next_id = AtomicU32::new(1);
+    thread::scope(|scope| {
+        while !signal::interrupted() {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    let (next_id, make, on_result) = (&next_id, &make, &on_result);
+                    scope.spawn(move || {
+                        let _ = connection(stream, pool, next_id, make, on_result);
+                    });
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => thread::sleep(POLL),
+                Err(err) => eprintln!("warning: can't accept a connection: {}", err),
+            }
+        }
+    });
+    Ok(())
+}
+
+fn connection<T, M, R>(stream: TcpStream, pool: &ThreadPool, next_id: &AtomicU32, make: &M, on_result: &R) -> io::Result<()>
+where
+    T: rcp::Task + 'static,
+    M: Fn(&Value, u32) -> Result<(T, SubmitOptions), String>,
+    R: Fn(&TaskResult),
+{
+    // Accepted sockets can inherit the listener's non-blocking mode
+    stream.set_nonblocking(false)?;
+    let mut out = stream.try_clone()?;
+
+    // Read lines on a thread of their own so results can go out while the
+    // client is quiet. The channel disconnects when the client is done
+    let (lines, incoming) = mpsc::channel();
+    let reader = BufReader::new(stream);
+    thread::spawn(move || {
+        for line in reader.lines().map_while(Result::ok) {
+            if lines.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut results = pool.results();
+    let mut open = true;
+    loop {
+        open &= !signal::interrupted();
+        if !open && results.pending() == 0 {
+            return Ok(());
+        }
+        if !open {
+            thread::sleep(POLL);
+        } else {
+            match incoming.recv_timeout(POLL) {
+                Ok(line) if line.trim().is_empty() => {}
+                Ok(line) => {
+                    let id = next_id.fetch_add(1, Ordering::Relaxed);
+                    let reply = match task_from(&line, id, make) {
+                        Ok((task, options, correlation)) => {
+                            results.submit_with(task, options);
+                            tagged(Value::object([("submitted", id.into())]), correlation.as_deref())
+                        }
+                        Err(err) => Value::object([("error", err.into())]),
+                    };
+                    writeln!(out, "{}", reply)?;
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => open = false,
+            }
+        }
+        for result in results.try_iter() {
+            on_result(&result);
+            writeln!(out, "{}", result.to_json())?;
+        }
+    }
+}
+
+// The task on one of a client's lines, and its correlation id if any
+fn task_from<T, M>(line: &str, id: u32, make: &M) -> Result<(T, SubmitOptions, Option<String>), String>
+where
+    M: Fn(&Value, u32) -> Result<(T, SubmitOptions), String>,
+{
+    let value = json::parse(line)?;
+    let (task, options) = make(&value, id)?;
+    let correlation = correlation_id(&value)?;
+    Ok((task, options, correlation))
+}
+
+// The "correlation_id" a client sent with a task, if it sent one
+pub fn correlation_id(value: &Value) -> Result<Option<String>, String> {
+    match value.get("correlation_id") {
+        Some(_) => Ok(Some(value.str_field("correlation_id")?.to_string())),
+        None => Ok(None),
+    }
+}
+
+// Adds the correlation id, if any, to a reply. Results carry their own
+fn tagged(value: Value, correlation: Option<&str>) -> Value {
+    match (value, correlation) {
+        (Value::Object(mut fields), Some(correlation)) => {
+            fields.push(("correlation_id".to_string(), correlation.into()));
+            Value::Object(fields)
+        },
+        (value, _) => value,
+    }
+}
@@ -0,0 +1,80 @@
+// An append-only record of a project run: every task as it's submitted,
+// then every result as it comes back, one JSON object per line. Replaying
+// it tells a resumed run which tasks never finished. Tasks whose results
+// hadn't been written yet run again, so a task may run more than once
+use crate::json::{self, FromJson, ToJson, Value};
+use crate::sink::ResultSink;
+use rust_concurrent_processor::{self as rcp, TaskResult};
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+pub const DEFAULT_PATH: &str = "project.journal";
+
+pub struct Journal {
+    file: File,
+}
+
+impl Journal {
+    // Starts a fresh journal for `tasks`, replacing any earlier one
+    pub fn create<T: ToJson>(path: &Path, tasks: &[T]) -> io::Result<Journal> {
+        let mut journal = Journal { file: File::create(path)? };
+        for task in tasks {
+            journal.append(Value::object([("task", task.to_json())]))?;
+        }
+        Ok(journal)
+    }
+
+    // Reopens an existing journal and returns the tasks that have no
+    // result yet, in the order they were first submitted
+    pub fn resume<T: FromJson + rcp::Task>(path: &Path) -> Result<(Journal, Vec<T>), String> {
+        let text = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        let mut tasks = Vec::new();
+        let mut finished = HashSet::new();
+
+        // A crash can cut the last line short. It's dropped, as if it had
+        // never been written, so new entries don't land on the end of it
+        let lines: Vec<&str> = text.split_inclusive('\n').collect();
+        let mut intact = 0;
+        for (number, line) in lines.iter().enumerate() {
+            let entry = match json::parse(line.trim_end()) {
+                Ok(entry) => entry,
+                Err(_) if number + 1 == lines.len() => break,
+                Err(err) => return Err(format!("line {}: {}", number + 1, err)),
+            };
+            let error = |err: String| format!("line {}: {}", number + 1, err);
+            if let Some(task) = entry.get("task") {
+                tasks.push(T::from_json(task).map_err(error)?);
+            } else if let Some(result) = entry.get("result") {
+                finished.insert(TaskResult::from_json(result).map_err(error)?.id());
+            } else {
+                return Err(error("expected a task or a result".to_string()));
+            }
+            intact += line.len();
+        }
+
+        tasks.retain(|task| !finished.contains(&task.id()));
+        let mut file = OpenOptions::new().append(true).open(path).map_err(|err| err.to_string())?;
+        file.set_len(intact as u64).map_err(|err| err.to_string())?;
+        if !text[..intact].is_empty() && !text[..intact].ends_with('\n') {
+            file.write_all(b"\n").map_err(|err| err.to_string())?;
+        }
+        Ok((Journal { file }, tasks))
+    }
+
+    pub fn record(&mut self, result: &TaskResult) -> io::Result<()> {
+        self.append(Value::object([("result", result.to_json())]))
+    }
+
+    // One write per line so a crash leaves at most one torn line behind
+    fn append(&mut self, entry: Value) -> io::Result<()> {
+        self.file.write_all(format!("{}\n", entry).as_bytes())
+    }
+}
+
+impl ResultSink for Journal {
+    fn record(&mut self, result: &TaskResult) -> io::Result<()> {
+        Journal::record(self, result)
+    }
+}
@@ -0,0 +1,405 @@
+// Just enough JSON to write results and stats for scripts to pick up,
+// and to read tasks and results back in
+use std::fmt::{self, Write};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    // Fields stay in the order they were added
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    pub fn object<'a>(fields: impl IntoIterator<Item = (&'a str, Value)>) -> Value {
+        Value::Object(fields.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+    }
+
+    // Indented two spaces per level, for documents people may read too
+    pub fn pretty(&self) -> String {
+        let mut out = String::new();
+        self.write(&mut out, Some(0)).expect("writing to a String can't fail");
+        out
+    }
+
+    fn write(&self, out: &mut impl Write, indent: Option<usize>) -> fmt::Result {
+        let fields = match self {
+            Value::Null => return out.write_str("null"),
+            Value::Bool(b) => return write!(out, "{}", b),
+            // Whole numbers print without a fraction; JSON has no NaN or infinity
+            Value::Number(n) if !n.is_finite() => return out.write_str("null"),
+            Value::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => return write!(out, "{}", *n as i64),
+            Value::Number(n) => return write!(out, "{}", n),
+            Value::String(s) => return write_string(out, s),
+            Value::Array(items) => return write_array(out, items, indent),
+            Value::Object(fields) => fields,
+        };
+
+        out.write_char('{')?;
+        let inner = indent.map(|level| level + 1);
+        for (i, (key, value)) in fields.iter().enumerate() {
+            if i > 0 {
+                out.write_char(',')?;
+            }
+            if let Some(level) = inner {
+                write!(out, "\n{:width$}", "", width = level * 2)?;
+            }
+            write_string(out, key)?;
+            out.write_str(if indent.is_some() { ": " } else { ":" })?;
+            value.write(out, inner)?;
+        }
+        if let Some(level) = indent
+            && !fields.is_empty()
+        {
+            write!(out, "\n{:width$}", "", width = level * 2)?;
+        }
+        out.write_char('}')
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    // Typed lookups for FromJson impls, with errors that name the field
+    pub fn field(&self, key: &str) -> Result<&Value, String> {
+        self.get(key).ok_or_else(|| format!("missing field '{}'", key))
+    }
+
+    pub fn str_field(&self, key: &str) -> Result<&str, String> {
+        match self.field(key)? {
+            Value::String(s) => Ok(s),
+            _ => Err(format!("'{}' must be a string", key)),
+        }
+    }
+
+    pub fn bool_field(&self, key: &str) -> Result<bool, String> {
+        match self.field(key)? {
+            Value::Bool(b) => Ok(*b),
+            _ => Err(format!("'{}' must be true or false", key)),
+        }
+    }
+
+    pub fn u32_field(&self, key: &str) -> Result<u32, String> {
+        self.field(key)?.as_u32().ok_or_else(|| format!("'{}' must be a whole number", key))
+    }
+
+    pub fn u64_field(&self, key: &str) -> Result<u64, String> {
+        match self.field(key)? {
+            Value::Number(n) if n.fract() == 0.0 && (0.0..=u64::MAX as f64).contains(n) => Ok(*n as u64),
+            _ => Err(format!("'{}' must be a whole number", key)),
+        }
+    }
+
+    pub fn f64_field(&self, key: &str) -> Result<f64, String> {
+        match self.field(key)? {
+            Value::Number(n) => Ok(*n),
+            _ => Err(format!("'{}' must be a number", key)),
+        }
+    }
+
+    pub fn u128_field(&self, key: &str) -> Result<u128, String> {
+        match self.field(key)? {
+            Value::Number(n) if n.fract() == 0.0 && *n >= 0.0 => Ok(*n as u128),
+            _ => Err(format!("'{}' must be a whole number", key)),
+        }
+    }
+
+    pub fn as_u32(&self) -> Option<u32> {
+        match self {
+            Value::Number(n) if n.fract() == 0.0 && (0.0..=u32::MAX as f64).contains(n) => Some(*n as u32),
+            _ => None,
+        }
+    }
+}
+
+// Compact, one line: what JSON-lines output wants
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, None)
+    }
+}
+
+fn write_array(out: &mut impl Write, items: &[Value], indent: Option<usize>) -> fmt::Result {
+    // Arrays of plain values stay on one line even when pretty-printing
+    let nested = items.iter().any(|item| matches!(item, Value::Array(_) | Value::Object(_)));
+    let separator = if indent.is_some() && !nested { ", " } else { "," };
+    let indent = indent.filter(|_| nested);
+    let inner = indent.map(|level| level + 1);
+
+    out.write_char('[')?;
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            out.write_str(separator)?;
+        }
+        if let Some(level) = inner {
+            write!(out, "\n{:width$}", "", width = level * 2)?;
+        }
+        item.write(out, inner)?;
+    }
+    if let Some(level) = indent
+        && !items.is_empty()
+    {
+        write!(out, "\n{:width$}", "", width = level * 2)?;
+    }
+    out.write_char(']')
+}
+
+fn write_string(out: &mut impl Write, s: &str) -> fmt::Result {
+    out.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => out.write_str("\\\"")?,
+            '\\' => out.write_str("\\\\")?,
+            '\n' => out.write_str("\\n")?,
+            '\r' => out.write_str("\\r")?,
+            '\t' => out.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32)?,
+            c => out.write_char(c)?,
+        }
+    }
+    out.write_char('"')
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Value {
+        Value::Bool(b)
+    }
+}
+
+impl From<u32> for Value {
+    fn from(n: u32) -> Value {
+        Value::Number(n.into())
+    }
+}
+
+impl From<u64> for Value {
+    fn from(n: u64) -> Value {
+        Value::Number(n as f64)
+    }
+}
+
+impl From<u128> for Value {
+    fn from(n: u128) -> Value {
+        Value::Number(n as f64)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(n: f64) -> Value {
+        Value::Number(n)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Value {
+        Value::String(s)
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(items: Vec<Value>) -> Value {
+        Value::Array(items)
+    }
+}
+
+// Conversions for types that go in and out of JSON as a whole
+pub trait ToJson {
+    fn to_json(&self) -> Value;
+}
+
+pub trait FromJson: Sized {
+    fn from_json(value: &Value) -> Result<Self, String>;
+}
+
+pub fn parse(text: &str) -> Result<Value, String> {
+    let mut parser = Parser { text, pos: 0 };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.pos < text.len() {
+        return Err(parser.error("unexpected trailing characters"));
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> String {
+        // Byte offsets mean little to people; report line and column
+        let before = &self.text[..self.pos];
+        let line = before.matches('\n').count() + 1;
+        let column = before.rsplit('\n').next().unwrap_or_default().chars().count() + 1;
+        format!("{} at line {}, column {}", message, line, column)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek()
+            && c.is_ascii_whitespace()
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.next() {
+            Some(c) if c == expected => Ok(()),
+            _ => Err(self.error(&format!("expected '{}'", expected))),
+        }
+    }
+
+    fn keyword(&mut self, word: &str, value: Value) -> Result<Value, String> {
+        if self.text[self.pos..].starts_with(word) {
+            self.pos += word.len();
+            Ok(value)
+        } else {
+            Err(self.error("invalid value"))
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some('{') => self.object(),
+            Some('[') => self.array(),
+            Some('"') => self.string().map(Value::String),
+            Some('t') => self.keyword("true", Value::Bool(true)),
+            Some('f') => self.keyword("false", Value::Bool(false)),
+            Some('n') => self.keyword("null", Value::Null),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            Some(_) => Err(self.error("invalid value")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn object(&mut self) -> Result<Value, String> {
+        self.expect('{')?;
+        let mut fields = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.pos += 1;
+            return Ok(Value::Object(fields));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            fields.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => {}
+                Some('}') => return Ok(Value::Object(fields)),
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, String> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.pos += 1;
+            return Ok(Value::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.next() {
+                Some(',') => {}
+                Some(']') => return Ok(Value::Array(items)),
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(s),
+                Some('\\') => {
+                    let escaped = match self.next() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => self.unicode_escape()?,
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    s.push(escaped);
+                }
+                Some(c) => s.push(c),
+                None => return Err(self.error("unterminated string")),
+            }
+        }
+    }
+
+    // After "\\u"; characters outside the BMP come as a surrogate pair
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let high = self.hex4()?;
+        if !(0xD800..0xDC00).contains(&high) {
+            return char::from_u32(high).ok_or_else(|| self.error("invalid \\u escape"));
+        }
+        if !self.text[self.pos..].starts_with("\\u") {
+            return Err(self.error("unpaired surrogate"));
+        }
+        self.pos += 2;
+        let low = self.hex4()?;
+        if !(0xDC00..0xE000).contains(&low) {
+            return Err(self.error("unpaired surrogate"));
+        }
+        char::from_u32(0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00))
+            .ok_or_else(|| self.error("invalid \\u escape"))
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let digits = self.text.get(self.pos..self.pos + 4).ok_or_else(|| self.error("invalid \\u escape"))?;
+        let code = u32::from_str_radix(digits, 16).map_err(|_| self.error("invalid \\u escape"))?;
+        self.pos += 4;
+        Ok(code)
+    }
+
+    fn number(&mut self) -> Result<Value, String> {
+        let start = self.pos;
+        while let Some(c) = self.peek()
+            && (c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E'))
+        {
+            self.pos += 1;
+        }
+        self.text[start..self.pos]
+            .parse()
+            .map(Value::Number)
+            .map_err(|_| self.error("invalid number"))
+    }
+}
@@ -0,0 +1,72 @@
+mod affinity;
+mod batch;
+mod builder;
+mod cancel;
+mod chaos;
+mod circuit;
+mod clock;
+mod dag;
+mod dispatch;
+mod executor;
+mod fork;
+mod handle;
+mod histogram;
+mod hooks;
+mod local;
+mod monitor;
+mod pipeline;
+mod pool;
+mod queue;
+mod rate_limit;
+mod recurring;
+mod registry;
+mod reporter;
+mod results;
+mod retry;
+mod runner;
+mod scope;
+mod semaphore;
+mod simulation;
+mod stats;
+mod subscribe;
+mod sync;
+mod task;
+mod timer;
+mod watchdog;
+mod worker;
+
+pub use batch::BatchHandle;
+pub use builder::ThreadPoolBuilder;
+pub use cancel::CancellationToken;
+pub use chaos::Chaos;
+pub use circuit::{CircuitBreaker, CircuitState};
+pub use clock::{Clock, SystemClock, VirtualClock};
+pub use dag::{CycleError, NodeId, TaskGraph};
+pub use dispatch::{DispatchError, Dispatched};
+pub use executor::{Executor, ThreadPerTask};
+pub use fork::{join, spawn};
+pub use handle::{TaskHandle, WaitError};
+pub use histogram::LatencyHistogram;
+pub use hooks::TaskListener;
+pub use monitor::{Monitor, WorkerState};
+pub use pipeline::{Pipeline, PipelineBuilder};
+pub use pool::{Job, ShutdownMode, SubmitOptions, ThreadPool};
+pub use queue::{
+    BoundedQueue, FairQueue, FifoQueue, JobInfo, Pop, Priority, PriorityQueue, QueueFull,
+    Scheduler, TaskQueue, TryPushError,
+};
+pub use rate_limit::RateLimit;
+pub use recurring::{Cron, CronError, RecurringTask, Schedule};
+pub use registry::TaskStatus;
+pub use reporter::{LiveReport, LiveReporter};
+pub use results::{Results, TryIter};
+pub use retry::RetryPolicy;
+pub use scope::Scope;
+pub use semaphore::{Semaphore, SemaphorePermit};
+pub use simulation::Simulation;
+pub use stats::SystemStats;
+pub use subscribe::{Reduction, ResultFilter, Subscription};
+pub use sync::PoisonPolicy;
+pub use task::{ProgressReporter, Task, TaskContext, TaskError, TaskOutput, TaskResult};
+pub use watchdog::{OnStall, Stall, Watchdog};
+pub use worker::WaitStrategy;
@@ -0,0 +1,141 @@
+use crate::cli::Args;
+use crate::rng::Rng;
+use rust_concurrent_processor::{self as rcp, ShutdownMode, TaskContext, TaskError, TaskOutput, ThreadPool};
+use std::ops::Range;
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const DEFAULT_RATE: f64 = 100.0;
+pub const DEFAULT_DURATION: Duration = Duration::from_secs(10);
+// How long each task works unless --durations says otherwise
+const DEFAULT_WORK: Duration = Duration::from_millis(1);
+const WORKERS: usize = 4;
+
+// When one task was due, started and finished
+struct Sample {
+    due: Instant,
+    started: Instant,
+    finished: Instant,
+}
+
+struct Probe {
+    id: u32,
+    due: Instant,
+    work: Duration,
+    samples: Sender<Sample>,
+}
+
+impl rcp::Task for Probe {
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn execute(&self, ctx: &TaskContext) -> Result<TaskOutput, TaskError> {
+        let started = Instant::now();
+        ctx.sleep(self.work);
+        let _ = self.samples.send(Sample { due: self.due, started, finished: Instant::now() });
+        Ok(TaskOutput::new(format!("Task {} done", self.id)))
+    }
+}
+
+// Submits tasks at a steady --rate for --duration, then reports what the
+// pool kept up with. Waits are measured from when each task was due rather
+// than when it got submitted, so a submitter held up by a full queue
+// doesn't hide the stall (coordinated omission)
+pub fn run(args: &Args) {
+    let rate = args.rate.unwrap_or(DEFAULT_RATE);
+    let duration = args.duration.unwrap_or(DEFAULT_DURATION);
+    let seed = args.seed.unwrap_or_default();
+    let pool = args.configure(ThreadPool::builder().workers(args.workers_or(WORKERS))).build();
+    let (samples_tx, samples) = mpsc::channel();
+
+    println!("Target: {:.1} tasks/s for {:.1}s on {} workers", rate, duration.as_secs_f64(), args.workers_or(WORKERS));
+    let interval = Duration::from_secs_f64(1.0 / rate);
+    let start = Instant::now();
+    let mut submitted = 0;
+    let mut most_behind = Duration::ZERO;
+    loop {
+        // Counted from the start each time so rounding doesn't add up
+        let due = start + interval.mul_f64(submitted as f64);
+        if due >= start + duration {
+            break;
+        }
+        let now = Instant::now();
+        if due > now {
+            thread::sleep(due - now);
+        }
+        most_behind = most_behind.max(now.saturating_duration_since(due));
+        let work = match args.durations {
+            Some(durations) => durations.sample(&mut Rng::for_task(seed, submitted, "duration")),
+            None => DEFAULT_WORK,
+        };
+        pool.submit(Probe { id: submitted, due, work, samples: samples_tx.clone() });
+        submitted += 1;
+    }
+    let submitting = start.elapsed();
+    drop(samples_tx);
+    let stats = pool.shutdown(ShutdownMode::Drain);
+    let elapsed = start.elapsed();
+
+    let samples: Vec<Sample> = samples.into_iter().collect();
+    println!(
+        "Offered: {:.1} tasks/s ({} submitted, the submitter up to {:.2}ms behind)",
+        submitted as f64 / submitting.as_secs_f64(),
+        submitted,
+        ms(most_behind)
+    );
+    println!(
+        "Completed: {} ({} failed or timed out) in {:.2}s",
+        samples.len(),
+        submitted as usize - samples.len(),
+        elapsed.as_secs_f64()
+    );
+    if stats.retries > 0 {
+        println!("Retries: {}", stats.retries);
+    }
+
+    // Spawning threads and filling caches make the first tasks slower, so
+    // with --warm-up those are reported apart and left out of the rest
+    let warm_up = args.warm_up.unwrap_or_default().min(duration);
+    if warm_up.is_zero() {
+        window("Whole run", &samples, start, Duration::ZERO..elapsed);
+        return;
+    }
+    let (warming, steady): (Vec<Sample>, Vec<Sample>) = samples.into_iter().partition(|s| s.due < start + warm_up);
+    window("Warm-up, not counted", &warming, start, Duration::ZERO..warm_up);
+    window("Steady state", &steady, start, warm_up..elapsed);
+}
+
+// The tasks due in one stretch of the run, and how many a second of them
+// finished during it
+fn window(name: &str, samples: &[Sample], start: Instant, span: Range<Duration>) {
+    let finished = samples.iter().filter(|s| span.contains(&(s.finished - start))).count();
+    println!("{} ({:.1}s to {:.1}s):", name, span.start.as_secs_f64(), span.end.as_secs_f64());
+    println!("  Achieved:      {:.1} tasks/s", finished as f64 / (span.end - span.start).as_secs_f64());
+    report("Queue wait", samples.iter().map(|s| s.started.saturating_duration_since(s.due)).collect());
+    report("Service time", samples.iter().map(|s| s.finished - s.started).collect());
+    report("Response time", samples.iter().map(|s| s.finished.saturating_duration_since(s.due)).collect());
+}
+
+fn report(name: &str, mut times: Vec<Duration>) {
+    if times.is_empty() {
+        println!("  {:<14} no tasks completed", format!("{}:", name));
+        return;
+    }
+    times.sort();
+    let percentile = |p: f64| times[((p / 100.0 * times.len() as f64).ceil() as usize).clamp(1, times.len()) - 1];
+    println!(
+        "  {:<14} p50 {:.2}ms, p90 {:.2}ms, p99 {:.2}ms, p99.9 {:.2}ms, max {:.2}ms",
+        format!("{}:", name),
+        ms(percentile(50.0)),
+        ms(percentile(90.0)),
+        ms(percentile(99.0)),
+        ms(percentile(99.9)),
+        ms(times[times.len() - 1])
+    );
+}
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
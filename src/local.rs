@@ -0,0 +1,64 @@
+use std::any::Any;
+use std::fmt;
+use std::sync::{Arc, Mutex, TryLockError};
+
+use crate::fork;
+
+/// A worker's state, made by the pool's [`WorkerInit`] the first time a
+/// task on that worker asks for it.
+pub(crate) type WorkerState = Arc<Mutex<Option<Box<dyn Any + Send>>>>;
+
+/// Makes the state each worker keeps for its tasks, set with
+/// [`ThreadPoolBuilder::worker_init`](crate::ThreadPoolBuilder::worker_init).
+#[derive(Clone)]
+pub(crate) struct WorkerInit(Arc<dyn Fn() -> Box<dyn Any + Send> + Send + Sync>);
+
+impl WorkerInit {
+    pub(crate) fn new<S, F>(init: F) -> Self
+    where
+        S: Send + 'static,
+        F: Fn() -> S + Send + Sync + 'static,
+    {
+        WorkerInit(Arc::new(move || Box::new(init())))
+    }
+
+    fn make(&self) -> Box<dyn Any + Send> {
+        (self.0)()
+    }
+}
+
+impl fmt::Debug for WorkerInit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("WorkerInit")
+    }
+}
+
+/// Runs `f` on the calling worker's state, making it first if need be.
+/// `None` off the pool, or if the pool's state isn't an `S`.
+pub(crate) fn with_state<S, R>(f: impl FnOnce(&mut S) -> R) -> Option<R>
+where
+    S: 'static,
+{
+    let worker = fork::current()?;
+    let init = worker.lane.worker_init.as_ref()?;
+    let mut state = match worker.state.try_lock() {
+        Ok(state) => state,
+        // A task panicked halfway through changing it, so start over.
+        Err(TryLockError::Poisoned(err)) => {
+            let mut state = err.into_inner();
+            *state = None;
+            worker.state.clear_poison();
+            state
+        }
+        // Held by the task waiting on this one further up the stack, which
+        // doesn't let go until this one is done, so this task gets a
+        // state of its own.
+        Err(TryLockError::WouldBlock) => {
+            return init.make().downcast_mut::<S>().map(f);
+        }
+    };
+    state
+        .get_or_insert_with(|| init.make())
+        .downcast_mut::<S>()
+        .map(f)
+}
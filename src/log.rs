@@ -0,0 +1,153 @@
+// Log lines from inside the demos' workers, tagged with the worker, task
+// and task type they came from. --log-level picks how much shows and
+// --log-format whether it's for people or for a log collector. They go to
+// stderr so stdout stays free for results
+use crate::json::Value;
+use rust_concurrent_processor::TaskContext;
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+}
+
+impl Level {
+    pub fn from_name(name: &str) -> Option<Level> {
+        match name {
+            "error" => Some(Level::Error),
+            "warn" => Some(Level::Warn),
+            "info" => Some(Level::Info),
+            "debug" => Some(Level::Debug),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl LogFormat {
+    pub fn from_name(name: &str) -> Option<LogFormat> {
+        match name {
+            "text" => Some(LogFormat::Text),
+            "json" => Some(LogFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+// Set once from the flags before any demo runs
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+static JSON: AtomicBool = AtomicBool::new(false);
+
+pub fn init(level: Level, format: LogFormat) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+    JSON.store(format == LogFormat::Json, Ordering::Relaxed);
+}
+
+// Where a line came from. Anything not known is left off
+#[derive(Clone, Debug, Default)]
+pub struct Tags {
+    worker: Option<usize>,
+    task: Option<u32>,
+    task_type: Option<String>,
+    correlation: Option<String>,
+}
+
+impl Tags {
+    // The task `ctx` belongs to, the worker running it and the correlation
+    // id it was submitted with
+    pub fn of(ctx: &TaskContext) -> Tags {
+        Tags {
+            worker: ctx.worker(),
+            task: Some(ctx.task_id()),
+            task_type: Some(ctx.task_type().to_string()),
+            correlation: ctx.correlation_id().map(String::from),
+        }
+    }
+
+    // A task running outside the pool
+    pub fn task(id: u32, task_type: &str) -> Tags {
+        Tags { worker: None, task: Some(id), task_type: Some(task_type.to_string()), correlation: None }
+    }
+
+    pub fn worker(mut self, worker: usize) -> Tags {
+        self.worker = Some(worker);
+        self
+    }
+}
+
+pub fn warn(tags: &Tags, message: impl Display) {
+    log(Level::Warn, tags, message);
+}
+
+pub fn info(tags: &Tags, message: impl Display) {
+    log(Level::Info, tags, message);
+}
+
+pub fn debug(tags: &Tags, message: impl Display) {
+    log(Level::Debug, tags, message);
+}
+
+fn log(level: Level, tags: &Tags, message: impl Display) {
+    if level as u8 > LEVEL.load(Ordering::Relaxed) {
+        return;
+    }
+    if JSON.load(Ordering::Relaxed) {
+        let mut fields = vec![("level", level.name().into())];
+        if let Some(worker) = tags.worker {
+            fields.push(("worker", (worker as u64).into()));
+        }
+        if let Some(task) = tags.task {
+            fields.push(("task", task.into()));
+        }
+        if let Some(task_type) = &tags.task_type {
+            fields.push(("task_type", task_type.as_str().into()));
+        }
+        if let Some(correlation) = &tags.correlation {
+            fields.push(("correlation_id", correlation.as_str().into()));
+        }
+        fields.push(("message", message.to_string().into()));
+        write_line(format!("{}\n", Value::object(fields)));
+        return;
+    }
+    let mut line = format!("{:<5}", level.name().to_uppercase());
+    if let Some(worker) = tags.worker {
+        line += &format!(" worker={}", worker);
+    }
+    if let Some(task) = tags.task {
+        line += &format!(" task={}", task);
+    }
+    if let Some(task_type) = &tags.task_type {
+        line += &format!(" type={}", task_type);
+    }
+    if let Some(correlation) = &tags.correlation {
+        line += &format!(" correlation={}", correlation);
+    }
+    write_line(format!("{} {}\n", line, message));
+}
+
+// In a single write, so lines from different workers can't interleave the
+// way eprintln!'s piecemeal writes let them
+fn write_line(line: String) {
+    let _ = io::stderr().lock().write_all(line.as_bytes());
+}
@@ -1,22 +1,154 @@
+mod bench;
+mod cache;
+mod cli;
+mod config;
+mod console;
+mod control;
+mod dashboard;
+#[cfg(feature = "http")]
+mod http;
+mod jobs;
+mod journal;
+mod json;
+mod loadtest;
+mod log;
+mod metrics;
+mod outcome;
 mod part1;
 mod part2a;
 mod part2b;
 mod part3;
+mod part4;
 mod project;
+mod rng;
+mod runtime;
+mod server;
+mod sha256;
+mod signal;
+mod sink;
+mod status;
+mod summary;
+mod throttle;
+mod timeline;
+mod workload;
+
+use cli::{Args, Demo};
+use outcome::{Comparison, RunSummary};
+use std::path::Path;
+use std::{env, process};
 
 fn main() {
-    println!("===Part 1: Basic Threads===");
-    part1::run();
+    let mut args = match Args::parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, cli::USAGE);
+            process::exit(2);
+        }
+    };
+    if args.help {
+        println!("{}", cli::USAGE);
+        return;
+    }
+
+    // An explicit --config has to exist; the default file is optional
+    let config_path = args.config.clone();
+    let path = config_path.as_deref().unwrap_or(Path::new(config::DEFAULT_PATH));
+    if (config_path.is_some() || path.exists())
+        && let Err(err) = config::apply(&mut args, path)
+    {
+        eprintln!("error: {}: {}", path.display(), err);
+        process::exit(2);
+    }
+    console::init(args.verbosity());
+    log::init(args.log_level(), args.log_format.unwrap_or_default());
+
+    // A load test runs on its own, instead of the demos
+    if args.load_test {
+        println!("===Load test===");
+        loadtest::run(&args);
+        return;
+    }
+
+    // The other demos still run when the project fails, and the exit
+    // status says so at the end
+    let mut too_many_failures = false;
+    // Read up front so a bad path doesn't cost a whole run
+    let baseline = args.baseline.as_ref().filter(|_| args.runs(Demo::Project)).map(|path| {
+        RunSummary::load(path).unwrap_or_else(|err| {
+            eprintln!("error: {}: {}", path.display(), err);
+            process::exit(2);
+        })
+    });
+
+    if args.runs(Demo::Part1) {
+        println!("===Part 1: Basic Threads===");
+        part1::run(&args);
+    }
+
+    if args.runs(Demo::Part2a) {
+        println!("===Part 2a: Message Passing (naive)===");
+        part2a::run(&args);
+    }
+
+    if args.runs(Demo::Part2b) {
+        println!("===Part 2a: Message Passing (thread pool)===");
+        part2b::run(&args);
+    }
+
+    if args.runs(Demo::Part3) {
+        println!("===Part 3: Shared Counter===");
+        part3::run(&args);
+    }
+
+    if args.runs(Demo::Part4) {
+        println!("===Part 4: Async Tasks===");
+        part4::run(&args);
+    }
+
+    if args.runs(Demo::Project) {
+        // Keep stdout parseable when it's JSON
+        if !args.json() {
+            println!("===Project===");
+        }
+        let summary = project::run(&args);
+        if signal::interrupted() {
+            return;
+        }
+        too_many_failures = args.max_failures.is_some_and(|limit| summary.fails(limit));
+        if let (Some(baseline), Some(path)) = (&baseline, &args.baseline) {
+            let comparison = Comparison::new(baseline, &summary);
+            // Stdout only has room for the stats when it's JSON
+            if args.json() {
+                eprintln!("{}", comparison);
+            } else {
+                println!("\n=== Compared with {} ===\n{}", path.display(), comparison);
+            }
+        }
+    }
+
+    if args.runs(Demo::Pipeline) {
+        println!("===Project (pipeline)===");
+        project::run_pipeline(&args);
+    }
+
+    if args.runs(Demo::Bench) {
+        println!("===Scheduler comparison===");
+        bench::run(&args);
+
+        println!("===Shortest job first===");
+        bench::run_sjf(&args);
 
-    println!("===Part 2a: Message Passing (naive)===");
-    part2a::run();
+        println!("===Fair queuing===");
+        bench::run_fair(&args);
 
-    println!("===Part 2a: Message Passing (thread pool)===");
-    part2b::run();
+        println!("===Stats contention===");
+        bench::run_stats(&args);
 
-    println!("===Part 3: Shared Counter===");
-    part3::run();
+        println!("===Pool overhead===");
+        bench::run_overhead(&args);
+    }
 
-    println!("===Project===");
-    project::run();
-}
\ No newline at end of file
+    if too_many_failures {
+        process::exit(outcome::TOO_MANY_FAILURES);
+    }
+}
@@ -0,0 +1,73 @@
+// Serves the pool's latest numbers at http://<host>:<port>/metrics in
+// Prometheus' text format
+use crate::server::{self, Response};
+use rust_concurrent_processor::{LatencyHistogram, SystemStats};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+// Upper bounds of the histograms' buckets, in seconds
+const BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+#[derive(Default)]
+struct Snapshot {
+    queued: usize,
+    stats: SystemStats,
+}
+
+// Holds what the next scrape sees. Clones share it
+#[derive(Clone, Default)]
+pub struct Metrics {
+    latest: Arc<Mutex<Snapshot>>,
+}
+
+impl Metrics {
+    pub fn update(&self, queued: usize, stats: SystemStats) {
+        *self.latest.lock().unwrap() = Snapshot { queued, stats };
+    }
+
+    fn render(&self) -> String {
+        let latest = self.latest.lock().unwrap();
+        let stats = &latest.stats;
+        let mut out = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, value: &dyn std::fmt::Display| {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}\n{} {}", name, help, name, kind, name, value);
+        };
+        metric("tasks_completed_total", "counter", "Tasks that succeeded", &stats.tasks_completed);
+        metric("tasks_failed_total", "counter", "Tasks that failed with an error", &stats.tasks_failed);
+        metric("tasks_timed_out_total", "counter", "Tasks that ran past their timeout", &stats.tasks_timed_out);
+        metric("task_retries_total", "counter", "Extra attempts made after failures", &stats.retries);
+        metric("queue_depth", "gauge", "Tasks waiting for a worker", &latest.queued);
+        metric("active_workers", "gauge", "Worker threads alive", &stats.active_workers);
+
+        histogram(&mut out, "task_duration_seconds", "How long successful tasks ran, not counting their queue wait", &stats.latency);
+        histogram(&mut out, "task_queue_wait_seconds", "How long tasks waited for a worker", &stats.queue_wait);
+        out
+    }
+}
+
+// One histogram per task type, under the same name
+fn histogram(out: &mut String, name: &str, help: &str, histograms: &BTreeMap<String, LatencyHistogram>) {
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} histogram", name, help, name);
+    for (task_type, histogram) in histograms {
+        for bound in BUCKETS {
+            let within = histogram.count_within_ms((bound * 1000.0) as u64);
+            let _ = writeln!(out, "{}_bucket{{task_type=\"{}\",le=\"{}\"}} {}", name, task_type, bound, within);
+        }
+        let _ = writeln!(out, "{}_bucket{{task_type=\"{}\",le=\"+Inf\"}} {}", name, task_type, histogram.count());
+        let _ = writeln!(out, "{}_sum{{task_type=\"{}\"}} {}", name, task_type, histogram.sum_ms() as f64 / 1000.0);
+        let _ = writeln!(out, "{}_count{{task_type=\"{}\"}} {}", name, task_type, histogram.count());
+    }
+}
+
+// Starts answering scrapes on `port`
+pub fn serve(port: u16) -> io::Result<Metrics> {
+    let metrics = Metrics::default();
+    let served = metrics.clone();
+    server::serve(port, move |path| match path {
+        "/metrics" => Response::ok("text/plain; version=0.0.4", served.render()),
+        _ => Response::not_found("Not found; try /metrics"),
+    })?;
+    Ok(metrics)
+}
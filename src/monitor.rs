@@ -0,0 +1,75 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::pool::{Shared, ThreadPool};
+use crate::queue::TaskQueue;
+use crate::registry::TaskStatus;
+use crate::stats::SystemStats;
+
+/// What one worker thread is doing, from [`Monitor::workers`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WorkerState {
+    /// The worker's id, as in [`TaskStatus::Running`].
+    pub worker: usize,
+    /// The task it's running, or `None` if it's idle or running a closure.
+    pub task_id: Option<u32>,
+    /// How long it has been on its current job, or `None` if it's idle.
+    pub busy_for: Option<Duration>,
+}
+
+/// A read-only view of a [`ThreadPool`], returned by
+/// [`ThreadPool::monitor`]. It can be cloned and handed to other threads,
+/// such as one serving status pages, and stays usable after the pool has
+/// shut down, showing things as they were left.
+#[derive(Clone)]
+pub struct Monitor {
+    /// The general queue first, then the dedicated ones.
+    lanes: Vec<Arc<Shared>>,
+}
+
+impl Monitor {
+    /// See [`ThreadPool::stats`].
+    pub fn stats(&self) -> SystemStats {
+        self.lanes[0].snapshot()
+    }
+
+    /// See [`ThreadPool::queued`].
+    pub fn queued(&self) -> usize {
+        self.lanes.iter().map(|lane| lane.queue.len()).sum()
+    }
+
+    /// See [`ThreadPool::status`].
+    pub fn status(&self, task_id: u32) -> Option<TaskStatus> {
+        self.lanes[0].registry.status(task_id)
+    }
+
+    /// See [`ThreadPool::progress`].
+    pub fn progress(&self, task_id: u32) -> Option<f64> {
+        self.lanes[0].registry.progress_of(task_id)
+    }
+
+    /// See [`ThreadPool::in_flight`].
+    pub fn in_flight(&self) -> Vec<(u32, TaskStatus)> {
+        self.lanes[0].registry.in_flight()
+    }
+
+    /// Every live worker and what it's doing, by worker id.
+    pub fn workers(&self) -> Vec<WorkerState> {
+        let mut workers: Vec<_> = self
+            .lanes
+            .iter()
+            .flat_map(|lane| lane.heartbeats.states())
+            .collect();
+        workers.sort_unstable_by_key(|state| state.worker);
+        workers
+    }
+}
+
+impl ThreadPool {
+    /// A handle for keeping an eye on the pool from elsewhere.
+    pub fn monitor(&self) -> Monitor {
+        Monitor {
+            lanes: self.lanes().cloned().collect(),
+        }
+    }
+}
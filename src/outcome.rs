@@ -0,0 +1,263 @@
+// What a project run came to, for CI jobs to gate on: written as JSON to
+// --summary-out, held against --max-failures to pick the exit status, and
+// compared with an earlier run's summary given with --baseline
+use crate::json::{self, FromJson, ToJson, Value};
+use rust_concurrent_processor::{LatencyHistogram, SystemStats};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+// Exit status when more tasks failed than --max-failures allows
+pub const TOO_MANY_FAILURES: i32 = 3;
+
+// How many failed tasks a run may have and still pass: a count, or a
+// percentage of the tasks that finished
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FailureLimit {
+    Count(u32),
+    Fraction(f64),
+}
+
+impl FailureLimit {
+    // 5 or 2.5%
+    pub fn parse(spec: &str) -> Option<FailureLimit> {
+        match spec.strip_suffix('%') {
+            Some(percent) => {
+                let percent: f64 = percent.parse().ok()?;
+                (0.0..=100.0).contains(&percent).then(|| FailureLimit::Fraction(percent / 100.0))
+            },
+            None => spec.parse().ok().map(FailureLimit::Count),
+        }
+    }
+
+    fn exceeded_by(self, failed: u32, finished: u32) -> bool {
+        match self {
+            FailureLimit::Count(limit) => failed > limit,
+            FailureLimit::Fraction(limit) => finished > 0 && failed as f64 / finished as f64 > limit,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Percentiles {
+    pub count: u64,
+    pub p50_ms: u64,
+    pub p95_ms: u64,
+    pub p99_ms: u64,
+    pub max_ms: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RunSummary {
+    pub completed: u32,
+    // Failed or timed out, the tasks --max-failures counts
+    pub failed: u32,
+    pub cancelled: u32,
+    pub skipped: u32,
+    pub expired: u32,
+    pub discarded: u32,
+    pub rejected: u32,
+    pub retries: u32,
+    pub duration_ms: u128,
+    // Tasks finished a second, however they finished
+    pub throughput: f64,
+    // Service time and queue wait by task type
+    pub latency: BTreeMap<String, Percentiles>,
+    pub queue_wait: BTreeMap<String, Percentiles>,
+}
+
+impl RunSummary {
+    pub fn new(stats: &SystemStats, elapsed: Duration) -> RunSummary {
+        let percentiles = |histograms: &BTreeMap<String, LatencyHistogram>| {
+            histograms
+                .iter()
+                .map(|(task_type, histogram)| {
+                    let percentiles = Percentiles {
+                        count: histogram.count(),
+                        p50_ms: histogram.percentile_ms(50.0),
+                        p95_ms: histogram.percentile_ms(95.0),
+                        p99_ms: histogram.percentile_ms(99.0),
+                        max_ms: histogram.max_ms(),
+                    };
+                    (task_type.clone(), percentiles)
+                })
+                .collect()
+        };
+        let mut summary = RunSummary {
+            completed: stats.tasks_completed,
+            failed: stats.tasks_failed + stats.tasks_timed_out,
+            cancelled: stats.tasks_cancelled,
+            skipped: stats.tasks_skipped,
+            expired: stats.tasks_expired,
+            discarded: stats.tasks_discarded,
+            rejected: stats.tasks_rejected,
+            retries: stats.retries,
+            duration_ms: elapsed.as_millis(),
+            throughput: 0.0,
+            latency: percentiles(&stats.latency),
+            queue_wait: percentiles(&stats.queue_wait),
+        };
+        summary.throughput = summary.finished() as f64 / elapsed.as_secs_f64().max(0.001);
+        summary
+    }
+
+    fn finished(&self) -> u32 {
+        self.completed + self.failed + self.cancelled + self.skipped + self.expired + self.discarded + self.rejected
+    }
+
+    pub fn fails(&self, limit: FailureLimit) -> bool {
+        limit.exceeded_by(self.failed, self.finished())
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_json().pretty() + "\n")
+    }
+
+    // A summary an earlier run wrote with --summary-out
+    pub fn load(path: &Path) -> Result<RunSummary, String> {
+        let text = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        RunSummary::from_json(&json::parse(&text)?)
+    }
+}
+
+// How far a number may move from the baseline before it counts as better
+// or worse, so the usual run-to-run jitter isn't reported as a change.
+// Latencies also have to move by a few milliseconds, as a 2ms task taking
+// 3ms is 50% slower but nothing to worry about
+const TOLERANCE: f64 = 0.10;
+const LATENCY_SLACK_MS: u64 = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Change {
+    Improved,
+    Regressed,
+    Unchanged,
+}
+
+// This run next to a baseline: throughput, then each task type's service
+// time percentiles. Task types only one of the runs had are left out
+pub struct Comparison {
+    lines: Vec<(String, Change)>,
+}
+
+impl Comparison {
+    pub fn new(baseline: &RunSummary, current: &RunSummary) -> Comparison {
+        let mut lines = Vec::new();
+        let change = (current.throughput - baseline.throughput) / baseline.throughput.max(f64::EPSILON);
+        let verdict = if change > TOLERANCE {
+            Change::Improved
+        } else if change < -TOLERANCE {
+            Change::Regressed
+        } else {
+            Change::Unchanged
+        };
+        let line = format!("Throughput: {:.1} tasks/s, was {:.1} ({:+.1}%)", current.throughput, baseline.throughput, change * 100.0);
+        lines.push((line, verdict));
+        for (task_type, now) in &current.latency {
+            let Some(was) = baseline.latency.get(task_type) else {
+                continue;
+            };
+            let percentiles = [("p50", now.p50_ms, was.p50_ms), ("p95", now.p95_ms, was.p95_ms), ("p99", now.p99_ms, was.p99_ms)];
+            for (name, now, was) in percentiles {
+                let verdict = if now > was + LATENCY_SLACK_MS && now as f64 > was as f64 * (1.0 + TOLERANCE) {
+                    Change::Regressed
+                } else if now + LATENCY_SLACK_MS < was && (now as f64) < was as f64 * (1.0 - TOLERANCE) {
+                    Change::Improved
+                } else {
+                    Change::Unchanged
+                };
+                lines.push((format!("{} {}: {}ms, was {}ms", task_type, name, now, was), verdict));
+            }
+        }
+        Comparison { lines }
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (line, change) in &self.lines {
+            match change {
+                Change::Improved => writeln!(f, "{} - improved", line)?,
+                Change::Regressed => writeln!(f, "{} - regressed", line)?,
+                Change::Unchanged => writeln!(f, "{}", line)?,
+            }
+        }
+        let count = |wanted: Change| self.lines.iter().filter(|(_, change)| *change == wanted).count();
+        write!(f, "{} regressed, {} improved", count(Change::Regressed), count(Change::Improved))
+    }
+}
+
+impl ToJson for Percentiles {
+    fn to_json(&self) -> Value {
+        Value::object([
+            ("count", self.count.into()),
+            ("p50_ms", self.p50_ms.into()),
+            ("p95_ms", self.p95_ms.into()),
+            ("p99_ms", self.p99_ms.into()),
+            ("max_ms", self.max_ms.into()),
+        ])
+    }
+}
+
+impl ToJson for RunSummary {
+    fn to_json(&self) -> Value {
+        let by_type = |percentiles: &BTreeMap<String, Percentiles>| {
+            Value::object(percentiles.iter().map(|(task_type, percentiles)| (task_type.as_str(), percentiles.to_json())))
+        };
+        Value::object([
+            ("completed", self.completed.into()),
+            ("failed", self.failed.into()),
+            ("cancelled", self.cancelled.into()),
+            ("skipped", self.skipped.into()),
+            ("expired", self.expired.into()),
+            ("discarded", self.discarded.into()),
+            ("rejected", self.rejected.into()),
+            ("retries", self.retries.into()),
+            ("duration_ms", self.duration_ms.into()),
+            ("throughput", self.throughput.into()),
+            ("latency", by_type(&self.latency)),
+            ("queue_wait", by_type(&self.queue_wait)),
+        ])
+    }
+}
+
+impl FromJson for Percentiles {
+    fn from_json(value: &Value) -> Result<Percentiles, String> {
+        Ok(Percentiles {
+            count: value.u64_field("count")?,
+            p50_ms: value.u64_field("p50_ms")?,
+            p95_ms: value.u64_field("p95_ms")?,
+            p99_ms: value.u64_field("p99_ms")?,
+            max_ms: value.u64_field("max_ms")?,
+        })
+    }
+}
+
+impl FromJson for RunSummary {
+    fn from_json(value: &Value) -> Result<RunSummary, String> {
+        let by_type = |key: &str| match value.field(key)? {
+            Value::Object(fields) => fields
+                .iter()
+                .map(|(task_type, percentiles)| Ok((task_type.clone(), Percentiles::from_json(percentiles)?)))
+                .collect(),
+            _ => Err(format!("'{}' must be an object", key)),
+        };
+        Ok(RunSummary {
+            completed: value.u32_field("completed")?,
+            failed: value.u32_field("failed")?,
+            cancelled: value.u32_field("cancelled")?,
+            skipped: value.u32_field("skipped")?,
+            expired: value.u32_field("expired")?,
+            discarded: value.u32_field("discarded")?,
+            rejected: value.u32_field("rejected")?,
+            retries: value.u32_field("retries")?,
+            duration_ms: value.u128_field("duration_ms")?,
+            throughput: value.f64_field("throughput")?,
+            latency: by_type("latency")?,
+            queue_wait: by_type("queue_wait")?,
+        })
+    }
+}
@@ -1,3 +1,5 @@
+use crate::cli::Args;
+use crate::log::{self, Tags};
 use std::thread;
 use std::time::Duration;
 
@@ -6,12 +8,15 @@ struct Task {
     work_duration: u64,
 }
 
-pub fn run() {
-    let tasks = vec![
-        Task { id: 1, work_duration: 100 },
-        Task { id: 2, work_duration: 200 },
-        Task { id: 3, work_duration: 150 }, 
-    ];
+pub fn run(args: &Args) {
+    let tasks = match args.tasks {
+        Some(count) => (1..=count).map(|id| Task { id, work_duration: args.work_duration(id) }).collect(),
+        None => vec![
+            Task { id: 1, work_duration: 100 },
+            Task { id: 2, work_duration: 200 },
+            Task { id: 3, work_duration: 150 }, 
+        ],
+    };
 
     let mut handles = vec![];
 
@@ -29,7 +34,8 @@ pub fn run() {
 }
 
 fn process_task(task: Task) {
-    println!("Starting task {}", task.id);
+    let tags = Tags::task(task.id, "task");
+    log::info(&tags, format_args!("Starting task {}", task.id));
     thread::sleep(Duration::from_millis(task.work_duration));
-    println!("Completed task {}", task.id);
+    log::info(&tags, format_args!("Completed task {}", task.id));
 }
\ No newline at end of file
@@ -1,3 +1,6 @@
+use crate::cli::Args;
+use crate::console::{self, Mark};
+use crate::log::{self, Tags};
 use std::sync::mpsc;
 use std::thread;
 use std::time::Duration;
@@ -7,20 +10,24 @@ struct Task {
     work_duration: u64,
 }
 
+// Only the line to print goes along; nothing reads an id off a result
 enum TaskResult {
-    Success { id: u32, result: String },
-    Error { id: u32, error: String },
+    Success { result: String },
+    Error { error: String },
 }
 
-pub fn run() {
+pub fn run(args: &Args) {
     let (tx, rx) = mpsc::channel();
 
-    let tasks = vec![
-        Task { id: 1, work_duration: 100 },
-        Task { id: 2, work_duration: 200 },
-        Task { id: 3, work_duration: 150 },
-        Task { id: 4, work_duration: 50 },
-    ];
+    let tasks = match args.tasks {
+        Some(count) => (1..=count).map(|id| Task { id, work_duration: args.work_duration(id) }).collect(),
+        None => vec![
+            Task { id: 1, work_duration: 100 },
+            Task { id: 2, work_duration: 200 },
+            Task { id: 3, work_duration: 150 },
+            Task { id: 4, work_duration: 50 },
+        ],
+    };
 
     // TODO: Spawn worker threads that process tasks
     // TODO: Each worker sends TaskResult through the channel
@@ -28,8 +35,9 @@ pub fn run() {
 
     for t in tasks {
         let tx = tx.clone();
+        let fails = args.should_fail("task", t.id, 5);
         thread::spawn(move || {
-            let r = process_task(t);
+            let r = process_task(t, fails);
             tx.send(r).unwrap();
         });
     }
@@ -37,12 +45,15 @@ pub fn run() {
     drop(tx);
 
     for r in rx {
+        if console::quiet() {
+            continue;
+        }
         match r {
-            TaskResult::Success {result, ..} => {
-                println!("{result}");
+            TaskResult::Success {result} => {
+                println!("{}", console::line(Mark::Success, result));
             },
-            TaskResult::Error {error, ..} => {
-                println!("{error}");
+            TaskResult::Error {error} => {
+                println!("{}", console::line(Mark::Failure, error));
             }
         }
     }
@@ -50,19 +61,17 @@ pub fn run() {
     // Hint: Clone tx for each thread, or pass ownership carefully
 }
 
-fn process_task(task: Task) -> TaskResult {
-    println!("Processing task {}", task.id);
+fn process_task(task: Task, fails: bool) -> TaskResult {
+    log::info(&Tags::task(task.id, "task"), format_args!("Processing task {}", task.id));
     thread::sleep(Duration::from_millis(task.work_duration));
 
     // Simulate occasional failures
-    if task.id % 5 == 0 {
+    if fails {
         TaskResult::Error {
-            id: task.id,
             error: "Task failed".to_string(),
         }
     } else {
         TaskResult::Success {
-            id: task.id,
             result: format!("Task {} completed", task.id),
         }
     }
@@ -1,94 +1,77 @@
-use std::sync::{mpsc, Arc, Mutex};
+use rust_concurrent_processor::{
+    self as rcp, ShutdownMode, TaskContext, TaskError, TaskOutput, ThreadPool,
+};
+use crate::cli::Args;
+use crate::console;
+use crate::log::{self, Tags};
+use crate::project::GANTT_WIDTH;
+use crate::summary::Summary;
+use crate::timeline::Timeline;
+use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 struct Task {
     id: u32,
     work_duration: u64,
+    fails: bool,
 }
 
-enum TaskResult {
-    Success { id: u32, result: String },
-    Error { id: u32, error: String },
-}
-
-pub fn run() {
-    // we need worker threads to be able to receive tasks
-    let (task_tx, task_rx) = mpsc::channel();
-    let (result_tx, result_rx) = mpsc::channel();
-    
-    let task_rx = Arc::new(Mutex::new(task_rx));
+impl rcp::Task for Task {
+    fn id(&self) -> u32 {
+        self.id
+    }
 
-    let tasks = vec![
-        Task { id: 1, work_duration: 100 },
-        Task { id: 2, work_duration: 200 },
-        Task { id: 3, work_duration: 150 },
-        Task { id: 4, work_duration: 50 },
-        Task { id: 5, work_duration: 180 },
-        Task { id: 6, work_duration: 90 },
-        Task { id: 7, work_duration: 220 },
-        Task { id: 8, work_duration: 130 },
-        Task { id: 9, work_duration: 170 },
-        Task { id: 10, work_duration: 60 },
-    ];
+    // Lets --scheduler sjf run the quick ones first
+    fn cost(&self) -> Option<Duration> {
+        Some(Duration::from_millis(self.work_duration))
+    }
 
-    // TODO: Spawn worker threads that process tasks
-    // TODO: Each worker sends TaskResult through the channel
-    // TODO: Main thread receives and prints results
+    fn execute(&self, ctx: &TaskContext) -> Result<TaskOutput, TaskError> {
+        log::info(&Tags::of(ctx), format_args!("Processing task {}", self.id));
+        thread::sleep(Duration::from_millis(self.work_duration));
 
-    for i in 0..3 {
-        let task_rx = Arc::clone(&task_rx);
-        let result_tx = result_tx.clone();
-        thread::spawn(move || {
-            loop {
-                let task = task_rx.lock().unwrap().recv();
-                match task {
-                    Ok(task) => {
-                        let result = process_task(task);
-                        result_tx.send(result).unwrap();
-                    },
-                    Err(_) => {
-                        break;
-                    }
-                }
-            }
-        });
+        // Simulate occasional failures
+        if self.fails {
+            Err("Task failed".into())
+        } else {
+            Ok(format!("Task {} completed", self.id).into())
+        }
     }
+}
 
-    for t in tasks {
-        task_tx.send(t).unwrap();
+pub fn run(args: &Args) {
+    // Only a handful of tasks may wait at once; submit() blocks past that
+    let mut builder = ThreadPool::builder().workers(args.workers_or(3)).queue_capacity(4);
+    let timeline = args.gantt.then(|| Arc::new(Timeline::new()));
+    if let Some(timeline) = &timeline {
+        builder = builder.listener(Arc::clone(timeline));
     }
-    drop(task_tx);
-    drop(result_tx);
+    let pool = args.configure(builder).build();
 
-    for result in result_rx {
-        match result {
-            TaskResult::Success {id, result} => {
-                println!("{result}");
-            },
-            TaskResult::Error{id, error} => {
-                println!("{error}");
-            }
-        }
-    }
+    let durations = match args.tasks {
+        Some(count) => (1..=count).map(|id| (id, args.work_duration(id))).collect(),
+        None => vec![(1, 100), (2, 200), (3, 150), (4, 50), (5, 180), (6, 90), (7, 220), (8, 130), (9, 170), (10, 60)],
+    };
+    let tasks = durations
+        .into_iter()
+        .map(|(id, work_duration)| Task { id, work_duration, fails: args.should_fail("task", id, 5) });
 
-    // Hint: Clone tx for each thread, or pass ownership carefully
-}
+    // Printed in submission order however the tasks finish, and tallied
+    // on the side as they come in
+    let summary = pool.reduce(Summary::default(), |summary, result| summary.add(&result));
+    let started = Instant::now();
+    let mut results = pool.ordered_results();
+    tasks.for_each(|t| results.submit(t));
 
-fn process_task(task: Task) -> TaskResult {
-    println!("Processing task {}", task.id);
-    thread::sleep(Duration::from_millis(task.work_duration));
+    for result in results {
+        console::result(&result);
+    }
+    let elapsed = started.elapsed();
 
-    // Simulate occasional failures
-    if task.id % 5 == 0 {
-        TaskResult::Error {
-            id: task.id,
-            error: "Task failed".to_string(),
-        }
-    } else {
-        TaskResult::Success {
-            id: task.id,
-            result: format!("Task {} completed", task.id),
-        }
+    pool.shutdown(ShutdownMode::Drain);
+    summary.wait().print(elapsed);
+    if let Some(timeline) = timeline {
+        print!("{}", timeline.gantt(GANTT_WIDTH));
     }
 }
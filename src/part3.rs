@@ -1,4 +1,7 @@
-use std::sync::{Arc, Mutex};
+use crate::cli::Args;
+use crate::log::{self, Tags};
+use rust_concurrent_processor::ThreadPool;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 use std::thread;
 use std::time::Duration;
 
@@ -7,8 +10,17 @@ struct Task {
     work_duration: u64,
 }
 
-#[derive(Debug)]
+// Each counter is its own atomic, so threads never wait on each other to
+// record a result
+#[derive(Debug, Default)]
 struct Stats {
+    completed: AtomicU32,
+    failed: AtomicU32,
+    total_time_ms: AtomicU64,
+}
+
+#[derive(Debug)]
+struct StatsSnapshot {
     completed: u32,
     failed: u32,
     total_time_ms: u64,
@@ -16,67 +28,68 @@ struct Stats {
 
 impl Stats {
     fn new() -> Self {
-        Stats {
-            completed: 0,
-            failed: 0,
-            total_time_ms: 0,
+        Stats::default()
+    }
+
+    fn snapshot(&self) -> StatsSnapshot {
+        StatsSnapshot {
+            completed: self.completed.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            total_time_ms: self.total_time_ms.load(Ordering::Relaxed),
         }
     }
 }
 
-pub fn run() {
-    let stats = Arc::new(Mutex::new(Stats::new()));
+pub fn run(args: &Args) {
+    let stats = Stats::new();
 
-    let tasks = vec![
-        Task { id: 1, work_duration: 100 },
-        Task { id: 2, work_duration: 200 },
-        Task { id: 3, work_duration: 150 },
-        Task { id: 4, work_duration: 80 },
-        Task { id: 5, work_duration: 120 },
-    ];
+    let tasks = match args.tasks {
+        Some(count) => (1..=count).map(|id| Task { id, work_duration: args.work_duration(id) }).collect(),
+        None => vec![
+            Task { id: 1, work_duration: 100 },
+            Task { id: 2, work_duration: 200 },
+            Task { id: 3, work_duration: 150 },
+            Task { id: 4, work_duration: 80 },
+            Task { id: 5, work_duration: 120 },
+        ],
+    };
 
     // TODO: Spawn threads that share the stats
     // TODO: Each thread updates stats after processing
     // TODO: Print final statistics
 
-    // Hint: Clone the Arc for each thread
-
-    let mut handles = vec![];
-
-    for task in tasks {
-        let stats = Arc::clone(&stats);
-        let handle = thread::spawn(move || {
-            process_task(task, stats);
-        });
-        handles.push(handle);
-    }
-
-    for handle in handles {
-        handle.join().unwrap();
-    }
+    // Scoped tasks borrow the stats and tasks straight off the stack, and
+    // the scope only returns once every one of them has finished
+    let pool = ThreadPool::new(args.workers_or(tasks.len().max(1)));
+    pool.scope(|s| {
+        for task in &tasks {
+            let fails = args.should_fail("task", task.id, 5);
+            let stats = &stats;
+            s.spawn(move || process_task(task, fails, stats));
+        }
+    });
 
-    let final_stats = stats.lock().unwrap();
+    let final_stats = stats.snapshot();
     println!("Final Statistics:");
     println!("  Completed: {}", final_stats.completed);
     println!("  Failed: {}", final_stats.failed);
     println!("  Total time: {}ms", final_stats.total_time_ms);
 }
 
-fn process_task(task: Task, stats: Arc<Mutex<Stats>>) {
-    println!("Processing task {}", task.id);
+fn process_task(task: &Task, fails: bool, stats: &Stats) {
+    log::info(&Tags::task(task.id, "task"), format_args!("Processing task {}", task.id));
 
     let start = std::time::Instant::now();
     thread::sleep(Duration::from_millis(task.work_duration));
     let duration = start.elapsed().as_millis() as u64;
 
-    // TODO: Lock the mutex and update stats
+    // Update stats
     // Handle simulated failures (e.g., if id % 5 == 0)
 
-    let mut stats_guard = stats.lock().unwrap();
-    if task.id % 5 == 0 {
-        stats_guard.failed += 1;
+    if fails {
+        stats.failed.fetch_add(1, Ordering::Relaxed);
     } else {
-        stats_guard.completed += 1;
+        stats.completed.fetch_add(1, Ordering::Relaxed);
     }
-    stats_guard.total_time_ms += duration;
+    stats.total_time_ms.fetch_add(duration, Ordering::Relaxed);
 }
@@ -0,0 +1,56 @@
+// Part 2b's workload again, as async tasks on a handful of threads instead
+// of a thread pool: a task sleeping doesn't hold a thread, so they all
+// wait at once however few threads there are
+use crate::cli::Args;
+use crate::console;
+use crate::log::{self, Tags};
+use crate::runtime::{self, Runtime};
+use crate::summary::Summary;
+use rust_concurrent_processor::{TaskError, TaskResult};
+use std::time::{Duration, Instant};
+
+pub fn run(args: &Args) {
+    let runtime = Runtime::new(args.workers_or(3));
+    let durations = match args.tasks {
+        Some(count) => (1..=count).map(|id| (id, args.work_duration(id))).collect(),
+        None => vec![(1, 100), (2, 200), (3, 150), (4, 50), (5, 180), (6, 90), (7, 220), (8, 130), (9, 170), (10, 60)],
+    };
+
+    let started = Instant::now();
+    let (results_tx, mut results_rx) = runtime::channel();
+    for (id, work_duration) in durations {
+        let fails = args.should_fail("task", id, 5);
+        let results_tx = results_tx.clone();
+        runtime.spawn(async move {
+            results_tx.send(process_task(id, work_duration, fails).await);
+        });
+    }
+    // The channel closes once the last task's sender is gone
+    drop(results_tx);
+
+    // Printed as the tasks finish
+    let results = runtime.block_on(async {
+        let mut results = Vec::new();
+        while let Some(result) = results_rx.recv().await {
+            console::result(&result);
+            results.push(result);
+        }
+        results
+    });
+    Summary::new(&results).print(started.elapsed());
+}
+
+async fn process_task(id: u32, work_duration: u64, fails: bool) -> TaskResult {
+    log::info(&Tags::task(id, "task"), format_args!("Processing task {}", id));
+    let start = Instant::now();
+    runtime::sleep(Duration::from_millis(work_duration)).await;
+
+    // Simulate occasional failures
+    let task_type = "task".to_string();
+    if fails {
+        TaskResult::Error { id, task_type, correlation_id: None, error: TaskError::new("Task failed"), attempts: 1 }
+    } else {
+        let output = format!("Task {} completed", id).into();
+        TaskResult::Success { id, task_type, correlation_id: None, output, duration_ms: start.elapsed().as_millis(), queued_ms: 0, attempts: 1 }
+    }
+}
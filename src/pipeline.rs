@@ -0,0 +1,115 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::sync::Recover;
+
+/// A chain of stages, each with its own worker threads, where every item a
+/// stage produces is handed to the next one as soon as it is ready.
+pub struct Pipeline<I, O> {
+    input: Sender<I>,
+    output: Receiver<O>,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+impl<I: Send + 'static> Pipeline<I, I> {
+    pub fn builder() -> PipelineBuilder<I, I> {
+        let (input, output) = mpsc::channel();
+        PipelineBuilder {
+            input,
+            output,
+            threads: Vec::new(),
+        }
+    }
+}
+
+impl<I, O> Pipeline<I, O> {
+    /// Feeds one item into the first stage.
+    pub fn send(&self, item: I) {
+        // Stage workers only exit once the input is closed, so this can't fail.
+        let _ = self.input.send(item);
+    }
+
+    /// Returns an item that made it through every stage, if one is ready.
+    pub fn try_recv(&self) -> Option<O> {
+        self.output.try_recv().ok()
+    }
+
+    /// Closes the input, waits for every item in flight to come out the
+    /// other end and returns those not already taken by
+    /// [`try_recv`](Self::try_recv). Items whose stage panicked are dropped.
+    pub fn finish(self) -> Vec<O> {
+        drop(self.input);
+        let outputs = self.output.iter().collect();
+        for thread in self.threads {
+            let _ = thread.join();
+        }
+        outputs
+    }
+}
+
+/// Adds stages to a [`Pipeline`]. Each stage's workers start as soon as the
+/// stage is added.
+pub struct PipelineBuilder<I, O> {
+    input: Sender<I>,
+    output: Receiver<O>,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+impl<I, O: Send + 'static> PipelineBuilder<I, O> {
+    /// Appends a stage that turns each item from the previous stage into a
+    /// new one using `workers` threads named after `name`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `workers` is zero.
+    pub fn stage<N, F>(mut self, name: &str, workers: usize, f: F) -> PipelineBuilder<I, N>
+    where
+        N: Send + 'static,
+        F: Fn(O) -> N + Send + Sync + 'static,
+    {
+        assert!(workers > 0, "a pipeline stage needs at least one worker");
+        let (sender, receiver) = mpsc::channel();
+        let input = Arc::new(Mutex::new(self.output));
+        let f = Arc::new(f);
+
+        for index in 0..workers {
+            let input = Arc::clone(&input);
+            let sender = sender.clone();
+            let f = Arc::clone(&f);
+            let thread = thread::Builder::new()
+                .name(format!("{name}-{index}"))
+                .spawn(move || {
+                    loop {
+                        let item = match input.lock().recover().recv() {
+                            Ok(item) => item,
+                            Err(_) => break,
+                        };
+                        // A panic loses this one item, not the whole stage.
+                        if let Ok(next) = panic::catch_unwind(AssertUnwindSafe(|| f(item)))
+                            && sender.send(next).is_err()
+                        {
+                            break;
+                        }
+                    }
+                })
+                .expect("failed to spawn pipeline worker");
+            self.threads.push(thread);
+        }
+
+        PipelineBuilder {
+            input: self.input,
+            output: receiver,
+            threads: self.threads,
+        }
+    }
+
+    pub fn build(self) -> Pipeline<I, O> {
+        Pipeline {
+            input: self.input,
+            output: self.output,
+            threads: self.threads,
+        }
+    }
+}
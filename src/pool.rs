@@ -0,0 +1,1133 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::affinity;
+use crate::builder::ThreadPoolBuilder;
+use crate::cancel::CancellationToken;
+use crate::chaos::Chaos;
+use crate::circuit::{Breaker, Pass};
+use crate::clock::SharedClock;
+use crate::dag::{Completion, Gate, Outcome};
+use crate::dispatch::Handlers;
+use crate::handle::TaskHandle;
+use crate::hooks::Listeners;
+use crate::local::WorkerInit;
+use crate::queue::{
+    self, ChannelQueue, FairQueue, FifoQueue, InstrumentedQueue, JobInfo, Priority, PriorityQueue,
+    QueueFull, Scheduler, TaskQueue, TryPushError, WorkStealingQueue,
+};
+use crate::rate_limit::RateLimiter;
+use crate::registry::{self, TaskRegistry};
+use crate::retry::RetryPolicy;
+use crate::runner::{self, RunSpec};
+use crate::stats::{LiveStats, SystemStats};
+use crate::subscribe::Subscribers;
+use crate::sync::{Mutex, PoisonPolicy, Recover};
+use crate::task::{Task, TaskInfo, TaskResult};
+use crate::timer::Timer;
+use crate::watchdog::Heartbeats;
+use crate::worker::{self, Pause, WaitStrategy, Workers};
+
+/// What a [`TaskQueue`] holds: a task or closure bundled with everything
+/// needed to run it and report back. Queues only store and hand these out.
+pub type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// What happens to tasks that are still queued when the pool shuts down.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShutdownMode {
+    /// Run every queued task before the workers exit.
+    Drain,
+    /// Drop queued tasks and cancel the ones already running. A running
+    /// task still finishes unless it stops at a cancellation point, such
+    /// as [`TaskContext::is_cancelled`](crate::TaskContext::is_cancelled),
+    /// and isn't retried.
+    Immediate,
+}
+
+/// Per-submission settings for [`ThreadPool::submit_with`].
+#[derive(Clone, Debug, Default)]
+pub struct SubmitOptions {
+    pub(crate) priority: Priority,
+    cost: Option<Duration>,
+    cancellation: CancellationToken,
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    retry: Option<RetryPolicy>,
+    correlation_id: Option<String>,
+    metadata: BTreeMap<String, String>,
+    /// When a delayed task goes onto the queue, so its wait is counted
+    /// from then rather than from when it was submitted.
+    pub(crate) queued_at: Option<Instant>,
+}
+
+impl SubmitOptions {
+    pub fn new() -> Self {
+        SubmitOptions::default()
+    }
+
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// How long the task is expected to take, for schedulers that care
+    /// such as [`Scheduler::ShortestJobFirst`]. Overrides [`Task::cost`].
+    pub fn cost(mut self, cost: Duration) -> Self {
+        self.cost = Some(cost);
+        self
+    }
+
+    /// What the queue gets to know about `task` submitted with these
+    /// options.
+    pub(crate) fn job_info(&self, task: &dyn Task) -> JobInfo {
+        JobInfo {
+            priority: self.priority,
+            task_type: Some(task.kind().into()),
+            cost: self.cost.or_else(|| task.cost()),
+        }
+    }
+
+    /// Lets the caller cancel the task through `token` while it is queued
+    /// or running.
+    pub fn cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = token;
+        self
+    }
+
+    /// Reports the task as [`TaskResult::TimedOut`] if it runs longer than
+    /// `timeout`. Overrides the pool's
+    /// [default timeout](crate::ThreadPoolBuilder::default_timeout), whose
+    /// notes on tasks that ignore it apply here too.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Reports the task as [`TaskResult::Expired`] instead of running it if
+    /// it's still queued at `deadline`.
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Overrides the pool's default [`RetryPolicy`] for this task.
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    /// Tags the task with `id`, e.g. the request it was made for. The task
+    /// can read it from [`TaskContext::correlation_id`](crate::TaskContext::correlation_id),
+    /// and its result carries it, for listeners and subscribers too.
+    pub fn correlation_id(mut self, id: impl Into<String>) -> Self {
+        self.correlation_id = Some(id.into());
+        self
+    }
+
+    /// Attaches `value` under `key`, for the task to read from
+    /// [`TaskContext::metadata`](crate::TaskContext::metadata). Can be
+    /// called more than once; a key given twice keeps the last value.
+    pub fn metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+}
+
+/// When the pool grows and shrinks.
+pub(crate) struct Scaling {
+    pub(crate) min_workers: usize,
+    pub(crate) max_workers: usize,
+    pub(crate) scale_up_threshold: usize,
+    pub(crate) keep_alive: Duration,
+}
+
+impl Scaling {
+    fn enabled(&self) -> bool {
+        self.max_workers > self.min_workers
+    }
+
+    /// How long an idle worker waits before offering to exit.
+    pub(crate) fn idle_timeout(&self) -> Option<Duration> {
+        self.enabled().then_some(self.keep_alive)
+    }
+}
+
+/// One queue and the workers serving it. A pool has one for general use
+/// and one per task type given [dedicated
+/// workers](ThreadPoolBuilder::dedicated_workers); the stats are shared by
+/// all of them.
+pub(crate) struct Shared {
+    pub(crate) queue: InstrumentedQueue,
+    pub(crate) stats: Arc<LiveStats>,
+    pub(crate) registry: Arc<TaskRegistry>,
+    pub(crate) scaling: Scaling,
+    /// Workers alive on this queue, as opposed to the pool-wide count in
+    /// the stats.
+    active_workers: AtomicU32,
+    pub(crate) workers: Mutex<Workers>,
+    pub(crate) next_worker: AtomicUsize,
+    pub(crate) heartbeats: Heartbeats,
+    /// Cancelled by [`ShutdownMode::Immediate`]. Running tasks see it as
+    /// their own cancellation, and from then on jobs a worker takes off
+    /// the queue and dependents released during shutdown are dropped
+    /// rather than run.
+    pub(crate) shutdown: CancellationToken,
+    default_timeout: Option<Duration>,
+    default_retry: RetryPolicy,
+    rate_limiters: HashMap<String, Arc<RateLimiter>>,
+    breakers: HashMap<String, Arc<Breaker>>,
+    listeners: Listeners,
+    pub(crate) poison_policy: PoisonPolicy,
+    pub(crate) clock: SharedClock,
+    chaos: Option<Chaos>,
+    pub(crate) worker_init: Option<WorkerInit>,
+    /// Cores to pin workers to, taken in turn. Empty unless
+    /// [`pin_workers`](ThreadPoolBuilder::pin_workers) asked for it.
+    pub(crate) cpus: Vec<usize>,
+    pub(crate) thread_name_prefix: String,
+    pub(crate) stack_size: Option<usize>,
+    pub(crate) wait_strategy: WaitStrategy,
+    pub(crate) pause: Pause,
+}
+
+impl Shared {
+    /// Sets up a queue with its own `workers` to `max_workers` threads and
+    /// starts the first `workers` of them.
+    fn start(
+        builder: &ThreadPoolBuilder,
+        workers: usize,
+        max_workers: usize,
+        stats: &Arc<LiveStats>,
+        registry: &Arc<TaskRegistry>,
+        rate_limiters: &HashMap<String, Arc<RateLimiter>>,
+        breakers: &HashMap<String, Arc<Breaker>>,
+    ) -> Arc<Shared> {
+        let shared = Arc::new(Shared {
+            queue: InstrumentedQueue::new(
+                match &builder.queue {
+                    Some(factory) => factory.make(),
+                    None => Self::scheduler_queue(builder, max_workers),
+                },
+                stats,
+                &builder.clock,
+            ),
+            stats: Arc::clone(stats),
+            registry: Arc::clone(registry),
+            scaling: Scaling {
+                min_workers: workers,
+                max_workers,
+                scale_up_threshold: builder.scale_up_threshold,
+                keep_alive: builder.keep_alive,
+            },
+            active_workers: AtomicU32::new(0),
+            workers: Mutex::default(),
+            next_worker: AtomicUsize::new(0),
+            heartbeats: Heartbeats::default(),
+            shutdown: CancellationToken::new(),
+            default_timeout: builder.default_timeout,
+            default_retry: builder.retry_policy.clone(),
+            rate_limiters: rate_limiters.clone(),
+            breakers: breakers.clone(),
+            listeners: builder.listeners.with_policy(builder.poison_policy),
+            poison_policy: builder.poison_policy,
+            clock: builder.clock.clone(),
+            chaos: builder.chaos.clone(),
+            worker_init: builder.worker_init.clone(),
+            cpus: if builder.pin_workers {
+                affinity::cpus()
+            } else {
+                Vec::new()
+            },
+            thread_name_prefix: builder.thread_name_prefix.clone(),
+            stack_size: builder.stack_size,
+            wait_strategy: builder.wait_strategy,
+            pause: Pause::default(),
+        });
+
+        for _ in 0..workers {
+            shared.add_worker();
+        }
+        shared
+    }
+
+    /// The queue the builder's [`Scheduler`] and capacity call for.
+    fn scheduler_queue(builder: &ThreadPoolBuilder, max_workers: usize) -> Box<dyn TaskQueue<Job>> {
+        match builder.scheduler {
+            Scheduler::SharedQueue => {
+                Box::new(PriorityQueue::with_capacity(builder.queue_capacity))
+            }
+            Scheduler::WorkStealing => {
+                Box::new(WorkStealingQueue::new(max_workers, builder.queue_capacity))
+            }
+            Scheduler::Channel => Box::new(ChannelQueue::new(builder.queue_capacity)),
+            Scheduler::Fifo => Box::new(FifoQueue::with_capacity(builder.queue_capacity)),
+            Scheduler::ShortestJobFirst => {
+                Box::new(PriorityQueue::with_capacity(builder.queue_capacity).by_cost())
+            }
+            Scheduler::WeightedFair => Box::new(builder.weights.iter().fold(
+                FairQueue::with_capacity(builder.queue_capacity),
+                |queue, (task_type, &weight)| queue.weight(task_type.as_str(), weight),
+            )),
+        }
+    }
+
+    /// Starts one more worker on this queue, whatever its limits.
+    pub(crate) fn add_worker(self: &Arc<Self>) {
+        self.active_workers.fetch_add(1, Ordering::Relaxed);
+        self.stats.worker_started();
+        worker::spawn(self);
+    }
+
+    /// Queues `job`, adding a worker if the backlog calls for one.
+    pub(crate) fn push(self: &Arc<Self>, job: Job, info: JobInfo) {
+        if self.queue.push(job, info).is_err() {
+            panic!("pool is shut down");
+        }
+        self.scale_up_if_busy();
+    }
+
+    /// Adds a worker when the backlog is deeper than the scale-up threshold
+    /// and the queue is below its maximum number of workers.
+    pub(crate) fn scale_up_if_busy(self: &Arc<Self>) {
+        let scaling = &self.scaling;
+        if !scaling.enabled() || self.queue.len() <= scaling.scale_up_threshold {
+            return;
+        }
+        let started = self
+            .active_workers
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |active| {
+                (active < scaling.max_workers as u32).then_some(active + 1)
+            })
+            .is_ok();
+        if started {
+            self.stats.worker_started();
+            worker::spawn(self);
+        }
+    }
+
+    /// Lets an idle worker go unless that would leave fewer than the
+    /// minimum. Returns whether it should exit.
+    pub(crate) fn try_worker_stopped(&self) -> bool {
+        let stopped = self
+            .active_workers
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |active| {
+                (active > self.scaling.min_workers as u32).then_some(active - 1)
+            })
+            .is_ok();
+        if stopped {
+            self.stats.worker_stopped();
+        }
+        stopped
+    }
+
+    pub(crate) fn worker_stopped(&self) {
+        self.active_workers.fetch_sub(1, Ordering::Relaxed);
+        self.stats.worker_stopped();
+    }
+
+    /// Wraps `task` in a job that runs it under `options` and passes the
+    /// recorded result to `report`. With a `gate`, the job skips the task
+    /// if a dependency failed; past its deadline, or when chaos drops it,
+    /// it skips it too.
+    pub(crate) fn job<T, F>(
+        self: &Arc<Self>,
+        task: Arc<T>,
+        options: SubmitOptions,
+        gate: Option<Arc<Gate>>,
+        report: F,
+    ) -> Job
+    where
+        T: Task + 'static,
+        F: FnOnce(TaskResult) + Send + 'static,
+    {
+        let shared = Arc::clone(self);
+        let deadline = options.deadline;
+        let mut spec = RunSpec {
+            cancellation: options.cancellation,
+            shutdown: self.shutdown.clone(),
+            timeout: options.timeout.or(self.default_timeout),
+            retry: options.retry.unwrap_or_else(|| self.default_retry.clone()),
+            rate_limit: self.rate_limiters.get(task.kind()).cloned(),
+            registry: Arc::clone(&self.registry),
+            listeners: self.listeners.clone(),
+            clock: self.clock.clone(),
+            chaos: self.chaos.clone(),
+            queued: Duration::ZERO,
+            info: TaskInfo {
+                task_type: task.kind().into(),
+                correlation_id: options.correlation_id.map(Arc::from),
+                metadata: Arc::new(options.metadata),
+                submitted_at: None,
+            },
+        };
+        let breaker = self.breakers.get(task.kind()).cloned();
+        self.registry.queued(task.id());
+        self.listeners.submitted(task.id(), task.kind());
+        let submitted = options
+            .queued_at
+            .map_or_else(|| self.clock.now(), |at| at.max(self.clock.now()));
+        spec.info.submitted_at = Some(submitted);
+        Box::new(move || {
+            let now = shared.clock.now();
+            spec.queued = queue::current_wait();
+            let worker = registry::current_worker();
+            shared.listeners.started(task.id(), task.kind(), worker);
+            let failed_dependency = gate.and_then(|gate| gate.failed_dependency());
+            let missed_deadline = deadline.filter(|&deadline| now > deadline);
+            let correlation_id = spec.info.correlation_id();
+            let result = if let Some(dependency) = failed_dependency {
+                TaskResult::DependencyFailed {
+                    id: task.id(),
+                    task_type: task.kind().to_string(),
+                    correlation_id,
+                    dependency,
+                }
+            } else if let Some(deadline) = missed_deadline {
+                TaskResult::Expired {
+                    id: task.id(),
+                    task_type: task.kind().to_string(),
+                    correlation_id,
+                    late_ms: (now - deadline).as_millis(),
+                }
+            } else if spec.chaos.as_ref().is_some_and(Chaos::drop_next) {
+                TaskResult::Dropped {
+                    id: task.id(),
+                    task_type: task.kind().to_string(),
+                    correlation_id,
+                }
+            } else if let Some(breaker) = breaker {
+                match breaker.try_pass() {
+                    Pass::Reject => TaskResult::CircuitOpen {
+                        id: task.id(),
+                        task_type: task.kind().to_string(),
+                        correlation_id,
+                    },
+                    pass => {
+                        let result = runner::run(&task, &spec);
+                        breaker.record(&pass, &result);
+                        result
+                    }
+                }
+            } else {
+                runner::run(&task, &spec)
+            };
+            shared.stats.record(&result);
+            shared.registry.finished(&result);
+            shared.listeners.finished(&result);
+            report(result);
+        })
+    }
+
+    /// The pool's stats, along with the state of every circuit breaker.
+    pub(crate) fn snapshot(&self) -> SystemStats {
+        let mut stats = self.stats.snapshot();
+        stats.circuits = self
+            .breakers
+            .iter()
+            .map(|(task_type, breaker)| (task_type.clone(), breaker.state()))
+            .collect();
+        stats
+    }
+
+    /// Wraps `f` in a job that sends its return value to the returned
+    /// handle. A panic is counted and drops the value instead.
+    pub(crate) fn closure<F, R>(self: &Arc<Self>, f: F) -> (Job, TaskHandle<R>)
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (value_tx, handle) = TaskHandle::new();
+        let shared = Arc::clone(self);
+        let job = Box::new(move || {
+            let start = shared.clock.now();
+            let outcome = panic::catch_unwind(AssertUnwindSafe(f));
+            match outcome {
+                Ok(value) => {
+                    shared
+                        .stats
+                        .closure_completed((shared.clock.now() - start).as_millis());
+                    let _ = value_tx.send(value);
+                }
+                Err(_) => shared.stats.worker_panicked(),
+            }
+        });
+        (job, handle)
+    }
+
+    /// Runs a job a worker took off the queue, unless an immediate
+    /// shutdown has begun since, in which case it's dropped.
+    pub(crate) fn run_job(&self, job: Job) {
+        if self.shutdown.is_cancelled() {
+            self.stats.tasks_discarded(1);
+        } else {
+            job();
+        }
+    }
+
+    /// Queues a dependent whose dependencies have all finished. Once the
+    /// pool is shutting down the queue is closed, but dependencies finish on
+    /// worker threads, so a draining pool runs the dependent right here.
+    pub(crate) fn release(&self, job: Job, info: JobInfo) {
+        if let Err(job) = self.queue.push(job, info) {
+            if self.shutdown.is_cancelled() {
+                self.stats.tasks_discarded(1);
+            } else {
+                job();
+            }
+        }
+    }
+}
+
+/// The pool's queues, kept by a continuation so it can queue its task
+/// from a worker once the task before it is done.
+#[derive(Clone)]
+pub(crate) struct Submitter {
+    shared: Arc<Shared>,
+    lanes: HashMap<String, Arc<Shared>>,
+}
+
+impl Submitter {
+    /// Queues `task` the way a released dependent is queued. Its job hands
+    /// the result to `report`, then completes `completion`.
+    pub(crate) fn release<T, F>(&self, task: T, completion: Arc<Completion>, report: F)
+    where
+        T: Task + 'static,
+        F: FnOnce(TaskResult) + Send + 'static,
+    {
+        let options = SubmitOptions::new();
+        let info = options.job_info(&task);
+        let lane = self.lanes.get(task.kind()).unwrap_or(&self.shared);
+        let job = self
+            .shared
+            .job(Arc::new(task), options, None, move |result| {
+                let outcome = outcome(&result);
+                report(result);
+                completion.complete(outcome);
+            });
+        lane.release(job, info);
+    }
+}
+
+/// A pool of worker threads pulling jobs off a shared queue (see
+/// [`Scheduler`]). Fixed-size unless a larger
+/// [`max_workers`](ThreadPoolBuilder::max_workers) is configured.
+pub struct ThreadPool {
+    pub(crate) shared: Arc<Shared>,
+    /// Queues for task types with dedicated workers, by task type.
+    lanes: HashMap<String, Arc<Shared>>,
+    pub(crate) timer: Timer,
+    pub(crate) subscribers: Arc<Subscribers>,
+    pub(crate) handlers: Handlers,
+    /// How dropping the pool shuts it down; `None` once it has been.
+    on_drop: Option<ShutdownMode>,
+}
+
+impl ThreadPool {
+    /// Spawns `size` worker threads with default settings.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is zero.
+    pub fn new(size: usize) -> ThreadPool {
+        ThreadPool::builder().workers(size).build()
+    }
+
+    pub fn builder() -> ThreadPoolBuilder {
+        ThreadPoolBuilder::new()
+    }
+
+    pub(crate) fn from_builder(mut builder: ThreadPoolBuilder) -> ThreadPool {
+        let size = builder.workers;
+        assert!(size > 0, "a thread pool needs at least one worker");
+        assert!(
+            builder.queue_capacity != Some(0),
+            "a bounded queue needs room for at least one task"
+        );
+
+        let subscribers = Arc::new(Subscribers::default());
+        builder.listeners.add(subscribers.clone());
+
+        let max_workers = builder.max_workers.max(size);
+        let stats = Arc::new(LiveStats::new());
+        let registry = Arc::new(TaskRegistry::new());
+        let limited: HashSet<&String> = builder
+            .rate_limits
+            .keys()
+            .chain(builder.max_concurrent.keys())
+            .collect();
+        let rate_limiters: HashMap<String, Arc<RateLimiter>> = limited
+            .into_iter()
+            .map(|task_type| {
+                let limiter = RateLimiter::new(
+                    builder.rate_limits.get(task_type).cloned(),
+                    builder.max_concurrent.get(task_type).copied(),
+                );
+                (task_type.clone(), Arc::new(limiter))
+            })
+            .collect();
+        let breakers: HashMap<String, Arc<Breaker>> = builder
+            .circuit_breakers
+            .iter()
+            .map(|(task_type, breaker)| {
+                (
+                    task_type.clone(),
+                    Arc::new(Breaker::new(breaker.clone(), builder.clock.clone())),
+                )
+            })
+            .collect();
+
+        let shared = Shared::start(
+            &builder,
+            size,
+            max_workers,
+            &stats,
+            &registry,
+            &rate_limiters,
+            &breakers,
+        );
+        let lanes = builder
+            .dedicated_workers
+            .iter()
+            .map(|(task_type, &workers)| {
+                assert!(
+                    workers > 0,
+                    "dedicated workers for {task_type:?} can't be zero"
+                );
+                let lane = Shared::start(
+                    &builder,
+                    workers,
+                    workers,
+                    &stats,
+                    &registry,
+                    &rate_limiters,
+                    &breakers,
+                );
+                (task_type.clone(), lane)
+            })
+            .collect();
+
+        ThreadPool {
+            shared,
+            lanes,
+            timer: Timer::new(builder.clock.clone()),
+            subscribers,
+            handlers: Handlers::default(),
+            on_drop: Some(builder.on_drop),
+        }
+    }
+
+    /// The queue that tasks of `task_type` go to.
+    pub(crate) fn lane(&self, task_type: &str) -> &Arc<Shared> {
+        self.lanes.get(task_type).unwrap_or(&self.shared)
+    }
+
+    pub(crate) fn submitter(&self) -> Submitter {
+        Submitter {
+            shared: Arc::clone(&self.shared),
+            lanes: self.lanes.clone(),
+        }
+    }
+
+    /// The general queue followed by every dedicated one.
+    pub(crate) fn lanes(&self) -> impl Iterator<Item = &Arc<Shared>> {
+        std::iter::once(&self.shared).chain(self.lanes.values())
+    }
+
+    /// Queues `task` at [`Priority::Normal`] and returns a handle to its
+    /// [`TaskResult`].
+    pub fn submit<T>(&self, task: T) -> TaskHandle<TaskResult>
+    where
+        T: Task + 'static,
+    {
+        self.submit_with_priority(task, Priority::Normal)
+    }
+
+    /// Queues `task` ahead of anything with a lower `priority`.
+    pub fn submit_with_priority<T>(&self, task: T, priority: Priority) -> TaskHandle<TaskResult>
+    where
+        T: Task + 'static,
+    {
+        self.submit_with(task, SubmitOptions::new().priority(priority))
+    }
+
+    /// Queues `task` using the given [`SubmitOptions`]. Blocks while a
+    /// bounded queue is full.
+    pub fn submit_with<T>(&self, task: T, options: SubmitOptions) -> TaskHandle<TaskResult>
+    where
+        T: Task + 'static,
+    {
+        let info = options.job_info(&task);
+        let lane = Arc::clone(self.lane(task.kind()));
+        let (job, handle) = self.prepare(Arc::new(task), options);
+        lane.push(job, info);
+        handle
+    }
+
+    /// Queues `task` once every task behind `dependencies` has succeeded. If
+    /// one of them fails, `task` is skipped and reported as
+    /// [`TaskResult::DependencyFailed`], which in turn fails its own
+    /// dependents.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a dependency handle didn't come from one of the `submit`
+    /// methods.
+    pub fn submit_after<T>(
+        &self,
+        task: T,
+        dependencies: &[&TaskHandle<TaskResult>],
+    ) -> TaskHandle<TaskResult>
+    where
+        T: Task + 'static,
+    {
+        self.submit_after_with(task, dependencies, SubmitOptions::new())
+    }
+
+    /// Like [`submit_after`](Self::submit_after), with [`SubmitOptions`].
+    pub fn submit_after_with<T>(
+        &self,
+        task: T,
+        dependencies: &[&TaskHandle<TaskResult>],
+        options: SubmitOptions,
+    ) -> TaskHandle<TaskResult>
+    where
+        T: Task + 'static,
+    {
+        if dependencies.is_empty() {
+            return self.submit_with(task, options);
+        }
+        let completions: Vec<Arc<Completion>> = dependencies
+            .iter()
+            .map(|handle| {
+                Arc::clone(
+                    handle
+                        .completion()
+                        .expect("dependency handle doesn't belong to a submitted task"),
+                )
+            })
+            .collect();
+
+        let completion = Arc::new(Completion::new());
+        let (result_tx, handle) = TaskHandle::with_completion(Arc::clone(&completion));
+        self.submit_gated(
+            Arc::new(task),
+            completions,
+            options,
+            completion,
+            move |result| {
+                // The caller may have dropped the handle; that's not the worker's problem.
+                let _ = result_tx.send(result);
+            },
+        );
+        handle
+    }
+
+    /// Queues `task` once every one of `dependencies` is done, or skips it
+    /// if one of them failed. The job hands its result to `report`, then
+    /// completes `completion`.
+    pub(crate) fn submit_gated<T, F>(
+        &self,
+        task: Arc<T>,
+        dependencies: Vec<Arc<Completion>>,
+        options: SubmitOptions,
+        completion: Arc<Completion>,
+        report: F,
+    ) where
+        T: Task + 'static,
+        F: FnOnce(TaskResult) + Send + 'static,
+    {
+        let info = options.job_info(&*task);
+        let lane = Arc::clone(self.lane(task.kind()));
+        let gate = (!dependencies.is_empty()).then(|| Arc::new(Gate::new(dependencies.len())));
+        let job = self.shared.job(task, options, gate.clone(), move |result| {
+            let outcome = outcome(&result);
+            report(result);
+            completion.complete(outcome);
+        });
+        let Some(gate) = gate else {
+            lane.push(job, info);
+            return;
+        };
+        let job = Arc::new(Mutex::new(Some(job)));
+        for dependency in dependencies {
+            let gate = Arc::clone(&gate);
+            let job = Arc::clone(&job);
+            let lane = Arc::clone(&lane);
+            let info = info.clone();
+            dependency.on_done(Box::new(move |outcome| {
+                if gate.arrive(outcome) {
+                    let job = job.lock().recover().take().expect("a gate only opens once");
+                    lane.release(job, info);
+                }
+            }));
+        }
+    }
+
+    /// Runs `f` on the pool without waiting for it.
+    pub fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        drop(self.spawn(f));
+    }
+
+    /// Runs `f` on the pool and returns a handle to its return value. If
+    /// `f` panics the worker survives and [`TaskHandle::wait`] returns `None`.
+    pub fn spawn<F, R>(&self, f: F) -> TaskHandle<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (job, handle) = self.shared.closure(f);
+        self.shared.push(job, JobInfo::default());
+        handle
+    }
+
+    /// Runs `f` on every input at once, one job each, and waits for them
+    /// all. The results line up with `inputs` however the jobs finish.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `f` panicked on any input, once the rest have finished.
+    pub fn scatter_gather<I, F, T, E>(&self, inputs: I, f: F) -> Vec<Result<T, E>>
+    where
+        I: IntoIterator,
+        I::Item: Send + 'static,
+        F: Fn(I::Item) -> Result<T, E> + Send + Sync + 'static,
+        T: Send + 'static,
+        E: Send + 'static,
+    {
+        let f = Arc::new(f);
+        let handles: Vec<_> = inputs
+            .into_iter()
+            .map(|input| {
+                let f = Arc::clone(&f);
+                self.spawn(move || f(input))
+            })
+            .collect();
+        let results: Vec<_> = handles.into_iter().map(TaskHandle::wait).collect();
+        results
+            .into_iter()
+            .map(|result| result.expect("scatter_gather function panicked"))
+            .collect()
+    }
+
+    /// Like [`submit`](Self::submit), but hands the task back instead of
+    /// blocking when a bounded queue is full.
+    pub fn try_submit<T>(&self, task: T) -> Result<TaskHandle<TaskResult>, QueueFull<T>>
+    where
+        T: Task + 'static,
+    {
+        self.try_submit_with(task, SubmitOptions::new())
+    }
+
+    /// Like [`submit_with`](Self::submit_with), but hands the task back
+    /// instead of blocking when a bounded queue is full.
+    pub fn try_submit_with<T>(
+        &self,
+        task: T,
+        options: SubmitOptions,
+    ) -> Result<TaskHandle<TaskResult>, QueueFull<T>>
+    where
+        T: Task + 'static,
+    {
+        let task = Arc::new(task);
+        let info = options.job_info(&*task);
+        let lane = self.lane(task.kind());
+        let (job, handle) = self.prepare(Arc::clone(&task), options);
+        match lane.queue.try_push(job, info) {
+            Ok(()) => {
+                lane.scale_up_if_busy();
+                Ok(handle)
+            }
+            Err(TryPushError::Full(job)) => {
+                // Dropping the job releases its reference to the task.
+                drop(job);
+                self.shared.registry.forget(task.id());
+                let task = Arc::into_inner(task).expect("rejected job still holds the task");
+                Err(QueueFull(task))
+            }
+            Err(TryPushError::Closed(_)) => panic!("pool is shut down"),
+        }
+    }
+
+    /// Wraps `task` in a job that reports to the returned handle.
+    pub(crate) fn prepare<T>(
+        &self,
+        task: Arc<T>,
+        options: SubmitOptions,
+    ) -> (Job, TaskHandle<TaskResult>)
+    where
+        T: Task + 'static,
+    {
+        let completion = Arc::new(Completion::new());
+        let (result_tx, handle) = TaskHandle::with_completion(Arc::clone(&completion));
+        let job = self.shared.job(task, options, None, move |result| {
+            let outcome = outcome(&result);
+            // The caller may have dropped the handle; that's not the worker's problem.
+            let _ = result_tx.send(result);
+            completion.complete(outcome);
+        });
+        (job, handle)
+    }
+
+    /// Stops workers taking tasks off the queues until
+    /// [`resume`](Self::resume). Tasks already running carry on, along with
+    /// any subtasks they wait on; new tasks can still be submitted and wait
+    /// in the queue. Shutting down resumes the pool first.
+    pub fn pause(&self) {
+        for lane in self.lanes() {
+            lane.pause.set(true);
+        }
+    }
+
+    pub fn resume(&self) {
+        for lane in self.lanes() {
+            lane.pause.set(false);
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.shared.pause.is_set()
+    }
+
+    /// Tasks waiting in the queues right now, across every dedicated one.
+    pub fn queued(&self) -> usize {
+        self.lanes().map(|lane| lane.queue.len()).sum()
+    }
+
+    /// Current counters, all as of the same moment. Safe to call from any
+    /// thread while tasks are running, e.g. through a [`monitor`](Self::monitor);
+    /// a task finishing meanwhile is either counted everywhere or nowhere
+    /// yet.
+    pub fn stats(&self) -> SystemStats {
+        self.shared.snapshot()
+    }
+
+    /// Closes the queue, joins every worker and returns the final stats.
+    ///
+    /// With [`ShutdownMode::Drain`] all queued tasks still run, delayed ones
+    /// once they're due; with [`ShutdownMode::Immediate`] they are discarded
+    /// and counted in [`SystemStats::tasks_discarded`].
+    pub fn shutdown(mut self, mode: ShutdownMode) -> SystemStats {
+        self.close(mode);
+        self.join(None);
+        self.shared.snapshot()
+    }
+
+    /// Like [`shutdown`](Self::shutdown), but gives up on workers still busy
+    /// after `grace`. Their threads are left to finish in the background and
+    /// the stats are returned as they stand.
+    pub fn shutdown_timeout(mut self, mode: ShutdownMode, grace: Duration) -> SystemStats {
+        let deadline = Instant::now() + grace;
+        self.close(mode);
+        self.join(Some(deadline));
+        self.shared.snapshot()
+    }
+
+    /// Stops the queues taking new work. Dropping the pool afterwards
+    /// leaves it be.
+    fn close(&mut self, mode: ShutdownMode) {
+        self.on_drop = None;
+        // Delayed tasks go onto the queues, so those have to stay open until
+        // the timer is done with them.
+        self.timer.close(mode);
+        for lane in self.lanes() {
+            if mode == ShutdownMode::Immediate {
+                lane.shutdown.cancel();
+            }
+            lane.queue.close();
+            if mode == ShutdownMode::Immediate {
+                let discarded = lane.queue.clear();
+                lane.stats.tasks_discarded(discarded as u32);
+            }
+            // Only once the queue is cleared, or a paused worker could take
+            // more of what an immediate shutdown throws away.
+            lane.pause.set(false);
+        }
+    }
+
+    /// Joins every worker, along with any started in place of one that
+    /// died meanwhile, after which no more can start. Workers still busy
+    /// at `deadline` are left to finish in the background.
+    fn join(&self, deadline: Option<Instant>) {
+        for lane in self.lanes() {
+            loop {
+                let workers = lane.workers.lock().recover().take();
+                if workers.is_empty() {
+                    break;
+                }
+                for worker in workers {
+                    // A pool shut down or dropped by one of its own tasks
+                    // can't wait for the worker running that task, which
+                    // exits once the task is done.
+                    if worker.thread().id() == thread::current().id() {
+                        continue;
+                    }
+                    if let Some(deadline) = deadline {
+                        while !worker.is_finished() && Instant::now() < deadline {
+                            thread::sleep(Duration::from_millis(5));
+                        }
+                        if !worker.is_finished() {
+                            continue;
+                        }
+                    }
+                    // A worker that panicked has already been replaced.
+                    let _ = worker.join();
+                }
+            }
+        }
+    }
+}
+
+/// Shuts the pool down as [`ThreadPoolBuilder::on_drop`] says, if
+/// [`shutdown`](ThreadPool::shutdown) hasn't already, so a pool that goes
+/// out of scope doesn't leave its workers running.
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        if let Some(mode) = self.on_drop {
+            self.close(mode);
+            self.join(None);
+        }
+    }
+}
+
+/// What a finished task means for the tasks waiting on it.
+pub(crate) fn outcome(result: &TaskResult) -> Outcome {
+    if result.is_success() {
+        Ok(())
+    } else {
+        Err(result.id())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::{TaskContext, TaskError, TaskOutput};
+
+    /// Ends one of four ways, by its id: succeeds, fails, panics or runs
+    /// until its timeout calls it off.
+    struct Scripted(u32);
+
+    impl Task for Scripted {
+        fn id(&self) -> u32 {
+            self.0
+        }
+
+        fn execute(&self, ctx: &TaskContext) -> Result<TaskOutput, TaskError> {
+            match self.0 % 4 {
+                0 => Ok(TaskOutput::new("done")),
+                1 => Err(TaskError::new("failed")),
+                2 => panic!("panicked"),
+                _ => {
+                    while !ctx.is_cancelled() {
+                        thread::sleep(Duration::from_millis(1));
+                    }
+                    Err(TaskError::Cancelled)
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn every_submission_ends_once_and_is_counted() {
+        let pool = ThreadPool::builder()
+            .workers(3)
+            .default_timeout(Duration::from_millis(20))
+            .build();
+        let subscription = pool.subscribe();
+        for id in 0..40 {
+            pool.submit(Scripted(id));
+        }
+        let stats = pool.shutdown(ShutdownMode::Drain);
+
+        let mut ids: Vec<u32> = subscription.try_iter().map(|result| result.id()).collect();
+        ids.sort_unstable();
+        assert_eq!(ids, (0..40).collect::<Vec<_>>());
+        let counted = [
+            stats.tasks_completed,
+            stats.tasks_failed,
+            stats.worker_panics,
+            stats.tasks_timed_out,
+        ];
+        assert_eq!(counted, [10; 4]);
+        // Nothing is reported once shutdown has returned.
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(subscription.try_iter().count(), 0);
+    }
+
+    /// Ignores its timeout and succeeds anyway.
+    struct Late;
+
+    impl Task for Late {
+        fn id(&self) -> u32 {
+            0
+        }
+
+        fn execute(&self, _ctx: &TaskContext) -> Result<TaskOutput, TaskError> {
+            thread::sleep(Duration::from_millis(30));
+            Ok(TaskOutput::new("late"))
+        }
+    }
+
+    #[test]
+    fn a_late_success_keeps_its_output() {
+        let pool = ThreadPool::builder()
+            .workers(1)
+            .default_timeout(Duration::from_millis(5))
+            .build();
+        let result = pool.submit(Late).wait();
+
+        assert!(matches!(result, Some(TaskResult::Success { .. })));
+        assert_eq!(pool.shutdown(ShutdownMode::Drain).tasks_timed_out, 0);
+    }
+
+    #[test]
+    fn drain_runs_queued_and_delayed_tasks() {
+        let pool = ThreadPool::builder().workers(1).build();
+        for id in 0..20 {
+            pool.submit(Scripted(id * 4));
+        }
+        for id in 20..25 {
+            pool.submit_delayed(Duration::from_millis(30), Scripted(id * 4));
+        }
+        let stats = pool.shutdown(ShutdownMode::Drain);
+
+        assert_eq!(stats.tasks_completed, 25);
+        assert_eq!(stats.tasks_discarded, 0);
+    }
+
+    #[test]
+    fn immediate_discards_queued_tasks() {
+        let pool = ThreadPool::builder().workers(1).build();
+        pool.pause();
+        for id in 0..10 {
+            pool.submit(Scripted(id * 4));
+        }
+        let stats = pool.shutdown(ShutdownMode::Immediate);
+
+        assert_eq!(stats.tasks_completed + stats.tasks_discarded, 10);
+        assert!(stats.tasks_discarded >= 9);
+    }
+
+    #[test]
+    fn dropping_a_pool_drains_it() {
+        let ran = Arc::new(AtomicU32::new(0));
+        {
+            let pool = ThreadPool::builder().workers(2).build();
+            for _ in 0..10 {
+                let ran = Arc::clone(&ran);
+                pool.execute(move || {
+                    thread::sleep(Duration::from_millis(2));
+                    ran.fetch_add(1, Ordering::Relaxed);
+                });
+            }
+        }
+        assert_eq!(ran.load(Ordering::Relaxed), 10);
+    }
+}
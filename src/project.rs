@@ -1,4 +1,35 @@
-use std::sync::{mpsc, Arc, Mutex};
+use crate::cache::DownloadCache;
+use crate::cli::{Args, Backend, Failures};
+use crate::console::{self, Mark};
+use crate::control::{Command, Control, Event};
+use crate::dashboard::Dashboard;
+use crate::jobs;
+use crate::journal::{self, Journal};
+use crate::log::{self, Tags};
+use crate::json::{self, FromJson, ToJson, Value};
+use crate::metrics::{self, Metrics};
+use crate::outcome::RunSummary;
+use crate::rng::Rng;
+use crate::sha256::{self, Sha256};
+use crate::throttle::Bandwidth;
+use crate::signal;
+use crate::sink::{self, Csv, JsonLines, Sinks};
+use crate::status;
+use crate::timeline::Timeline;
+use crate::workload::Arrivals;
+#[cfg(not(feature = "http"))]
+use crate::workload::Durations;
+use rust_concurrent_processor::{
+    self as rcp, CancellationToken, CircuitBreaker, CircuitState, DispatchError, Dispatched, Executor, LatencyHistogram, OnStall, Pipeline, Priority, RateLimit, RetryPolicy, Schedule, ShutdownMode, SubmitOptions, TaskContext, TaskError,
+    TaskGraph, TaskOutput, TaskResult, TaskStatus, ThreadPerTask, ThreadPool, SystemStats,
+};
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Arc, Mutex, PoisonError};
 use std::thread;
 use std::time::{Duration, Instant};
 
@@ -6,164 +37,1082 @@ use std::time::{Duration, Instant};
 #[derive(Clone, Debug)]
 enum Task {
     Compute { id: u32, iterations: u32 },
-    Download { id: u32, url: String },
+    // `sha256`, if given, is what the downloaded data must hash to. With
+    // `save_to` the data streams to that file instead of into memory
+    Download { id: u32, url: String, fails: bool, sha256: Option<String>, save_to: Option<PathBuf> },
     Process { id: u32, data: Vec<u32> },
+    // Any other type, run by the handler registered for it on the pool.
+    // The handler gets the task's whole JSON object
+    Named { id: u32, task_type: String, payload: Value },
 }
 
-// Results
-#[derive(Debug)]
-enum TaskResult {
-    Success { id: u32, task_type: String, duration_ms: u128 },
-    Error { id: u32, message: String },
-}
-
-// Shared statistics
-struct SystemStats {
-    tasks_completed: u32,
-    tasks_failed: u32,
-    total_duration_ms: u128,
-    active_workers: u32,
-}
-
-impl SystemStats {
-    fn new() -> Self {
-        SystemStats {
-            tasks_completed: 0,
-            tasks_failed: 0,
-            total_duration_ms: 0,
-            active_workers: 0,
-        }
-    }
-}
-
-pub fn run() {
-    // Create 20 random tasks
-    let tasks = generate_tasks(20);
-
-    // Set up channels and shared state
-    let (task_tx, task_rx) = mpsc::channel();
-    let (result_tx, result_rx) = mpsc::channel();
-    let stats = Arc::new(Mutex::new(SystemStats::new()));
-
-    // TODO: Create 4 worker threads that:
-    //   1. Receive tasks from task_rx (need to share receiver - use Arc<Mutex<Receiver>>)
-    //   2. Process them
-    //   3. Send results to result_tx
-    //   4. Update shared stats
-
-    let task_rx = Arc::new(Mutex::new(task_rx));
-
-    for i in 0..4 {
-        let task_rx = Arc::clone(&task_rx);
-        let result_tx = result_tx.clone();
-        thread::spawn(move || {
-            loop {
-                let task = task_rx.lock().unwrap().recv();
-                match task {
-                    Ok(task) => {
-                        let start = Instant::now();
-
-                        let task_result = match task {
-                            Task::Compute {id, iterations} => {
-                                let result = process_compute(id, iterations);
-                                let duration_ms = start.elapsed().as_millis();
-                                match result {
-                                    Ok(msg) => TaskResult::Success {
-                                        id,
-                                        task_type: "compute".to_string(),
-                                        duration_ms
-                                    },
-                                    Err(msg) => TaskResult::Error {
-                                        id,
-                                        message: msg
-                                    }
-                                }
-                            },
-                            Task::Download {id, url} => {
-                                let result = process_download(id, &url);
-                                let duration_ms = start.elapsed().as_millis();
-                                match result {
-                                    Ok(msg) => TaskResult::Success {
-                                        id,
-                                        task_type: "download".to_string(),
-                                        duration_ms
-                                    },
-                                    Err(msg) => TaskResult::Error {
-                                        id,
-                                        message: msg
-                                    }
-                                }                            
-                            },
-                            Task::Process {id, data} => {
-                                let result = process_data(id, data);
-                                let duration_ms = start.elapsed().as_millis();
-                                match result {
-                                    Ok(msg) => TaskResult::Success {
-                                        id,
-                                        task_type: "process".to_string(),
-                                        duration_ms
-                                    },
-                                    Err(msg) => TaskResult::Error {
-                                        id,
-                                        message: msg
-                                    }
-                                }                            
-                            }
-                        };
-
-                        result_tx.send(task_result).unwrap();
-                    },
-                    Err(_) => {
-                        break;
-                    }
+impl rcp::Task for Task {
+    fn id(&self) -> u32 {
+        match self {
+            Task::Compute { id, .. } | Task::Download { id, .. } | Task::Process { id, .. } | Task::Named { id, .. } => *id,
+        }
+    }
+
+    fn kind(&self) -> &str {
+        match self {
+            Task::Compute { .. } => "compute",
+            Task::Download { .. } => "download",
+            Task::Process { .. } => "process",
+            Task::Named { task_type, .. } => task_type,
+        }
+    }
+
+    fn execute(&self, ctx: &TaskContext) -> Result<TaskOutput, TaskError> {
+        self.run(ctx, &Settings::default())
+    }
+}
+
+impl Task {
+    fn run(&self, ctx: &TaskContext, settings: &Settings) -> Result<TaskOutput, TaskError> {
+        log::debug(&Tags::of(ctx), format_args!("attempt {}", ctx.attempt()));
+        let result = match self {
+            // Only downloads fail by themselves; the rest only with --fail-rate
+            Task::Compute { id, .. } | Task::Process { id, .. } if settings.failures.should_fail(rcp::Task::kind(self), *id, None) => {
+                Err(TaskError::new(format!("{} task {} failed", rcp::Task::kind(self), id)))
+            },
+            Task::Compute { id, iterations } => process_compute(*id, *iterations, ctx),
+            Task::Download { id, url, fails, sha256, save_to: Some(path) } => save_download(*id, url, *fails, sha256.as_deref(), path, settings, ctx),
+            Task::Download { id, url, fails, sha256, save_to: None } => process_download(*id, url, *fails, sha256.as_deref(), settings, ctx),
+            Task::Process { id, data } => process_data(*id, data, settings.chunk_size, ctx),
+            // Only the pool has handlers, so this one can't run anywhere else
+            Task::Named { task_type, .. } => Err(TaskError::InvalidInput(format!("no handler for task type '{}' without a pool", task_type))),
+        };
+        result.map(TaskOutput::from)
+    }
+}
+
+// Process payloads bigger than this are split up unless --chunk-size says
+// otherwise
+const DEFAULT_CHUNK_SIZE: usize = 256;
+
+// What the tasks of one run share
+struct Settings {
+    chunk_size: usize,
+    cache: Option<DownloadCache>,
+    bandwidth: Option<Bandwidth>,
+    failures: Failures,
+    // How long simulated downloads take, 100ms each if not given
+    #[cfg(not(feature = "http"))]
+    durations: Option<Durations>,
+    seed: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            cache: None,
+            bandwidth: None,
+            failures: Failures::default(),
+            #[cfg(not(feature = "http"))]
+            durations: None,
+            seed: 0,
+        }
+    }
+}
+
+impl Settings {
+    #[cfg(not(feature = "http"))]
+    fn download_time(&self, id: u32) -> Duration {
+        match &self.durations {
+            Some(durations) => durations.sample(&mut Rng::for_task(self.seed, id, "duration")),
+            None => Duration::from_millis(100),
+        }
+    }
+}
+
+// Task types the project doesn't know itself, run by handlers on the
+// pool. {"type": "hash", "id": 7, "text": "..."} hashes the text
+fn register_handlers(pool: &ThreadPool) {
+    pool.register("hash", |task: &Value, _ctx: &TaskContext| {
+        let text = task.str_field("text").map_err(TaskError::InvalidInput)?;
+        Ok(sha256::hex_digest(text.as_bytes()).into())
+    });
+}
+
+// A task as submitted to the pool: one of the project's own, or one handed
+// to the handler registered for its type
+enum Submitted {
+    Chunked(Chunked),
+    Handled(Dispatched),
+}
+
+impl Submitted {
+    fn new(task: Task, pool: &ThreadPool, settings: &Arc<Settings>) -> Result<Submitted, DispatchError> {
+        match task {
+            Task::Named { id, task_type, payload } => pool.dispatch(id, &task_type, payload).map(Submitted::Handled),
+            task => Ok(Submitted::Chunked(Chunked { task, settings: Arc::clone(settings) })),
+        }
+    }
+}
+
+impl rcp::Task for Submitted {
+    fn id(&self) -> u32 {
+        match self {
+            Submitted::Chunked(task) => rcp::Task::id(task),
+            Submitted::Handled(task) => task.id(),
+        }
+    }
+
+    fn kind(&self) -> &str {
+        match self {
+            Submitted::Chunked(task) => rcp::Task::kind(task),
+            Submitted::Handled(task) => task.kind(),
+        }
+    }
+
+    fn execute(&self, ctx: &TaskContext) -> Result<TaskOutput, TaskError> {
+        match self {
+            Submitted::Chunked(task) => task.execute(ctx),
+            Submitted::Handled(task) => task.execute(ctx),
+        }
+    }
+}
+
+// One of the project's own tasks, carrying the run's settings along
+struct Chunked {
+    task: Task,
+    settings: Arc<Settings>,
+}
+
+impl rcp::Task for Chunked {
+    fn id(&self) -> u32 {
+        rcp::Task::id(&self.task)
+    }
+
+    fn kind(&self) -> &str {
+        rcp::Task::kind(&self.task)
+    }
+
+    fn execute(&self, ctx: &TaskContext) -> Result<TaskOutput, TaskError> {
+        self.task.run(ctx, &self.settings)
+    }
+}
+
+// Counts the tasks each worker picks up, to show how evenly the work was
+// spread. Each count is one step, so the map is sound even if a panic
+// poisoned its lock and counting carries on
+#[derive(Default)]
+struct WorkerTally {
+    started: Mutex<BTreeMap<usize, u32>>,
+}
+
+impl rcp::TaskListener for WorkerTally {
+    fn on_start(&self, _task_id: u32, _task_type: &str, worker: usize) {
+        *self.started.lock().unwrap_or_else(PoisonError::into_inner).entry(worker).or_default() += 1;
+    }
+}
+
+// The status check polled while the run goes on. Its id is out of the way
+// of the generated tasks'
+const POLL_ID: u32 = u32::MAX;
+
+// Columns in the --gantt chart
+pub const GANTT_WIDTH: usize = 60;
+
+// How long running tasks get to finish after Ctrl-C or a stop command
+const GRACE_PERIOD: Duration = Duration::from_secs(2);
+
+// How long the run waits for a result or command before looking for
+// Ctrl-C and redrawing the dashboard
+const TICK: Duration = Duration::from_millis(50);
+
+pub fn run(args: &Args) -> RunSummary {
+    signal::install();
+
+    // Pick up where an interrupted run left off, or run the tasks from
+    // --tasks-file, or make up 20 unless told otherwise. With a journal
+    // every task and result is written down as the run goes. With --listen
+    // clients bring the tasks instead
+    let journal_path = args.journal.clone().unwrap_or_else(|| PathBuf::from(journal::DEFAULT_PATH));
+    let (tasks, journal) = if args.listen.is_some() {
+        (Vec::new(), None)
+    } else if args.resume {
+        match Journal::resume(&journal_path) {
+            Ok((journal, tasks)) => {
+                if !args.json() {
+                    println!("Resuming {} unfinished tasks from {}", tasks.len(), journal_path.display());
                 }
-            }
+                (tasks, Some(journal))
+            },
+            Err(err) => exit_with_error(&journal_path, err),
+        }
+    } else {
+        let tasks = match &args.tasks_file {
+            Some(path) => load_tasks(path).unwrap_or_else(|err| exit_with_error(path, err)),
+            None => generate_tasks(args.tasks_or(20), args),
+        };
+        let journal = args.journal.as_ref().map(|path| {
+            Journal::create(path, &tasks).unwrap_or_else(|err| exit_with_error(path, err))
         });
+        (tasks, journal)
+    };
+    let workers = args.workers_or(2);
+
+    // With --cache a URL downloaded once isn't downloaded again this run,
+    // nor in later ones with --cache-dir. With --max-bandwidth downloads
+    // share that many bytes a second between them
+    let cache = args.cache().then(|| match DownloadCache::new(args.cache_dir.clone()) {
+        Ok(cache) => cache,
+        Err(err) => exit_with_error(args.cache_dir.as_deref().unwrap_or(Path::new(".")), err),
+    });
+    let settings = Arc::new(Settings {
+        chunk_size: args.chunk_size.unwrap_or(DEFAULT_CHUNK_SIZE),
+        cache,
+        bandwidth: args.max_bandwidth.map(Bandwidth::new),
+        failures: args.failures(),
+        #[cfg(not(feature = "http"))]
+        durations: args.durations,
+        seed: args.seed.unwrap_or_default(),
+    });
+
+    // Every result goes to the journal and any --results-csv or
+    // --results-jsonl file, and to the screen unless the dashboard shows it
+    let mut sinks = Sinks::default();
+    if let Some(journal) = journal {
+        sinks.add(journal_path.display().to_string(), journal);
+    }
+    if let Some(path) = &args.results_csv {
+        sinks.add(path.display().to_string(), Csv::create(path).unwrap_or_else(|err| exit_with_error(path, err)));
+    }
+    if let Some(path) = &args.results_jsonl {
+        sinks.add(path.display().to_string(), JsonLines::create(path).unwrap_or_else(|err| exit_with_error(path, err)));
+    }
+    if !args.tui() && !console::quiet() {
+        let format: fn(&TaskResult) -> String = if args.json() { |result| result.to_json().to_string() } else { rendered };
+        sinks.add("stdout", sink::Stdout::new(format));
     }
 
-    // TODO: Main thread:
-    //   1. Sends all tasks to task_tx
-    //   2. Receives results from result_rx
-    //   3. Prints results as they arrive
-    //   4. Prints final statistics
+    // Any other backend just runs the tasks. A job server always gets a pool
+    if args.listen.is_none() && args.executor == Some(Backend::ThreadPerTask) {
+        return run_on(Box::new(ThreadPerTask::new()), args, tasks, sinks, &settings);
+    }
+
+    // Start small and grow while work is backing up. Nothing in this
+    // workload should take anywhere near 250ms, and flaky downloads get a
+    // couple more chances before they count as failures. Downloads mostly
+    // wait on the network, so they get workers of their own rather than
+    // tying up the ones doing CPU work, and they're polite: no more than 20
+    // a second and 2 at a time. If 3 downloads in a row fail, the server is
+    // given a second's rest before the next one tries it. The settings file
+    // and flags can override any of that
+    let tally = Arc::new(WorkerTally::default());
+    let builder = ThreadPool::builder()
+        .listener(Arc::clone(&tally))
+        .workers(workers)
+        .max_workers(workers.max(6))
+        .scale_up_threshold(2)
+        .keep_alive(Duration::from_millis(100))
+        .default_timeout(Duration::from_millis(250))
+        .retry_policy(
+            RetryPolicy::exponential(3, Duration::from_millis(50))
+                .with_jitter(Duration::from_millis(20)),
+        )
+        .dedicated_workers("download", 4)
+        .rate_limit("download", RateLimit::per_second(20.0).with_burst(4))
+        .max_concurrent("download", 2)
+        .circuit_breaker("download", CircuitBreaker::new(3, Duration::from_secs(1)));
 
-    for task in tasks {
-        task_tx.send(task).unwrap();
+    // With --trace-out or --gantt, note when every task ran and where
+    let timeline = (args.trace_out.is_some() || args.gantt).then(|| Arc::new(Timeline::new()));
+    let builder = match &timeline {
+        Some(timeline) => builder.listener(Arc::clone(timeline)),
+        None => builder,
+    };
+    let pool = args.configure(builder).build();
+    register_handlers(&pool);
+    let started = Instant::now();
+
+    // Every task of a type the project doesn't know needs a handler
+    if let Some(task) = tasks.iter().find(|task| matches!(task, Task::Named { .. }) && !pool.handles(rcp::Task::kind(*task))) {
+        let source = args.tasks_file.as_ref().filter(|_| !args.resume).unwrap_or(&journal_path);
+        exit_with_error(source, format!("task {}: unknown task type '{}'", rcp::Task::id(task), rcp::Task::kind(task)));
     }
-    drop(task_tx);
-    drop(result_tx);
 
-    for task_result in result_rx {
-        match task_result {
-            TaskResult::Success {id, task_type, duration_ms} => {
-                println!("✓ Task {} ({}) completed in {}ms", id, task_type, duration_ms);
-                let mut stats_guard = stats.lock().unwrap();
-                stats_guard.tasks_completed += 1;
-                stats_guard.total_duration_ms += duration_ms;
+    // Print a progress line every so often while the tasks run, unless
+    // stdout is for a script, the dashboard shows progress instead or
+    // --quiet only wants the summary. A server sitting idle has nothing to
+    // report
+    let reporter = (!args.json() && !args.tui() && args.listen.is_none() && !console::quiet()).then(|| {
+        pool.live_reporter(Duration::from_millis(200), |report| {
+            // Tasks part way through, e.g. " [#14 50%]"
+            let progress: String = report.progress.iter()
+                .filter(|(_, fraction)| *fraction < 1.0)
+                .map(|(id, fraction)| format!(" [#{} {:.0}%]", id, fraction * 100.0))
+                .collect();
+            println!(
+                "[{:>5}ms] queued: {}, workers: {}, completed: {}, failed: {}, {:.1} tasks/s{}",
+                report.elapsed.as_millis(),
+                report.queued,
+                report.stats.active_workers,
+                report.stats.tasks_completed,
+                report.stats.tasks_failed + report.stats.tasks_timed_out,
+                report.throughput,
+                progress
+            );
+        })
+    });
+
+    // Keep the metrics endpoint, if any, up to date as the tasks run
+    let metrics = args.metrics_port.and_then(|port| match metrics::serve(port) {
+        Ok(metrics) => Some(metrics),
+        Err(err) => {
+            eprintln!("warning: can't serve metrics on port {}: {}", port, err);
+            None
+        }
+    });
+    let metrics_feed = metrics.clone().map(|metrics| {
+        pool.live_reporter(Duration::from_millis(250), move |report| {
+            metrics.update(report.queued, report.stats.clone());
+        })
+    });
+
+    // Answer questions about the pool over HTTP too, if asked to
+    if let Some(port) = args.status_port
+        && let Err(err) = status::serve(port, pool.monitor())
+    {
+        eprintln!("warning: can't serve status on port {}: {}", port, err);
+    }
+
+    // A download that hangs ties up its worker until the timeout gives up
+    // on it; hand its work to a fresh worker rather than wait
+    let watchdog = pool.watchdog(Duration::from_millis(200), OnStall::ReplaceWorker, |stall| {
+        let task = stall.task_id.map_or("a closure".to_string(), |id| format!("task {}", id));
+        let replaced = if stall.replaced { ", started another" } else { "" };
+        log::warn(&Tags::default().worker(stall.worker), format_args!("stuck on {} for {}ms{}", task, stall.silent_for.as_millis(), replaced));
+    });
+
+    // As a job server, take tasks from clients until Ctrl-C
+    if let Some(port) = args.listen {
+        if !args.json() {
+            println!("Listening for tasks on port {}, Ctrl-C to stop", port);
+        }
+        let served = jobs::serve(
+            port,
+            &pool,
+            |value, id| listened_task(value, id, &pool, &settings),
+            |result| match (args.json(), result.correlation_id()) {
+                (true, _) => println!("{}", result.to_json()),
+                (false, Some(correlation)) => println!("{} [{}]", rendered(result), correlation),
+                (false, None) => println!("{}", rendered(result)),
             },
-            TaskResult::Error {id, message} => {
-                println!("✗ Task {} failed: {}", id, message);
-                let mut stats_guard = stats.lock().unwrap();
-                stats_guard.tasks_failed += 1;
+        );
+        if let Err(err) = served {
+            eprintln!("error: can't listen on port {}: {}", port, err);
+            process::exit(1);
+        }
+        watchdog.stop();
+        drop(metrics_feed);
+        let final_stats = pool.shutdown(ShutdownMode::Drain);
+        return finish(args, final_stats, started.elapsed(), metrics.as_ref(), &tally, timeline, settings.cache.as_ref());
+    }
+
+    // Meanwhile keep checking on the server every 100ms, the way a service
+    // polls for new work, until the tasks are done
+    let poll = pool.submit_recurring(
+        Schedule::every(Duration::from_millis(100)),
+        Task::Download { id: POLL_ID, url: "http://example.com/status".to_string(), fails: false, sha256: None, save_to: None },
+    );
+
+    // All compute tasks share one token so they can be called off together
+    let compute_cancel = CancellationToken::new();
+
+    // Compute tasks that arrive over time aren't called off with the rest
+    let arrivals = args.arrivals.filter(|&arrivals| arrivals != Arrivals::All);
+    let expected = tasks.len();
+    let submissions = tasks.into_iter().map(|task| {
+        let mut options = SubmitOptions::new().priority(priority_of(&task));
+        if let Task::Compute { .. } = task
+            && arrivals.is_none()
+        {
+            options = options.cancellation(compute_cancel.clone());
+        }
+        let task = Submitted::new(task, &pool, &settings).expect("every task type has a handler");
+        (task, options)
+    });
+
+    // Results come back as tasks finish, whatever order they went in,
+    // on the same channel as any commands typed meanwhile. The poll's
+    // results stay with the poll
+    let control = Control::new();
+    control.forward(pool.subscribe(), |result| result.id() == POLL_ID);
+    let interactive = !args.tui() && control.read_stdin();
+    match arrivals {
+        // Tasks come in on their own schedule, whether or not the workers
+        // keep up. Each stands alone, as the download a process task would
+        // wait on may not have arrived yet
+        Some(arrivals) => {
+            let submissions: Vec<_> = submissions.collect();
+            let start = Instant::now();
+            let offsets = arrivals.offsets(submissions.len() as u32, &mut Rng::new(settings.seed));
+            for ((task, options), offset) in submissions.into_iter().zip(offsets) {
+                pool.submit_at_with(start + offset, task, options);
+            }
+        },
+        // Each process task works on what the download just before it
+        // fetched, so it only runs once that download has succeeded
+        None => {
+            let mut graph = TaskGraph::new();
+            let mut downloads = HashMap::new();
+            for (task, options) in submissions {
+                let id = rcp::Task::id(&task);
+                let is_download = rcp::Task::kind(&task) == "download";
+                let node = graph.add_with(task, options);
+                if is_download {
+                    downloads.insert(id, node);
+                } else if let Some(&download) = id.checked_sub(1).and_then(|prev| downloads.get(&prev)) {
+                    graph.add_dependency(node, download);
+                }
             }
+            pool.submit_graph(graph).expect("downloads never depend on anything");
+        },
+    }
+
+    // Give up on compute work that hasn't finished after a while
+    let cancel_after = Duration::from_millis(150);
+    thread::sleep(cancel_after);
+    compute_cancel.cancel();
+
+    // A quick look at what's still in the works at this point
+    if !args.json() && !args.tui() && !console::quiet() {
+        let in_flight = pool.in_flight();
+        let running = in_flight.iter().filter(|(_, status)| matches!(status, TaskStatus::Running { .. } | TaskStatus::Stuck { .. })).count();
+        let retrying = in_flight.iter().filter(|(_, status)| matches!(status, TaskStatus::Retrying { .. })).count();
+        println!(
+            "In flight after {}ms: {} running, {} retrying, {} queued",
+            cancel_after.as_millis(),
+            running,
+            retrying,
+            in_flight.len() - running - retrying
+        );
+    }
+
+    if interactive && !args.json() && !console::quiet() {
+        eprintln!("Type pause, resume, stats or stop to steer the run");
+    }
+
+    // Wait on results and commands until every task is in or the run is
+    // stopped. Ctrl-C can't send anything from its handler, so it's
+    // checked whenever the wait times out, as is the dashboard's redraw
+    let mut dashboard = args.tui().then(|| Dashboard::new(started));
+    let mut received = 0;
+    let mut stopped = false;
+    // The poll only keeps so many results for us, so count them as they come
+    let mut answered = 0;
+    while received < expected && !stopped {
+        match control.events.recv_timeout(TICK) {
+            Ok(Event::Result(result)) => {
+                received += 1;
+                sinks.record(&result);
+                if let Some(dashboard) = &mut dashboard {
+                    dashboard.log(console::line(Mark::of(&result), describe(&result)));
+                }
+            },
+            Ok(Event::Command(command)) => stopped = obey(command, &pool, started),
+            Err(_) => stopped = signal::interrupted(),
         }
+        if let Some(dashboard) = &mut dashboard
+            && dashboard.due()
+        {
+            dashboard.draw(&pool);
+        }
+        answered += poll.try_iter().filter(TaskResult::is_success).count();
+    }
+    // Back to the normal screen for the final stats
+    drop(dashboard);
+    sinks.finish();
+    poll.cancel();
+    watchdog.stop();
+    drop(metrics_feed);
+    if let Some(reporter) = reporter {
+        reporter.stop();
+    }
+    let final_stats = if stopped {
+        // Drop whatever is still queued and call off what is running, giving
+        // tasks that ignore that a moment to wrap up
+        eprintln!("\nStopped, giving running tasks up to {}ms to finish", GRACE_PERIOD.as_millis());
+        pool.shutdown_timeout(ShutdownMode::Immediate, GRACE_PERIOD)
+    } else {
+        pool.shutdown(ShutdownMode::Drain)
+    };
+    // Shutting down let the last poll finish, so every answer is in
+    if !args.json() {
+        answered += poll.try_iter().filter(TaskResult::is_success).count();
+        println!("Polled the server {} times, {} answered", poll.runs(), answered);
+    }
+    finish(args, final_stats, started.elapsed(), metrics.as_ref(), &tally, timeline, settings.cache.as_ref())
+}
+
+// Carries out a command from the control channel. Whether it stops the run
+fn obey(command: Command, pool: &ThreadPool, started: Instant) -> bool {
+    match command {
+        Command::Pause => {
+            pool.pause();
+            eprintln!("Paused with {} tasks queued, resume to carry on", pool.queued());
+        },
+        Command::Resume => {
+            pool.resume();
+            eprintln!("Resumed");
+        },
+        Command::Stats => {
+            let stats = pool.stats();
+            eprintln!(
+                "[{:>5}ms] queued: {}, workers: {}, completed: {}, failed: {}{}",
+                started.elapsed().as_millis(),
+                pool.queued(),
+                stats.active_workers,
+                stats.tasks_completed,
+                stats.tasks_failed + stats.tasks_timed_out,
+                if pool.is_paused() { ", paused" } else { "" }
+            );
+        },
+        Command::Shutdown => return true,
+    }
+    false
+}
+
+// Runs the tasks on `executor` without any of the pool's extras: they all
+// go in at once, process tasks don't wait for their downloads, and results
+// are printed in the order the tasks went in
+fn run_on(executor: Box<dyn Executor>, args: &Args, tasks: Vec<Task>, mut sinks: Sinks, settings: &Arc<Settings>) -> RunSummary {
+    let started = Instant::now();
+    let handles: Vec<_> = tasks
+        .into_iter()
+        .map(|task| executor.submit(Box::new(Chunked { task, settings: Arc::clone(settings) })))
+        .collect();
+    for result in handles.into_iter().filter_map(|handle| handle.wait()) {
+        sinks.record(&result);
+    }
+    sinks.finish();
+    let final_stats = executor.shutdown(ShutdownMode::Drain);
+    finish(args, final_stats, started.elapsed(), None, &WorkerTally::default(), None, settings.cache.as_ref())
+}
+
+// Hands the final numbers to whoever asked for them and prints them
+fn finish(args: &Args, final_stats: SystemStats, elapsed: Duration, metrics: Option<&Metrics>, tally: &WorkerTally, timeline: Option<Arc<Timeline>>, cache: Option<&DownloadCache>) -> RunSummary {
+    if let Some(metrics) = metrics {
+        metrics.update(0, final_stats.clone());
+    }
+    let summary = RunSummary::new(&final_stats, elapsed);
+    if let Some(path) = &args.summary_out
+        && let Err(err) = summary.write(path)
+    {
+        eprintln!("warning: can't write {}: {}", path.display(), err);
+    }
+    if let (Some(timeline), Some(path)) = (&timeline, &args.trace_out)
+        && let Err(err) = timeline.write_chrome_trace(path)
+    {
+        eprintln!("warning: can't write {}: {}", path.display(), err);
+    }
+    if args.json() {
+        let mut stats = final_stats.to_json();
+        if let (Some(cache), Value::Object(fields)) = (cache, &mut stats) {
+            fields.push(("cache".to_string(), Value::object([("hits", cache.hits().into()), ("misses", cache.misses().into())])));
+        }
+        println!("{}", stats.pretty());
+        return summary;
     }
-    let final_stats = stats.lock().unwrap();
     println!("\n=== Final Statistics ===");
     println!("Tasks completed: {}", final_stats.tasks_completed);
     println!("Tasks failed: {}", final_stats.tasks_failed);
+    for (kind, count) in &final_stats.errors {
+        println!("  {}: {}", kind, count);
+    }
+    println!("Tasks cancelled: {}", final_stats.tasks_cancelled);
+    println!("Tasks timed out: {}", final_stats.tasks_timed_out);
+    println!("Tasks skipped: {}", final_stats.tasks_skipped);
+    println!("Tasks expired: {}", final_stats.tasks_expired);
+    println!("Tasks discarded: {}", final_stats.tasks_discarded);
+    println!("Tasks rejected: {}", final_stats.tasks_rejected);
+    for (task_type, state) in &final_stats.circuits {
+        println!("Circuit for {}: {}", task_type, circuit_name(*state));
+    }
+    println!("Worker panics: {}", final_stats.worker_panics);
+    let started = tally.started.lock().unwrap_or_else(PoisonError::into_inner);
+    // Only a pool has workers to count
+    if !started.is_empty() {
+        let per_worker: Vec<String> = started.iter().map(|(worker, count)| format!("#{}: {}", worker, count)).collect();
+        println!("Tasks started per worker: {}", per_worker.join(", "));
+    }
+    println!("Retries: {}", final_stats.retries);
+    if let Some(cache) = cache {
+        println!("Download cache: {} hits, {} misses", cache.hits(), cache.misses());
+    }
+    println!("Peak workers: {}", final_stats.peak_workers);
     println!("Total duration: {}ms", final_stats.total_duration_ms);
+    // Long waits with short service times call for more workers; long
+    // service times for faster tasks
+    let percentiles = |name: &str, task_type: &str, histogram: &LatencyHistogram| {
+        println!(
+            "{} {} (n={}): p50 {}ms, p95 {}ms, p99 {}ms, max {}ms",
+            name,
+            task_type,
+            histogram.count(),
+            histogram.percentile_ms(50.0),
+            histogram.percentile_ms(95.0),
+            histogram.percentile_ms(99.0),
+            histogram.max_ms()
+        );
+    };
+    for (task_type, latency) in &final_stats.latency {
+        percentiles("Service time", task_type, latency);
+    }
+    for (task_type, wait) in &final_stats.queue_wait {
+        percentiles("Queue wait", task_type, wait);
+    }
+    if let Some(timeline) = timeline.filter(|_| args.gantt) {
+        println!("\n=== Worker Activity ===");
+        print!("{}", timeline.gantt(GANTT_WIDTH));
+    }
+    summary
+}
+
+// Tasks go in and out of JSON as {"type": "compute", "id": 1, ...} with
+// the variant's fields alongside the type
+impl ToJson for Task {
+    fn to_json(&self) -> Value {
+        match self {
+            Task::Compute { id, iterations } => Value::object([
+                ("type", "compute".into()),
+                ("id", (*id).into()),
+                ("iterations", (*iterations).into()),
+            ]),
+            Task::Download { id, url, fails, sha256, save_to } => {
+                let mut fields = vec![
+                    ("type", "download".into()),
+                    ("id", (*id).into()),
+                    ("url", url.as_str().into()),
+                    ("fails", (*fails).into()),
+                ];
+                if let Some(sha256) = sha256 {
+                    fields.push(("sha256", sha256.as_str().into()));
+                }
+                if let Some(path) = save_to {
+                    fields.push(("save_to", path.to_string_lossy().into_owned().into()));
+                }
+                Value::object(fields)
+            },
+            Task::Process { id, data } => Value::object([
+                ("type", "process".into()),
+                ("id", (*id).into()),
+                ("data", data.iter().map(|&n| n.into()).collect::<Vec<Value>>().into()),
+            ]),
+            Task::Named { payload, .. } => payload.clone(),
+        }
+    }
+}
+
+impl FromJson for Task {
+    fn from_json(value: &Value) -> Result<Task, String> {
+        let id = value.u32_field("id")?;
+        match value.str_field("type")? {
+            "compute" => Ok(Task::Compute { id, iterations: value.u32_field("iterations")? }),
+            "download" => Ok(Task::Download {
+                id,
+                url: value.str_field("url")?.to_string(),
+                // Real tasks don't fail on purpose
+                fails: match value.get("fails") {
+                    Some(_) => value.bool_field("fails")?,
+                    None => false,
+                },
+                sha256: match value.get("sha256") {
+                    Some(_) => Some(value.str_field("sha256")?.to_ascii_lowercase()),
+                    None => None,
+                },
+                save_to: match value.get("save_to") {
+                    Some(_) => Some(value.str_field("save_to")?.into()),
+                    None => None,
+                },
+            }),
+            "process" => {
+                let Value::Array(items) = value.field("data")? else {
+                    return Err("'data' must be an array of whole numbers".to_string());
+                };
+                let data = items
+                    .iter()
+                    .map(|item| item.as_u32().ok_or("'data' must be an array of whole numbers"))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if data.is_empty() {
+                    return Err("'data' must have at least one number".to_string());
+                }
+                Ok(Task::Process { id, data })
+            }
+            // Whether anything handles it is up to the pool
+            other => Ok(Task::Named { id, task_type: other.to_string(), payload: value.clone() }),
+        }
+    }
+}
+
+// One line per result for --output json. Every result has an id, a type
+// and a status, and a correlation id if it was given one; the rest depends
+// on how it ended
+impl ToJson for TaskResult {
+    fn to_json(&self) -> Value {
+        let mut fields = vec![("id", self.id().into()), ("task_type", self.task_type().into())];
+        if let Some(correlation_id) = self.correlation_id() {
+            fields.push(("correlation_id", correlation_id.into()));
+        }
+        match self {
+            TaskResult::Success {output, duration_ms, queued_ms, attempts, ..} => {
+                fields.push(("status", "success".into()));
+                fields.push(("output", output.message.as_str().into()));
+                fields.push(("duration_ms", (*duration_ms).into()));
+                fields.push(("queued_ms", (*queued_ms).into()));
+                fields.push(("attempts", (*attempts).into()));
+            },
+            TaskResult::Error {error, attempts, ..} => {
+                fields.push(("status", "error".into()));
+                fields.push(("error", error.to_json()));
+                fields.push(("attempts", (*attempts).into()));
+            },
+            TaskResult::Cancelled {..} => {
+                fields.push(("status", "cancelled".into()));
+            },
+            TaskResult::Panicked {message, ..} => {
+                fields.push(("status", "panicked".into()));
+                fields.push(("message", message.as_str().into()));
+            },
+            TaskResult::TimedOut {timeout_ms, attempts, ..} => {
+                fields.push(("status", "timed_out".into()));
+                fields.push(("timeout_ms", (*timeout_ms).into()));
+                fields.push(("attempts", (*attempts).into()));
+            },
+            TaskResult::DependencyFailed {dependency, ..} => {
+                fields.push(("status", "dependency_failed".into()));
+                fields.push(("dependency", (*dependency).into()));
+            },
+            TaskResult::Expired {late_ms, ..} => {
+                fields.push(("status", "expired".into()));
+                fields.push(("late_ms", (*late_ms).into()));
+            },
+            TaskResult::CircuitOpen {..} => {
+                fields.push(("status", "circuit_open".into()));
+            },
+            TaskResult::Dropped {..} => {
+                fields.push(("status", "dropped".into()));
+            }
+        }
+        Value::object(fields)
+    }
+}
+
+impl FromJson for TaskResult {
+    fn from_json(value: &Value) -> Result<TaskResult, String> {
+        let id = value.u32_field("id")?;
+        let task_type = value.str_field("task_type")?.to_string();
+        let correlation_id = jobs::correlation_id(value)?;
+        let result = match value.str_field("status")? {
+            "success" => TaskResult::Success {
+                id,
+                task_type,
+                correlation_id,
+                output: TaskOutput::new(value.str_field("output")?),
+                duration_ms: value.u128_field("duration_ms")?,
+                // Journals written before queue waits were recorded lack it
+                queued_ms: match value.get("queued_ms") {
+                    Some(_) => value.u128_field("queued_ms")?,
+                    None => 0,
+                },
+                attempts: value.u32_field("attempts")?,
+            },
+            "error" => TaskResult::Error {
+                id,
+                task_type,
+                correlation_id,
+                error: TaskError::from_json(value.field("error")?)?,
+                attempts: value.u32_field("attempts")?,
+            },
+            "cancelled" => TaskResult::Cancelled { id, task_type, correlation_id },
+            "panicked" => TaskResult::Panicked {
+                id,
+                task_type,
+                correlation_id,
+                message: value.str_field("message")?.to_string(),
+            },
+            "timed_out" => TaskResult::TimedOut {
+                id,
+                task_type,
+                correlation_id,
+                timeout_ms: value.u128_field("timeout_ms")?,
+                attempts: value.u32_field("attempts")?,
+            },
+            "dependency_failed" => TaskResult::DependencyFailed {
+                id,
+                task_type,
+                correlation_id,
+                dependency: value.u32_field("dependency")?,
+            },
+            "expired" => TaskResult::Expired {
+                id,
+                task_type,
+                correlation_id,
+                late_ms: value.u128_field("late_ms")?,
+            },
+            "circuit_open" => TaskResult::CircuitOpen { id, task_type, correlation_id },
+            "dropped" => TaskResult::Dropped { id, task_type, correlation_id },
+            other => return Err(format!("unknown status '{}'", other)),
+        };
+        Ok(result)
+    }
+}
+
+// Errors go out as {"kind": "network", ...} with the variant's fields
+// alongside, plus the message as it's printed
+impl ToJson for TaskError {
+    fn to_json(&self) -> Value {
+        let mut fields = vec![("kind", self.kind().into())];
+        match self {
+            TaskError::Timeout { after_ms } => fields.push(("after_ms", (*after_ms).into())),
+            TaskError::Network { url, message } => {
+                fields.push(("url", url.as_str().into()));
+                fields.push(("detail", message.as_str().into()));
+            },
+            TaskError::ChecksumMismatch { url, expected, actual } => {
+                fields.push(("url", url.as_str().into()));
+                fields.push(("expected", expected.as_str().into()));
+                fields.push(("actual", actual.as_str().into()));
+            },
+            TaskError::InvalidInput(detail) | TaskError::Panicked(detail) | TaskError::Other(detail) => {
+                fields.push(("detail", detail.as_str().into()));
+            },
+            TaskError::Cancelled => {}
+        }
+        fields.push(("message", self.to_string().into()));
+        Value::object(fields)
+    }
+}
+
+impl FromJson for TaskError {
+    fn from_json(value: &Value) -> Result<TaskError, String> {
+        let detail = || value.str_field("detail").map(str::to_string);
+        match value.str_field("kind")? {
+            "timeout" => Ok(TaskError::Timeout { after_ms: value.u128_field("after_ms")? }),
+            "network" => Ok(TaskError::Network { url: value.str_field("url")?.to_string(), message: detail()? }),
+            "checksum_mismatch" => Ok(TaskError::ChecksumMismatch {
+                url: value.str_field("url")?.to_string(),
+                expected: value.str_field("expected")?.to_string(),
+                actual: value.str_field("actual")?.to_string(),
+            }),
+            "invalid_input" => Ok(TaskError::InvalidInput(detail()?)),
+            "panicked" => Ok(TaskError::Panicked(detail()?)),
+            "cancelled" => Ok(TaskError::Cancelled),
+            "other" => Ok(TaskError::Other(detail()?)),
+            other => Err(format!("unknown error kind '{}'", other)),
+        }
+    }
+}
+
+fn circuit_name(state: CircuitState) -> &'static str {
+    match state {
+        CircuitState::Closed => "closed",
+        CircuitState::Open => "open",
+        CircuitState::HalfOpen => "half_open",
+    }
+}
+
+impl ToJson for SystemStats {
+    fn to_json(&self) -> Value {
+        let latency = self.latency.iter().map(|(task_type, latency)| {
+            let summary = Value::object([
+                ("count", latency.count().into()),
+                ("p50_ms", latency.percentile_ms(50.0).into()),
+                ("p95_ms", latency.percentile_ms(95.0).into()),
+                ("p99_ms", latency.percentile_ms(99.0).into()),
+                ("max_ms", latency.max_ms().into()),
+            ]);
+            (task_type.as_str(), summary)
+        });
+        Value::object([
+            ("tasks_completed", self.tasks_completed.into()),
+            ("tasks_failed", self.tasks_failed.into()),
+            ("errors", Value::object(self.errors.iter().map(|(&kind, &count)| (kind, count.into())))),
+            ("tasks_cancelled", self.tasks_cancelled.into()),
+            ("tasks_timed_out", self.tasks_timed_out.into()),
+            ("tasks_skipped", self.tasks_skipped.into()),
+            ("tasks_expired", self.tasks_expired.into()),
+            ("tasks_discarded", self.tasks_discarded.into()),
+            ("tasks_rejected", self.tasks_rejected.into()),
+            ("circuits", Value::object(self.circuits.iter().map(|(task_type, state)| (task_type.as_str(), circuit_name(*state).into())))),
+            ("worker_panics", self.worker_panics.into()),
+            ("retries", self.retries.into()),
+            ("peak_workers", self.peak_workers.into()),
+            ("total_duration_ms", self.total_duration_ms.into()),
+            ("latency", Value::object(latency)),
+        ])
+    }
+}
+
+// The same download-then-process flow as a streaming pipeline: each page
+// moves on to parsing as soon as it arrives instead of waiting on a queue
+pub fn run_pipeline(args: &Args) {
+    let pipeline = Pipeline::builder()
+        .stage("download", args.workers_or(3), |id: u32| {
+            thread::sleep(Duration::from_millis(50));
+            (id, format!("{},{},{},{}", id, id * 2, id * 3, id * 4))
+        })
+        .stage("parse", 2, |(id, body): (u32, String)| {
+            let values: Vec<u32> = body.split(',').filter_map(|v| v.parse().ok()).collect();
+            (id, values)
+        })
+        .stage("aggregate", 1, |(id, values): (u32, Vec<u32>)| {
+            (id, values.len(), values.iter().sum::<u32>())
+        })
+        .build();
+
+    for id in 1..=args.tasks_or(10) {
+        pipeline.send(id);
+    }
+
+    let mut results = pipeline.finish();
+    results.sort();
+    for (id, count, sum) in results.iter().filter(|_| !console::quiet()) {
+        println!("{}", console::line(Mark::Success, format_args!("Page {} aggregated: {} values, sum {}", id, count, sum)));
+    }
+    let total: u32 = results.iter().map(|(_, _, sum)| sum).sum();
+    println!("Pipeline total: {}", total);
+}
+
+// Downloads wait on the network, so get them started first
+fn priority_of(task: &Task) -> Priority {
+    match task {
+        Task::Download { .. } => Priority::High,
+        Task::Compute { .. } => Priority::Normal,
+        Task::Process { .. } => Priority::Low,
+        Task::Named { .. } => Priority::Normal,
+    }
 }
 
 // Helper functions to implement
-fn generate_tasks(count: u32) -> Vec<Task> {
+fn exit_with_error(path: &Path, err: impl std::fmt::Display) -> ! {
+    eprintln!("error: {}: {}", path.display(), err);
+    process::exit(2);
+}
+
+// A task file is a JSON array of tasks, e.g.
+//   [{"type": "compute", "id": 1, "iterations": 1000},
+//    {"type": "download", "id": 2, "url": "http://example.com/2"},
+//    {"type": "process", "id": 3, "data": [1, 2, 3]},
+//    {"type": "hash", "id": 4, "text": "hello"}]
+// Ids have to be unique; a process task depends on the download whose id
+// is one less, if there is one
+fn load_tasks(path: &Path) -> Result<Vec<Task>, String> {
+    let text = fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let Value::Array(items) = json::parse(&text)? else {
+        return Err("expected an array of tasks".to_string());
+    };
+
+    let mut seen = HashSet::new();
+    items
+        .iter()
+        .enumerate()
+        .map(|(i, item)| {
+            let task = Task::from_json(item).map_err(|err| format!("task {}: {}", i + 1, err))?;
+            let id = rcp::Task::id(&task);
+            // 0 has no download before it and the largest id is the poll's
+            if id == 0 || id == POLL_ID {
+                return Err(format!("task {}: id must be between 1 and {}", i + 1, POLL_ID - 1));
+            }
+            if !seen.insert(id) {
+                return Err(format!("task {}: id {} is used more than once", i + 1, id));
+            }
+            Ok(task)
+        })
+        .collect()
+}
+
+// A task sent to --listen. Whatever id the client gave is replaced by the
+// one the server picked. A "correlation_id" and a "metadata" object of
+// strings go along to the task's context
+fn listened_task(value: &Value, id: u32, pool: &ThreadPool, settings: &Arc<Settings>) -> Result<(Submitted, SubmitOptions), String> {
+    let Value::Object(fields) = value else {
+        return Err("expected a task object".to_string());
+    };
+    let mut fields: Vec<_> = fields.iter().filter(|(key, _)| key != "id").cloned().collect();
+    fields.push(("id".to_string(), id.into()));
+    let task = Task::from_json(&Value::Object(fields))?;
+    let mut options = SubmitOptions::new().priority(priority_of(&task));
+    if let Some(correlation) = jobs::correlation_id(value)? {
+        options = options.correlation_id(correlation);
+    }
+    match value.get("metadata") {
+        Some(Value::Object(entries)) => {
+            for (key, entry) in entries {
+                let Value::String(entry) = entry else {
+                    return Err("'metadata' values must be strings".to_string());
+                };
+                options = options.metadata(key.as_str(), entry.as_str());
+            }
+        },
+        Some(_) => return Err("'metadata' must be an object".to_string()),
+        None => {},
+    }
+    let task = Submitted::new(task, pool, settings).map_err(|err| err.to_string())?;
+    Ok((task, options))
+}
+
+// One line about how a task ended, for people to read
+// A result as one line behind its ✓ or ✗, and with the task's output or
+// error under it when --verbose
+fn rendered(result: &TaskResult) -> String {
+    let line = console::line(Mark::of(result), describe(result));
+    match result {
+        TaskResult::Success { output, .. } if console::verbose() => format!("{}\n    {}", line, output),
+        _ => line,
+    }
+}
+
+fn describe(result: &TaskResult) -> String {
+    match result {
+        TaskResult::Success {id, task_type, duration_ms, queued_ms, ..} => {
+            format!("Task {} ({}) completed in {}ms after {}ms queued", id, task_type, duration_ms, queued_ms)
+        },
+        TaskResult::Error {id, error, attempts, ..} => {
+            format!("Task {} failed after {} attempts: {}", id, attempts, error)
+        },
+        TaskResult::Cancelled {id, task_type, ..} => {
+            format!("Task {} ({}) cancelled", id, task_type)
+        },
+        TaskResult::Panicked {id, task_type, message, ..} => {
+            format!("Task {} ({}) panicked: {}", id, task_type, message)
+        },
+        TaskResult::TimedOut {id, task_type, timeout_ms, attempts, ..} => {
+            format!("Task {} ({}) timed out after {}ms ({} attempts)", id, task_type, timeout_ms, attempts)
+        },
+        TaskResult::DependencyFailed {id, task_type, dependency, ..} => {
+            format!("Task {} ({}) skipped, task {} did not succeed", id, task_type, dependency)
+        },
+        TaskResult::Expired {id, task_type, late_ms, ..} => {
+            format!("Task {} ({}) expired {}ms past its deadline", id, task_type, late_ms)
+        },
+        TaskResult::CircuitOpen {id, task_type, ..} => {
+            format!("Task {} ({}) rejected, too many {} tasks failing", id, task_type, task_type)
+        },
+        TaskResult::Dropped {id, task_type, ..} => {
+            format!("Task {} ({}) dropped from the queue", id, task_type)
+        }
+    }
+}
+
+fn generate_tasks(count: u32, args: &Args) -> Vec<Task> {
     use Task::*;
+    if let Some(seed) = args.seed {
+        return (1..=count).map(|id| random_task(id, seed, args)).collect();
+    }
     let mut tasks = vec![];
 
     for i in 1..=count {
         let task = match i % 3 {
             0 => Compute { id: i, iterations: 1000 },
-            1 => Download { id: i, url: format!("http://example.com/{}", i) },
+            1 => Download { id: i, url: format!("http://example.com/{}", i), fails: args.should_fail("download", i, 7), sha256: None, save_to: None },
+            // An empty batch now and then, which the task turns down
+            _ if i.is_multiple_of(17) => Process { id: i, data: vec![] },
+            // Now and then a batch big enough to be split up
+            _ if i.is_multiple_of(7) => Process { id: i, data: (1..=1000).collect() },
             _ => Process { id: i, data: vec![1, 2, 3, 4, 5] },
         };
         tasks.push(task);
@@ -172,22 +1121,321 @@ fn generate_tasks(count: u32) -> Vec<Task> {
     tasks
 }
 
-fn process_compute(id: u32, iterations: u32) -> Result<String, String> {
-    thread::sleep(Duration::from_millis(50));
-    Ok(format!("Computed {} iterations", iterations))
+// About the same mix as above, but drawn at random
+fn random_task(id: u32, seed: u64, args: &Args) -> Task {
+    let mut rng = Rng::for_task(seed, id, "task");
+    match rng.below(3) {
+        0 => Task::Compute { id, iterations: 500 + rng.below(1000) as u32 },
+        1 => Task::Download { id, url: format!("http://example.com/{}", id), fails: args.should_fail("download", id, 7), sha256: None, save_to: None },
+        _ => match rng.below(17) {
+            0 => Task::Process { id, data: vec![] },
+            1 | 2 => Task::Process { id, data: (1..=500 + rng.below(1000) as u32).collect() },
+            _ => Task::Process { id, data: (0..1 + rng.below(10)).map(|_| rng.below(100) as u32).collect() },
+        },
+    }
+}
+
+fn process_compute(_id: u32, iterations: u32, ctx: &TaskContext) -> Result<String, TaskError> {
+    // Count primes the slow way: each iteration checks another block of
+    // numbers by trial division. Checking cancellation between blocks
+    // keeps the task responsive without slowing the arithmetic down
+    const NUMBERS_PER_ITERATION: u32 = 30;
+    const BATCH: u32 = 100;
+
+    let mut primes = 0;
+    for batch_start in (0..iterations).step_by(BATCH as usize) {
+        ctx.check_cancelled()?;
+        let batch_end = (batch_start + BATCH).min(iterations);
+        let numbers = batch_start * NUMBERS_PER_ITERATION..batch_end * NUMBERS_PER_ITERATION;
+        primes += numbers.filter(|&n| is_prime(n)).count();
+        ctx.progress(f64::from(batch_end) / f64::from(iterations));
+    }
+    Ok(format!("Computed {} iterations, found {} primes", iterations, primes))
+}
+
+fn is_prime(n: u32) -> bool {
+    if n < 2 {
+        return false;
+    }
+    (2..).take_while(|d| d * d <= n).all(|d| !n.is_multiple_of(d))
+}
+
+fn process_download(id: u32, url: &str, fails: bool, sha256: Option<&str>, settings: &Settings, ctx: &TaskContext) -> Result<String, TaskError> {
+    // Bodies are checked before they're cached, so only good ones are kept,
+    // and again when they come out of the cache in case this task expects
+    // different data
+    let verified_in = Cell::new(Duration::ZERO);
+    let check = |body: &[u8]| -> Result<(), TaskError> {
+        verified_in.set(verified_in.get() + verify(url, body, sha256)?);
+        Ok(())
+    };
+    let fetch_checked = || -> Result<Vec<u8>, TaskError> {
+        let body = fetch(id, url, fails, settings, ctx)?;
+        check(&body)?;
+        Ok(body)
+    };
+    let (body, cached) = match &settings.cache {
+        Some(cache) => {
+            let (body, cached) = cache.get_or_fetch(url, fetch_checked)?;
+            if cached {
+                check(&body)?;
+            }
+            (body, cached)
+        },
+        None => (fetch_checked()?.into(), false),
+    };
+
+    let mut message = format!("Downloaded {} bytes from {}", body.len(), url);
+    if cached {
+        message += " (cached)";
+    }
+    if sha256.is_some() {
+        message += &format!(", checksum verified in {}µs", verified_in.get().as_micros());
+    }
+    Ok(message)
+}
+
+// Hashes `body` if there's a checksum to hold it to, and says how long
+// that took
+fn verify(url: &str, body: &[u8], expected: Option<&str>) -> Result<Duration, TaskError> {
+    let Some(expected) = expected else { return Ok(Duration::ZERO) };
+    let started = Instant::now();
+    let actual = sha256::hex_digest(body);
+    if actual != expected {
+        return Err(TaskError::ChecksumMismatch { url: url.to_string(), expected: expected.to_string(), actual });
+    }
+    Ok(started.elapsed())
+}
+
+// Streams the body into `path` as it arrives, hashing it on the way if
+// there's a checksum to check, so a large file never sits in memory. It's
+// written to `path` with `.part` on the end and only renamed once it's all
+// there and checked, so a failed download leaves nothing behind
+fn save_download(id: u32, url: &str, fails: bool, sha256: Option<&str>, path: &Path, settings: &Settings, ctx: &TaskContext) -> Result<String, TaskError> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".part");
+    let partial = PathBuf::from(partial);
+    let saved = fetch_with(id, url, fails, settings, ctx, |body| stream_to(&partial, body, url, sha256.is_some()));
+    let renamed = saved.and_then(|saved| {
+        if let (Some(expected), Some(actual)) = (sha256, &saved.digest)
+            && actual != expected
+        {
+            return Err(TaskError::ChecksumMismatch { url: url.to_string(), expected: expected.to_string(), actual: actual.clone() });
+        }
+        fs::rename(&partial, path).map_err(|err| TaskError::Other(format!("{}: {}", path.display(), err)))?;
+        Ok(saved)
+    });
+    let saved = renamed.inspect_err(|_| {
+        let _ = fs::remove_file(&partial);
+    })?;
+
+    let per_second = saved.bytes as f64 / saved.took.as_secs_f64().max(0.000_001);
+    let mut message = format!("Saved {} bytes from {} to {} at {:.1} KB/s", saved.bytes, url, path.display(), per_second / 1024.0);
+    if sha256.is_some() {
+        message += &format!(", checksum verified in {}µs", saved.hashing.as_micros());
+    }
+    Ok(message)
+}
+
+// What streaming a body to disk came to
+struct Saved {
+    bytes: u64,
+    digest: Option<String>,
+    // Writing the whole body, and the part of that spent hashing it
+    took: Duration,
+    hashing: Duration,
 }
 
-fn process_download(id: u32, url: &str) -> Result<String, String> {
-    thread::sleep(Duration::from_millis(100));
-    if id % 7 == 0 {
-        Err("Download failed".to_string())
+fn stream_to(path: &Path, body: &mut dyn Read, url: &str, hash: bool) -> Result<Saved, TaskError> {
+    let file_error = |err: io::Error| TaskError::Other(format!("{}: {}", path.display(), err));
+    let mut file = BufWriter::new(File::create(path).map_err(file_error)?);
+    let mut hasher = hash.then(Sha256::new);
+    let mut buf = vec![0; 64 * 1024];
+    let started = Instant::now();
+    let mut saved = Saved { bytes: 0, digest: None, took: Duration::ZERO, hashing: Duration::ZERO };
+    loop {
+        let read = match body.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(TaskError::Network { url: url.to_string(), message: err.to_string() }),
+        };
+        if let Some(hasher) = &mut hasher {
+            let hashing = Instant::now();
+            hasher.update(&buf[..read]);
+            saved.hashing += hashing.elapsed();
+        }
+        file.write_all(&buf[..read]).map_err(file_error)?;
+        saved.bytes += read as u64;
+    }
+    file.flush().map_err(file_error)?;
+    saved.digest = hasher.map(Sha256::finish);
+    saved.took = started.elapsed();
+    Ok(saved)
+}
+
+// The whole body in memory
+fn fetch(id: u32, url: &str, fails: bool, settings: &Settings, ctx: &TaskContext) -> Result<Vec<u8>, TaskError> {
+    fetch_with(id, url, fails, settings, ctx, |body| {
+        let mut data = Vec::new();
+        body.read_to_end(&mut data).map_err(|err| TaskError::Network { url: url.to_string(), message: err.to_string() })?;
+        Ok(data)
+    })
+}
+
+// Hands `read` the body to read as it arrives, no faster than the
+// bandwidth budget allows if there is one
+#[cfg(not(feature = "http"))]
+fn fetch_with<T>(id: u32, url: &str, fails: bool, settings: &Settings, ctx: &TaskContext, read: impl FnOnce(&mut dyn Read) -> Result<T, TaskError>) -> Result<T, TaskError> {
+    // Simulate a server that takes 2s to answer, giving up on it as soon as
+    // the task times out or is called off
+    if id.is_multiple_of(10) {
+        let asked = Instant::now();
+        while asked.elapsed() < Duration::from_secs(2) {
+            ctx.check_cancelled()?;
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+    thread::sleep(settings.download_time(id));
+    if fails {
+        Err(TaskError::Network { url: url.to_string(), message: "download failed".to_string() })
     } else {
-        Ok(format!("Downloaded from {}", url))
+        let body = format!("Contents of {}", url);
+        within(settings.bandwidth.as_ref(), body.as_bytes(), read)
+    }
+}
+
+// No more than this many connections to one host at a time, and no more
+// than this many requests to it a second
+#[cfg(feature = "http")]
+const CONNECTIONS_PER_HOST: usize = 2;
+#[cfg(feature = "http")]
+const REQUESTS_PER_HOST_PER_SECOND: f64 = 5.0;
+
+#[cfg(feature = "http")]
+static HOSTS: std::sync::LazyLock<crate::http::HostLimits> =
+    std::sync::LazyLock::new(|| crate::http::HostLimits::new(CONNECTIONS_PER_HOST, REQUESTS_PER_HOST_PER_SECOND));
+
+// With the `http` feature downloads hit the network for real and the
+// status code decides whether they succeeded
+#[cfg(feature = "http")]
+fn fetch_with<T>(_id: u32, url: &str, _fails: bool, settings: &Settings, _ctx: &TaskContext, read: impl FnOnce(&mut dyn Read) -> Result<T, TaskError>) -> Result<T, TaskError> {
+    let network_error = |message| TaskError::Network { url: url.to_string(), message };
+    HOSTS
+        .with(url, || {
+            let mut response = crate::http::open(url)?;
+            if !(200..300).contains(&response.status) {
+                return Err(format!("answered {}", response.status));
+            }
+            // The host's connection is held until the body has been read
+            Ok(within(settings.bandwidth.as_ref(), &mut response, read))
+        })
+        .map_err(network_error)?
+}
+
+// Gives `read` the body, throttled if there's a bandwidth budget
+fn within<R: Read, T>(bandwidth: Option<&Bandwidth>, mut body: R, read: impl FnOnce(&mut dyn Read) -> Result<T, TaskError>) -> Result<T, TaskError> {
+    match bandwidth {
+        Some(bandwidth) => read(&mut bandwidth.throttle(body)),
+        None => read(&mut body),
     }
 }
 
-fn process_data(id: u32, data: Vec<u32>) -> Result<String, String> {
-    thread::sleep(Duration::from_millis(75));
-    let sum: u32 = data.iter().sum();
-    Ok(format!("Processed {} items, sum: {}", data.len(), sum))
+fn process_data(_id: u32, data: &[u32], chunk_size: usize, ctx: &TaskContext) -> Result<String, TaskError> {
+    if data.is_empty() {
+        return Err(TaskError::InvalidInput("nothing to process".to_string()));
+    }
+    // Big payloads are cut into chunks that other workers sum at the same
+    // time, and the partial sums added up here
+    let sum: u64 = if data.len() > chunk_size {
+        let chunks: Vec<_> = data
+            .chunks(chunk_size)
+            .map(|chunk| {
+                let chunk = chunk.to_vec();
+                rcp::spawn(move || sum_chunk(&chunk))
+            })
+            .collect();
+        let mut sum = 0;
+        let count = chunks.len();
+        for (done, chunk) in chunks.into_iter().enumerate() {
+            sum += chunk.wait().ok_or_else(|| TaskError::Panicked("summing a chunk".to_string()))?;
+            ctx.progress((done + 1) as f64 / count as f64);
+        }
+        sum
+    } else {
+        sum_chunk(data)
+    };
+    let mean = sum / data.len() as u64;
+    Ok(format!("Processed {} items, sum: {}, mean: {}", data.len(), sum, mean))
+}
+
+// Some setup plus a little more for every item
+fn sum_chunk(data: &[u32]) -> u64 {
+    thread::sleep(Duration::from_millis(75) + Duration::from_micros(100) * data.len() as u32);
+    data.iter().map(|&item| u64::from(item)).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Out to text and back, which must come out the same again
+    fn round_trip<T: ToJson + FromJson>(item: &T) -> Value {
+        let value = item.to_json();
+        let read = T::from_json(&json::parse(&value.to_string()).unwrap()).unwrap();
+        assert_eq!(read.to_json(), value);
+        value
+    }
+
+    #[test]
+    fn tasks_round_trip() {
+        round_trip(&Task::Compute { id: 1, iterations: 500 });
+        round_trip(&Task::Process { id: 2, data: vec![1, 2, 3] });
+        let download = Task::Download { id: 3, url: "http://example.com/a".to_string(), fails: true, sha256: Some("ab12".to_string()), save_to: Some(PathBuf::from("out/a")) };
+        round_trip(&download);
+        round_trip(&Task::Download { id: 4, url: "http://example.com/b".to_string(), fails: false, sha256: None, save_to: None });
+        let named = json::parse(r#"{"type": "resize", "id": 5, "width": 64}"#).unwrap();
+        assert_eq!(round_trip(&Task::from_json(&named).unwrap()), named);
+    }
+
+    #[test]
+    fn results_round_trip_with_their_correlation_ids() {
+        let task_type = || "download".to_string();
+        let correlation_id = Some("req-7".to_string());
+        let errors = [
+            TaskError::Timeout { after_ms: 30 },
+            TaskError::Network { url: "http://example.com".to_string(), message: "refused".to_string() },
+            TaskError::ChecksumMismatch { url: "http://example.com".to_string(), expected: "aa".to_string(), actual: "bb".to_string() },
+            TaskError::InvalidInput("empty".to_string()),
+            TaskError::Panicked("boom".to_string()),
+            TaskError::Cancelled,
+            TaskError::Other("odd".to_string()),
+        ];
+        let mut results: Vec<TaskResult> = errors.into_iter().map(|error| TaskResult::Error { id: 1, task_type: task_type(), correlation_id: correlation_id.clone(), error, attempts: 2 }).collect();
+        results.extend([
+            TaskResult::Success { id: 2, task_type: task_type(), correlation_id: correlation_id.clone(), output: TaskOutput::new("done"), duration_ms: 12, queued_ms: 3, attempts: 1 },
+            TaskResult::Cancelled { id: 3, task_type: task_type(), correlation_id: None },
+            TaskResult::Panicked { id: 4, task_type: task_type(), correlation_id: correlation_id.clone(), message: "boom".to_string() },
+            TaskResult::TimedOut { id: 5, task_type: task_type(), correlation_id: correlation_id.clone(), timeout_ms: 100, attempts: 3 },
+            TaskResult::DependencyFailed { id: 6, task_type: task_type(), correlation_id: correlation_id.clone(), dependency: 2 },
+            TaskResult::Expired { id: 7, task_type: task_type(), correlation_id: correlation_id.clone(), late_ms: 40 },
+            TaskResult::CircuitOpen { id: 8, task_type: task_type(), correlation_id: correlation_id.clone() },
+            TaskResult::Dropped { id: 9, task_type: task_type(), correlation_id: correlation_id.clone() },
+        ]);
+        for result in &results {
+            let value = round_trip(result);
+            assert_eq!(value.get("correlation_id").is_some(), result.correlation_id().is_some());
+        }
+    }
+
+    #[test]
+    fn bad_tasks_and_results_say_why() {
+        let task = |text: &str| Task::from_json(&json::parse(text).unwrap()).unwrap_err();
+        assert_eq!(task(r#"{"type": "process", "id": 1, "data": []}"#), "'data' must have at least one number");
+        assert_eq!(task(r#"{"type": "process", "id": 1, "data": [1, -2]}"#), "'data' must be an array of whole numbers");
+        let result = |text: &str| TaskResult::from_json(&json::parse(text).unwrap()).unwrap_err();
+        assert_eq!(result(r#"{"id": 1, "task_type": "compute", "status": "lost"}"#), "unknown status 'lost'");
+        assert_eq!(result(r#"{"id": 1, "task_type": "compute", "status": "error", "attempts": 1, "error": {"kind": "gone"}}"#), "unknown error kind 'gone'");
+        assert!(result(r#"{"id": 1, "task_type": "compute", "correlation_id": 5, "status": "cancelled"}"#).contains("correlation_id"));
+    }
 }
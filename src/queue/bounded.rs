@@ -0,0 +1,143 @@
+use std::sync::{Condvar, MutexGuard};
+use std::time::Duration;
+
+use super::{JobInfo, Pop, TaskQueue, TryPushError};
+use crate::sync::{Mutex, Recover};
+
+struct Slots {
+    /// Items pushed and not yet popped or cleared, including any on their
+    /// way into the inner queue.
+    taken: usize,
+    closed: bool,
+}
+
+/// Caps any other [`TaskQueue`] at `capacity` waiting items. Once it's
+/// full, [`push`](TaskQueue::push) waits for a worker to take something
+/// and [`try_push`](TaskQueue::try_push) fails with
+/// [`TryPushError::Full`].
+pub struct BoundedQueue<Q> {
+    inner: Q,
+    capacity: usize,
+    slots: Mutex<Slots>,
+    not_full: Condvar,
+}
+
+impl<Q> BoundedQueue<Q> {
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn new(inner: Q, capacity: usize) -> Self {
+        assert!(
+            capacity > 0,
+            "a bounded queue needs room for at least one item"
+        );
+        BoundedQueue {
+            inner,
+            capacity,
+            slots: Mutex::new(Slots {
+                taken: 0,
+                closed: false,
+            }),
+            not_full: Condvar::new(),
+        }
+    }
+
+    /// Hands `item` to the inner queue in the slot just taken, giving the
+    /// slot back if the inner queue refuses it.
+    fn insert<T>(&self, mut slots: MutexGuard<'_, Slots>, item: T, info: JobInfo) -> Result<(), T>
+    where
+        Q: TaskQueue<T>,
+    {
+        slots.taken += 1;
+        drop(slots);
+        self.inner.push(item, info).inspect_err(|_| self.release(1))
+    }
+
+    fn release(&self, count: usize) {
+        let mut slots = self.slots.lock().recover();
+        slots.taken = slots.taken.saturating_sub(count);
+        drop(slots);
+        self.not_full.notify_all();
+    }
+}
+
+impl<Q: TaskQueue<T>, T> TaskQueue<T> for BoundedQueue<Q> {
+    fn push(&self, item: T, info: JobInfo) -> Result<(), T> {
+        let mut slots = self.slots.lock().recover();
+        while !slots.closed && slots.taken >= self.capacity {
+            slots = self.not_full.wait(slots).recover();
+        }
+        if slots.closed {
+            return Err(item);
+        }
+        self.insert(slots, item, info)
+    }
+
+    fn try_push(&self, item: T, info: JobInfo) -> Result<(), TryPushError<T>> {
+        let slots = self.slots.lock().recover();
+        if slots.closed {
+            return Err(TryPushError::Closed(item));
+        }
+        if slots.taken >= self.capacity {
+            return Err(TryPushError::Full(item));
+        }
+        self.insert(slots, item, info).map_err(TryPushError::Closed)
+    }
+
+    fn pop(&self, worker: usize, timeout: Option<Duration>) -> Pop<T> {
+        let popped = self.inner.pop(worker, timeout);
+        if let Pop::Item(_) = popped {
+            self.release(1);
+        }
+        popped
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn close(&self) {
+        self.slots.lock().recover().closed = true;
+        self.inner.close();
+        self.not_full.notify_all();
+    }
+
+    fn clear(&self) -> usize {
+        let dropped = self.inner.clear();
+        self.release(dropped);
+        dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::queue::FifoQueue;
+
+    #[test]
+    fn a_full_queue_frees_room_when_popped_or_cleared() {
+        let queue = BoundedQueue::new(FifoQueue::new(), 2);
+        queue.push(1, JobInfo::default()).unwrap();
+        queue.try_push(2, JobInfo::default()).ok().unwrap();
+        assert!(matches!(
+            queue.try_push(3, JobInfo::default()),
+            Err(TryPushError::Full(3))
+        ));
+        assert!(matches!(queue.pop(0, None), Pop::Item(1)));
+        queue.try_push(3, JobInfo::default()).ok().unwrap();
+        assert_eq!(queue.clear(), 2);
+        queue.try_push(4, JobInfo::default()).ok().unwrap();
+        queue.try_push(5, JobInfo::default()).ok().unwrap();
+        queue.close();
+        assert!(matches!(
+            queue.try_push(6, JobInfo::default()),
+            Err(TryPushError::Closed(6))
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "room for at least one item")]
+    fn a_zero_capacity_is_refused() {
+        let _ = BoundedQueue::new(FifoQueue::<()>::new(), 0);
+    }
+}
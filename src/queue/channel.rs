@@ -0,0 +1,166 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::time::{Duration, Instant};
+
+use super::{JobInfo, Pop, TaskQueue, TryPushError};
+use crate::sync::{Mutex, Recover};
+
+enum Sender<T> {
+    Unbounded(mpsc::Sender<T>),
+    Bounded(SyncSender<T>),
+}
+
+// Derived Clone would needlessly require `T: Clone`.
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        match self {
+            Sender::Unbounded(sender) => Sender::Unbounded(sender.clone()),
+            Sender::Bounded(sender) => Sender::Bounded(sender.clone()),
+        }
+    }
+}
+
+/// Workers receiving straight from a channel. std's receivers can't be
+/// shared, so workers take turns on it behind a mutex; any MPMC channel
+/// with the same send/receive/disconnect behavior could stand in.
+pub(crate) struct ChannelQueue<T> {
+    /// `None` once the queue is closed, which disconnects the receiver.
+    sender: Mutex<Option<Sender<T>>>,
+    receiver: Mutex<Receiver<T>>,
+    len: AtomicUsize,
+}
+
+impl<T> ChannelQueue<T> {
+    pub(crate) fn new(capacity: Option<usize>) -> Self {
+        let (sender, receiver) = match capacity {
+            Some(capacity) => {
+                let (sender, receiver) = mpsc::sync_channel(capacity);
+                (Sender::Bounded(sender), receiver)
+            }
+            None => {
+                let (sender, receiver) = mpsc::channel();
+                (Sender::Unbounded(sender), receiver)
+            }
+        };
+        ChannelQueue {
+            sender: Mutex::new(Some(sender)),
+            receiver: Mutex::new(receiver),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    /// A clone of the sender, so a blocked send doesn't hold up `close`.
+    fn sender(&self) -> Option<Sender<T>> {
+        self.sender.lock().recover().clone()
+    }
+}
+
+impl<T: Send> TaskQueue<T> for ChannelQueue<T> {
+    fn push(&self, item: T, _info: JobInfo) -> Result<(), T> {
+        let Some(sender) = self.sender() else {
+            return Err(item);
+        };
+        // Counted first so a fast worker never takes `len` below zero.
+        self.len.fetch_add(1, Ordering::Relaxed);
+        let sent = match sender {
+            Sender::Unbounded(sender) => sender.send(item).map_err(|err| err.0),
+            Sender::Bounded(sender) => sender.send(item).map_err(|err| err.0),
+        };
+        if sent.is_err() {
+            self.len.fetch_sub(1, Ordering::Relaxed);
+        }
+        sent
+    }
+
+    fn try_push(&self, item: T, _info: JobInfo) -> Result<(), TryPushError<T>> {
+        let Some(sender) = self.sender() else {
+            return Err(TryPushError::Closed(item));
+        };
+        self.len.fetch_add(1, Ordering::Relaxed);
+        let sent = match sender {
+            Sender::Unbounded(sender) => {
+                sender.send(item).map_err(|err| TryPushError::Closed(err.0))
+            }
+            Sender::Bounded(sender) => sender.try_send(item).map_err(|err| match err {
+                TrySendError::Full(item) => TryPushError::Full(item),
+                TrySendError::Disconnected(item) => TryPushError::Closed(item),
+            }),
+        };
+        if sent.is_err() {
+            self.len.fetch_sub(1, Ordering::Relaxed);
+        }
+        sent
+    }
+
+    fn pop(&self, _worker: usize, timeout: Option<Duration>) -> Pop<T> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let receiver = self.receiver.lock().recover();
+        let received = match deadline {
+            None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            // Waiting for the lock may already have used up the timeout.
+            Some(deadline) => {
+                receiver.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            }
+        };
+        match received {
+            Ok(item) => {
+                self.len.fetch_sub(1, Ordering::Relaxed);
+                Pop::Item(item)
+            }
+            Err(RecvTimeoutError::Timeout) => Pop::TimedOut,
+            Err(RecvTimeoutError::Disconnected) => Pop::Closed,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    fn close(&self) {
+        self.sender.lock().recover().take();
+    }
+
+    fn clear(&self) -> usize {
+        let receiver = self.receiver.lock().recover();
+        let mut dropped = 0;
+        while receiver.try_recv().is_ok() {
+            self.len.fetch_sub(1, Ordering::Relaxed);
+            dropped += 1;
+        }
+        dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_bounded_channel_refuses_when_full_and_drains_after_close() {
+        let queue = ChannelQueue::new(Some(2));
+        queue.push(1, JobInfo::default()).unwrap();
+        queue.push(2, JobInfo::default()).unwrap();
+        assert!(matches!(
+            queue.try_push(3, JobInfo::default()),
+            Err(TryPushError::Full(3))
+        ));
+        assert_eq!(queue.len(), 2);
+        queue.close();
+        assert!(queue.push(4, JobInfo::default()).is_err());
+        assert!(matches!(queue.pop(0, None), Pop::Item(1)));
+        assert!(matches!(queue.pop(0, None), Pop::Item(2)));
+        assert!(matches!(queue.pop(0, None), Pop::Closed));
+        assert_eq!(queue.len(), 0);
+    }
+
+    #[test]
+    fn clear_counts_what_it_drops() {
+        let queue = ChannelQueue::new(None);
+        for item in 0..3 {
+            queue.push(item, JobInfo::default()).unwrap();
+        }
+        assert_eq!(queue.clear(), 3);
+        assert!(queue.is_empty());
+        assert!(matches!(queue.pop(0, Some(Duration::ZERO)), Pop::TimedOut));
+    }
+}
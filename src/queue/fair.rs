@@ -0,0 +1,252 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::{Arc, Condvar, MutexGuard};
+use std::time::{Duration, Instant};
+
+use super::{JobInfo, Pop, TaskQueue, TryPushError};
+use crate::sync::{Mutex, Recover};
+
+/// How far a flow of weight 1 moves on for every item it has taken.
+const STRIDE: u64 = 1 << 20;
+
+/// The items of one task type, waiting their turn.
+struct Flow<T> {
+    items: VecDeque<T>,
+    /// How much of its share the flow has used up. The flow furthest
+    /// behind goes next.
+    pass: u64,
+    stride: u64,
+}
+
+struct State<T> {
+    /// By task type; `None` for closures.
+    flows: BTreeMap<Option<Arc<str>>, Flow<T>>,
+    len: usize,
+    /// Pass of the flow served last. A flow that went idle picks up from
+    /// here rather than claiming the turns it missed.
+    now: u64,
+    closed: bool,
+}
+
+/// A blocking queue that takes turns between task types in proportion to
+/// their weights, so a flood of one type can't starve the rest: with
+/// `download` at weight 2 and everything else at 1, busy workers start two
+/// downloads for every compute task. Within a type it's first in, first
+/// out. Ignores [`Priority`](crate::Priority). This is what
+/// [`Scheduler::WeightedFair`](crate::Scheduler::WeightedFair) uses.
+pub struct FairQueue<T> {
+    weights: HashMap<String, u32>,
+    state: Mutex<State<T>>,
+    capacity: Option<usize>,
+    available: Condvar,
+    not_full: Condvar,
+}
+
+impl<T> FairQueue<T> {
+    /// An unbounded queue where every task type weighs 1.
+    pub fn new() -> Self {
+        FairQueue::with_capacity(None)
+    }
+
+    /// Optionally bounded so producers wait instead of growing it without
+    /// limit.
+    pub(crate) fn with_capacity(capacity: Option<usize>) -> Self {
+        FairQueue {
+            weights: HashMap::new(),
+            state: Mutex::new(State {
+                flows: BTreeMap::new(),
+                len: 0,
+                now: 0,
+                closed: false,
+            }),
+            capacity,
+            available: Condvar::new(),
+            not_full: Condvar::new(),
+        }
+    }
+
+    /// Gives `task_type` `weight` turns for every one a type of weight 1
+    /// gets.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `weight` is zero.
+    pub fn weight(mut self, task_type: impl Into<String>, weight: u32) -> Self {
+        assert!(weight > 0, "a task type needs a weight of at least 1");
+        self.weights.insert(task_type.into(), weight);
+        self
+    }
+
+    fn is_full(&self, state: &State<T>) -> bool {
+        self.capacity.is_some_and(|capacity| state.len >= capacity)
+    }
+
+    fn insert(&self, mut state: MutexGuard<'_, State<T>>, item: T, info: JobInfo) {
+        let weight = info
+            .task_type
+            .as_deref()
+            .and_then(|task_type| self.weights.get(task_type))
+            .copied()
+            .unwrap_or(1);
+        let now = state.now;
+        let flow = state.flows.entry(info.task_type).or_insert_with(|| Flow {
+            items: VecDeque::new(),
+            pass: now,
+            stride: STRIDE / u64::from(weight),
+        });
+        if flow.items.is_empty() {
+            flow.pass = flow.pass.max(now);
+        }
+        flow.items.push_back(item);
+        state.len += 1;
+        drop(state);
+        self.available.notify_one();
+    }
+
+    fn take(state: &mut State<T>) -> Option<T> {
+        let flow = state
+            .flows
+            .values_mut()
+            .filter(|flow| !flow.items.is_empty())
+            .min_by_key(|flow| flow.pass)?;
+        let item = flow.items.pop_front()?;
+        state.now = flow.pass;
+        flow.pass += flow.stride;
+        state.len -= 1;
+        Some(item)
+    }
+}
+
+impl<T> Default for FairQueue<T> {
+    fn default() -> Self {
+        FairQueue::new()
+    }
+}
+
+impl<T: Send> TaskQueue<T> for FairQueue<T> {
+    fn push(&self, item: T, info: JobInfo) -> Result<(), T> {
+        let mut state = self.state.lock().recover();
+        while !state.closed && self.is_full(&state) {
+            state = self.not_full.wait(state).recover();
+        }
+        if state.closed {
+            return Err(item);
+        }
+        self.insert(state, item, info);
+        Ok(())
+    }
+
+    fn try_push(&self, item: T, info: JobInfo) -> Result<(), TryPushError<T>> {
+        let state = self.state.lock().recover();
+        if state.closed {
+            return Err(TryPushError::Closed(item));
+        }
+        if self.is_full(&state) {
+            return Err(TryPushError::Full(item));
+        }
+        self.insert(state, item, info);
+        Ok(())
+    }
+
+    fn pop(&self, _worker: usize, timeout: Option<Duration>) -> Pop<T> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut state = self.state.lock().recover();
+        loop {
+            if let Some(item) = Self::take(&mut state) {
+                drop(state);
+                self.not_full.notify_one();
+                return Pop::Item(item);
+            }
+            if state.closed {
+                return Pop::Closed;
+            }
+            state = match deadline {
+                None => self.available.wait(state).recover(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Pop::TimedOut;
+                    }
+                    self.available
+                        .wait_timeout(state, deadline - now)
+                        .recover()
+                        .0
+                }
+            };
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.state.lock().recover().len
+    }
+
+    fn close(&self) {
+        self.state.lock().recover().closed = true;
+        self.available.notify_all();
+        self.not_full.notify_all();
+    }
+
+    fn clear(&self) -> usize {
+        let mut state = self.state.lock().recover();
+        let dropped = state.len;
+        state.flows.clear();
+        state.len = 0;
+        drop(state);
+        self.not_full.notify_all();
+        dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn of(task_type: &str) -> JobInfo {
+        JobInfo {
+            task_type: Some(Arc::from(task_type)),
+            ..JobInfo::default()
+        }
+    }
+
+    fn drain<T: Send>(queue: &FairQueue<T>) -> Vec<T> {
+        let mut items = Vec::new();
+        while let Pop::Item(item) = queue.pop(0, Some(Duration::ZERO)) {
+            items.push(item);
+        }
+        items
+    }
+
+    #[test]
+    fn types_take_turns_by_weight() {
+        let queue = FairQueue::new().weight("a", 2);
+        for _ in 0..6 {
+            queue.push('a', of("a")).unwrap();
+        }
+        for _ in 0..3 {
+            queue.push('b', of("b")).unwrap();
+        }
+        let order: String = drain(&queue).into_iter().collect();
+        assert_eq!(order, "abaabaaba");
+    }
+
+    #[test]
+    fn an_idle_type_doesnt_claim_the_turns_it_missed() {
+        let queue = FairQueue::new();
+        for _ in 0..4 {
+            queue.push('a', of("a")).unwrap();
+        }
+        assert!(matches!(queue.pop(0, None), Pop::Item('a')));
+        assert!(matches!(queue.pop(0, None), Pop::Item('a')));
+        for _ in 0..2 {
+            queue.push('b', of("b")).unwrap();
+        }
+        // One turn to catch up with `a`, not the two it sat out.
+        let order: String = drain(&queue).into_iter().collect();
+        assert_eq!(order, "baba");
+    }
+
+    #[test]
+    #[should_panic(expected = "weight of at least 1")]
+    fn a_zero_weight_is_refused() {
+        let _ = FairQueue::<()>::new().weight("a", 0);
+    }
+}
@@ -0,0 +1,165 @@
+use std::collections::VecDeque;
+use std::sync::{Condvar, MutexGuard};
+use std::time::{Duration, Instant};
+
+use super::{JobInfo, Pop, TaskQueue, TryPushError};
+use crate::sync::{Mutex, Recover};
+
+struct State<T> {
+    items: VecDeque<T>,
+    closed: bool,
+}
+
+/// A blocking first in, first out queue shared by the pool's workers: a
+/// `VecDeque` behind a mutex, with condition variables to wait on instead
+/// of a channel. Ignores [`Priority`](crate::Priority). This is what
+/// [`Scheduler::Fifo`](crate::Scheduler::Fifo) uses.
+pub struct FifoQueue<T> {
+    state: Mutex<State<T>>,
+    capacity: Option<usize>,
+    available: Condvar,
+    not_full: Condvar,
+}
+
+impl<T> FifoQueue<T> {
+    /// An unbounded queue. Wrap it in a
+    /// [`BoundedQueue`](crate::BoundedQueue) to cap it.
+    pub fn new() -> Self {
+        FifoQueue::with_capacity(None)
+    }
+
+    /// Optionally bounded so producers wait instead of growing it without
+    /// limit.
+    pub(crate) fn with_capacity(capacity: Option<usize>) -> Self {
+        FifoQueue {
+            state: Mutex::new(State {
+                items: VecDeque::new(),
+                closed: false,
+            }),
+            capacity,
+            available: Condvar::new(),
+            not_full: Condvar::new(),
+        }
+    }
+
+    fn is_full(&self, state: &State<T>) -> bool {
+        self.capacity
+            .is_some_and(|capacity| state.items.len() >= capacity)
+    }
+
+    fn insert(&self, mut state: MutexGuard<'_, State<T>>, item: T) {
+        state.items.push_back(item);
+        drop(state);
+        self.available.notify_one();
+    }
+}
+
+impl<T> Default for FifoQueue<T> {
+    fn default() -> Self {
+        FifoQueue::new()
+    }
+}
+
+impl<T: Send> TaskQueue<T> for FifoQueue<T> {
+    fn push(&self, item: T, _info: JobInfo) -> Result<(), T> {
+        let mut state = self.state.lock().recover();
+        while !state.closed && self.is_full(&state) {
+            state = self.not_full.wait(state).recover();
+        }
+        if state.closed {
+            return Err(item);
+        }
+        self.insert(state, item);
+        Ok(())
+    }
+
+    fn try_push(&self, item: T, _info: JobInfo) -> Result<(), TryPushError<T>> {
+        let state = self.state.lock().recover();
+        if state.closed {
+            return Err(TryPushError::Closed(item));
+        }
+        if self.is_full(&state) {
+            return Err(TryPushError::Full(item));
+        }
+        self.insert(state, item);
+        Ok(())
+    }
+
+    fn pop(&self, _worker: usize, timeout: Option<Duration>) -> Pop<T> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut state = self.state.lock().recover();
+        loop {
+            if let Some(item) = state.items.pop_front() {
+                drop(state);
+                self.not_full.notify_one();
+                return Pop::Item(item);
+            }
+            if state.closed {
+                return Pop::Closed;
+            }
+            state = match deadline {
+                None => self.available.wait(state).recover(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Pop::TimedOut;
+                    }
+                    self.available
+                        .wait_timeout(state, deadline - now)
+                        .recover()
+                        .0
+                }
+            };
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.state.lock().recover().items.len()
+    }
+
+    fn close(&self) {
+        self.state.lock().recover().closed = true;
+        self.available.notify_all();
+        self.not_full.notify_all();
+    }
+
+    fn clear(&self) -> usize {
+        let mut state = self.state.lock().recover();
+        let dropped = state.items.len();
+        state.items.clear();
+        drop(state);
+        self.not_full.notify_all();
+        dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn items_come_out_in_order_then_closed() {
+        let queue = FifoQueue::new();
+        for item in [3, 1, 2] {
+            queue.push(item, JobInfo::default()).unwrap();
+        }
+        assert!(matches!(queue.pop(0, None), Pop::Item(3)));
+        queue.close();
+        assert!(queue.push(4, JobInfo::default()).is_err());
+        // What was queued before closing still comes out.
+        assert!(matches!(queue.pop(0, None), Pop::Item(1)));
+        assert!(matches!(queue.pop(0, None), Pop::Item(2)));
+        assert!(matches!(queue.pop(0, None), Pop::Closed));
+    }
+
+    #[test]
+    fn an_empty_queue_times_out() {
+        let queue = FifoQueue::<u32>::new();
+        let started = Instant::now();
+        assert!(matches!(
+            queue.pop(0, Some(Duration::from_millis(20))),
+            Pop::TimedOut
+        ));
+        assert!(started.elapsed() >= Duration::from_millis(20));
+    }
+}
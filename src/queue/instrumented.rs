@@ -0,0 +1,99 @@
+use std::cell::Cell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicIsize, Ordering};
+use std::time::Duration;
+
+use super::{JobInfo, Pop, TaskQueue, TryPushError};
+use crate::clock::SharedClock;
+use crate::pool::Job;
+use crate::stats::LiveStats;
+
+thread_local! {
+    static WAIT: Cell<Duration> = const { Cell::new(Duration::ZERO) };
+}
+
+/// How long the job running on this thread waited in its queue, from
+/// being pushed to a worker taking it.
+pub(crate) fn current_wait() -> Duration {
+    WAIT.get()
+}
+
+/// A lane's queue, whichever kind it is, timing how long every job waits
+/// in it and counting how many are waiting. The wait goes into the stats
+/// as the job starts, so none of the ways a job gets queued has to time
+/// it; the count answers [`len`](TaskQueue::len) without taking the
+/// queue's locks, for idle workers and the queue depth gauges.
+pub(crate) struct InstrumentedQueue {
+    inner: Box<dyn TaskQueue<Job>>,
+    /// Signed, as a worker can pop a job before its push has counted it.
+    depth: AtomicIsize,
+    stats: Arc<LiveStats>,
+    clock: SharedClock,
+}
+
+impl InstrumentedQueue {
+    pub(crate) fn new(
+        inner: Box<dyn TaskQueue<Job>>,
+        stats: &Arc<LiveStats>,
+        clock: &SharedClock,
+    ) -> Self {
+        InstrumentedQueue {
+            inner,
+            depth: AtomicIsize::new(0),
+            stats: Arc::clone(stats),
+            clock: clock.clone(),
+        }
+    }
+
+    /// `job`, made to record how long it waited once it starts.
+    fn stamp(&self, job: Job, info: &JobInfo) -> Job {
+        let stats = Arc::clone(&self.stats);
+        let clock = self.clock.clone();
+        let task_type = info.task_type.clone();
+        let queued = clock.now();
+        Box::new(move || {
+            let wait = clock.now().saturating_duration_since(queued);
+            stats.waited(task_type.as_deref().unwrap_or("closure"), wait);
+            WAIT.set(wait);
+            job();
+        })
+    }
+}
+
+impl TaskQueue<Job> for InstrumentedQueue {
+    fn push(&self, item: Job, info: JobInfo) -> Result<(), Job> {
+        let job = self.stamp(item, &info);
+        self.inner.push(job, info)?;
+        self.depth.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn try_push(&self, item: Job, info: JobInfo) -> Result<(), TryPushError<Job>> {
+        let job = self.stamp(item, &info);
+        self.inner.try_push(job, info)?;
+        self.depth.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn pop(&self, worker: usize, timeout: Option<Duration>) -> Pop<Job> {
+        let pop = self.inner.pop(worker, timeout);
+        if let Pop::Item(_) = pop {
+            self.depth.fetch_sub(1, Ordering::Relaxed);
+        }
+        pop
+    }
+
+    fn len(&self) -> usize {
+        self.depth.load(Ordering::Relaxed).max(0) as usize
+    }
+
+    fn close(&self) {
+        self.inner.close();
+    }
+
+    fn clear(&self) -> usize {
+        let dropped = self.inner.clear();
+        self.depth.fetch_sub(dropped as isize, Ordering::Relaxed);
+        dropped
+    }
+}
@@ -0,0 +1,161 @@
+mod bounded;
+mod channel;
+mod fair;
+mod fifo;
+mod instrumented;
+mod priority;
+mod stealing;
+
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::pool::Job;
+
+pub use bounded::BoundedQueue;
+pub(crate) use channel::ChannelQueue;
+pub use fair::FairQueue;
+pub use fifo::FifoQueue;
+pub(crate) use instrumented::{InstrumentedQueue, current_wait};
+pub use priority::PriorityQueue;
+pub(crate) use stealing::WorkStealingQueue;
+
+/// How urgently a submitted task should run. Higher priorities are popped
+/// first; tasks of equal priority keep their submission order.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+/// Returned by [`ThreadPool::try_submit`](crate::ThreadPool::try_submit)
+/// when the queue is at capacity. Carries the rejected task back to the
+/// caller.
+pub struct QueueFull<T>(pub T);
+
+impl<T> fmt::Debug for QueueFull<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("QueueFull(..)")
+    }
+}
+
+impl<T> fmt::Display for QueueFull<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("task queue is full")
+    }
+}
+
+impl<T> Error for QueueFull<T> {}
+
+/// What a queue is told about each job it's given, to decide when the job
+/// runs.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct JobInfo {
+    pub priority: Priority,
+    /// The task's [`kind`](crate::Task::kind); `None` for closures.
+    pub task_type: Option<Arc<str>>,
+    /// How long the job is expected to take, from
+    /// [`SubmitOptions::cost`](crate::SubmitOptions::cost) or
+    /// [`Task::cost`](crate::Task::cost). `None` for closures and tasks
+    /// that don't say.
+    pub cost: Option<Duration>,
+}
+
+/// Why [`TaskQueue::try_push`] refused an item.
+pub enum TryPushError<T> {
+    Full(T),
+    Closed(T),
+}
+
+/// How workers get their next task.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Scheduler {
+    /// One queue shared by every worker. Honours [`Priority`].
+    #[default]
+    SharedQueue,
+    /// One deque per worker; idle workers steal from busy ones. Spreads
+    /// the locking across workers but ignores [`Priority`].
+    WorkStealing,
+    /// Workers receive straight from a FIFO channel. Ignores [`Priority`].
+    Channel,
+    /// Like `Channel`, but a plain `VecDeque` behind a mutex, with a
+    /// condition variable for workers to wait on: a [`FifoQueue`]. Ignores
+    /// [`Priority`].
+    Fifo,
+    /// Like `SharedQueue`, but within a priority the cheapest job by
+    /// [`JobInfo::cost`] goes first, which keeps the average time to
+    /// completion down. Jobs without a cost go after those with one.
+    ShortestJobFirst,
+    /// Takes turns between task types by their
+    /// [weights](crate::ThreadPoolBuilder::weight): a [`FairQueue`].
+    /// Ignores [`Priority`].
+    WeightedFair,
+}
+
+/// What a worker got back from [`TaskQueue::pop`].
+pub enum Pop<T> {
+    Item(T),
+    /// Nothing arrived before the timeout.
+    TimedOut,
+    /// The queue is closed and empty; the worker should exit.
+    Closed,
+}
+
+/// The queue between submitters and workers. The pool picks one from its
+/// [`Scheduler`] unless given another through
+/// [`ThreadPoolBuilder::queue`](crate::ThreadPoolBuilder::queue), which
+/// takes anything implementing this for [`Job`].
+pub trait TaskQueue<T>: Send + Sync {
+    /// Adds `item`, waiting for room if the queue is bounded and full.
+    /// Hands the item back if the queue is closed.
+    fn push(&self, item: T, info: JobInfo) -> Result<(), T>;
+
+    /// Like [`push`](Self::push), but fails instead of waiting when full.
+    fn try_push(&self, item: T, info: JobInfo) -> Result<(), TryPushError<T>>;
+
+    /// Blocks until an item is available for `worker`, the queue is closed
+    /// and empty, or `timeout` (if any) elapses.
+    fn pop(&self, worker: usize, timeout: Option<Duration>) -> Pop<T>;
+
+    /// Number of items waiting.
+    fn len(&self) -> usize;
+
+    /// Whether nothing is waiting.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Stops accepting new items and wakes every waiting worker and producer.
+    fn close(&self);
+
+    /// Removes everything still queued and returns how many items were dropped.
+    fn clear(&self) -> usize;
+}
+
+/// Makes the queue for each of a pool's lanes, as given to
+/// [`ThreadPoolBuilder::queue`](crate::ThreadPoolBuilder::queue).
+#[derive(Clone)]
+pub(crate) struct QueueFactory(Arc<dyn Fn() -> Box<dyn TaskQueue<Job>> + Send + Sync>);
+
+impl QueueFactory {
+    pub(crate) fn new<Q, F>(make: F) -> Self
+    where
+        Q: TaskQueue<Job> + 'static,
+        F: Fn() -> Q + Send + Sync + 'static,
+    {
+        QueueFactory(Arc::new(move || Box::new(make())))
+    }
+
+    pub(crate) fn make(&self) -> Box<dyn TaskQueue<Job>> {
+        (self.0)()
+    }
+}
+
+impl fmt::Debug for QueueFactory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("QueueFactory(..)")
+    }
+}
@@ -0,0 +1,244 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::{Condvar, MutexGuard};
+use std::time::{Duration, Instant};
+
+use super::{JobInfo, Pop, Priority, TaskQueue, TryPushError};
+use crate::sync::{Mutex, Recover};
+
+struct Entry<T> {
+    priority: Priority,
+    /// `Duration::MAX` when unknown or not ordering by cost.
+    cost: Duration,
+    seq: u64,
+    item: T,
+}
+
+impl<T> PartialEq for Entry<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T> Eq for Entry<T> {}
+
+impl<T> PartialOrd for Entry<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Entry<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // BinaryHeap is a max-heap: higher priority wins, then the cheaper
+        // entry, then the older one.
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.cost.cmp(&self.cost))
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+struct State<T> {
+    heap: BinaryHeap<Entry<T>>,
+    next_seq: u64,
+    closed: bool,
+}
+
+/// A blocking priority queue shared by the pool's workers: higher
+/// [`Priority`] first, then first in, first out. This is what
+/// [`Scheduler::SharedQueue`](crate::Scheduler::SharedQueue) uses.
+pub struct PriorityQueue<T> {
+    by_cost: bool,
+    state: Mutex<State<T>>,
+    capacity: Option<usize>,
+    available: Condvar,
+    not_full: Condvar,
+}
+
+impl<T> PriorityQueue<T> {
+    /// An unbounded queue. Wrap it in a
+    /// [`BoundedQueue`](crate::BoundedQueue) to cap it.
+    pub fn new() -> Self {
+        PriorityQueue::with_capacity(None)
+    }
+
+    /// An unbounded queue that runs the cheapest job by [`JobInfo::cost`]
+    /// first within each priority, as
+    /// [`Scheduler::ShortestJobFirst`](crate::Scheduler::ShortestJobFirst)
+    /// does.
+    pub fn shortest_job_first() -> Self {
+        PriorityQueue::new().by_cost()
+    }
+
+    /// Optionally bounded so producers wait instead of growing it without
+    /// limit.
+    pub(crate) fn with_capacity(capacity: Option<usize>) -> Self {
+        PriorityQueue {
+            by_cost: false,
+            state: Mutex::new(State {
+                heap: BinaryHeap::new(),
+                next_seq: 0,
+                closed: false,
+            }),
+            capacity,
+            available: Condvar::new(),
+            not_full: Condvar::new(),
+        }
+    }
+
+    pub(crate) fn by_cost(mut self) -> Self {
+        self.by_cost = true;
+        self
+    }
+
+    fn is_full(&self, state: &State<T>) -> bool {
+        self.capacity
+            .is_some_and(|capacity| state.heap.len() >= capacity)
+    }
+
+    fn insert(&self, mut state: MutexGuard<'_, State<T>>, item: T, info: JobInfo) {
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        let cost = info.cost.filter(|_| self.by_cost);
+        state.heap.push(Entry {
+            priority: info.priority,
+            cost: cost.unwrap_or(Duration::MAX),
+            seq,
+            item,
+        });
+        drop(state);
+        self.available.notify_one();
+    }
+}
+
+impl<T> Default for PriorityQueue<T> {
+    fn default() -> Self {
+        PriorityQueue::new()
+    }
+}
+
+impl<T: Send> TaskQueue<T> for PriorityQueue<T> {
+    fn push(&self, item: T, info: JobInfo) -> Result<(), T> {
+        let mut state = self.state.lock().recover();
+        while !state.closed && self.is_full(&state) {
+            state = self.not_full.wait(state).recover();
+        }
+        if state.closed {
+            return Err(item);
+        }
+        self.insert(state, item, info);
+        Ok(())
+    }
+
+    fn try_push(&self, item: T, info: JobInfo) -> Result<(), TryPushError<T>> {
+        let state = self.state.lock().recover();
+        if state.closed {
+            return Err(TryPushError::Closed(item));
+        }
+        if self.is_full(&state) {
+            return Err(TryPushError::Full(item));
+        }
+        self.insert(state, item, info);
+        Ok(())
+    }
+
+    fn pop(&self, _worker: usize, timeout: Option<Duration>) -> Pop<T> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut state = self.state.lock().recover();
+        loop {
+            if let Some(entry) = state.heap.pop() {
+                drop(state);
+                self.not_full.notify_one();
+                return Pop::Item(entry.item);
+            }
+            if state.closed {
+                return Pop::Closed;
+            }
+            state = match deadline {
+                None => self.available.wait(state).recover(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Pop::TimedOut;
+                    }
+                    self.available
+                        .wait_timeout(state, deadline - now)
+                        .recover()
+                        .0
+                }
+            };
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.state.lock().recover().heap.len()
+    }
+
+    fn close(&self) {
+        self.state.lock().recover().closed = true;
+        self.available.notify_all();
+        self.not_full.notify_all();
+    }
+
+    fn clear(&self) -> usize {
+        let mut state = self.state.lock().recover();
+        let dropped = state.heap.len();
+        state.heap.clear();
+        drop(state);
+        self.not_full.notify_all();
+        dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(priority: Priority, cost_ms: Option<u64>) -> JobInfo {
+        JobInfo {
+            priority,
+            cost: cost_ms.map(Duration::from_millis),
+            ..JobInfo::default()
+        }
+    }
+
+    fn drain<T: Send>(queue: &PriorityQueue<T>) -> Vec<T> {
+        let mut items = Vec::new();
+        while let Pop::Item(item) = queue.pop(0, Some(Duration::ZERO)) {
+            items.push(item);
+        }
+        items
+    }
+
+    #[test]
+    fn higher_priorities_first_and_ties_in_order() {
+        let queue = PriorityQueue::new();
+        queue.push("low", info(Priority::Low, None)).unwrap();
+        queue
+            .push("normal 1", info(Priority::Normal, Some(5)))
+            .unwrap();
+        queue.push("high", info(Priority::High, None)).unwrap();
+        queue
+            .push("normal 2", info(Priority::Normal, Some(1)))
+            .unwrap();
+        // Costs only count when ordering by them.
+        assert_eq!(drain(&queue), ["high", "normal 1", "normal 2", "low"]);
+    }
+
+    #[test]
+    fn shortest_job_first_puts_unknown_costs_last() {
+        let queue = PriorityQueue::shortest_job_first();
+        queue.push("unknown", info(Priority::Normal, None)).unwrap();
+        queue
+            .push("slow", info(Priority::Normal, Some(50)))
+            .unwrap();
+        queue
+            .push("quick", info(Priority::Normal, Some(1)))
+            .unwrap();
+        queue
+            .push("urgent", info(Priority::High, Some(90)))
+            .unwrap();
+        assert_eq!(drain(&queue), ["urgent", "quick", "slow", "unknown"]);
+    }
+}
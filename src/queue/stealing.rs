@@ -0,0 +1,282 @@
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::sync::Condvar;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use super::{JobInfo, Pop, TaskQueue, TryPushError};
+use crate::sync::{Mutex, Recover};
+
+thread_local! {
+    /// The work-stealing queue the calling worker takes jobs from, by
+    /// address, and which of its deques is the worker's own.
+    static HOME: Cell<Option<(usize, usize)>> = const { Cell::new(None) };
+}
+
+/// One deque per worker. A worker's own submissions go onto its own deque
+/// and everyone else's are spread round-robin; a worker takes from the
+/// front of its own deque and, when that is empty, steals from the back of
+/// someone else's. The shared `sleep` lock is only taken to park, or to
+/// wake a parked worker, so neither busy workers nor submitters contend on
+/// a single queue lock.
+pub(crate) struct WorkStealingQueue<T> {
+    locals: Vec<Mutex<VecDeque<T>>>,
+    /// Items queued, plus any a push has counted in but not yet inserted.
+    len: AtomicUsize,
+    next: AtomicUsize,
+    closed: AtomicBool,
+    capacity: Option<usize>,
+    /// Workers parked, or about to park, on `available`.
+    sleepers: AtomicUsize,
+    sleep: Mutex<()>,
+    available: Condvar,
+    not_full: Condvar,
+}
+
+/// Why a push couldn't count its item in.
+enum Refused {
+    Closed,
+    Full,
+}
+
+impl<T> WorkStealingQueue<T> {
+    pub(crate) fn new(workers: usize, capacity: Option<usize>) -> Self {
+        WorkStealingQueue {
+            locals: (0..workers).map(|_| Mutex::new(VecDeque::new())).collect(),
+            len: AtomicUsize::new(0),
+            next: AtomicUsize::new(0),
+            closed: AtomicBool::new(false),
+            capacity,
+            sleepers: AtomicUsize::new(0),
+            sleep: Mutex::new(()),
+            available: Condvar::new(),
+            not_full: Condvar::new(),
+        }
+    }
+
+    fn address(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    fn is_full(&self) -> bool {
+        self.capacity
+            .is_some_and(|capacity| self.len.load(Ordering::SeqCst) >= capacity)
+    }
+
+    /// Counts an item in before it is inserted, so that a worker can't
+    /// decide the queue is closed and empty while the item is on its way.
+    /// Once the queue is closed every push is refused.
+    fn reserve(&self) -> Result<(), Refused> {
+        let queued = self.len.fetch_add(1, Ordering::SeqCst);
+        let refused = if self.closed.load(Ordering::SeqCst) {
+            Refused::Closed
+        } else if self.capacity.is_some_and(|capacity| queued >= capacity) {
+            Refused::Full
+        } else {
+            return Ok(());
+        };
+        self.len.fetch_sub(1, Ordering::SeqCst);
+        // A push blocked on the room this one briefly took gets to look
+        // again.
+        if self.capacity.is_some() {
+            let _sleep = self.sleep.lock().recover();
+            self.not_full.notify_one();
+        }
+        Err(refused)
+    }
+
+    /// Inserts an item [reserved](Self::reserve) for, onto the caller's own
+    /// deque if it is one of this queue's workers.
+    fn insert(&self, item: T) {
+        let target = match HOME.with(Cell::get) {
+            Some((queue, own)) if queue == self.address() => own,
+            _ => self.next.fetch_add(1, Ordering::Relaxed) % self.locals.len(),
+        };
+        self.locals[target].lock().recover().push_back(item);
+        if self.sleepers.load(Ordering::SeqCst) > 0 {
+            let _sleep = self.sleep.lock().recover();
+            self.available.notify_one();
+        }
+    }
+
+    fn find(&self, worker: usize) -> Option<T> {
+        let own = worker % self.locals.len();
+        if let Some(item) = self.locals[own].lock().recover().pop_front() {
+            return Some(item);
+        }
+        (1..self.locals.len())
+            .map(|offset| (own + offset) % self.locals.len())
+            .find_map(|victim| self.locals[victim].lock().recover().pop_back())
+    }
+}
+
+impl<T: Send> TaskQueue<T> for WorkStealingQueue<T> {
+    fn push(&self, item: T, _info: JobInfo) -> Result<(), T> {
+        loop {
+            match self.reserve() {
+                Ok(()) => {
+                    self.insert(item);
+                    return Ok(());
+                }
+                Err(Refused::Closed) => return Err(item),
+                Err(Refused::Full) => {
+                    let mut sleep = self.sleep.lock().recover();
+                    while !self.closed.load(Ordering::SeqCst) && self.is_full() {
+                        sleep = self.not_full.wait(sleep).recover();
+                    }
+                }
+            }
+        }
+    }
+
+    fn try_push(&self, item: T, _info: JobInfo) -> Result<(), TryPushError<T>> {
+        match self.reserve() {
+            Ok(()) => {
+                self.insert(item);
+                Ok(())
+            }
+            Err(Refused::Closed) => Err(TryPushError::Closed(item)),
+            Err(Refused::Full) => Err(TryPushError::Full(item)),
+        }
+    }
+
+    fn pop(&self, worker: usize, timeout: Option<Duration>) -> Pop<T> {
+        HOME.with(|home| home.set(Some((self.address(), worker % self.locals.len()))));
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            if let Some(item) = self.find(worker) {
+                self.len.fetch_sub(1, Ordering::SeqCst);
+                if self.capacity.is_some() {
+                    let _sleep = self.sleep.lock().recover();
+                    self.not_full.notify_one();
+                }
+                return Pop::Item(item);
+            }
+
+            let sleep = self.sleep.lock().recover();
+            // Counted before looking at `len`, so a push that counts its
+            // item in after this sees a sleeper to wake.
+            self.sleepers.fetch_add(1, Ordering::SeqCst);
+            let ended = if self.len.load(Ordering::SeqCst) > 0 {
+                // Someone pushed (or another worker is mid-steal); look again.
+                None
+            } else if self.closed.load(Ordering::SeqCst) {
+                Some(Pop::Closed)
+            } else {
+                match deadline {
+                    None => {
+                        drop(self.available.wait(sleep).recover());
+                        None
+                    }
+                    Some(deadline) => {
+                        let now = Instant::now();
+                        if now >= deadline {
+                            Some(Pop::TimedOut)
+                        } else {
+                            drop(self.available.wait_timeout(sleep, deadline - now).recover());
+                            None
+                        }
+                    }
+                }
+            };
+            self.sleepers.fetch_sub(1, Ordering::SeqCst);
+            if let Some(pop) = ended {
+                return pop;
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.len.load(Ordering::SeqCst)
+    }
+
+    fn close(&self) {
+        let _sleep = self.sleep.lock().recover();
+        self.closed.store(true, Ordering::SeqCst);
+        self.available.notify_all();
+        self.not_full.notify_all();
+    }
+
+    fn clear(&self) -> usize {
+        let mut dropped = 0;
+        for local in &self.locals {
+            let mut local = local.lock().recover();
+            dropped += local.len();
+            local.clear();
+        }
+        self.len.fetch_sub(dropped, Ordering::SeqCst);
+        let _sleep = self.sleep.lock().recover();
+        self.not_full.notify_all();
+        dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn a_workers_push_goes_onto_its_own_deque() {
+        let queue = WorkStealingQueue::new(4, None);
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                assert!(matches!(queue.pop(2, Some(Duration::ZERO)), Pop::TimedOut));
+                for item in 0..3 {
+                    queue.push(item, JobInfo::default()).unwrap();
+                }
+            });
+        });
+        assert_eq!(queue.locals[2].lock().unwrap().len(), 3);
+        // Anyone else's pushes are spread around.
+        for item in 3..7 {
+            queue.push(item, JobInfo::default()).unwrap();
+        }
+        assert!(
+            queue
+                .locals
+                .iter()
+                .all(|local| !local.lock().unwrap().is_empty())
+        );
+    }
+
+    #[test]
+    fn a_parked_worker_wakes_for_a_push_and_for_close() {
+        let queue = WorkStealingQueue::new(2, Some(1));
+        thread::scope(|scope| {
+            let worker = scope.spawn(|| {
+                let first = queue.pop(0, None);
+                let second = queue.pop(0, None);
+                (matches!(first, Pop::Item(7)), matches!(second, Pop::Closed))
+            });
+            thread::sleep(Duration::from_millis(20));
+            queue.push(7, JobInfo::default()).unwrap();
+            thread::sleep(Duration::from_millis(20));
+            queue.close();
+            assert_eq!(worker.join().unwrap(), (true, true));
+        });
+        assert!(matches!(
+            queue.try_push(8, JobInfo::default()),
+            Err(TryPushError::Closed(8))
+        ));
+    }
+
+    #[test]
+    fn a_full_queue_refuses_until_a_worker_takes_something() {
+        let queue = WorkStealingQueue::new(2, Some(2));
+        queue.push(1, JobInfo::default()).unwrap();
+        queue.push(2, JobInfo::default()).unwrap();
+        assert!(matches!(
+            queue.try_push(3, JobInfo::default()),
+            Err(TryPushError::Full(3))
+        ));
+        assert_eq!(queue.len(), 2);
+        thread::scope(|scope| {
+            let blocked = scope.spawn(|| queue.push(3, JobInfo::default()));
+            thread::sleep(Duration::from_millis(20));
+            assert!(matches!(queue.pop(0, None), Pop::Item(_)));
+            assert!(blocked.join().unwrap().is_ok());
+        });
+        assert_eq!(queue.len(), 2);
+    }
+}
@@ -0,0 +1,165 @@
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::semaphore::{Semaphore, SemaphorePermit};
+use crate::sync::Recover;
+
+/// How fast tasks of one type may start, set through
+/// [`ThreadPoolBuilder::rate_limit`](crate::ThreadPoolBuilder::rate_limit).
+/// Every attempt counts, retries included.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RateLimit {
+    /// Attempts started per second, on average.
+    pub per_second: f64,
+    /// Attempts that may start back to back after a quiet spell.
+    pub burst: u32,
+    /// Attempts that may run at once, if limited.
+    pub max_concurrent: Option<usize>,
+}
+
+impl RateLimit {
+    /// At most `per_second` starts per second, evenly spaced.
+    pub fn per_second(per_second: f64) -> Self {
+        RateLimit {
+            per_second,
+            burst: 1,
+            max_concurrent: None,
+        }
+    }
+
+    pub fn with_burst(mut self, burst: u32) -> Self {
+        self.burst = burst;
+        self
+    }
+
+    pub fn with_max_concurrent(mut self, max: usize) -> Self {
+        self.max_concurrent = Some(max);
+        self
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+/// What a task type is held to: a token bucket, a cap on attempts in
+/// flight, or both. Workers wait here before each attempt, so a limited
+/// task type holds up only the workers that picked one up.
+pub(crate) struct RateLimiter {
+    limit: Option<RateLimit>,
+    bucket: Mutex<Bucket>,
+    slots: Option<Semaphore>,
+}
+
+/// Taken from [`RateLimiter::acquire`]; frees the attempt's slot, if it
+/// took one, when dropped.
+pub(crate) struct Permit<'a> {
+    _slot: Option<SemaphorePermit<'a>>,
+}
+
+impl RateLimiter {
+    /// Holds attempts to `limit`, if given, and to `max_concurrent` at
+    /// once, or the limit's own `max_concurrent` if that's lower.
+    pub(crate) fn new(limit: Option<RateLimit>, max_concurrent: Option<usize>) -> Self {
+        if let Some(limit) = &limit {
+            assert!(
+                limit.per_second > 0.0,
+                "a rate limit must allow some tasks through"
+            );
+        }
+        let max_concurrent = [
+            max_concurrent,
+            limit.as_ref().and_then(|limit| limit.max_concurrent),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+        if let Some(max) = max_concurrent {
+            assert!(max > 0, "a concurrency limit must allow some tasks through");
+        }
+        RateLimiter {
+            bucket: Mutex::new(Bucket {
+                tokens: limit.as_ref().map_or(1, |limit| limit.burst.max(1)) as f64,
+                refilled: Instant::now(),
+            }),
+            limit,
+            slots: max_concurrent.map(Semaphore::new),
+        }
+    }
+
+    /// Blocks until there's room for another attempt and a token to start it.
+    pub(crate) fn acquire(&self) -> Permit<'_> {
+        let permit = Permit {
+            _slot: self.slots.as_ref().map(Semaphore::acquire),
+        };
+        while let Some(wait) = self.take_token() {
+            thread::sleep(wait);
+        }
+        permit
+    }
+
+    /// Takes a token, or says how long until the next one.
+    fn take_token(&self) -> Option<Duration> {
+        let limit = self.limit.as_ref()?;
+        let mut bucket = self.bucket.lock().recover();
+        let now = Instant::now();
+        let capacity = limit.burst.max(1) as f64;
+        let refill = (now - bucket.refilled).as_secs_f64() * limit.per_second;
+        bucket.tokens = (bucket.tokens + refill).min(capacity);
+        bucket.refilled = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            None
+        } else {
+            Some(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / limit.per_second,
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[test]
+    fn a_burst_goes_straight_through_then_tokens_come_at_the_rate() {
+        let limiter = RateLimiter::new(Some(RateLimit::per_second(10.0).with_burst(3)), None);
+        for _ in 0..3 {
+            assert_eq!(limiter.take_token(), None);
+        }
+        let wait = limiter.take_token().expect("the burst is used up");
+        assert!(wait > Duration::from_millis(90) && wait <= Duration::from_millis(100));
+        thread::sleep(wait);
+        assert_eq!(limiter.take_token(), None);
+    }
+
+    #[test]
+    fn the_lower_concurrency_cap_holds_attempts_back() {
+        let limit = RateLimit::per_second(1000.0)
+            .with_burst(10)
+            .with_max_concurrent(3);
+        let limiter = RateLimiter::new(Some(limit), Some(1));
+        let first = limiter.acquire();
+        let second_started = AtomicBool::new(false);
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                let _second = limiter.acquire();
+                second_started.store(true, Ordering::SeqCst);
+            });
+            thread::sleep(Duration::from_millis(20));
+            assert!(!second_started.load(Ordering::SeqCst));
+            drop(first);
+        });
+        assert!(second_started.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    #[should_panic(expected = "must allow some tasks through")]
+    fn a_rate_of_zero_is_refused() {
+        RateLimiter::new(Some(RateLimit::per_second(0.0)), None);
+    }
+}
@@ -0,0 +1,450 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::cancel::CancellationToken;
+use crate::clock::SharedClock;
+use crate::pool::{Shared, SubmitOptions, ThreadPool};
+use crate::task::{Task, TaskResult};
+use crate::timer::{Pending, Timer};
+
+/// When a recurring task runs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Schedule {
+    /// Once per interval, the first time one interval from now. A run that
+    /// overruns its interval is followed straight away by the next, never
+    /// overlapped by it.
+    Every(Duration),
+    /// At every minute the expression matches, in UTC.
+    Cron(Cron),
+}
+
+impl Schedule {
+    pub fn every(interval: Duration) -> Self {
+        Schedule::Every(interval)
+    }
+
+    /// Parses a five-field cron expression; see [`Cron`].
+    pub fn cron(expression: &str) -> Result<Self, CronError> {
+        expression.parse().map(Schedule::Cron)
+    }
+
+    /// The run after one that was due at `previous`, or `None` if the
+    /// schedule never matches again, as `clock` tells the time.
+    fn next_after(&self, previous: Instant, clock: &SharedClock) -> Option<Instant> {
+        let now = clock.now();
+        match self {
+            Schedule::Every(interval) => Some((previous + *interval).max(now)),
+            Schedule::Cron(cron) => {
+                let wall = clock.wall();
+                let next = cron.next_after(wall)?;
+                Some(now + next.duration_since(wall).unwrap_or_default())
+            }
+        }
+    }
+}
+
+/// A cron expression of five space-separated fields: minute (0-59), hour
+/// (0-23), day of month (1-31), month (1-12) and day of week (0-6, Sunday
+/// is 0). Each field is `*`, a number, a range `a-b`, any of those with a
+/// step such as `*/15`, or a comma-separated list of them. As in cron, when
+/// both day fields are restricted a day matching either one will do.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+/// Returned when a cron expression doesn't parse.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CronError {
+    pub expression: String,
+    pub reason: String,
+}
+
+impl fmt::Display for CronError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid cron expression '{}': {}",
+            self.expression, self.reason
+        )
+    }
+}
+
+impl std::error::Error for CronError {}
+
+impl FromStr for Cron {
+    type Err = CronError;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let error = |reason: String| CronError {
+            expression: expression.to_string(),
+            reason,
+        };
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(error(format!("expected 5 fields, found {}", fields.len())));
+        };
+        Ok(Cron {
+            minutes: parse_field(minute, 0, 59).map_err(&error)?,
+            hours: parse_field(hour, 0, 23).map_err(&error)?,
+            days: parse_field(day, 1, 31).map_err(&error)?,
+            months: parse_field(month, 1, 12).map_err(&error)?,
+            weekdays: parse_field(weekday, 0, 6).map_err(&error)?,
+            any_day: day == "*",
+            any_weekday: weekday == "*",
+        })
+    }
+}
+
+/// Parses one field into a bit set of the values it allows.
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let mut set = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, parse_number(step, 1, max.max(1))?),
+            None => (part, 1),
+        };
+        let (first, last) = if range == "*" {
+            (min, max)
+        } else if let Some((first, last)) = range.split_once('-') {
+            (
+                parse_number(first, min, max)?,
+                parse_number(last, min, max)?,
+            )
+        } else {
+            let value = parse_number(range, min, max)?;
+            (value, if step > 1 { max } else { value })
+        };
+        if first > last {
+            return Err(format!("'{}' is an empty range", part));
+        }
+        for value in (first..=last).step_by(step as usize) {
+            set |= 1 << value;
+        }
+    }
+    Ok(set)
+}
+
+fn parse_number(text: &str, min: u32, max: u32) -> Result<u32, String> {
+    match text.parse() {
+        Ok(value) if (min..=max).contains(&value) => Ok(value),
+        _ => Err(format!("'{}' isn't a number from {} to {}", text, min, max)),
+    }
+}
+
+impl Cron {
+    /// The first whole minute after `after` that matches.
+    fn next_after(&self, after: SystemTime) -> Option<SystemTime> {
+        let seconds = after.duration_since(UNIX_EPOCH).ok()?.as_secs();
+        let mut minute = seconds / 60 + 1;
+        // Every day-of-month and weekday combination comes round within
+        // 28 years; past that the expression can't ever match.
+        for _ in 0..28 * 366 {
+            let day = minute / (24 * 60);
+            if self.matches_day(day) {
+                let start = (minute % (24 * 60)) as u32;
+                for time in start..24 * 60 {
+                    if self.hours & 1 << (time / 60) != 0 && self.minutes & 1 << (time % 60) != 0 {
+                        let at = (day * 24 * 60 + u64::from(time)) * 60;
+                        return Some(UNIX_EPOCH + Duration::from_secs(at));
+                    }
+                }
+            }
+            minute = (day + 1) * 24 * 60;
+        }
+        None
+    }
+
+    /// Whether the date `days` after 1970-01-01 matches.
+    fn matches_day(&self, days: u64) -> bool {
+        let (month, day) = month_and_day(days);
+        // 1970-01-01 was a Thursday.
+        let weekday = (days + 4) % 7;
+        let day_matches = self.days & 1 << day != 0;
+        let weekday_matches = self.weekdays & 1 << weekday != 0;
+        let day_matches = match (self.any_day, self.any_weekday) {
+            (false, false) => day_matches || weekday_matches,
+            _ => day_matches && weekday_matches,
+        };
+        day_matches && self.months & 1 << month != 0
+    }
+}
+
+/// The month (1-12) and day of the month for a count of days since
+/// 1970-01-01, after Howard Hinnant's `civil_from_days`.
+fn month_and_day(days: u64) -> (u64, u64) {
+    let days = days + 719_468;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    (month, day)
+}
+
+/// How many results of a [`RecurringTask`] wait to be read at most.
+const RESULTS_KEPT: usize = 64;
+
+/// A task that the pool keeps submitting on a [`Schedule`], returned by
+/// [`ThreadPool::submit_recurring`]. It stops at [`cancel`](Self::cancel)
+/// or when the pool shuts down; dropping it leaves it running.
+pub struct RecurringTask {
+    recurrence: Arc<dyn Recurrence>,
+    results: Receiver<TaskResult>,
+}
+
+impl RecurringTask {
+    /// Stops any further runs. One already queued or running still finishes.
+    pub fn cancel(&self) {
+        self.recurrence.token().cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.recurrence.token().is_cancelled()
+    }
+
+    /// How many times the task has been submitted so far.
+    pub fn runs(&self) -> u64 {
+        self.recurrence.runs()
+    }
+
+    /// The results of finished runs, oldest first, without blocking. Up to
+    /// 64 wait to be read; runs that finish while that many are waiting
+    /// still count in the stats, but their results aren't kept.
+    pub fn try_iter(&self) -> mpsc::TryIter<'_, TaskResult> {
+        self.results.try_iter()
+    }
+}
+
+/// Lets [`RecurringTask`] stay free of the task type.
+trait Recurrence: Send + Sync {
+    fn token(&self) -> &CancellationToken;
+    fn runs(&self) -> u64;
+}
+
+struct Recurring<T> {
+    task: Arc<T>,
+    schedule: Schedule,
+    options: SubmitOptions,
+    /// The general lane, which builds the jobs, and the one they run on.
+    shared: Arc<Shared>,
+    lane: Arc<Shared>,
+    timer: Timer,
+    token: CancellationToken,
+    runs: AtomicU64,
+    results: SyncSender<TaskResult>,
+}
+
+impl<T: Task + 'static> Recurring<T> {
+    /// Sets up the run due at `due`.
+    fn arm(self: &Arc<Self>, due: Instant) {
+        let recurring = Arc::clone(self);
+        // A closed timer means the pool is shutting down, which ends it.
+        self.timer.schedule(
+            due,
+            Pending::Occurrence(Box::new(move || recurring.submit(due))),
+        );
+    }
+
+    fn submit(self: Arc<Self>, due: Instant) {
+        if self.token.is_cancelled() {
+            return;
+        }
+        self.runs.fetch_add(1, Ordering::Relaxed);
+        let recurring = Arc::clone(&self);
+        let info = self.options.job_info(&*self.task);
+        let job = self.shared.job(
+            Arc::clone(&self.task),
+            self.options.clone(),
+            None,
+            move |result| {
+                // Nobody may be reading results; the runs carry on regardless.
+                let _ = recurring.results.try_send(result);
+                // The next run is only set up once this one is done, so runs
+                // never overlap.
+                if !recurring.token.is_cancelled()
+                    && let Some(next) = recurring.schedule.next_after(due, &recurring.shared.clock)
+                {
+                    recurring.arm(next);
+                }
+            },
+        );
+        self.lane.release(job, info);
+    }
+}
+
+impl<T: Task> Recurrence for Recurring<T> {
+    fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+
+    fn runs(&self) -> u64 {
+        self.runs.load(Ordering::Relaxed)
+    }
+}
+
+impl ThreadPool {
+    /// Submits `task` again and again on `schedule` until the returned
+    /// [`RecurringTask`] is cancelled or the pool shuts down. Each run
+    /// counts in the stats like any other task.
+    pub fn submit_recurring<T>(&self, schedule: Schedule, task: T) -> RecurringTask
+    where
+        T: Task + 'static,
+    {
+        self.submit_recurring_with(schedule, task, SubmitOptions::new())
+    }
+
+    /// Like [`submit_recurring`](Self::submit_recurring), with
+    /// [`SubmitOptions`] applied to every run.
+    pub fn submit_recurring_with<T>(
+        &self,
+        schedule: Schedule,
+        task: T,
+        options: SubmitOptions,
+    ) -> RecurringTask
+    where
+        T: Task + 'static,
+    {
+        if let Schedule::Every(interval) = schedule {
+            assert!(!interval.is_zero(), "a recurring interval must be non-zero");
+        }
+        let (results_tx, results) = mpsc::sync_channel(RESULTS_KEPT);
+        let recurring = Arc::new(Recurring {
+            lane: Arc::clone(self.lane(task.kind())),
+            task: Arc::new(task),
+            schedule,
+            options,
+            shared: Arc::clone(&self.shared),
+            timer: self.timer.clone(),
+            token: CancellationToken::new(),
+            runs: AtomicU64::new(0),
+            results: results_tx,
+        });
+        match recurring
+            .schedule
+            .next_after(self.shared.clock.now(), &self.shared.clock)
+        {
+            Some(due) => recurring.arm(due),
+            None => recurring.token.cancel(),
+        }
+        RecurringTask {
+            recurrence: recurring,
+            results,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Midnight UTC plus `hour:minute` on the given date, after Howard
+    /// Hinnant's `days_from_civil`.
+    fn at(year: u64, month: u64, day: u64, hour: u64, minute: u64) -> SystemTime {
+        let year = if month <= 2 { year - 1 } else { year };
+        let era = year / 400;
+        let year_of_era = year - era * 400;
+        let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146_097 + day_of_era - 719_468;
+        UNIX_EPOCH + Duration::from_secs(((days * 24 + hour) * 60 + minute) * 60)
+    }
+
+    fn next(expression: &str, after: SystemTime) -> Option<SystemTime> {
+        expression.parse::<Cron>().unwrap().next_after(after)
+    }
+
+    #[test]
+    fn cron_rolls_over_into_the_next_month_and_year() {
+        assert_eq!(
+            next("0 0 1 * *", at(2024, 1, 31, 12, 0)),
+            Some(at(2024, 2, 1, 0, 0))
+        );
+        assert_eq!(
+            next("30 23 31 12 *", at(2024, 12, 31, 23, 30)),
+            Some(at(2025, 12, 31, 23, 30))
+        );
+        // April has no 31st.
+        assert_eq!(
+            next("0 12 31 * *", at(2024, 4, 1, 0, 0)),
+            Some(at(2024, 5, 31, 12, 0))
+        );
+    }
+
+    #[test]
+    fn cron_finds_leap_days() {
+        let leap_day = "0 0 29 2 *";
+        assert_eq!(
+            next(leap_day, at(2023, 3, 1, 0, 0)),
+            Some(at(2024, 2, 29, 0, 0))
+        );
+        assert_eq!(
+            next(leap_day, at(2024, 2, 29, 0, 0)),
+            Some(at(2028, 2, 29, 0, 0))
+        );
+        // 2100 isn't a leap year.
+        assert_eq!(
+            next(leap_day, at(2097, 3, 1, 0, 0)),
+            Some(at(2104, 2, 29, 0, 0))
+        );
+    }
+
+    #[test]
+    fn cron_matches_weekdays_across_a_month_end() {
+        // 2024-02-28 was a Wednesday; the next Sunday is in March.
+        assert_eq!(
+            next("0 0 * * 0", at(2024, 2, 28, 0, 0)),
+            Some(at(2024, 3, 3, 0, 0))
+        );
+    }
+
+    #[test]
+    fn cron_that_never_matches_has_no_next_run() {
+        assert_eq!(next("0 0 31 2 *", at(2024, 1, 1, 0, 0)), None);
+    }
+
+    #[test]
+    fn cron_parse_errors_say_what_is_wrong() {
+        let reason = |expression: &str| expression.parse::<Cron>().unwrap_err().reason;
+        assert_eq!(reason("* * * *"), "expected 5 fields, found 4");
+        assert_eq!(reason("60 * * * *"), "'60' isn't a number from 0 to 59");
+        assert_eq!(reason("* * 0 * *"), "'0' isn't a number from 1 to 31");
+        assert_eq!(reason("* * * * 7"), "'7' isn't a number from 0 to 6");
+        assert_eq!(reason("*/0 * * * *"), "'0' isn't a number from 1 to 59");
+        assert_eq!(reason("* 5-2 * * *"), "'5-2' is an empty range");
+        assert_eq!(reason("* * * jan *"), "'jan' isn't a number from 1 to 12");
+        assert_eq!(reason("1,,2 * * * *"), "'' isn't a number from 0 to 59");
+        let error = "* * * * * *".parse::<Cron>().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "invalid cron expression '* * * * * *': expected 5 fields, found 6"
+        );
+    }
+
+    #[test]
+    fn cron_steps_and_lists() {
+        let cron: Cron = "*/20 9-17/4 * * 1,3".parse().unwrap();
+        assert_eq!(cron.minutes, 1 << 0 | 1 << 20 | 1 << 40);
+        assert_eq!(cron.hours, 1 << 9 | 1 << 13 | 1 << 17);
+        assert_eq!(cron.weekdays, 1 << 1 | 1 << 3);
+        // A step from a single value runs to the end of the field.
+        let cron: Cron = "50/5 * * * *".parse().unwrap();
+        assert_eq!(cron.minutes, 1 << 50 | 1 << 55);
+    }
+}
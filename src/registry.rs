@@ -0,0 +1,302 @@
+use std::cell::Cell;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::pool::ThreadPool;
+use crate::sync::{Mutex, Recover};
+use crate::task::TaskResult;
+
+/// Where a submitted task is, as of the last time anyone looked.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TaskStatus {
+    /// Waiting for a worker, or for the tasks it depends on.
+    Queued,
+    /// On the worker with this id.
+    Running {
+        worker: usize,
+    },
+    /// Still on the worker with this id, which a
+    /// [`Watchdog`](crate::Watchdog) found silent for too long.
+    Stuck {
+        worker: usize,
+    },
+    /// Waiting out the retry backoff before attempt number `attempt`.
+    Retrying {
+        attempt: u32,
+    },
+    Succeeded,
+    /// Finished without succeeding: failed, timed out, panicked, was
+    /// cancelled or never ran at all.
+    Failed,
+}
+
+impl TaskStatus {
+    pub fn is_finished(&self) -> bool {
+        matches!(self, TaskStatus::Succeeded | TaskStatus::Failed)
+    }
+}
+
+thread_local! {
+    static WORKER_ID: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// The id of the worker the calling thread belongs to.
+pub(crate) fn current_worker() -> usize {
+    // Jobs only run on worker threads, and every worker registers.
+    worker_id().unwrap_or_default()
+}
+
+/// Like [`current_worker`], but `None` off the pool's worker threads.
+pub(crate) fn worker_id() -> Option<usize> {
+    WORKER_ID.with(Cell::get)
+}
+
+/// What the registry knows about one task.
+#[derive(Clone, Copy, Debug)]
+struct Entry {
+    status: TaskStatus,
+    /// The last fraction done the task reported, if it reported any.
+    progress: Option<f64>,
+}
+
+/// How many finished tasks the registry remembers. Older ones are
+/// forgotten, so a long-running pool doesn't grow without bound.
+const RETAINED: usize = 10_000;
+
+/// The status of every task submitted to a pool, by task id. Unfinished
+/// tasks stay until they finish, and finished ones until [`RETAINED`]
+/// more have finished after them; a task submitted under an id that's
+/// already in use replaces the earlier entry.
+#[derive(Debug, Default)]
+pub(crate) struct TaskRegistry {
+    tasks: Mutex<HashMap<u32, Entry>>,
+    /// Finished ids, oldest first. Always locked after `tasks`.
+    finished: Mutex<VecDeque<u32>>,
+    next_worker_id: AtomicUsize,
+}
+
+impl TaskRegistry {
+    pub(crate) fn new() -> Self {
+        TaskRegistry::default()
+    }
+
+    /// An id for a worker about to be spawned, unique across the pool.
+    pub(crate) fn next_worker_id(&self) -> usize {
+        self.next_worker_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Gives the calling worker thread the `id` it shows up under in
+    /// [`TaskStatus::Running`].
+    pub(crate) fn register_worker(&self, id: usize) {
+        WORKER_ID.with(|worker| worker.set(Some(id)));
+    }
+
+    fn set(&self, id: u32, status: TaskStatus) {
+        self.tasks
+            .lock()
+            .recover()
+            .entry(id)
+            .and_modify(|entry| entry.status = status)
+            .or_insert(Entry {
+                status,
+                progress: None,
+            });
+    }
+
+    pub(crate) fn queued(&self, id: u32) {
+        let entry = Entry {
+            status: TaskStatus::Queued,
+            progress: None,
+        };
+        self.tasks.lock().recover().insert(id, entry);
+    }
+
+    /// Drops `id` again when its submission was turned away.
+    pub(crate) fn forget(&self, id: u32) {
+        self.tasks.lock().recover().remove(&id);
+    }
+
+    /// Marks `id` as running on the calling thread's worker.
+    pub(crate) fn running(&self, id: u32) {
+        self.set(
+            id,
+            TaskStatus::Running {
+                worker: current_worker(),
+            },
+        );
+    }
+
+    /// Marks `id` as stuck, unless it has moved on from `worker` since.
+    pub(crate) fn stuck(&self, id: u32, worker: usize) {
+        let mut tasks = self.tasks.lock().recover();
+        if let Some(entry) = tasks.get_mut(&id)
+            && entry.status == (TaskStatus::Running { worker })
+        {
+            entry.status = TaskStatus::Stuck { worker };
+        }
+    }
+
+    /// Records how far along `id` is, from 0.0 to 1.0.
+    pub(crate) fn progress(&self, id: u32, fraction: f64) {
+        if let Some(entry) = self.tasks.lock().recover().get_mut(&id) {
+            entry.progress = Some(fraction);
+        }
+    }
+
+    /// The next attempt starts over, so any progress is forgotten.
+    pub(crate) fn retrying(&self, id: u32, attempt: u32) {
+        let entry = Entry {
+            status: TaskStatus::Retrying { attempt },
+            progress: None,
+        };
+        self.tasks.lock().recover().insert(id, entry);
+    }
+
+    /// Marks the task as finished, forgetting the oldest finished task
+    /// once more than [`RETAINED`] have.
+    pub(crate) fn finished(&self, result: &TaskResult) {
+        let status = if result.is_success() {
+            TaskStatus::Succeeded
+        } else {
+            TaskStatus::Failed
+        };
+        let mut tasks = self.tasks.lock().recover();
+        tasks
+            .entry(result.id())
+            .and_modify(|entry| entry.status = status)
+            .or_insert(Entry {
+                status,
+                progress: None,
+            });
+        let mut finished = self.finished.lock().recover();
+        finished.push_back(result.id());
+        while finished.len() > RETAINED {
+            let Some(oldest) = finished.pop_front() else {
+                break;
+            };
+            // Unless its id has been submitted again since.
+            if tasks
+                .get(&oldest)
+                .is_some_and(|entry| entry.status.is_finished())
+            {
+                tasks.remove(&oldest);
+            }
+        }
+    }
+
+    pub(crate) fn status(&self, id: u32) -> Option<TaskStatus> {
+        Some(self.tasks.lock().recover().get(&id)?.status)
+    }
+
+    pub(crate) fn progress_of(&self, id: u32) -> Option<f64> {
+        self.tasks.lock().recover().get(&id)?.progress
+    }
+
+    pub(crate) fn in_flight(&self) -> Vec<(u32, TaskStatus)> {
+        let mut tasks: Vec<_> = self
+            .tasks
+            .lock()
+            .recover()
+            .iter()
+            .filter(|(_, entry)| !entry.status.is_finished())
+            .map(|(&id, entry)| (id, entry.status))
+            .collect();
+        tasks.sort_unstable_by_key(|&(id, _)| id);
+        tasks
+    }
+
+    /// The latest progress of every unfinished task that has reported any,
+    /// by id.
+    pub(crate) fn in_progress(&self) -> Vec<(u32, f64)> {
+        let mut tasks: Vec<_> = self
+            .tasks
+            .lock()
+            .recover()
+            .iter()
+            .filter(|(_, entry)| !entry.status.is_finished())
+            .filter_map(|(&id, entry)| Some((id, entry.progress?)))
+            .collect();
+        tasks.sort_unstable_by_key(|&(id, _)| id);
+        tasks
+    }
+}
+
+impl ThreadPool {
+    /// Where the task with id `task_id` is now, or `None` if no such task
+    /// was submitted. Only the 10,000 most recently finished tasks are
+    /// remembered; an older one is `None` as well.
+    pub fn status(&self, task_id: u32) -> Option<TaskStatus> {
+        self.shared.registry.status(task_id)
+    }
+
+    /// How far along the task with id `task_id` last said it was, from 0.0
+    /// to 1.0, or `None` if it hasn't said or no such task was submitted
+    /// or [remembered](Self::status).
+    /// See [`TaskContext::progress`](crate::TaskContext::progress).
+    pub fn progress(&self, task_id: u32) -> Option<f64> {
+        self.shared.registry.progress_of(task_id)
+    }
+
+    /// Every task that hasn't finished yet, by id.
+    pub fn in_flight(&self) -> Vec<(u32, TaskStatus)> {
+        self.shared.registry.in_flight()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cancelled(id: u32) -> TaskResult {
+        TaskResult::Cancelled {
+            id,
+            task_type: "work".to_string(),
+            correlation_id: None,
+        }
+    }
+
+    #[test]
+    fn only_the_most_recently_finished_are_remembered() {
+        let registry = TaskRegistry::new();
+        registry.queued(0);
+        registry.finished(&cancelled(0));
+        registry.queued(1);
+        registry.finished(&cancelled(1));
+        // Id 1 comes back and is still running when it ages out.
+        registry.queued(1);
+        registry.running(1);
+        registry.queued(u32::MAX);
+        for id in 2..RETAINED as u32 + 2 {
+            registry.finished(&cancelled(id));
+        }
+        assert_eq!(registry.status(0), None);
+        assert_eq!(registry.status(1), Some(TaskStatus::Running { worker: 0 }));
+        assert_eq!(registry.status(2), Some(TaskStatus::Failed));
+        assert_eq!(registry.status(u32::MAX), Some(TaskStatus::Queued));
+        assert_eq!(
+            registry.in_flight(),
+            [
+                (1, TaskStatus::Running { worker: 0 }),
+                (u32::MAX, TaskStatus::Queued)
+            ]
+        );
+    }
+
+    #[test]
+    fn progress_is_forgotten_on_retry() {
+        let registry = TaskRegistry::new();
+        registry.queued(7);
+        registry.running(7);
+        registry.progress(7, 0.5);
+        assert_eq!(registry.in_progress(), [(7, 0.5)]);
+        registry.retrying(7, 2);
+        assert_eq!(registry.progress_of(7), None);
+        assert_eq!(
+            registry.status(7),
+            Some(TaskStatus::Retrying { attempt: 2 })
+        );
+        // Unknown tasks don't start reporting.
+        registry.progress(8, 0.1);
+        assert_eq!(registry.status(8), None);
+    }
+}
@@ -0,0 +1,98 @@
+use std::sync::Arc;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::pool::{Shared, ThreadPool};
+use crate::queue::TaskQueue;
+use crate::stats::SystemStats;
+
+/// What a [`LiveReporter`] sees each time it wakes up.
+#[derive(Clone, Debug)]
+pub struct LiveReport {
+    /// Time since the reporter started.
+    pub elapsed: Duration,
+    /// Tasks waiting in the queues.
+    pub queued: usize,
+    /// Tasks finished per second since the previous report, counting
+    /// failures as well as successes.
+    pub throughput: f64,
+    /// The latest progress of the unfinished tasks that have reported any,
+    /// by id. See [`TaskContext::progress`](crate::TaskContext::progress).
+    pub progress: Vec<(u32, f64)>,
+    pub stats: SystemStats,
+}
+
+/// A background thread that hands a [`LiveReport`] to a callback at a fixed
+/// interval. Stops when dropped.
+pub struct LiveReporter {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl LiveReporter {
+    fn start<F>(lanes: Vec<Arc<Shared>>, interval: Duration, mut report: F) -> Self
+    where
+        F: FnMut(&LiveReport) + Send + 'static,
+    {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = thread::spawn(move || {
+            let start = Instant::now();
+            let mut last = (start, 0);
+            // Nothing is ever sent; the channel only disconnects on stop.
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                let now = Instant::now();
+                // Every queue shares one set of stats.
+                let stats = lanes[0].snapshot();
+                let finished = finished(&stats);
+                let throughput = (finished - last.1) as f64 / (now - last.0).as_secs_f64();
+                last = (now, finished);
+                report(&LiveReport {
+                    elapsed: now - start,
+                    queued: lanes.iter().map(|lane| lane.queue.len()).sum(),
+                    throughput,
+                    progress: lanes[0].registry.in_progress(),
+                    stats,
+                });
+            }
+        });
+        LiveReporter {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+
+    /// Stops the reporter and waits for its thread to exit.
+    pub fn stop(self) {
+        drop(self);
+    }
+}
+
+impl Drop for LiveReporter {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn finished(stats: &SystemStats) -> u32 {
+    stats.tasks_completed
+        + stats.tasks_failed
+        + stats.tasks_cancelled
+        + stats.tasks_timed_out
+        + stats.tasks_skipped
+        + stats.tasks_expired
+}
+
+impl ThreadPool {
+    /// Calls `report` every `interval` with the queue depth, worker count
+    /// and task counters until the returned [`LiveReporter`] is dropped.
+    pub fn live_reporter<F>(&self, interval: Duration, report: F) -> LiveReporter
+    where
+        F: FnMut(&LiveReport) + Send + 'static,
+    {
+        LiveReporter::start(self.lanes().cloned().collect(), interval, report)
+    }
+}
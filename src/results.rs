@@ -0,0 +1,260 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::Instant;
+
+use crate::dag::{Completion, CycleError, TaskGraph};
+use crate::pool::{SubmitOptions, ThreadPool};
+use crate::task::{Task, TaskResult};
+use crate::timer::Pending;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Order {
+    Completion,
+    Submission,
+}
+
+/// Tasks submitted together whose results are read back as an iterator,
+/// either as they finish ([`ThreadPool::results`]) or in the order the
+/// tasks were submitted ([`ThreadPool::ordered_results`]). In submission
+/// order, results that finish early are buffered until everything
+/// submitted before them has been yielded.
+///
+/// Iteration blocks for the next result and ends once every task submitted
+/// so far has been yielded; submitting more tasks continues the stream.
+pub struct Results<'pool> {
+    pool: &'pool ThreadPool,
+    order: Order,
+    sender: Sender<(u64, TaskResult)>,
+    receiver: Receiver<(u64, TaskResult)>,
+    submitted: u64,
+    yielded: u64,
+    buffered: BTreeMap<u64, TaskResult>,
+}
+
+impl<'pool> Results<'pool> {
+    fn new(pool: &'pool ThreadPool, order: Order) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Results {
+            pool,
+            order,
+            sender,
+            receiver,
+            submitted: 0,
+            yielded: 0,
+            buffered: BTreeMap::new(),
+        }
+    }
+
+    /// Queues `task` on the pool at [`Priority::Normal`](crate::Priority).
+    pub fn submit<T>(&mut self, task: T)
+    where
+        T: Task + 'static,
+    {
+        self.submit_with(task, SubmitOptions::new());
+    }
+
+    /// Queues `task` using the given [`SubmitOptions`]. Blocks while a
+    /// bounded queue is full.
+    pub fn submit_with<T>(&mut self, task: T, options: SubmitOptions)
+    where
+        T: Task + 'static,
+    {
+        let sequence = self.submitted;
+        let sender = self.sender.clone();
+        let info = options.job_info(&task);
+        let lane = Arc::clone(self.pool.lane(task.kind()));
+        let job = self
+            .pool
+            .shared
+            .job(Arc::new(task), options, None, move |result| {
+                // The caller may have dropped the results; that's fine.
+                let _ = sender.send((sequence, result));
+            });
+        lane.push(job, info);
+        self.submitted += 1;
+    }
+
+    /// Queues `task` at `when`, as [`ThreadPool::submit_at_with`] does.
+    /// Its result still comes back through these results.
+    pub fn submit_at_with<T>(&mut self, when: Instant, task: T, mut options: SubmitOptions)
+    where
+        T: Task + 'static,
+    {
+        let sequence = self.submitted;
+        let sender = self.sender.clone();
+        options.queued_at = Some(when);
+        let info = options.job_info(&task);
+        let lane = Arc::clone(self.pool.lane(task.kind()));
+        let job = self
+            .pool
+            .shared
+            .job(Arc::new(task), options, None, move |result| {
+                let _ = sender.send((sequence, result));
+            });
+        let scheduled = self
+            .pool
+            .timer
+            .schedule(when, Pending::Job { job, info, lane });
+        assert!(scheduled, "pool is shut down");
+        self.submitted += 1;
+    }
+
+    /// Submits every task in `graph` as [`ThreadPool::submit_graph`] does.
+    /// In submission order, results follow the order the tasks were added.
+    pub fn submit_graph<T>(&mut self, graph: TaskGraph<T>) -> Result<(), CycleError>
+    where
+        T: Task + 'static,
+    {
+        let first = self.submitted;
+        let len = graph.len() as u64;
+        graph.submit_each(|node, task, options, dependencies| {
+            let completion = Arc::new(Completion::new());
+            let sender = self.sender.clone();
+            let sequence = first + node as u64;
+            self.pool.submit_gated(
+                Arc::new(task),
+                dependencies,
+                options,
+                Arc::clone(&completion),
+                move |result| {
+                    let _ = sender.send((sequence, result));
+                },
+            );
+            completion
+        })?;
+        self.submitted += len;
+        Ok(())
+    }
+
+    /// Yields the results that are ready now without blocking. In
+    /// submission order that stops at the first task still running.
+    pub fn try_iter(&mut self) -> TryIter<'_, 'pool> {
+        TryIter { results: self }
+    }
+
+    /// Tasks submitted whose results haven't been yielded yet.
+    pub fn pending(&self) -> usize {
+        (self.submitted - self.yielded) as usize
+    }
+}
+
+impl Results<'_> {
+    fn take(&mut self, block: bool) -> Option<TaskResult> {
+        if self.yielded == self.submitted {
+            return None;
+        }
+        let result = match self.order {
+            Order::Completion => self.receive(block)?.1,
+            Order::Submission => {
+                while !self.buffered.contains_key(&self.yielded) {
+                    let (sequence, result) = self.receive(block)?;
+                    self.buffered.insert(sequence, result);
+                }
+                self.buffered.remove(&self.yielded).expect("just checked")
+            }
+        };
+        self.yielded += 1;
+        Some(result)
+    }
+
+    fn receive(&self, block: bool) -> Option<(u64, TaskResult)> {
+        if block {
+            // The pool can't shut down while borrowed, so every job reports back.
+            self.receiver.recv().ok()
+        } else {
+            self.receiver.try_recv().ok()
+        }
+    }
+}
+
+impl Iterator for Results<'_> {
+    type Item = TaskResult;
+
+    fn next(&mut self) -> Option<TaskResult> {
+        self.take(true)
+    }
+}
+
+/// Non-blocking iterator returned by [`Results::try_iter`].
+pub struct TryIter<'a, 'pool> {
+    results: &'a mut Results<'pool>,
+}
+
+impl Iterator for TryIter<'_, '_> {
+    type Item = TaskResult;
+
+    fn next(&mut self) -> Option<TaskResult> {
+        self.results.take(false)
+    }
+}
+
+impl ThreadPool {
+    /// Starts a batch of tasks whose [`TaskResult`]s are yielded as they
+    /// finish.
+    pub fn results(&self) -> Results<'_> {
+        Results::new(self, Order::Completion)
+    }
+
+    /// Starts a batch of tasks whose [`TaskResult`]s are yielded in
+    /// submission order rather than completion order, like an ordered
+    /// parallel map.
+    pub fn ordered_results(&self) -> Results<'_> {
+        Results::new(self, Order::Submission)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::pool::ThreadPool;
+    use crate::task::{Task, TaskContext, TaskError, TaskOutput};
+
+    struct Sleepy {
+        id: u32,
+        millis: u64,
+    }
+
+    impl Task for Sleepy {
+        fn id(&self) -> u32 {
+            self.id
+        }
+
+        fn execute(&self, _ctx: &TaskContext) -> Result<TaskOutput, TaskError> {
+            thread::sleep(Duration::from_millis(self.millis));
+            Ok(TaskOutput::new("done"))
+        }
+    }
+
+    #[test]
+    fn ordered_results_keep_submission_order() {
+        let pool = ThreadPool::builder().workers(4).build();
+        let mut results = pool.ordered_results();
+        // Later tasks are quicker, so they tend to finish first.
+        for id in 0..12 {
+            results.submit(Sleepy {
+                id,
+                millis: u64::from(12 - id) * 2,
+            });
+        }
+        let ids: Vec<u32> = results.map(|result| result.id()).collect();
+        assert_eq!(ids, (0..12).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn results_yield_each_task_once() {
+        let pool = ThreadPool::builder().workers(4).build();
+        let mut results = pool.results();
+        for id in 0..12 {
+            results.submit(Sleepy {
+                id,
+                millis: u64::from(id % 3),
+            });
+        }
+        let mut ids: Vec<u32> = results.map(|result| result.id()).collect();
+        ids.sort_unstable();
+        assert_eq!(ids, (0..12).collect::<Vec<_>>());
+    }
+}
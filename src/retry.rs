@@ -0,0 +1,93 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+/// How often, and how patiently, the pool re-runs a task that failed or
+/// timed out. Cancelled tasks are never retried.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Total attempts including the first one; `1` disables retries.
+    pub max_attempts: u32,
+    /// Delay before the first retry; doubled for every retry after that.
+    pub backoff: Duration,
+    /// Upper bound of a random delay added on top of the backoff so that
+    /// tasks failing together don't retry in lockstep.
+    pub jitter: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::none()
+    }
+}
+
+impl RetryPolicy {
+    /// Run every task exactly once.
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            backoff: Duration::ZERO,
+            jitter: Duration::ZERO,
+        }
+    }
+
+    pub fn exponential(max_attempts: u32, backoff: Duration) -> Self {
+        RetryPolicy {
+            max_attempts,
+            backoff,
+            jitter: Duration::ZERO,
+        }
+    }
+
+    pub fn with_jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// How long to wait after failed attempt number `attempt` (1-based).
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31);
+        let backoff = self.backoff.saturating_mul(1 << exponent);
+        backoff + random_up_to(self.jitter)
+    }
+}
+
+fn random_up_to(max: Duration) -> Duration {
+    if max.is_zero() {
+        return Duration::ZERO;
+    }
+    // RandomState is randomly keyed per instance, which is plenty for jitter.
+    let random = RandomState::new().build_hasher().finish();
+    Duration::from_nanos((u128::from(random) % max.as_nanos()) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_from_the_first_retry_and_saturates() {
+        let policy = RetryPolicy::exponential(5, Duration::from_millis(10));
+        let delays: Vec<u128> = (1..=4)
+            .map(|attempt| policy.delay(attempt).as_millis())
+            .collect();
+        assert_eq!(delays, [10, 20, 40, 80]);
+        assert_eq!(policy.delay(0), Duration::from_millis(10));
+        assert_eq!(
+            RetryPolicy::exponential(99, Duration::MAX).delay(99),
+            Duration::MAX
+        );
+        assert_eq!(RetryPolicy::none().delay(3), Duration::ZERO);
+    }
+
+    #[test]
+    fn jitter_stays_under_its_bound() {
+        let policy = RetryPolicy::exponential(3, Duration::from_millis(10))
+            .with_jitter(Duration::from_millis(5));
+        for _ in 0..100 {
+            let delay = policy.delay(2);
+            assert!(delay >= Duration::from_millis(20));
+            assert!(delay < Duration::from_millis(25));
+        }
+    }
+}
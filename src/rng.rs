@@ -0,0 +1,43 @@
+// A small seeded random number generator (SplitMix64), so that --seed N
+// makes the same "random" run every time
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed)
+    }
+
+    // A generator of its own for each task and purpose, so what task 7
+    // gets doesn't depend on how many tasks came before it or what else
+    // was drawn for them
+    pub fn for_task(seed: u64, id: u32, purpose: &str) -> Rng {
+        let mut rng = Rng::new(seed ^ (id as u64).rotate_left(32));
+        for byte in purpose.bytes() {
+            rng.0 ^= rng.next_u64() ^ byte as u64;
+        }
+        rng
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    // Somewhere in 0..bound
+    pub fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound.max(1)
+    }
+
+    // Somewhere in [0, 1)
+    pub fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // True with the given probability
+    pub fn chance(&mut self, probability: f64) -> bool {
+        self.unit() < probability
+    }
+}
@@ -0,0 +1,303 @@
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::cancel::CancellationToken;
+use crate::chaos::Chaos;
+use crate::clock::SharedClock;
+use crate::fork;
+use crate::hooks::Listeners;
+use crate::rate_limit::RateLimiter;
+use crate::registry::TaskRegistry;
+use crate::retry::RetryPolicy;
+use crate::task::{
+    ProgressReporter, Task, TaskContext, TaskError, TaskInfo, TaskOutput, TaskResult,
+};
+
+/// Everything the worker needs to know to run one submitted task.
+pub(crate) struct RunSpec {
+    pub(crate) cancellation: CancellationToken,
+    /// Cancelled when the pool shuts down immediately.
+    pub(crate) shutdown: CancellationToken,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) retry: RetryPolicy,
+    pub(crate) rate_limit: Option<Arc<RateLimiter>>,
+    pub(crate) registry: Arc<TaskRegistry>,
+    pub(crate) listeners: Listeners,
+    pub(crate) clock: SharedClock,
+    pub(crate) chaos: Option<Chaos>,
+    /// How long the task waited for a worker, set once one picks it up.
+    pub(crate) queued: Duration,
+    pub(crate) info: TaskInfo,
+}
+
+/// Runs `task`, retrying failures and timeouts as `spec.retry` allows.
+pub(crate) fn run<T>(task: &Arc<T>, spec: &RunSpec) -> TaskResult
+where
+    T: Task + 'static,
+{
+    let mut attempt = 1;
+    loop {
+        // Waiting for the limiter doesn't eat into the attempt's timeout.
+        let _permit = spec.rate_limit.as_deref().map(RateLimiter::acquire);
+        spec.registry.running(task.id());
+        fork::heartbeat(Some(task.id()));
+        let ctx = TaskContext::new(
+            spec.cancellation.clone(),
+            spec.timeout.map(|timeout| spec.clock.now() + timeout),
+            ProgressReporter::new(task.id(), Arc::clone(&spec.registry)),
+            spec.clock.clone(),
+        )
+        .for_attempt(task.id(), attempt, spec.info.clone())
+        .with_shutdown(spec.shutdown.clone());
+        let result = run_once(task, &ctx, spec, attempt);
+
+        let retryable = match &result {
+            TaskResult::Error { error, .. } => error.is_retryable(),
+            TaskResult::TimedOut { .. } => true,
+            _ => false,
+        };
+        // A pool shutting down immediately has no time for another try.
+        if !retryable || attempt >= spec.retry.max_attempts || spec.shutdown.is_cancelled() {
+            return result;
+        }
+        let delay = spec.retry.delay(attempt);
+        spec.registry.retrying(task.id(), attempt + 1);
+        spec.listeners.retrying(&result, delay);
+        spec.clock.sleep(delay);
+        attempt += 1;
+    }
+}
+
+/// How a single attempt ended, before it is turned into a [`TaskResult`].
+enum Outcome {
+    Finished(Result<TaskOutput, TaskError>),
+    Panicked(String),
+}
+
+fn run_once<T>(task: &Arc<T>, ctx: &TaskContext, spec: &RunSpec, attempts: u32) -> TaskResult
+where
+    T: Task + 'static,
+{
+    let id = task.id();
+    let task_type = task.kind().to_string();
+    let correlation_id = spec.info.correlation_id();
+    if ctx.is_cancelled() {
+        return TaskResult::Cancelled {
+            id,
+            task_type,
+            correlation_id,
+        };
+    }
+
+    let timeout = spec.timeout;
+    let start = spec.clock.now();
+    // The attempt runs right here, so a timeout only takes effect when the
+    // task notices its deadline; a task that ignores its context holds the
+    // worker until it's done.
+    let outcome = execute(&**task, ctx, spec.chaos.as_ref());
+    let duration_ms = (spec.clock.now() - start).as_millis();
+
+    match outcome {
+        Outcome::Panicked(message) => TaskResult::Panicked {
+            id,
+            task_type,
+            correlation_id,
+            message,
+        },
+        // The task gave up because of its deadline. One that finishes late
+        // but fine still succeeded, so its output isn't lost.
+        Outcome::Finished(Err(_)) if ctx.is_timed_out() => TaskResult::TimedOut {
+            id,
+            task_type,
+            correlation_id,
+            timeout_ms: timeout.unwrap_or_default().as_millis(),
+            attempts,
+        },
+        // A task that gave up because of its token is cancelled, not failed.
+        Outcome::Finished(Err(error)) if ctx.is_cancelled() || error == TaskError::Cancelled => {
+            TaskResult::Cancelled {
+                id,
+                task_type,
+                correlation_id,
+            }
+        }
+        Outcome::Finished(Ok(output)) => TaskResult::Success {
+            id,
+            task_type,
+            correlation_id,
+            output,
+            duration_ms,
+            queued_ms: spec.queued.as_millis(),
+            attempts,
+        },
+        Outcome::Finished(Err(error)) => TaskResult::Error {
+            id,
+            task_type,
+            correlation_id,
+            error,
+            attempts,
+        },
+    }
+}
+
+/// Runs the task on the current thread, turning a panic into an outcome
+/// instead of letting it unwind through the worker. Any faults `chaos`
+/// injects come first, as if the task had caused them.
+fn execute<T>(task: &T, ctx: &TaskContext, chaos: Option<&Chaos>) -> Outcome
+where
+    T: Task + ?Sized,
+{
+    let attempt = || {
+        if let Some(chaos) = chaos {
+            chaos.before_attempt(ctx)?;
+        }
+        task.execute(ctx)
+    };
+    match panic::catch_unwind(AssertUnwindSafe(attempt)) {
+        Ok(outcome) => Outcome::Finished(outcome),
+        Err(payload) => Outcome::Panicked(panic_message(&*payload)),
+    }
+}
+
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "task panicked".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::VirtualClock;
+
+    /// Fails its first `fails` attempts with `error`, having slept `work`
+    /// on each, then succeeds if it can.
+    struct Flaky {
+        fails: u32,
+        error: fn() -> TaskError,
+        work: Duration,
+    }
+
+    impl Task for Flaky {
+        fn id(&self) -> u32 {
+            1
+        }
+
+        fn execute(&self, ctx: &TaskContext) -> Result<TaskOutput, TaskError> {
+            ctx.sleep(self.work);
+            if ctx.attempt() <= self.fails {
+                return Err((self.error)());
+            }
+            Ok(TaskOutput::new("done"))
+        }
+    }
+
+    fn flaky(fails: u32, error: fn() -> TaskError) -> Arc<Flaky> {
+        Arc::new(Flaky {
+            fails,
+            error,
+            work: Duration::ZERO,
+        })
+    }
+
+    fn spec(clock: &Arc<VirtualClock>, retry: RetryPolicy, timeout: Option<Duration>) -> RunSpec {
+        RunSpec {
+            cancellation: CancellationToken::new(),
+            shutdown: CancellationToken::new(),
+            timeout,
+            retry,
+            rate_limit: None,
+            registry: Arc::new(TaskRegistry::new()),
+            listeners: Listeners::default(),
+            clock: SharedClock::new(Arc::clone(clock)),
+            chaos: None,
+            queued: Duration::ZERO,
+            info: TaskInfo::default(),
+        }
+    }
+
+    fn attempts(result: &TaskResult) -> Option<u32> {
+        match result {
+            TaskResult::Success { attempts, .. }
+            | TaskResult::Error { attempts, .. }
+            | TaskResult::TimedOut { attempts, .. } => Some(*attempts),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn retries_back_off_until_an_attempt_succeeds() {
+        let clock = Arc::new(VirtualClock::new());
+        let retry = RetryPolicy::exponential(3, Duration::from_millis(10));
+        let result = run(
+            &flaky(2, || TaskError::new("flaky")),
+            &spec(&clock, retry, None),
+        );
+        assert!(result.is_success());
+        assert_eq!(attempts(&result), Some(3));
+        assert_eq!(clock.elapsed(), Duration::from_millis(30));
+    }
+
+    #[test]
+    fn retries_stop_at_max_attempts_or_a_permanent_error() {
+        let clock = Arc::new(VirtualClock::new());
+        let retry = RetryPolicy::exponential(2, Duration::from_millis(10));
+        let result = run(
+            &flaky(5, || TaskError::new("flaky")),
+            &spec(&clock, retry.clone(), None),
+        );
+        assert!(matches!(result, TaskResult::Error { attempts: 2, .. }));
+
+        let invalid = || TaskError::InvalidInput("bad".to_string());
+        let result = run(&flaky(5, invalid), &spec(&clock, retry, None));
+        assert!(matches!(result, TaskResult::Error { attempts: 1, .. }));
+    }
+
+    #[test]
+    fn giving_up_at_the_deadline_times_out_but_a_late_success_stands() {
+        let clock = Arc::new(VirtualClock::new());
+        let timeout = Some(Duration::from_millis(20));
+        let slow = Arc::new(Flaky {
+            fails: 1,
+            error: || TaskError::Timeout { after_ms: 50 },
+            work: Duration::from_millis(50),
+        });
+        let retry = RetryPolicy::exponential(2, Duration::ZERO);
+        let result = run(&slow, &spec(&clock, RetryPolicy::none(), timeout));
+        assert!(matches!(
+            result,
+            TaskResult::TimedOut {
+                timeout_ms: 20,
+                attempts: 1,
+                ..
+            }
+        ));
+        // The second attempt runs over too, but finishes fine.
+        let result = run(&slow, &spec(&clock, retry, timeout));
+        assert!(result.is_success());
+        assert_eq!(attempts(&result), Some(2));
+    }
+
+    #[test]
+    fn a_cancelled_task_is_not_retried() {
+        let clock = Arc::new(VirtualClock::new());
+        let retry = RetryPolicy::exponential(3, Duration::from_millis(10));
+        let cancelled = spec(&clock, retry.clone(), None);
+        cancelled.cancellation.cancel();
+        let result = run(&flaky(0, || TaskError::Cancelled), &cancelled);
+        assert!(matches!(result, TaskResult::Cancelled { .. }));
+        // Nor is one that gives up of its own accord.
+        let result = run(
+            &flaky(1, || TaskError::Cancelled),
+            &spec(&clock, retry, None),
+        );
+        assert!(matches!(result, TaskResult::Cancelled { .. }));
+        assert_eq!(clock.elapsed(), Duration::ZERO);
+    }
+}
@@ -0,0 +1,274 @@
+// A small async runtime on std alone, standing in for tokio in part4: a
+// few threads polling spawned futures, a timer thread behind sleep(), and
+// an unbounded channel whose receiver can be awaited
+use std::collections::{BinaryHeap, VecDeque};
+use std::cmp::Reverse;
+use std::future::{self, Future};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, LazyLock, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, JoinHandle, Thread};
+use std::time::{Duration, Instant};
+
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+// A spawned future. Waking it puts it back on the run queue; the lock
+// keeps two threads from polling it at once if it's woken mid-poll
+struct Task {
+    future: Mutex<Option<BoxFuture>>,
+    queue: Arc<RunQueue>,
+}
+
+impl Wake for Task {
+    fn wake(self: Arc<Self>) {
+        self.queue.push(Arc::clone(&self));
+    }
+}
+
+#[derive(Default)]
+struct RunQueue {
+    ready: Mutex<(VecDeque<Arc<Task>>, bool)>,
+    available: Condvar,
+}
+
+impl RunQueue {
+    fn push(&self, task: Arc<Task>) {
+        self.ready.lock().unwrap().0.push_back(task);
+        self.available.notify_one();
+    }
+
+    // The next task to poll, or None once the runtime is shutting down
+    fn pop(&self) -> Option<Arc<Task>> {
+        let mut ready = self.ready.lock().unwrap();
+        loop {
+            if ready.1 {
+                return None;
+            }
+            if let Some(task) = ready.0.pop_front() {
+                return Some(task);
+            }
+            ready = self.available.wait(ready).unwrap();
+        }
+    }
+
+    fn close(&self) {
+        self.ready.lock().unwrap().1 = true;
+        self.available.notify_all();
+    }
+}
+
+pub struct Runtime {
+    queue: Arc<RunQueue>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl Runtime {
+    pub fn new(threads: usize) -> Runtime {
+        let queue = Arc::new(RunQueue::default());
+        let threads = (0..threads.max(1))
+            .map(|_| {
+                let queue = Arc::clone(&queue);
+                thread::spawn(move || {
+                    while let Some(task) = queue.pop() {
+                        let waker = Waker::from(Arc::clone(&task));
+                        let mut future = task.future.lock().unwrap();
+                        // A task woken after it finished has nothing left to poll
+                        if let Some(running) = future.as_mut()
+                            && running.as_mut().poll(&mut Context::from_waker(&waker)).is_ready()
+                        {
+                            *future = None;
+                        }
+                    }
+                })
+            })
+            .collect();
+        Runtime { queue, threads }
+    }
+
+    // Runs `future` on the runtime's threads
+    pub fn spawn(&self, future: impl Future<Output = ()> + Send + 'static) {
+        self.queue.push(Arc::new(Task { future: Mutex::new(Some(Box::pin(future))), queue: Arc::clone(&self.queue) }));
+    }
+
+    // Runs `future` on this thread until it's done. Spawned tasks carry on
+    // meanwhile
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut context = Context::from_waker(&waker);
+        let mut future = Box::pin(future);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+}
+
+// Tasks still pending are dropped unfinished
+impl Drop for Runtime {
+    fn drop(&mut self) {
+        self.queue.close();
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+struct Unpark(Thread);
+
+impl Wake for Unpark {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+// Wakes sleepers when their time comes. One thread serves every runtime
+struct Timer {
+    // Soonest first; the counter keeps equal deadlines apart since wakers
+    // can't be compared
+    sleepers: Mutex<BinaryHeap<Reverse<(Instant, usize, WakerEntry)>>>,
+    changed: Condvar,
+    next: AtomicUsize,
+}
+
+struct WakerEntry(Waker);
+
+impl PartialEq for WakerEntry {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for WakerEntry {}
+
+impl PartialOrd for WakerEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for WakerEntry {
+    fn cmp(&self, _: &Self) -> std::cmp::Ordering {
+        std::cmp::Ordering::Equal
+    }
+}
+
+static TIMER: LazyLock<&'static Timer> = LazyLock::new(|| {
+    let timer: &'static Timer = Box::leak(Box::new(Timer {
+        sleepers: Mutex::new(BinaryHeap::new()),
+        changed: Condvar::new(),
+        next: AtomicUsize::new(0),
+    }));
+    thread::spawn(move || timer.run());
+    timer
+});
+
+impl Timer {
+    fn wake_at(&self, deadline: Instant, waker: Waker) {
+        let entry = (deadline, self.next.fetch_add(1, Ordering::Relaxed), WakerEntry(waker));
+        self.sleepers.lock().unwrap().push(Reverse(entry));
+        self.changed.notify_one();
+    }
+
+    fn run(&self) {
+        let mut sleepers = self.sleepers.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            while let Some(Reverse((deadline, ..))) = sleepers.peek()
+                && *deadline <= now
+            {
+                let Reverse((_, _, WakerEntry(waker))) = sleepers.pop().unwrap();
+                waker.wake();
+            }
+            sleepers = match sleepers.peek() {
+                Some(Reverse((deadline, ..))) => {
+                    let wait = *deadline - now;
+                    self.changed.wait_timeout(sleepers, wait).unwrap().0
+                }
+                None => self.changed.wait(sleepers).unwrap(),
+            };
+        }
+    }
+}
+
+// Finishes once `duration` has passed, without holding up a thread
+pub async fn sleep(duration: Duration) {
+    let deadline = Instant::now() + duration;
+    future::poll_fn(|context| {
+        if Instant::now() >= deadline {
+            return Poll::Ready(());
+        }
+        TIMER.wake_at(deadline, context.waker().clone());
+        Poll::Pending
+    })
+    .await
+}
+
+struct Channel<T> {
+    items: VecDeque<T>,
+    receiver: Option<Waker>,
+    senders: usize,
+}
+
+pub struct Sender<T> {
+    channel: Arc<Mutex<Channel<T>>>,
+}
+
+pub struct Receiver<T> {
+    channel: Arc<Mutex<Channel<T>>>,
+}
+
+// An unbounded many-to-one channel, like tokio::sync::mpsc's
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let channel = Arc::new(Mutex::new(Channel { items: VecDeque::new(), receiver: None, senders: 1 }));
+    (Sender { channel: Arc::clone(&channel) }, Receiver { channel })
+}
+
+impl<T> Sender<T> {
+    pub fn send(&self, item: T) {
+        let mut channel = self.channel.lock().unwrap();
+        channel.items.push_back(item);
+        if let Some(receiver) = channel.receiver.take() {
+            receiver.wake();
+        }
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.channel.lock().unwrap().senders += 1;
+        Sender { channel: Arc::clone(&self.channel) }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut channel = self.channel.lock().unwrap();
+        channel.senders -= 1;
+        if channel.senders == 0
+            && let Some(receiver) = channel.receiver.take()
+        {
+            receiver.wake();
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    // The next item, or None once every sender is gone and nothing's left
+    pub async fn recv(&mut self) -> Option<T> {
+        future::poll_fn(|context| {
+            let mut channel = self.channel.lock().unwrap();
+            if let Some(item) = channel.items.pop_front() {
+                return Poll::Ready(Some(item));
+            }
+            if channel.senders == 0 {
+                return Poll::Ready(None);
+            }
+            channel.receiver = Some(context.waker().clone());
+            Poll::Pending
+        })
+        .await
+    }
+}
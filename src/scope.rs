@@ -0,0 +1,123 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Mutex;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::Instant;
+
+use crate::pool::ThreadPool;
+use crate::sync::Recover;
+
+type ScopedJob<'env> = Box<dyn FnOnce() + Send + 'env>;
+
+/// Spawns closures that may borrow from outside the
+/// [`ThreadPool::scope`] call.
+pub struct Scope<'env> {
+    sender: Sender<ScopedJob<'env>>,
+}
+
+impl<'env> Scope<'env> {
+    /// Runs `f` on one of the scope's threads without waiting for it.
+    pub fn spawn<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'env,
+    {
+        // The workers only hang up once the scope is over.
+        let _ = self.sender.send(Box::new(f));
+    }
+}
+
+impl ThreadPool {
+    /// Runs `f` with a [`Scope`] whose tasks can borrow data that outlives
+    /// the call, and returns once all of them have finished. Tasks run on
+    /// as many scoped threads as the pool's minimum size and count toward
+    /// its stats.
+    ///
+    /// If a task panics, the rest still run and the panic resumes here
+    /// afterwards.
+    pub fn scope<'env, F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&Scope<'env>) -> R,
+    {
+        let (sender, receiver) = mpsc::channel::<ScopedJob<'env>>();
+        let receiver = Mutex::new(receiver);
+        let panicked = Mutex::new(None);
+
+        let result = thread::scope(|threads| {
+            for _ in 0..self.shared.scaling.min_workers {
+                threads.spawn(|| self.run_scoped(&receiver, &panicked));
+            }
+            let scope = Scope { sender };
+            let result = f(&scope);
+            // Hanging up lets the threads exit once they've drained the channel.
+            drop(scope);
+            result
+        });
+
+        if let Some(payload) = panicked.into_inner().recover() {
+            panic::resume_unwind(payload);
+        }
+        result
+    }
+
+    /// Applies `f` to every item and returns the results in input order.
+    /// The items are split into a few chunks per scoped thread, so `f` may
+    /// borrow like a [`scope`](Self::scope) task, and a panic in `f`
+    /// resumes here once the rest are done.
+    pub fn map<I, R, F>(&self, items: impl IntoIterator<Item = I>, f: F) -> Vec<R>
+    where
+        I: Send,
+        R: Send,
+        F: Fn(I) -> R + Sync,
+    {
+        let items: Vec<I> = items.into_iter().collect();
+        // More chunks than threads evens things out when some items take
+        // longer than others.
+        let chunk_size = items
+            .len()
+            .div_ceil(self.shared.scaling.min_workers * 4)
+            .max(1);
+        let mut items = items.into_iter();
+        let mut chunks = Vec::new();
+        loop {
+            let chunk: Vec<I> = items.by_ref().take(chunk_size).collect();
+            if chunk.is_empty() {
+                break;
+            }
+            chunks.push(chunk);
+        }
+
+        let mut outputs: Vec<Vec<R>> = chunks.iter().map(|_| Vec::new()).collect();
+        self.scope(|scope| {
+            for (chunk, output) in chunks.into_iter().zip(&mut outputs) {
+                let f = &f;
+                scope.spawn(move || *output = chunk.into_iter().map(f).collect());
+            }
+        });
+        outputs.into_iter().flatten().collect()
+    }
+
+    fn run_scoped(
+        &self,
+        receiver: &Mutex<Receiver<ScopedJob<'_>>>,
+        panicked: &Mutex<Option<Box<dyn std::any::Any + Send>>>,
+    ) {
+        loop {
+            // Release the lock before running the job.
+            let job = receiver.lock().recover().recv();
+            let Ok(job) = job else {
+                return;
+            };
+            let start = Instant::now();
+            match panic::catch_unwind(AssertUnwindSafe(job)) {
+                Ok(()) => self
+                    .shared
+                    .stats
+                    .closure_completed(start.elapsed().as_millis()),
+                Err(payload) => {
+                    self.shared.stats.worker_panicked();
+                    panicked.lock().recover().get_or_insert(payload);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use std::sync::{Condvar, Mutex};
+
+use crate::sync::Recover;
+
+/// A counting semaphore: at most `permits` holders at once. The pool uses
+/// one per task type given
+/// [`max_concurrent`](crate::ThreadPoolBuilder::max_concurrent); tasks can
+/// use their own to share out anything scarce among themselves.
+#[derive(Debug)]
+pub struct Semaphore {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+/// A permit taken from a [`Semaphore`]; gives it back when dropped.
+#[derive(Debug)]
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl Semaphore {
+    pub fn new(permits: usize) -> Self {
+        Semaphore {
+            available: Mutex::new(permits),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Blocks until a permit is free and takes it.
+    pub fn acquire(&self) -> SemaphorePermit<'_> {
+        let mut available = self.available.lock().recover();
+        while *available == 0 {
+            available = self.released.wait(available).recover();
+        }
+        *available -= 1;
+        SemaphorePermit { semaphore: self }
+    }
+
+    /// Takes a permit if one is free right now.
+    pub fn try_acquire(&self) -> Option<SemaphorePermit<'_>> {
+        let mut available = self.available.lock().recover();
+        if *available == 0 {
+            return None;
+        }
+        *available -= 1;
+        Some(SemaphorePermit { semaphore: self })
+    }
+
+    /// Permits free right now.
+    pub fn available(&self) -> usize {
+        *self.available.lock().recover()
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        *self.semaphore.available.lock().recover() += 1;
+        self.semaphore.released.notify_one();
+    }
+}
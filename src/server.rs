@@ -0,0 +1,54 @@
+// The smallest HTTP/1.1 server that will do for status pages: GET only,
+// one request per connection, answered on a thread of its own for as
+// long as the program runs
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+pub struct Response {
+    pub status: &'static str,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    pub fn ok(content_type: &'static str, body: String) -> Response {
+        Response { status: "200 OK", content_type, body }
+    }
+
+    pub fn not_found(message: &str) -> Response {
+        Response { status: "404 Not Found", content_type: "text/plain", body: format!("{}\n", message) }
+    }
+}
+
+// Starts answering on `port`, passing each request's path to `handle`.
+// Fails only if the port can't be bound; trouble with a single request
+// is ignored
+pub fn serve<F>(port: u16, handle: F) -> io::Result<()>
+where
+    F: Fn(&str) -> Response + Send + 'static,
+{
+    let listener = TcpListener::bind(("0.0.0.0", port))?;
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let _ = respond(stream, &handle);
+        }
+    });
+    Ok(())
+}
+
+fn respond(mut stream: TcpStream, handle: &impl Fn(&str) -> Response) -> io::Result<()> {
+    // e.g. "GET /metrics HTTP/1.1"; the headers don't matter
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+    let response = handle(path);
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        response.content_type,
+        response.body.len(),
+        response.body
+    )
+}
@@ -0,0 +1,37 @@
+// Turns Ctrl-C into a flag the demos can poll instead of killing the
+// process. A second Ctrl-C exits straight away
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+#[cfg(unix)]
+pub fn install() {
+    use std::os::raw::c_int;
+
+    const SIGINT: c_int = 2;
+
+    // std already links against libc, so there's no need for a crate
+    unsafe extern "C" {
+        fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+        fn _exit(status: c_int) -> !;
+    }
+
+    // Only async-signal-safe work in here: an atomic swap and _exit
+    extern "C" fn on_sigint(_: c_int) {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            unsafe { _exit(130) }
+        }
+    }
+
+    unsafe {
+        signal(SIGINT, on_sigint);
+    }
+}
+
+// Elsewhere Ctrl-C keeps its default behavior
+#[cfg(not(unix))]
+pub fn install() {}
@@ -0,0 +1,139 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::cancel::CancellationToken;
+use crate::clock::{SharedClock, VirtualClock};
+use crate::hooks::Listeners;
+use crate::registry::TaskRegistry;
+use crate::retry::RetryPolicy;
+use crate::runner::{self, RunSpec};
+use crate::stats::{LiveStats, SystemStats};
+use crate::task::{Task, TaskInfo, TaskResult};
+
+/// Runs tasks as a pool of `workers` would, but one at a time on the
+/// calling thread and on a [`VirtualClock`]. Each task starts on whichever
+/// worker frees up first, at that worker's virtual time, and takes as long
+/// as it waits through [`TaskContext::sleep`](crate::TaskContext::sleep).
+/// Thousands of tasks run in an instant, and the same tasks always give
+/// the same results in the same order.
+///
+/// Tasks that block for real, e.g. with
+/// [`thread::sleep`](std::thread::sleep), take no virtual time.
+pub struct Simulation {
+    workers: usize,
+    timeout: Option<Duration>,
+    retry: RetryPolicy,
+    clock: Arc<VirtualClock>,
+    stats: LiveStats,
+    registry: Arc<TaskRegistry>,
+    /// Submitted but not yet run, with the virtual time they were submitted.
+    pending: Vec<(Duration, Box<dyn Task>)>,
+    /// When each worker is next free.
+    free_at: Vec<Duration>,
+}
+
+impl Simulation {
+    /// # Panics
+    ///
+    /// Panics if `workers` is zero.
+    pub fn new(workers: usize) -> Self {
+        assert!(workers > 0, "a simulation needs at least one worker");
+        Simulation {
+            workers,
+            timeout: None,
+            retry: RetryPolicy::none(),
+            clock: Arc::new(VirtualClock::new()),
+            stats: LiveStats::new(),
+            registry: Arc::new(TaskRegistry::new()),
+            pending: Vec::new(),
+            free_at: vec![Duration::ZERO; workers],
+        }
+    }
+
+    /// Timeout applied to every task, in virtual time.
+    pub fn default_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Retry policy applied to every task. Backoff takes virtual time on
+    /// the task's worker.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    /// Queues `task` at the current virtual time.
+    pub fn submit<T>(&mut self, task: T)
+    where
+        T: Task + 'static,
+    {
+        self.registry.queued(task.id());
+        self.pending.push((self.clock.elapsed(), Box::new(task)));
+    }
+
+    /// Runs everything submitted so far and returns the results in the
+    /// order they finished in virtual time. Ties go in submission order.
+    pub fn run(&mut self) -> Vec<TaskResult> {
+        let mut spec = RunSpec {
+            cancellation: CancellationToken::new(),
+            shutdown: CancellationToken::new(),
+            timeout: self.timeout,
+            retry: self.retry.clone(),
+            rate_limit: None,
+            registry: Arc::clone(&self.registry),
+            listeners: Listeners::default(),
+            clock: SharedClock::new(Arc::clone(&self.clock)),
+            chaos: None,
+            queued: Duration::ZERO,
+            info: TaskInfo::default(),
+        };
+        // Soonest free first, then the lowest numbered, so every run picks
+        // the same worker.
+        let mut workers: BinaryHeap<Reverse<(Duration, usize)>> = self
+            .free_at
+            .iter()
+            .enumerate()
+            .map(|(worker, &free_at)| Reverse((free_at, worker)))
+            .collect();
+        let mut finished = Vec::with_capacity(self.pending.len());
+        for (order, (submitted, task)) in self.pending.drain(..).enumerate() {
+            let Reverse((free_at, worker)) = workers.pop().expect("at least one worker");
+            let start = free_at.max(submitted);
+            self.clock.set(start);
+            spec.queued = start - submitted;
+            spec.info.task_type = task.kind().into();
+            spec.info.submitted_at = Some(spec.clock.now() - spec.queued);
+            self.stats.waited(task.kind(), spec.queued);
+            let result = runner::run(&Arc::new(task), &spec);
+            self.registry.finished(&result);
+            self.stats.record(&result);
+            let end = self.clock.elapsed();
+            self.free_at[worker] = end;
+            workers.push(Reverse((end, worker)));
+            finished.push((end, order, result));
+        }
+        // The clock ends when the last worker is done.
+        self.clock.set(self.elapsed());
+        finished.sort_by_key(|&(end, order, _)| (end, order));
+        finished.into_iter().map(|(_, _, result)| result).collect()
+    }
+
+    /// Virtual time from the start until the last task so far finished.
+    pub fn elapsed(&self) -> Duration {
+        self.free_at.iter().copied().max().unwrap_or_default()
+    }
+
+    pub fn stats(&self) -> SystemStats {
+        let mut stats = self.stats.snapshot();
+        stats.peak_workers = self.workers as u32;
+        stats
+    }
+
+    /// The clock the tasks run against.
+    pub fn clock(&self) -> &VirtualClock {
+        &self.clock
+    }
+}
@@ -0,0 +1,134 @@
+// Where the project's results go as they come in. The screen, the journal
+// and result files are all sinks, so the receive loop only hands each
+// result to every sink rather than knowing about each of them
+use crate::json::{ToJson, Value};
+use rust_concurrent_processor::TaskResult;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+pub trait ResultSink {
+    fn record(&mut self, result: &TaskResult) -> io::Result<()>;
+
+    // Called once after the last result, e.g. to flush a buffered file
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Prints each result on a line of its own, formatted by `format`
+pub struct Stdout {
+    format: fn(&TaskResult) -> String,
+}
+
+impl Stdout {
+    pub fn new(format: fn(&TaskResult) -> String) -> Stdout {
+        Stdout { format }
+    }
+}
+
+impl ResultSink for Stdout {
+    fn record(&mut self, result: &TaskResult) -> io::Result<()> {
+        writeln!(io::stdout(), "{}", (self.format)(result))
+    }
+}
+
+// One JSON object per result per line, as --output json prints them
+pub struct JsonLines {
+    out: BufWriter<File>,
+}
+
+impl JsonLines {
+    pub fn create(path: &Path) -> io::Result<JsonLines> {
+        Ok(JsonLines { out: BufWriter::new(File::create(path)?) })
+    }
+}
+
+impl ResultSink for JsonLines {
+    fn record(&mut self, result: &TaskResult) -> io::Result<()> {
+        writeln!(self.out, "{}", result.to_json())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+// A row per result under a header, for spreadsheets. Columns a result
+// doesn't have are left empty
+pub struct Csv {
+    out: BufWriter<File>,
+}
+
+const CSV_COLUMNS: [&str; 7] = ["id", "task_type", "status", "duration_ms", "queued_ms", "attempts", "message"];
+
+impl Csv {
+    pub fn create(path: &Path) -> io::Result<Csv> {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "{}", CSV_COLUMNS.join(","))?;
+        Ok(Csv { out })
+    }
+}
+
+impl ResultSink for Csv {
+    fn record(&mut self, result: &TaskResult) -> io::Result<()> {
+        // Taken from the JSON so both files agree on names and values
+        let json = result.to_json();
+        let cell = |column: &str| {
+            let value = match column {
+                "message" => json.get("message").or_else(|| json.get("error").and_then(|error| error.get("message"))),
+                column => json.get(column),
+            };
+            match value {
+                Some(Value::String(text)) => csv_escape(text),
+                Some(value) => value.to_string(),
+                None => String::new(),
+            }
+        };
+        let row: Vec<String> = CSV_COLUMNS.iter().map(|column| cell(column)).collect();
+        writeln!(self.out, "{}", row.join(","))
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+// Quoted only when it has to be, with quotes doubled
+fn csv_escape(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+// Every sink a run writes to, named for the warnings when one can't be
+// written. A sink that fails keeps getting the later results, in case
+// the trouble passes
+#[derive(Default)]
+pub struct Sinks {
+    sinks: Vec<(String, Box<dyn ResultSink>)>,
+}
+
+impl Sinks {
+    pub fn add(&mut self, name: impl Into<String>, sink: impl ResultSink + 'static) {
+        self.sinks.push((name.into(), Box::new(sink)));
+    }
+
+    pub fn record(&mut self, result: &TaskResult) {
+        for (name, sink) in &mut self.sinks {
+            if let Err(err) = sink.record(result) {
+                eprintln!("warning: can't write to {}: {}", name, err);
+            }
+        }
+    }
+
+    pub fn finish(&mut self) {
+        for (name, sink) in &mut self.sinks {
+            if let Err(err) = sink.finish() {
+                eprintln!("warning: can't write to {}: {}", name, err);
+            }
+        }
+    }
+}
@@ -0,0 +1,478 @@
+use std::collections::BTreeMap;
+use std::hint;
+use std::sync::OnceLock;
+use std::sync::atomic::{self, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use crate::circuit::CircuitState;
+use crate::histogram::{AtomicHistogram, LatencyHistogram};
+use crate::task::{TaskError, TaskResult};
+
+/// Counters the pool keeps while it runs, as of one moment.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SystemStats {
+    pub tasks_completed: u32,
+    pub tasks_failed: u32,
+    /// Failed tasks by the [`kind`](crate::TaskError::kind) of their error.
+    pub errors: BTreeMap<&'static str, u32>,
+    pub tasks_cancelled: u32,
+    pub tasks_timed_out: u32,
+    /// Tasks still queued when the pool was shut down with
+    /// [`ShutdownMode::Immediate`](crate::ShutdownMode::Immediate), or
+    /// dropped by [`Chaos`](crate::Chaos).
+    pub tasks_discarded: u32,
+    /// Tasks that never ran because a task they depended on failed.
+    pub tasks_skipped: u32,
+    /// Tasks that never ran because they were still queued at their
+    /// deadline.
+    pub tasks_expired: u32,
+    /// Tasks failed fast by an open circuit breaker.
+    pub tasks_rejected: u32,
+    /// Where each task type's circuit breaker stands.
+    pub circuits: BTreeMap<String, CircuitState>,
+    pub total_duration_ms: u128,
+    /// Panics caught in tasks, plus any that took down a worker thread
+    /// (which is then replaced).
+    pub worker_panics: u32,
+    /// Extra attempts made by the retry policy, across all tasks.
+    pub retries: u32,
+    /// Worker threads currently alive.
+    pub active_workers: u32,
+    /// Most worker threads alive at once.
+    pub peak_workers: u32,
+    /// How long successful tasks ran, by task type: their service time,
+    /// leaving out [`queue_wait`](Self::queue_wait). Closures run through
+    /// [`ThreadPool::spawn`](crate::ThreadPool::spawn) are listed as
+    /// `"closure"`.
+    pub latency: BTreeMap<String, LatencyHistogram>,
+    /// How long tasks sat in the queue before a worker started them, by
+    /// task type. Delayed tasks and tasks waiting on dependencies count
+    /// that time as well.
+    pub queue_wait: BTreeMap<String, LatencyHistogram>,
+}
+
+impl SystemStats {
+    pub fn new() -> Self {
+        SystemStats::default()
+    }
+}
+
+/// The live counters behind [`SystemStats`]. Every worker updates them on
+/// every task, so they're split into shards that each thread adds to its
+/// own of, and only a snapshot adds them up.
+///
+/// Each shard is a seqlock: a thread recording a result marks the shard
+/// busy, updates it and marks it done, and a snapshot copies a shard again
+/// if a record was under way while it read. Recording never waits on a
+/// snapshot, and a snapshot taken mid-run never has a result half counted:
+/// the task counts, errors and histograms all agree with each other.
+#[derive(Debug)]
+pub(crate) struct LiveStats {
+    shards: Box<[Shard]>,
+    active_workers: AtomicU32,
+    peak_workers: AtomicU32,
+}
+
+/// One thread's share of the counts. Threads seldom share a shard, so
+/// marking it busy nearly always succeeds first time; it's aligned so no
+/// two shards share a cache line and workers finishing tasks at the same
+/// moment don't fight over one.
+#[derive(Debug, Default)]
+#[repr(align(128))]
+struct Shard {
+    /// Odd while a thread is recording into the shard.
+    sequence: AtomicU64,
+    tasks_completed: AtomicU32,
+    tasks_failed: AtomicU32,
+    /// By [`TaskError::KINDS`].
+    errors: [AtomicU32; TaskError::KINDS.len()],
+    tasks_cancelled: AtomicU32,
+    tasks_timed_out: AtomicU32,
+    tasks_discarded: AtomicU32,
+    tasks_skipped: AtomicU32,
+    tasks_expired: AtomicU32,
+    tasks_rejected: AtomicU32,
+    total_duration_ms: AtomicU64,
+    worker_panics: AtomicU32,
+    retries: AtomicU32,
+    latency: Histograms,
+    queue_wait: Histograms,
+}
+
+impl Shard {
+    /// Makes the changes `record` makes to this shard look like one to a
+    /// snapshot. Only one thread records into a shard at a time, so the
+    /// counts are bumped with plain loads and stores.
+    fn write(&self, record: impl FnOnce(&Shard)) {
+        let mut sequence = self.sequence.load(Ordering::Relaxed);
+        let mut tries = 0;
+        loop {
+            if sequence.is_multiple_of(2) {
+                match self.sequence.compare_exchange_weak(
+                    sequence,
+                    sequence + 1,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => break,
+                    Err(now) => sequence = now,
+                }
+                continue;
+            }
+            // Another thread sharing the shard is recording.
+            backoff(&mut tries);
+            sequence = self.sequence.load(Ordering::Relaxed);
+        }
+        atomic::fence(Ordering::Release);
+        record(self);
+        self.sequence.store(sequence + 2, Ordering::Release);
+    }
+
+    /// Copies the shard with no record half made, trying again for as
+    /// long as one gets in the way.
+    fn read<T>(&self, copy: impl Fn(&Shard) -> T) -> T {
+        let mut tries = 0;
+        loop {
+            let sequence = self.sequence.load(Ordering::Acquire);
+            if sequence.is_multiple_of(2) {
+                let copied = copy(self);
+                atomic::fence(Ordering::Acquire);
+                if self.sequence.load(Ordering::Relaxed) == sequence {
+                    return copied;
+                }
+            }
+            backoff(&mut tries);
+        }
+    }
+
+    fn completed(&self, task_type: &str, duration_ms: u128) {
+        let duration_ms = u64::try_from(duration_ms).unwrap_or(u64::MAX);
+        bump(&self.tasks_completed, 1);
+        let total = &self.total_duration_ms;
+        total.store(
+            total.load(Ordering::Relaxed).saturating_add(duration_ms),
+            Ordering::Relaxed,
+        );
+        self.latency.get(task_type).record(duration_ms);
+    }
+
+    /// Everything in the shard, as plain numbers.
+    fn copy(&self) -> SystemStats {
+        let load = |count: &AtomicU32| count.load(Ordering::Relaxed);
+        SystemStats {
+            tasks_completed: load(&self.tasks_completed),
+            tasks_failed: load(&self.tasks_failed),
+            errors: TaskError::KINDS
+                .iter()
+                .zip(&self.errors)
+                .map(|(&kind, count)| (kind, load(count)))
+                .filter(|&(_, count)| count > 0)
+                .collect(),
+            tasks_cancelled: load(&self.tasks_cancelled),
+            tasks_timed_out: load(&self.tasks_timed_out),
+            tasks_discarded: load(&self.tasks_discarded),
+            tasks_skipped: load(&self.tasks_skipped),
+            tasks_expired: load(&self.tasks_expired),
+            tasks_rejected: load(&self.tasks_rejected),
+            total_duration_ms: u128::from(self.total_duration_ms.load(Ordering::Relaxed)),
+            worker_panics: load(&self.worker_panics),
+            retries: load(&self.retries),
+            latency: self.latency.snapshot(),
+            queue_wait: self.queue_wait.snapshot(),
+            ..SystemStats::default()
+        }
+    }
+}
+
+/// How many times to try a busy shard again before yielding.
+const SPINS: u32 = 64;
+
+/// Waits a moment for a busy shard. A thread preempted while recording
+/// gets the core back before long, as this yields after a few tries.
+fn backoff(tries: &mut u32) {
+    if *tries < SPINS {
+        *tries += 1;
+        hint::spin_loop();
+    } else {
+        thread::yield_now();
+    }
+}
+
+/// Adds to a counter only the shard's writer touches.
+fn bump(counter: &AtomicU32, by: u32) {
+    counter.store(counter.load(Ordering::Relaxed) + by, Ordering::Relaxed);
+}
+
+/// How many task types a block of [`Histograms`] has room for.
+const SLOTS: usize = 8;
+
+/// A histogram per task type, which threads find and add to without a
+/// lock. Slots fill in as new task types turn up and never change after,
+/// with another block chained on once these are taken.
+#[derive(Debug, Default)]
+struct Histograms {
+    slots: [OnceLock<(String, AtomicHistogram)>; SLOTS],
+    more: OnceLock<Box<Histograms>>,
+}
+
+impl Histograms {
+    fn get(&self, task_type: &str) -> &AtomicHistogram {
+        for slot in &self.slots {
+            let (slot_type, histogram) =
+                slot.get_or_init(|| (task_type.to_string(), AtomicHistogram::new()));
+            if slot_type == task_type {
+                return histogram;
+            }
+        }
+        self.more.get_or_init(Box::default).get(task_type)
+    }
+
+    fn snapshot(&self) -> BTreeMap<String, LatencyHistogram> {
+        let mut histograms: BTreeMap<_, _> = self
+            .slots
+            .iter()
+            .map_while(OnceLock::get)
+            .map(|(task_type, histogram)| (task_type.clone(), histogram.snapshot()))
+            .collect();
+        if let Some(more) = self.more.get() {
+            histograms.extend(more.snapshot());
+        }
+        histograms
+    }
+}
+
+impl Default for LiveStats {
+    fn default() -> Self {
+        LiveStats::new()
+    }
+}
+
+static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// Threads take shards in the order they first record something, so
+    /// a pool's workers, started one after another, spread across them.
+    static SHARD: usize = NEXT_SHARD.fetch_add(1, Ordering::Relaxed);
+}
+
+impl LiveStats {
+    pub(crate) fn new() -> Self {
+        // More shards than cores only spreads the counts thinner.
+        let shards = thread::available_parallelism().map_or(4, |n| n.get());
+        LiveStats {
+            shards: (0..shards).map(|_| Shard::default()).collect(),
+            active_workers: AtomicU32::new(0),
+            peak_workers: AtomicU32::new(0),
+        }
+    }
+
+    /// The calling thread's shard.
+    fn shard(&self) -> &Shard {
+        &self.shards[SHARD.with(|shard| *shard) % self.shards.len()]
+    }
+
+    /// Adds up every shard. Safe to call from any thread while tasks are
+    /// running, and holds none of them up.
+    pub(crate) fn snapshot(&self) -> SystemStats {
+        let mut stats = SystemStats {
+            active_workers: self.active_workers.load(Ordering::Relaxed),
+            peak_workers: self.peak_workers.load(Ordering::Relaxed),
+            ..SystemStats::default()
+        };
+        for shard in &self.shards {
+            let counts = shard.read(Shard::copy);
+            stats.tasks_completed += counts.tasks_completed;
+            stats.tasks_failed += counts.tasks_failed;
+            for (kind, count) in counts.errors {
+                *stats.errors.entry(kind).or_default() += count;
+            }
+            stats.tasks_cancelled += counts.tasks_cancelled;
+            stats.tasks_timed_out += counts.tasks_timed_out;
+            stats.tasks_discarded += counts.tasks_discarded;
+            stats.tasks_skipped += counts.tasks_skipped;
+            stats.tasks_expired += counts.tasks_expired;
+            stats.tasks_rejected += counts.tasks_rejected;
+            stats.total_duration_ms += counts.total_duration_ms;
+            stats.worker_panics += counts.worker_panics;
+            stats.retries += counts.retries;
+            merge(&mut stats.latency, counts.latency);
+            merge(&mut stats.queue_wait, counts.queue_wait);
+        }
+        stats
+    }
+
+    pub(crate) fn worker_started(&self) {
+        let active = self.active_workers.fetch_add(1, Ordering::Relaxed) + 1;
+        self.peak_workers.fetch_max(active, Ordering::Relaxed);
+    }
+
+    pub(crate) fn worker_stopped(&self) {
+        self.active_workers.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn worker_panicked(&self) {
+        self.shard().write(|shard| {
+            bump(&shard.worker_panics, 1);
+        });
+    }
+
+    pub(crate) fn tasks_discarded(&self, count: u32) {
+        self.shard().write(|shard| {
+            bump(&shard.tasks_discarded, count);
+        });
+    }
+
+    /// Counts a closure run through [`ThreadPool::spawn`](crate::ThreadPool::spawn).
+    pub(crate) fn closure_completed(&self, duration_ms: u128) {
+        self.shard()
+            .write(|shard| shard.completed("closure", duration_ms));
+    }
+
+    /// A worker has started a task of `task_type` that was queued for
+    /// `wait`.
+    pub(crate) fn waited(&self, task_type: &str, wait: Duration) {
+        let wait_ms = u64::try_from(wait.as_millis()).unwrap_or(u64::MAX);
+        self.shard()
+            .write(|shard| shard.queue_wait.get(task_type).record(wait_ms));
+    }
+
+    pub(crate) fn record(&self, result: &TaskResult) {
+        self.shard().write(|shard| {
+            let count = |counter: &AtomicU32| bump(counter, 1);
+            let attempts = match result {
+                TaskResult::Success {
+                    task_type,
+                    duration_ms,
+                    attempts,
+                    ..
+                } => {
+                    shard.completed(task_type, *duration_ms);
+                    *attempts
+                }
+                TaskResult::Error {
+                    error, attempts, ..
+                } => {
+                    count(&shard.tasks_failed);
+                    count(&shard.errors[error.kind_index()]);
+                    *attempts
+                }
+                TaskResult::Cancelled { .. } => {
+                    count(&shard.tasks_cancelled);
+                    1
+                }
+                TaskResult::Panicked { .. } => {
+                    count(&shard.worker_panics);
+                    1
+                }
+                TaskResult::TimedOut { attempts, .. } => {
+                    count(&shard.tasks_timed_out);
+                    *attempts
+                }
+                TaskResult::DependencyFailed { .. } => {
+                    count(&shard.tasks_skipped);
+                    0
+                }
+                TaskResult::Expired { .. } => {
+                    count(&shard.tasks_expired);
+                    0
+                }
+                TaskResult::CircuitOpen { .. } => {
+                    count(&shard.tasks_rejected);
+                    0
+                }
+                TaskResult::Dropped { .. } => {
+                    count(&shard.tasks_discarded);
+                    0
+                }
+            };
+            bump(&shard.retries, attempts.saturating_sub(1));
+        });
+    }
+}
+
+/// Adds one shard's histograms to the totals.
+fn merge(
+    totals: &mut BTreeMap<String, LatencyHistogram>,
+    shard: BTreeMap<String, LatencyHistogram>,
+) {
+    for (task_type, histogram) in shard {
+        totals.entry(task_type).or_default().merge(&histogram);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicBool;
+
+    fn success(id: u32) -> TaskResult {
+        TaskResult::Success {
+            id,
+            task_type: "work".to_string(),
+            correlation_id: None,
+            output: format!("done {id}").into(),
+            duration_ms: 2,
+            queued_ms: 0,
+            attempts: 2,
+        }
+    }
+
+    fn failure(id: u32) -> TaskResult {
+        TaskResult::Error {
+            id,
+            task_type: "work".to_string(),
+            correlation_id: None,
+            error: TaskError::new("broken"),
+            attempts: 1,
+        }
+    }
+
+    #[test]
+    fn snapshots_never_see_a_result_half_counted() {
+        let stats = Arc::new(LiveStats::new());
+        let done = Arc::new(AtomicBool::new(false));
+        let writers: Vec<_> = (0..4)
+            .map(|_| {
+                let stats = Arc::clone(&stats);
+                thread::spawn(move || {
+                    for id in 0..2_000 {
+                        if id % 3 == 0 {
+                            stats.record(&failure(id));
+                        } else {
+                            stats.record(&success(id));
+                        }
+                    }
+                })
+            })
+            .collect();
+        let reader = {
+            let stats = Arc::clone(&stats);
+            let done = Arc::clone(&done);
+            thread::spawn(move || {
+                while !done.load(Ordering::Relaxed) {
+                    let snapshot = stats.snapshot();
+                    let latency = snapshot.latency.get("work").map_or(0, |h| h.count());
+                    assert_eq!(u64::from(snapshot.tasks_completed), latency);
+                    assert_eq!(snapshot.retries, snapshot.tasks_completed);
+                    assert_eq!(snapshot.tasks_failed, snapshot.errors.values().sum());
+                    assert_eq!(
+                        snapshot.total_duration_ms,
+                        2 * u128::from(snapshot.tasks_completed)
+                    );
+                }
+            })
+        };
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        done.store(true, Ordering::Relaxed);
+        reader.join().unwrap();
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.tasks_completed, 4 * 1_333);
+        assert_eq!(snapshot.tasks_failed, 4 * 667);
+        assert_eq!(snapshot.errors.get("other"), Some(&(4 * 667)));
+    }
+}
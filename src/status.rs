@@ -0,0 +1,62 @@
+// Lets a running project be looked at from elsewhere: GET /status for the
+// whole pool and /tasks/<id> for a single task, both as JSON
+use crate::json::{ToJson, Value};
+use crate::server::{self, Response};
+use rust_concurrent_processor::{Monitor, TaskStatus, WorkerState};
+use std::io;
+
+pub fn serve(port: u16, monitor: Monitor) -> io::Result<()> {
+    server::serve(port, move |path| route(&monitor, path))
+}
+
+fn route(monitor: &Monitor, path: &str) -> Response {
+    if path == "/status" {
+        return Response::ok("application/json", status(monitor).pretty());
+    }
+    if let Some(id) = path.strip_prefix("/tasks/") {
+        return match id.parse().ok().and_then(|id| Some((id, monitor.status(id)?))) {
+            Some((id, status)) => Response::ok("application/json", task(monitor, id, status).pretty()),
+            None => Response::not_found(&format!("No task '{}'", id)),
+        };
+    }
+    Response::not_found("Not found; try /status or /tasks/<id>")
+}
+
+fn status(monitor: &Monitor) -> Value {
+    let in_flight = monitor.in_flight().into_iter().map(|(id, status)| task(monitor, id, status));
+    Value::object([
+        ("queued", (monitor.queued() as u64).into()),
+        ("workers", Value::Array(monitor.workers().iter().map(ToJson::to_json).collect())),
+        ("in_flight", Value::Array(in_flight.collect())),
+        ("stats", monitor.stats().to_json()),
+    ])
+}
+
+fn task(monitor: &Monitor, id: u32, status: TaskStatus) -> Value {
+    let progress = monitor.progress(id).map_or(Value::Null, Value::from);
+    Value::object([("id", id.into()), ("status", status.to_json()), ("progress", progress)])
+}
+
+impl ToJson for TaskStatus {
+    fn to_json(&self) -> Value {
+        let (state, detail) = match *self {
+            TaskStatus::Queued => ("queued", None),
+            TaskStatus::Running { worker } => ("running", Some(("worker", worker as u64))),
+            TaskStatus::Stuck { worker } => ("stuck", Some(("worker", worker as u64))),
+            TaskStatus::Retrying { attempt } => ("retrying", Some(("attempt", attempt.into()))),
+            TaskStatus::Succeeded => ("succeeded", None),
+            TaskStatus::Failed => ("failed", None),
+        };
+        Value::object([("state", state.into())].into_iter().chain(detail.map(|(key, n)| (key, n.into()))))
+    }
+}
+
+impl ToJson for WorkerState {
+    fn to_json(&self) -> Value {
+        Value::object([
+            ("worker", (self.worker as u64).into()),
+            ("task", self.task_id.map_or(Value::Null, Value::from)),
+            ("busy_ms", self.busy_for.map_or(Value::Null, |busy| busy.as_millis().into())),
+        ])
+    }
+}
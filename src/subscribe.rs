@@ -0,0 +1,213 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::hooks::TaskListener;
+use crate::pool::ThreadPool;
+use crate::sync::{PoisonPolicy, Recover};
+use crate::task::TaskResult;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum Outcome {
+    #[default]
+    Any,
+    Successes,
+    Failures,
+}
+
+/// Which results a [`Subscription`] receives. The default lets every
+/// result through.
+#[derive(Clone, Debug, Default)]
+pub struct ResultFilter {
+    task_types: Vec<String>,
+    outcome: Outcome,
+}
+
+impl ResultFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only results for tasks of `task_type`. Given more than once, any of
+    /// the types will do.
+    pub fn task_type(mut self, task_type: impl Into<String>) -> Self {
+        self.task_types.push(task_type.into());
+        self
+    }
+
+    /// Only tasks that succeeded.
+    pub fn successes(mut self) -> Self {
+        self.outcome = Outcome::Successes;
+        self
+    }
+
+    /// Only tasks that finished any other way.
+    pub fn failures(mut self) -> Self {
+        self.outcome = Outcome::Failures;
+        self
+    }
+
+    fn matches(&self, result: &TaskResult) -> bool {
+        let outcome = match self.outcome {
+            Outcome::Any => true,
+            Outcome::Successes => result.is_success(),
+            Outcome::Failures => !result.is_success(),
+        };
+        outcome
+            && (self.task_types.is_empty()
+                || self.task_types.iter().any(|t| t == result.task_type()))
+    }
+}
+
+/// A copy of every result the pool records from the moment it subscribed,
+/// returned by [`ThreadPool::subscribe`]. Each subscription gets its own,
+/// so a logger and an aggregator can both watch the same pool without
+/// taking results from each other or from the tasks' handles.
+///
+/// Iteration blocks for the next result and ends once the pool has shut
+/// down. Results pile up until they're read, so a subscription nobody
+/// reads should be dropped.
+pub struct Subscription {
+    receiver: Receiver<TaskResult>,
+}
+
+impl Subscription {
+    /// Yields the results that are ready now without blocking.
+    pub fn try_iter(&self) -> mpsc::TryIter<'_, TaskResult> {
+        self.receiver.try_iter()
+    }
+
+    /// Waits up to `timeout` for the next result. `None` if none came in
+    /// time or the pool has shut down.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<TaskResult> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+}
+
+impl Iterator for Subscription {
+    type Item = TaskResult;
+
+    fn next(&mut self) -> Option<TaskResult> {
+        self.receiver.recv().ok()
+    }
+}
+
+/// An accumulator that results are folded into on a thread of its own,
+/// returned by [`ThreadPool::reduce`].
+pub struct Reduction<A> {
+    value: Arc<Mutex<A>>,
+    reducer: thread::JoinHandle<()>,
+}
+
+impl<A> Reduction<A> {
+    /// The accumulator as it stands, with whatever results have been
+    /// folded in so far.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the fold panicked under [`PoisonPolicy::FailFast`].
+    pub fn get(&self) -> A
+    where
+        A: Clone,
+    {
+        self.value.lock().unwrap().clone()
+    }
+
+    /// Waits for the pool to shut down and the last result to be folded
+    /// in, then hands back the accumulator.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the fold panicked under [`PoisonPolicy::FailFast`].
+    pub fn wait(self) -> A {
+        self.reducer.join().expect("reducer panicked");
+        let value = Arc::into_inner(self.value).expect("reducer has finished");
+        value.into_inner().unwrap()
+    }
+}
+
+/// Everyone subscribed to a pool. Registered as one of its listeners, so
+/// it sees every result as the worker records it.
+#[derive(Default)]
+pub(crate) struct Subscribers(Mutex<Vec<(ResultFilter, Sender<TaskResult>)>>);
+
+impl Subscribers {
+    fn subscribe(&self, filter: ResultFilter) -> Subscription {
+        let (sender, receiver) = mpsc::channel();
+        self.0.lock().recover().push((filter, sender));
+        Subscription { receiver }
+    }
+
+    /// Sends `result` on to every subscriber that wants it, forgetting
+    /// those that have gone.
+    fn publish(&self, result: &TaskResult) {
+        self.0.lock().recover().retain(|(filter, sender)| {
+            !filter.matches(result) || sender.send(result.clone()).is_ok()
+        });
+    }
+}
+
+impl TaskListener for Subscribers {
+    fn on_complete(&self, result: &TaskResult) {
+        self.publish(result);
+    }
+
+    fn on_failure(&self, result: &TaskResult) {
+        self.publish(result);
+    }
+}
+
+impl ThreadPool {
+    /// Starts receiving a copy of every result from here on, alongside
+    /// whoever submitted the task.
+    pub fn subscribe(&self) -> Subscription {
+        self.subscribers.subscribe(ResultFilter::new())
+    }
+
+    /// Like [`subscribe`](Self::subscribe), but only for the results
+    /// `filter` lets through.
+    pub fn subscribe_filtered(&self, filter: ResultFilter) -> Subscription {
+        self.subscribers.subscribe(filter)
+    }
+
+    /// Folds every result from here on into `init` with `f`, on a reducer
+    /// thread so the caller doesn't have to run a receive loop. The
+    /// accumulator can be read at any time and is final once the pool has
+    /// shut down. To fold on the calling thread instead, iterate over a
+    /// [`subscribe`](Self::subscribe) or [`results`](Self::results).
+    ///
+    /// A panic in `f` is dealt with as the pool's
+    /// [`poison_policy`](crate::ThreadPoolBuilder::poison_policy) says.
+    pub fn reduce<A, F>(&self, init: A, mut f: F) -> Reduction<A>
+    where
+        A: Send + 'static,
+        F: FnMut(&mut A, TaskResult) + Send + 'static,
+    {
+        let subscription = self.subscribe();
+        let policy = self.shared.poison_policy;
+        let value = Arc::new(Mutex::new(init));
+        let reducer = {
+            let value = Arc::clone(&value);
+            thread::Builder::new()
+                .name("reducer".to_string())
+                .spawn(move || {
+                    for result in subscription {
+                        let mut accumulator = value.lock().unwrap();
+                        match policy {
+                            // Caught while the lock is still held, so it
+                            // isn't poisoned.
+                            PoisonPolicy::Recover => {
+                                let fold = AssertUnwindSafe(|| f(&mut accumulator, result));
+                                let _ = panic::catch_unwind(fold);
+                            }
+                            PoisonPolicy::FailFast => f(&mut accumulator, result),
+                        }
+                    }
+                })
+                .expect("failed to spawn the reducer thread")
+        };
+        Reduction { value, reducer }
+    }
+}
@@ -0,0 +1,42 @@
+// How a demo's run went, in the same words for part2b's thread pool and
+// part4's async tasks so the two can be read side by side
+use rust_concurrent_processor::TaskResult;
+use std::time::Duration;
+
+#[derive(Default)]
+pub struct Summary {
+    completed: usize,
+    failed: usize,
+    // Time successful tasks took, added up
+    busy_ms: u128,
+}
+
+impl Summary {
+    pub fn new(results: &[TaskResult]) -> Summary {
+        let mut summary = Summary::default();
+        results.iter().for_each(|result| summary.add(result));
+        summary
+    }
+
+    pub fn add(&mut self, result: &TaskResult) {
+        match result {
+            TaskResult::Success { duration_ms, .. } => {
+                self.completed += 1;
+                self.busy_ms += duration_ms;
+            },
+            _ => self.failed += 1,
+        }
+    }
+
+    pub fn print(&self, elapsed: Duration) {
+        let per_second = (self.completed + self.failed) as f64 / elapsed.as_secs_f64().max(0.001);
+        println!(
+            "Completed: {}, Failed: {}, Task time: {}ms, Wall time: {}ms ({:.1} tasks/s)",
+            self.completed,
+            self.failed,
+            self.busy_ms,
+            elapsed.as_millis(),
+            per_second
+        );
+    }
+}
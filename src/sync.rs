@@ -0,0 +1,82 @@
+#[cfg(feature = "spin-lock")]
+use std::hint;
+#[cfg(feature = "spin-lock")]
+use std::sync::TryLockError;
+use std::sync::{self, LockResult, MutexGuard, PoisonError};
+#[cfg(feature = "spin-lock")]
+use std::thread;
+
+/// What the pool does about a panic in code it calls back into outside a
+/// task, set with
+/// [`ThreadPoolBuilder::poison_policy`](crate::ThreadPoolBuilder::poison_policy):
+/// a [`TaskListener`](crate::TaskListener) or the fold given to
+/// [`ThreadPool::reduce`](crate::ThreadPool::reduce). Panics in tasks are
+/// caught either way and only fail the task.
+///
+/// The pool's own locks, around its queues, stats and registry, never
+/// have a callback running under them and are always taken back after a
+/// panic, so a stray one can't wedge the whole pool.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PoisonPolicy {
+    /// Catches the panic and carries on. A panicking listener misses that
+    /// one call and the task's result still reaches everyone else; a
+    /// panicking fold leaves the accumulator as it was when it panicked
+    /// and goes on to the next result.
+    #[default]
+    Recover,
+    /// Lets the panic unwind. A listener takes down the worker it ran on,
+    /// which is replaced, and the task's result is lost; a fold stops the
+    /// reduction, and reading it panics from then on.
+    FailFast,
+}
+
+/// Takes a lock whether or not the last thread to hold it panicked. For
+/// the pool's own locks, which never run a task or callback while held,
+/// so their data is whole at every point a panic could come from.
+pub(crate) trait Recover<T> {
+    fn recover(self) -> T;
+}
+
+impl<T> Recover<T> for LockResult<T> {
+    fn recover(self) -> T {
+        self.unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// The mutex around the pool's queues, workers, timer and registry. A
+/// plain std mutex, unless the `spin-lock` feature is on: then a thread
+/// that finds it held spins, then yields, for a while before it blocks,
+/// betting the holder lets go first, as parking_lot's mutex does. The
+/// guard is std's either way, so it still goes with a std `Condvar`.
+#[derive(Debug, Default)]
+pub(crate) struct Mutex<T: ?Sized>(sync::Mutex<T>);
+
+/// How many times a held [`Mutex`] is tried again, spinning, before it
+/// yields instead.
+#[cfg(feature = "spin-lock")]
+const SPINS: u32 = 40;
+
+/// How many more times it's tried, yielding, before the thread blocks.
+#[cfg(feature = "spin-lock")]
+const YIELDS: u32 = 10;
+
+impl<T> Mutex<T> {
+    pub(crate) const fn new(value: T) -> Self {
+        Mutex(sync::Mutex::new(value))
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    pub(crate) fn lock(&self) -> LockResult<MutexGuard<'_, T>> {
+        #[cfg(feature = "spin-lock")]
+        for tries in 0..SPINS + YIELDS {
+            match self.0.try_lock() {
+                Ok(guard) => return Ok(guard),
+                Err(TryLockError::Poisoned(err)) => return Err(err),
+                Err(TryLockError::WouldBlock) if tries < SPINS => hint::spin_loop(),
+                Err(TryLockError::WouldBlock) => thread::yield_now(),
+            }
+        }
+        self.0.lock()
+    }
+}
@@ -0,0 +1,521 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::cancel::CancellationToken;
+use crate::clock::SharedClock;
+use crate::local;
+use crate::registry::{self, TaskRegistry};
+
+/// What a task hands back when it succeeds.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TaskOutput {
+    pub message: String,
+}
+
+impl TaskOutput {
+    pub fn new(message: impl Into<String>) -> Self {
+        TaskOutput {
+            message: message.into(),
+        }
+    }
+}
+
+impl From<String> for TaskOutput {
+    fn from(message: String) -> Self {
+        TaskOutput::new(message)
+    }
+}
+
+impl From<&str> for TaskOutput {
+    fn from(message: &str) -> Self {
+        TaskOutput::new(message)
+    }
+}
+
+impl fmt::Display for TaskOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Why a task failed. The variant says what kind of failure it was, so
+/// retries and stats can tell them apart; [`Other`](TaskError::Other) covers
+/// anything that doesn't fit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TaskError {
+    /// Something the task waited on took too long.
+    Timeout {
+        after_ms: u128,
+    },
+    /// A remote service couldn't be reached or answered with an error.
+    Network {
+        url: String,
+        message: String,
+    },
+    /// Downloaded data didn't hash to the checksum the task expected. Worth
+    /// another try, in case it was damaged on the way.
+    ChecksumMismatch {
+        url: String,
+        expected: String,
+        actual: String,
+    },
+    /// The task was given something it can't work with. Retrying won't help.
+    InvalidInput(String),
+    /// Part of the task panicked, e.g. a subtask it was waiting on.
+    Panicked(String),
+    /// The task gave up because it was cancelled.
+    Cancelled,
+    Other(String),
+}
+
+impl TaskError {
+    /// An [`Other`](TaskError::Other) error with the given message.
+    pub fn new(message: impl Into<String>) -> Self {
+        TaskError::Other(message.into())
+    }
+
+    /// Short name of the variant, e.g. `"network"`, as used in
+    /// [`SystemStats::errors`](crate::SystemStats::errors).
+    pub fn kind(&self) -> &'static str {
+        Self::KINDS[self.kind_index()]
+    }
+
+    /// Every [`kind`](Self::kind) there is.
+    pub(crate) const KINDS: [&'static str; 7] = [
+        "timeout",
+        "network",
+        "checksum_mismatch",
+        "invalid_input",
+        "panicked",
+        "cancelled",
+        "other",
+    ];
+
+    /// Where the error's kind is in [`KINDS`](Self::KINDS).
+    pub(crate) fn kind_index(&self) -> usize {
+        match self {
+            TaskError::Timeout { .. } => 0,
+            TaskError::Network { .. } => 1,
+            TaskError::ChecksumMismatch { .. } => 2,
+            TaskError::InvalidInput(_) => 3,
+            TaskError::Panicked(_) => 4,
+            TaskError::Cancelled => 5,
+            TaskError::Other(_) => 6,
+        }
+    }
+
+    /// Whether another attempt might succeed. Invalid input, panics and
+    /// cancellation fail the same way every time, so the retry policy
+    /// gives up on them straight away.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            TaskError::Timeout { .. }
+                | TaskError::Network { .. }
+                | TaskError::ChecksumMismatch { .. }
+                | TaskError::Other(_)
+        )
+    }
+}
+
+impl From<String> for TaskError {
+    fn from(message: String) -> Self {
+        TaskError::new(message)
+    }
+}
+
+impl From<&str> for TaskError {
+    fn from(message: &str) -> Self {
+        TaskError::new(message)
+    }
+}
+
+impl fmt::Display for TaskError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TaskError::Timeout { after_ms } => write!(f, "timed out after {}ms", after_ms),
+            TaskError::Network { url, message } => write!(f, "{}: {}", url, message),
+            TaskError::ChecksumMismatch {
+                url,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{}: checksum mismatch, expected {} but got {}",
+                url, expected, actual
+            ),
+            TaskError::InvalidInput(message) => write!(f, "invalid input: {}", message),
+            TaskError::Panicked(message) => write!(f, "panicked: {}", message),
+            TaskError::Cancelled => f.write_str("cancelled"),
+            TaskError::Other(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for TaskError {}
+
+/// What the submitter attached to a task, and when it was submitted.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct TaskInfo {
+    pub(crate) task_type: Arc<str>,
+    pub(crate) correlation_id: Option<Arc<str>>,
+    pub(crate) metadata: Arc<BTreeMap<String, String>>,
+    /// `None` outside a pool.
+    pub(crate) submitted_at: Option<Instant>,
+}
+
+impl TaskInfo {
+    /// A copy of the correlation id for the task's result to carry.
+    pub(crate) fn correlation_id(&self) -> Option<String> {
+        self.correlation_id.as_deref().map(String::from)
+    }
+}
+
+/// What the pool hands a task while it runs.
+#[derive(Clone, Debug, Default)]
+pub struct TaskContext {
+    cancellation: CancellationToken,
+    shutdown: CancellationToken,
+    deadline: Option<Instant>,
+    progress: ProgressReporter,
+    clock: SharedClock,
+    task_id: u32,
+    attempt: u32,
+    worker: Option<usize>,
+    info: TaskInfo,
+}
+
+impl TaskContext {
+    pub(crate) fn new(
+        cancellation: CancellationToken,
+        deadline: Option<Instant>,
+        progress: ProgressReporter,
+        clock: SharedClock,
+    ) -> Self {
+        TaskContext {
+            cancellation,
+            deadline,
+            progress,
+            clock,
+            ..TaskContext::default()
+        }
+    }
+
+    /// Cancels the task along with `shutdown` too, as a pool does when it
+    /// shuts down immediately.
+    pub(crate) fn with_shutdown(mut self, shutdown: CancellationToken) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Says which task and which attempt at it this context is for.
+    pub(crate) fn for_attempt(mut self, task_id: u32, attempt: u32, info: TaskInfo) -> Self {
+        self.task_id = task_id;
+        self.attempt = attempt;
+        self.worker = registry::worker_id();
+        self.info = info;
+        self
+    }
+
+    /// The id of the task running.
+    pub fn task_id(&self) -> u32 {
+        self.task_id
+    }
+
+    /// The [`Task::kind`] of the task running.
+    pub fn task_type(&self) -> &str {
+        &self.info.task_type
+    }
+
+    /// The pool worker running the task, as in
+    /// [`TaskStatus::Running`](crate::TaskStatus::Running). `None` off a
+    /// pool.
+    pub fn worker(&self) -> Option<usize> {
+        self.worker
+    }
+
+    /// Which attempt this is, counting from 1. Higher after a retry.
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    /// The id given with [`SubmitOptions::correlation_id`](crate::SubmitOptions::correlation_id),
+    /// for tying the task's logs to whatever asked for it.
+    pub fn correlation_id(&self) -> Option<&str> {
+        self.info.correlation_id.as_deref()
+    }
+
+    /// One value given with [`SubmitOptions::metadata`](crate::SubmitOptions::metadata).
+    pub fn metadata(&self, key: &str) -> Option<&str> {
+        self.info.metadata.get(key).map(String::as_str)
+    }
+
+    /// Every value given with [`SubmitOptions::metadata`](crate::SubmitOptions::metadata),
+    /// by key.
+    pub fn all_metadata(&self) -> &BTreeMap<String, String> {
+        &self.info.metadata
+    }
+
+    /// When the task was submitted. `None` for a context made outside a
+    /// pool.
+    pub fn submitted_at(&self) -> Option<Instant> {
+        self.info.submitted_at
+    }
+
+    /// Reports that the task is `fraction` of the way done, from 0.0 to
+    /// 1.0. Shorthand for `ctx.progress_reporter().report(fraction)`.
+    pub fn progress(&self, fraction: f64) {
+        self.progress.report(fraction);
+    }
+
+    /// A handle for reporting progress from somewhere else, such as the
+    /// subtasks the task splits into.
+    pub fn progress_reporter(&self) -> ProgressReporter {
+        self.progress.clone()
+    }
+
+    /// Long-running tasks should poll this and bail out early when it
+    /// turns true. It covers explicit cancellation, the pool shutting down
+    /// with [`ShutdownMode::Immediate`](crate::ShutdownMode::Immediate)
+    /// and running past the task's timeout.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.is_cancelled() || self.shutdown.is_cancelled() || self.is_timed_out()
+    }
+
+    pub fn is_timed_out(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| self.clock.now() >= deadline)
+    }
+
+    /// The time on the pool's [`Clock`](crate::Clock).
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    /// Waits `duration` on the pool's [`Clock`](crate::Clock): really, or
+    /// not at all under a [`VirtualClock`](crate::VirtualClock), which just
+    /// moves forward. Tasks that wait this way rather than with
+    /// [`thread::sleep`](std::thread::sleep) can be simulated.
+    pub fn sleep(&self, duration: Duration) {
+        self.clock.sleep(duration);
+    }
+
+    /// Runs `f` on the state the worker keeps from
+    /// [`ThreadPoolBuilder::worker_init`](crate::ThreadPoolBuilder::worker_init),
+    /// making it if this is the worker's first task to ask. `None` if the
+    /// pool keeps no state of type `S`, or off the pool.
+    ///
+    /// If another task still holds the state, such as the one waiting on
+    /// this task, `f` gets a fresh state that's dropped afterwards.
+    pub fn with_worker_state<S, R>(&self, f: impl FnOnce(&mut S) -> R) -> Option<R>
+    where
+        S: 'static,
+    {
+        local::with_state(f)
+    }
+
+    /// Convenience for `?`: fails with a "cancelled" error once the task's
+    /// token has been cancelled.
+    pub fn check_cancelled(&self) -> Result<(), TaskError> {
+        if self.is_cancelled() {
+            Err(TaskError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Lets a running task say how far along it is. The latest value shows up
+/// in [`ThreadPool::progress`](crate::ThreadPool::progress) and the
+/// [`LiveReport`](crate::LiveReport).
+#[derive(Clone, Debug, Default)]
+pub struct ProgressReporter {
+    /// The task reporting and where it goes; `None` for a context made
+    /// outside a pool, which reports nowhere.
+    target: Option<(u32, Arc<TaskRegistry>)>,
+}
+
+impl ProgressReporter {
+    pub(crate) fn new(id: u32, registry: Arc<TaskRegistry>) -> Self {
+        ProgressReporter {
+            target: Some((id, registry)),
+        }
+    }
+
+    /// Records `fraction` as the task's progress, clamped to 0.0 to 1.0.
+    /// Not a number is ignored.
+    pub fn report(&self, fraction: f64) {
+        if let Some((id, registry)) = &self.target
+            && !fraction.is_nan()
+        {
+            registry.progress(*id, fraction.clamp(0.0, 1.0));
+        }
+    }
+}
+
+/// A unit of work the pool knows how to run.
+pub trait Task: Send + Sync {
+    fn id(&self) -> u32;
+
+    /// Short label used when reporting results, e.g. `"download"`.
+    fn kind(&self) -> &str {
+        "task"
+    }
+
+    /// How long the task is expected to take, if known. Only schedulers
+    /// that order by cost, such as
+    /// [`Scheduler::ShortestJobFirst`](crate::Scheduler::ShortestJobFirst),
+    /// look at it.
+    fn cost(&self) -> Option<Duration> {
+        None
+    }
+
+    fn execute(&self, ctx: &TaskContext) -> Result<TaskOutput, TaskError>;
+}
+
+/// Lets boxed tasks, e.g. `Box<dyn Task>`, go wherever a task can.
+impl<T: Task + ?Sized> Task for Box<T> {
+    fn id(&self) -> u32 {
+        (**self).id()
+    }
+
+    fn kind(&self) -> &str {
+        (**self).kind()
+    }
+
+    fn cost(&self) -> Option<Duration> {
+        (**self).cost()
+    }
+
+    fn execute(&self, ctx: &TaskContext) -> Result<TaskOutput, TaskError> {
+        (**self).execute(ctx)
+    }
+}
+
+/// Outcome of one task, as reported by the pool.
+#[derive(Clone, Debug)]
+pub enum TaskResult {
+    Success {
+        id: u32,
+        task_type: String,
+        correlation_id: Option<String>,
+        output: TaskOutput,
+        /// How long the successful attempt ran: the service time.
+        duration_ms: u128,
+        /// How long the task waited for a worker before its first attempt.
+        queued_ms: u128,
+        /// How many times the task ran, retries included.
+        attempts: u32,
+    },
+    Error {
+        id: u32,
+        task_type: String,
+        correlation_id: Option<String>,
+        error: TaskError,
+        attempts: u32,
+    },
+    /// The task's [`CancellationToken`] fired before or while it ran.
+    Cancelled {
+        id: u32,
+        task_type: String,
+        correlation_id: Option<String>,
+    },
+    /// The task panicked. The worker that ran it survives.
+    Panicked {
+        id: u32,
+        task_type: String,
+        correlation_id: Option<String>,
+        message: String,
+    },
+    /// The task ran longer than its timeout.
+    TimedOut {
+        id: u32,
+        task_type: String,
+        correlation_id: Option<String>,
+        timeout_ms: u128,
+        attempts: u32,
+    },
+    /// A task this one depended on didn't succeed, so it never ran.
+    /// `dependency` is the id of that task.
+    DependencyFailed {
+        id: u32,
+        task_type: String,
+        correlation_id: Option<String>,
+        dependency: u32,
+    },
+    /// The circuit breaker for the task's type was open, so it failed fast
+    /// without running.
+    CircuitOpen {
+        id: u32,
+        task_type: String,
+        correlation_id: Option<String>,
+    },
+    /// The task was still queued when its deadline passed, so it never ran.
+    Expired {
+        id: u32,
+        task_type: String,
+        correlation_id: Option<String>,
+        /// How long after the deadline a worker got to it.
+        late_ms: u128,
+    },
+    /// [`Chaos`](crate::Chaos) dropped the task from the queue, so it
+    /// never ran.
+    Dropped {
+        id: u32,
+        task_type: String,
+        correlation_id: Option<String>,
+    },
+}
+
+impl TaskResult {
+    pub fn id(&self) -> u32 {
+        match self {
+            TaskResult::Success { id, .. }
+            | TaskResult::Error { id, .. }
+            | TaskResult::Cancelled { id, .. }
+            | TaskResult::Panicked { id, .. }
+            | TaskResult::TimedOut { id, .. }
+            | TaskResult::DependencyFailed { id, .. }
+            | TaskResult::CircuitOpen { id, .. }
+            | TaskResult::Expired { id, .. }
+            | TaskResult::Dropped { id, .. } => *id,
+        }
+    }
+
+    pub fn task_type(&self) -> &str {
+        match self {
+            TaskResult::Success { task_type, .. }
+            | TaskResult::Error { task_type, .. }
+            | TaskResult::Cancelled { task_type, .. }
+            | TaskResult::Panicked { task_type, .. }
+            | TaskResult::TimedOut { task_type, .. }
+            | TaskResult::DependencyFailed { task_type, .. }
+            | TaskResult::CircuitOpen { task_type, .. }
+            | TaskResult::Expired { task_type, .. }
+            | TaskResult::Dropped { task_type, .. } => task_type,
+        }
+    }
+
+    /// The id given with
+    /// [`SubmitOptions::correlation_id`](crate::SubmitOptions::correlation_id),
+    /// if there was one.
+    pub fn correlation_id(&self) -> Option<&str> {
+        match self {
+            TaskResult::Success { correlation_id, .. }
+            | TaskResult::Error { correlation_id, .. }
+            | TaskResult::Cancelled { correlation_id, .. }
+            | TaskResult::Panicked { correlation_id, .. }
+            | TaskResult::TimedOut { correlation_id, .. }
+            | TaskResult::DependencyFailed { correlation_id, .. }
+            | TaskResult::CircuitOpen { correlation_id, .. }
+            | TaskResult::Expired { correlation_id, .. }
+            | TaskResult::Dropped { correlation_id, .. } => correlation_id.as_deref(),
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        matches!(self, TaskResult::Success { .. })
+    }
+}
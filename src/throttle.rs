@@ -0,0 +1,60 @@
+// A budget of bytes a second shared by every download, so a run can go
+// easy on a shared network: a token bucket holding a second's worth. Each
+// read takes tokens for what it got, and waits off whatever it overdrew
+use std::io::{self, Read};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+pub struct Bandwidth {
+    per_second: f64,
+    bucket: Mutex<Bucket>,
+}
+
+impl Bandwidth {
+    pub fn new(bytes_per_second: u64) -> Bandwidth {
+        let per_second = bytes_per_second.max(1) as f64;
+        Bandwidth { per_second, bucket: Mutex::new(Bucket { tokens: per_second, refilled: Instant::now() }) }
+    }
+
+    // Reads from `inner` within the budget
+    pub fn throttle<R: Read>(&self, inner: R) -> Throttled<'_, R> {
+        Throttled { inner, bandwidth: self }
+    }
+
+    // Pays for `bytes` already read, waiting until the budget is back out
+    // of debt
+    fn take(&self, bytes: usize) {
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap();
+            let now = Instant::now();
+            let refill = (now - bucket.refilled).as_secs_f64() * self.per_second;
+            bucket.tokens = (bucket.tokens + refill).min(self.per_second) - bytes as f64;
+            bucket.refilled = now;
+            Duration::from_secs_f64((-bucket.tokens).max(0.0) / self.per_second)
+        };
+        thread::sleep(wait);
+    }
+}
+
+pub struct Throttled<'a, R> {
+    inner: R,
+    bandwidth: &'a Bandwidth,
+}
+
+impl<R: Read> Read for Throttled<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // A tenth of a second's worth at most, so one big read can't run up
+        // a long debt for everyone else
+        let most = ((self.bandwidth.per_second / 10.0) as usize).max(1);
+        let len = buf.len().min(most);
+        let read = self.inner.read(&mut buf[..len])?;
+        self.bandwidth.take(read);
+        Ok(read)
+    }
+}
@@ -0,0 +1,154 @@
+// Writes down when each task ran and on which worker, through the pool's
+// listener hooks, so a run can be looked at afterwards. Its locks are
+// taken back after a panic, which can't leave a span half written
+use crate::json::Value;
+use rust_concurrent_processor::{TaskListener, TaskResult};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Mutex, PoisonError};
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
+
+// One task's run, from the worker picking it up to its result
+#[derive(Clone, Debug)]
+pub struct Span {
+    pub id: u32,
+    pub task_type: String,
+    pub worker: usize,
+    pub start: Duration,
+    pub end: Duration,
+    pub success: bool,
+}
+
+struct Started {
+    id: u32,
+    task_type: String,
+    worker: usize,
+    start: Duration,
+}
+
+pub struct Timeline {
+    origin: Instant,
+    // Tasks a worker is in the middle of, innermost last: a worker waiting
+    // on subtasks may run other tasks meanwhile
+    running: Mutex<HashMap<ThreadId, Vec<Started>>>,
+    spans: Mutex<Vec<Span>>,
+}
+
+impl Timeline {
+    pub fn new() -> Timeline {
+        Timeline {
+            origin: Instant::now(),
+            running: Mutex::new(HashMap::new()),
+            spans: Mutex::new(Vec::new()),
+        }
+    }
+
+    // Every finished span so far, in the order they started
+    pub fn spans(&self) -> Vec<Span> {
+        let mut spans = self.spans.lock().unwrap_or_else(PoisonError::into_inner).clone();
+        spans.sort_by_key(|span| span.start);
+        spans
+    }
+
+    // Chrome's trace event format, which chrome://tracing and Perfetto
+    // open: one row per worker, one bar per task
+    pub fn to_chrome_trace(&self) -> Value {
+        let spans = self.spans();
+        let workers: BTreeSet<usize> = spans.iter().map(|span| span.worker).collect();
+        let names = workers.into_iter().map(|worker| {
+            Value::object([
+                ("name", "thread_name".into()),
+                ("ph", "M".into()),
+                ("pid", 1u32.into()),
+                ("tid", (worker as u64).into()),
+                ("args", Value::object([("name", format!("worker {}", worker).into())])),
+            ])
+        });
+        let events = spans.iter().map(|span| {
+            Value::object([
+                ("name", format!("{} {}", span.task_type, span.id).into()),
+                ("cat", span.task_type.as_str().into()),
+                ("ph", "X".into()),
+                ("ts", (span.start.as_micros()).into()),
+                ("dur", ((span.end - span.start).as_micros()).into()),
+                ("pid", 1u32.into()),
+                ("tid", (span.worker as u64).into()),
+                ("args", Value::object([("id", span.id.into()), ("success", span.success.into())])),
+            ])
+        });
+        Value::object([("traceEvents", Value::Array(names.chain(events).collect()))])
+    }
+
+    pub fn write_chrome_trace(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_chrome_trace().to_string())
+    }
+
+    // One row per worker, `width` columns wide from when the timeline was
+    // started to the end of the last task. Each task is drawn from its id onwards, with
+    // '=' while it ran or 'x' if it didn't succeed; blanks are idle time
+    pub fn gantt(&self, width: usize) -> String {
+        let spans = self.spans();
+        let Some(total) = spans.iter().map(|span| span.end).max() else {
+            return "No tasks ran\n".to_string();
+        };
+        let total = total.max(Duration::from_millis(1));
+        let column = |at: Duration| ((at.as_secs_f64() / total.as_secs_f64()) * width as f64) as usize;
+
+        let mut rows: BTreeMap<usize, (Vec<char>, Duration, u32)> = BTreeMap::new();
+        for span in &spans {
+            let (cells, busy, count) = rows.entry(span.worker).or_insert_with(|| (vec![' '; width], Duration::ZERO, 0));
+            *busy += span.end - span.start;
+            *count += 1;
+            let start = column(span.start).min(width - 1);
+            let end = column(span.end).clamp(start + 1, width);
+            let fill = if span.success { '=' } else { 'x' };
+            let label = span.id.to_string();
+            for (cell, at) in cells[start..end].iter_mut().zip(start..) {
+                *cell = label.chars().nth(at - start).unwrap_or(fill);
+            }
+        }
+
+        let ends = format!("{}ms", total.as_millis());
+        // Lined up with the bars below, which start after "worker NN |"
+        let mut out = format!("{:11}0ms{:>width$}\n", "", ends, width = width.saturating_sub(3));
+        for (worker, (cells, busy, count)) in rows {
+            let busy = 100.0 * busy.as_secs_f64() / total.as_secs_f64();
+            let cells: String = cells.into_iter().collect();
+            out += &format!("worker {:<2} |{}| {:.0}% busy, {} tasks\n", worker, cells, busy, count);
+        }
+        out
+    }
+
+    fn finish(&self, result: &TaskResult) {
+        let end = self.origin.elapsed();
+        let started = self.running.lock().unwrap_or_else(PoisonError::into_inner).get_mut(&thread::current().id()).and_then(Vec::pop);
+        // Every finish follows a start on the same thread
+        let Some(started) = started else { return };
+        self.spans.lock().unwrap_or_else(PoisonError::into_inner).push(Span {
+            id: started.id,
+            task_type: started.task_type,
+            worker: started.worker,
+            start: started.start,
+            end,
+            success: result.is_success(),
+        });
+    }
+}
+
+impl TaskListener for Timeline {
+    fn on_start(&self, task_id: u32, task_type: &str, worker: usize) {
+        let started = Started { id: task_id, task_type: task_type.to_string(), worker, start: self.origin.elapsed() };
+        self.running.lock().unwrap_or_else(PoisonError::into_inner).entry(thread::current().id()).or_default().push(started);
+    }
+
+    fn on_complete(&self, result: &TaskResult) {
+        self.finish(result);
+    }
+
+    fn on_failure(&self, result: &TaskResult) {
+        self.finish(result);
+    }
+}
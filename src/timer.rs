@@ -0,0 +1,244 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::{Arc, Condvar};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::clock::SharedClock;
+use crate::handle::TaskHandle;
+use crate::pool::{Job, Shared, ShutdownMode, SubmitOptions, ThreadPool};
+use crate::queue::JobInfo;
+use crate::sync::{Mutex, Recover};
+use crate::task::{Task, TaskResult};
+
+/// What the timer does once an entry is due.
+pub(crate) enum Pending {
+    /// Queues a delayed job.
+    Job {
+        job: Job,
+        info: JobInfo,
+        lane: Arc<Shared>,
+    },
+    /// Submits the next run of a recurring task. Dropped unrun when the pool
+    /// shuts down.
+    Occurrence(Box<dyn FnOnce() + Send>),
+}
+
+struct Entry {
+    due: Instant,
+    /// Breaks ties between entries due at the same instant, first come
+    /// first served.
+    sequence: u64,
+    pending: Pending,
+}
+
+// `BinaryHeap` is a max-heap, so the earliest entry has to compare greatest.
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.due, other.sequence).cmp(&(self.due, self.sequence))
+    }
+}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Entry {}
+
+#[derive(Default)]
+struct State {
+    entries: BinaryHeap<Entry>,
+    next_sequence: u64,
+    closed: bool,
+}
+
+struct Inner {
+    state: Mutex<State>,
+    changed: Condvar,
+    thread: Mutex<Option<thread::JoinHandle<()>>>,
+    clock: SharedClock,
+}
+
+/// Holds delayed jobs and moves each one onto its queue once it's due on
+/// the pool's clock. The thread doing that only starts with the first
+/// delayed job. Clones share the same timer.
+#[derive(Clone)]
+pub(crate) struct Timer {
+    inner: Arc<Inner>,
+}
+
+impl Timer {
+    pub(crate) fn new(clock: SharedClock) -> Self {
+        Timer {
+            inner: Arc::new(Inner {
+                state: Mutex::default(),
+                changed: Condvar::new(),
+                thread: Mutex::default(),
+                clock,
+            }),
+        }
+    }
+
+    pub(crate) fn now(&self) -> Instant {
+        self.inner.clock.now()
+    }
+
+    /// Hands `pending` to the timer thread to deal with at `due`. Returns
+    /// false, dropping `pending`, once the timer is closed.
+    pub(crate) fn schedule(&self, due: Instant, pending: Pending) -> bool {
+        let mut state = self.inner.state.lock().recover();
+        if state.closed {
+            return false;
+        }
+        let sequence = state.next_sequence;
+        state.next_sequence += 1;
+        state.entries.push(Entry {
+            due,
+            sequence,
+            pending,
+        });
+        drop(state);
+        self.inner.changed.notify_one();
+
+        let mut thread = self.inner.thread.lock().recover();
+        if thread.is_none() {
+            let inner = Arc::clone(&self.inner);
+            *thread = Some(thread::spawn(move || run(&inner)));
+        }
+        true
+    }
+
+    /// Stops taking jobs and ends recurring tasks. With
+    /// [`ShutdownMode::Drain`] this waits until the last delayed job is due
+    /// and queued; with [`ShutdownMode::Immediate`] the ones still waiting
+    /// are dropped and counted as discarded.
+    pub(crate) fn close(&self, mode: ShutdownMode) {
+        let mut state = self.inner.state.lock().recover();
+        state.closed = true;
+        let entries = std::mem::take(&mut state.entries);
+        for entry in entries {
+            match entry.pending {
+                Pending::Job { lane, .. } if mode == ShutdownMode::Immediate => {
+                    lane.stats.tasks_discarded(1);
+                }
+                Pending::Job { .. } => state.entries.push(entry),
+                Pending::Occurrence(_) => {}
+            }
+        }
+        drop(state);
+        self.inner.changed.notify_one();
+
+        if let Some(thread) = self.inner.thread.lock().recover().take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run(inner: &Inner) {
+    let mut state = inner.state.lock().recover();
+    loop {
+        let now = inner.clock.now();
+        match state.entries.peek() {
+            Some(entry) if entry.due <= now => {
+                let entry = state.entries.pop().expect("just peeked");
+                // Queueing can block on a full queue; don't hold up
+                // `schedule` meanwhile.
+                drop(state);
+                match entry.pending {
+                    Pending::Job { job, info, lane } => lane.release(job, info),
+                    Pending::Occurrence(submit) => submit(),
+                }
+                state = inner.state.lock().recover();
+            }
+            Some(entry) => {
+                let wait = entry.due - now;
+                state = inner.clock.wait_timeout(&inner.changed, state, wait);
+            }
+            None if state.closed => return,
+            None => state = inner.changed.wait(state).recover(),
+        }
+    }
+}
+
+impl ThreadPool {
+    /// Queues `task` once `delay` has passed and returns a handle to its
+    /// [`TaskResult`]. Until then the task waits off the queue, so it
+    /// doesn't hold up anything else.
+    pub fn submit_delayed<T>(&self, delay: Duration, task: T) -> TaskHandle<TaskResult>
+    where
+        T: Task + 'static,
+    {
+        self.submit_at(self.timer.now() + delay, task)
+    }
+
+    /// Queues `task` at `when`, as the pool's
+    /// [clock](crate::ThreadPoolBuilder::clock) tells the time. A time that
+    /// has already passed queues it right away.
+    pub fn submit_at<T>(&self, when: Instant, task: T) -> TaskHandle<TaskResult>
+    where
+        T: Task + 'static,
+    {
+        self.submit_at_with(when, task, SubmitOptions::new())
+    }
+
+    /// Like [`submit_at`](Self::submit_at), with [`SubmitOptions`].
+    pub fn submit_at_with<T>(
+        &self,
+        when: Instant,
+        task: T,
+        mut options: SubmitOptions,
+    ) -> TaskHandle<TaskResult>
+    where
+        T: Task + 'static,
+    {
+        options.queued_at = Some(when);
+        let info = options.job_info(&task);
+        let lane = Arc::clone(self.lane(task.kind()));
+        let (job, handle) = self.prepare(Arc::new(task), options);
+        let scheduled = self.timer.schedule(when, Pending::Job { job, info, lane });
+        assert!(scheduled, "pool is shut down");
+        handle
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::VirtualClock;
+    use crate::task::{TaskContext, TaskError, TaskOutput};
+
+    struct Noop;
+
+    impl Task for Noop {
+        fn id(&self) -> u32 {
+            0
+        }
+
+        fn execute(&self, _ctx: &TaskContext) -> Result<TaskOutput, TaskError> {
+            Ok(TaskOutput::new("done"))
+        }
+    }
+
+    #[test]
+    fn delayed_tasks_wait_on_the_pools_clock() {
+        let clock = Arc::new(VirtualClock::new());
+        let pool = ThreadPool::builder()
+            .workers(1)
+            .clock(Arc::clone(&clock))
+            .build();
+        let started = Instant::now();
+        let result = pool.submit_delayed(Duration::from_secs(3600), Noop).wait();
+
+        assert!(matches!(result, Some(TaskResult::Success { .. })));
+        assert!(clock.elapsed() >= Duration::from_secs(3600));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::monitor::WorkerState;
+use crate::pool::{Shared, ThreadPool};
+use crate::sync::Recover;
+
+/// What a [`Watchdog`] does about a worker that has gone quiet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OnStall {
+    /// Only report it.
+    Report,
+    /// Report it and start another worker in its place, so the queue keeps
+    /// moving. The stuck worker exits once its task finally returns.
+    ReplaceWorker,
+}
+
+/// A worker the [`Watchdog`] hasn't heard from in too long.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Stall {
+    /// The worker's id, as in [`TaskStatus::Running`](crate::TaskStatus).
+    pub worker: usize,
+    /// The task it's stuck on, now marked
+    /// [`TaskStatus::Stuck`](crate::TaskStatus), or `None` if it's running
+    /// a closure.
+    pub task_id: Option<u32>,
+    /// Time since the worker's last heartbeat.
+    pub silent_for: Duration,
+    /// Whether a replacement worker was started.
+    pub replaced: bool,
+}
+
+/// What one worker was last seen doing.
+struct Heartbeat {
+    worker: usize,
+    task: Option<u32>,
+    /// When the worker picked up the job it's on.
+    busy_since: Option<Instant>,
+    /// When the worker last showed signs of life while busy; `None` while
+    /// it waits for work.
+    last: Option<Instant>,
+    /// Already reported since the last heartbeat.
+    stalled: bool,
+    /// Someone else has taken over its place.
+    replaced: bool,
+}
+
+/// The heartbeats of one queue's workers, by their index on the queue.
+/// Workers beat when they pick up a job and each time a task starts an
+/// attempt.
+#[derive(Default)]
+pub(crate) struct Heartbeats {
+    workers: Mutex<HashMap<usize, Heartbeat>>,
+}
+
+impl Heartbeats {
+    pub(crate) fn register(&self, index: usize, worker: usize) {
+        self.workers.lock().recover().insert(
+            index,
+            Heartbeat {
+                worker,
+                task: None,
+                busy_since: None,
+                last: None,
+                stalled: false,
+                replaced: false,
+            },
+        );
+    }
+
+    /// The worker at `index` has picked up a job.
+    pub(crate) fn busy(&self, index: usize) {
+        if let Some(heartbeat) = self.workers.lock().recover().get_mut(&index) {
+            let now = Instant::now();
+            heartbeat.task = None;
+            heartbeat.busy_since = Some(now);
+            heartbeat.last = Some(now);
+            heartbeat.stalled = false;
+        }
+    }
+
+    /// The worker at `index` is still making progress, now on `task` if
+    /// given.
+    pub(crate) fn beat(&self, index: usize, task: Option<u32>) {
+        if let Some(heartbeat) = self.workers.lock().recover().get_mut(&index) {
+            heartbeat.task = task.or(heartbeat.task);
+            heartbeat.last = Some(Instant::now());
+            heartbeat.stalled = false;
+        }
+    }
+
+    /// The worker at `index` has finished its job. Returns whether it was
+    /// replaced meanwhile, in which case it should exit.
+    pub(crate) fn idle(&self, index: usize) -> bool {
+        match self.workers.lock().recover().get_mut(&index) {
+            Some(heartbeat) => {
+                heartbeat.task = None;
+                heartbeat.busy_since = None;
+                heartbeat.last = None;
+                heartbeat.replaced
+            }
+            None => false,
+        }
+    }
+
+    /// Forgets the worker at `index` as it exits. Returns whether it was
+    /// replaced.
+    pub(crate) fn remove(&self, index: usize) -> bool {
+        self.workers
+            .lock()
+            .recover()
+            .remove(&index)
+            .is_some_and(|heartbeat| heartbeat.replaced)
+    }
+
+    /// What each worker is doing right now.
+    pub(crate) fn states(&self) -> Vec<WorkerState> {
+        let now = Instant::now();
+        self.workers
+            .lock()
+            .recover()
+            .values()
+            .map(|heartbeat| WorkerState {
+                worker: heartbeat.worker,
+                task_id: heartbeat.task,
+                busy_for: heartbeat.busy_since.map(|since| now - since),
+            })
+            .collect()
+    }
+
+    /// Busy workers silent for longer than `threshold` that haven't been
+    /// reported yet. With `replace`, each is marked as replaced; starting
+    /// the replacement is up to the caller.
+    fn stalled(&self, threshold: Duration, replace: bool) -> Vec<Stall> {
+        let now = Instant::now();
+        let mut workers = self.workers.lock().recover();
+        let mut stalls = Vec::new();
+        for heartbeat in workers.values_mut() {
+            let Some(last) = heartbeat.last else {
+                continue;
+            };
+            if heartbeat.stalled || now - last <= threshold {
+                continue;
+            }
+            heartbeat.stalled = true;
+            let replaced = replace && !heartbeat.replaced;
+            heartbeat.replaced |= replaced;
+            stalls.push(Stall {
+                worker: heartbeat.worker,
+                task_id: heartbeat.task,
+                silent_for: now - last,
+                replaced,
+            });
+        }
+        stalls.sort_unstable_by_key(|stall| stall.worker);
+        stalls
+    }
+}
+
+/// A background thread that looks for workers stuck on one task for longer
+/// than a threshold, returned by [`ThreadPool::watchdog`]. Stops when
+/// dropped.
+pub struct Watchdog {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Watchdog {
+    fn start<F>(
+        lanes: Vec<Arc<Shared>>,
+        threshold: Duration,
+        on_stall: OnStall,
+        mut report: F,
+    ) -> Self
+    where
+        F: FnMut(&Stall) + Send + 'static,
+    {
+        let (stop, stopped) = mpsc::channel::<()>();
+        // Often enough that a stall is noticed well within another threshold.
+        let interval = (threshold / 4).max(Duration::from_millis(1));
+        let replace = on_stall == OnStall::ReplaceWorker;
+        let thread = thread::spawn(move || {
+            // Nothing is ever sent; the channel only disconnects on stop.
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                for lane in &lanes {
+                    for stall in lane.heartbeats.stalled(threshold, replace) {
+                        if let Some(task_id) = stall.task_id {
+                            lane.registry.stuck(task_id, stall.worker);
+                        }
+                        if stall.replaced {
+                            lane.add_worker();
+                        }
+                        report(&stall);
+                    }
+                }
+            }
+        });
+        Watchdog {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+
+    /// Stops the watchdog and waits for its thread to exit.
+    pub fn stop(self) {
+        drop(self);
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl ThreadPool {
+    /// Calls `report` for every worker that has been busy without a
+    /// heartbeat for longer than `stall_threshold`, until the returned
+    /// [`Watchdog`] is dropped. A worker beats whenever it picks up a job
+    /// and whenever a task starts an attempt, so a task that simply runs
+    /// long counts as stuck too; pick a threshold above the slowest task
+    /// you expect. Each stall is reported once, however long it lasts.
+    pub fn watchdog<F>(&self, stall_threshold: Duration, on_stall: OnStall, report: F) -> Watchdog
+    where
+        F: FnMut(&Stall) + Send + 'static,
+    {
+        Watchdog::start(
+            self.lanes().cloned().collect(),
+            stall_threshold,
+            on_stall,
+            report,
+        )
+    }
+}
@@ -0,0 +1,189 @@
+use std::hint;
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::affinity;
+use crate::fork;
+use crate::pool::{Job, Shared};
+use crate::queue::{Pop, TaskQueue};
+use crate::sync::{Mutex, Recover};
+
+/// What an idle worker does while it waits for its next task, set with
+/// [`ThreadPoolBuilder::wait_strategy`](crate::ThreadPoolBuilder::wait_strategy).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WaitStrategy {
+    /// Sleeps until a task is queued. Costs no CPU while idle, but every
+    /// task that arrives has to wake a thread first.
+    #[default]
+    Park,
+    /// Keeps checking the queue for up to the given time before going to
+    /// sleep. A task that arrives meanwhile starts without a wake-up,
+    /// which suits bursty workloads; the price is a busy core for that
+    /// long every time a worker runs out of work.
+    SpinThenPark(Duration),
+}
+
+/// A queue's worker threads, kept for the pool to join when it shuts down.
+#[derive(Default)]
+pub(crate) struct Workers {
+    handles: Vec<thread::JoinHandle<()>>,
+    /// Set once the pool has joined them all; no worker starts after that.
+    sealed: bool,
+}
+
+impl Workers {
+    /// The workers left to join, or none if they have all been, in which
+    /// case no more may start.
+    pub(crate) fn take(&mut self) -> Vec<thread::JoinHandle<()>> {
+        if self.handles.is_empty() {
+            self.sealed = true;
+        }
+        mem::take(&mut self.handles)
+    }
+}
+
+/// Holds a queue's workers back between tasks while the pool is
+/// [paused](crate::ThreadPool::pause).
+#[derive(Default)]
+pub(crate) struct Pause {
+    /// Checked before every task, so it's read without the lock.
+    paused: AtomicBool,
+    lock: Mutex<()>,
+    resumed: Condvar,
+}
+
+impl Pause {
+    pub(crate) fn set(&self, paused: bool) {
+        // Under the lock, so a worker can't check the flag and then miss
+        // the wake-up.
+        let _lock = self.lock.lock().recover();
+        self.paused.store(paused, Ordering::Release);
+        if !paused {
+            self.resumed.notify_all();
+        }
+    }
+
+    pub(crate) fn is_set(&self) -> bool {
+        self.paused.load(Ordering::Acquire)
+    }
+
+    /// Blocks while paused.
+    fn wait(&self) {
+        if !self.is_set() {
+            return;
+        }
+        let mut lock = self.lock.lock().recover();
+        while self.is_set() {
+            lock = self.resumed.wait(lock).recover();
+        }
+    }
+}
+
+/// Starts one more worker. The caller must already have counted it in
+/// the pool's active worker count. Once the pool has joined its workers
+/// for good, it uncounts it instead, as no one would join it.
+pub(crate) fn spawn(shared: &Arc<Shared>) {
+    let mut workers = shared.workers.lock().recover();
+    if workers.sealed {
+        shared.worker_stopped();
+        return;
+    }
+    let index = shared.next_worker.fetch_add(1, Ordering::Relaxed);
+    let id = shared.registry.next_worker_id();
+    let worker_shared = Arc::clone(shared);
+    let mut builder = thread::Builder::new().name(format!("{}-{}", shared.thread_name_prefix, id));
+    if let Some(size) = shared.stack_size {
+        builder = builder.stack_size(size);
+    }
+    let handle = builder
+        .spawn(move || run(worker_shared, index, id))
+        .expect("failed to spawn a worker thread");
+
+    // Workers that scaled themselves down are done; forget their handles.
+    workers.handles.retain(|worker| !worker.is_finished());
+    workers.handles.push(handle);
+}
+
+fn run(shared: Arc<Shared>, index: usize, id: usize) {
+    shared.registry.register_worker(id);
+    // Numbered across the whole pool, so workers dedicated to a task type
+    // don't all land on the first cores too. Best effort: a worker the OS
+    // won't pin runs wherever it's put.
+    if !shared.cpus.is_empty() {
+        affinity::pin(shared.cpus[id % shared.cpus.len()]);
+    }
+    shared.heartbeats.register(index, id);
+    fork::enter(&shared, index);
+    let sentinel = Sentinel {
+        shared: &shared,
+        index,
+    };
+    loop {
+        match next_job(&shared, index) {
+            Pop::Item(job) => {
+                // Paused after this worker took the job; it goes once the
+                // pool resumes.
+                shared.pause.wait();
+                shared.heartbeats.busy(index);
+                shared.run_job(job);
+                // The watchdog gave up on this worker and started another.
+                if shared.heartbeats.idle(index) {
+                    shared.worker_stopped();
+                    break;
+                }
+            }
+            Pop::TimedOut => {
+                if shared.try_worker_stopped() {
+                    break;
+                }
+            }
+            Pop::Closed => {
+                shared.worker_stopped();
+                break;
+            }
+        }
+    }
+    drop(sentinel);
+}
+
+fn next_job(shared: &Shared, index: usize) -> Pop<Job> {
+    if let WaitStrategy::SpinThenPark(spin) = shared.wait_strategy {
+        let started = Instant::now();
+        while started.elapsed() < spin {
+            // Only touch the queue's lock once there's something to take.
+            if !shared.queue.is_empty() {
+                match shared.queue.pop(index, Some(Duration::ZERO)) {
+                    Pop::TimedOut => {}
+                    pop => return pop,
+                }
+            }
+            hint::spin_loop();
+        }
+    }
+    shared.queue.pop(index, shared.scaling.idle_timeout())
+}
+
+/// Task panics are caught before they reach the worker loop, but if one
+/// slips through anyway the dying worker hands its slot to a replacement
+/// so the pool doesn't quietly shrink, unless the watchdog already did.
+struct Sentinel<'a> {
+    shared: &'a Arc<Shared>,
+    index: usize,
+}
+
+impl Drop for Sentinel<'_> {
+    fn drop(&mut self) {
+        let replaced = self.shared.heartbeats.remove(self.index);
+        if thread::panicking() {
+            self.shared.stats.worker_panicked();
+            if replaced {
+                self.shared.worker_stopped();
+            } else {
+                spawn(self.shared);
+            }
+        }
+    }
+}
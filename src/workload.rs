@@ -0,0 +1,123 @@
+use crate::rng::Rng;
+use std::time::Duration;
+
+// When generated tasks arrive. Anything but All is open-loop: tasks come
+// in on their own schedule, however far behind the workers are
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Arrivals {
+    // Everything at once, as the demos always did
+    All,
+    // On average this many tasks a second, at random
+    Poisson(f64),
+    // `size` tasks at once, every `every`
+    Bursty { size: u32, every: Duration },
+    // Steadily from `from` up to `to` tasks a second over the run
+    Ramp { from: f64, to: f64 },
+}
+
+impl Arrivals {
+    // all, poisson:RATE, bursty:SIZE/MS or ramp:FROM-TO, rates in tasks a
+    // second
+    pub fn parse(spec: &str) -> Option<Arrivals> {
+        let (name, params) = spec.split_once(':').unwrap_or((spec, ""));
+        let arrivals = match name {
+            "all" if params.is_empty() => Arrivals::All,
+            "poisson" => Arrivals::Poisson(params.parse().ok()?),
+            "bursty" => {
+                let (size, every) = params.split_once('/')?;
+                Arrivals::Bursty { size: size.parse().ok()?, every: Duration::from_millis(every.parse().ok()?) }
+            },
+            "ramp" => {
+                let (from, to) = params.split_once('-')?;
+                Arrivals::Ramp { from: from.parse().ok()?, to: to.parse().ok()? }
+            },
+            _ => return None,
+        };
+        let valid = match arrivals {
+            Arrivals::All => true,
+            Arrivals::Poisson(rate) => rate > 0.0,
+            Arrivals::Bursty { size, .. } => size > 0,
+            Arrivals::Ramp { from, to } => from > 0.0 && to > 0.0,
+        };
+        valid.then_some(arrivals)
+    }
+
+    // How long after the start each of `count` tasks arrives
+    pub fn offsets(&self, count: u32, rng: &mut Rng) -> Vec<Duration> {
+        let mut at = 0.0;
+        (0..count)
+            .map(|i| {
+                let gap = match *self {
+                    Arrivals::All => 0.0,
+                    // Exponential gaps make a Poisson process
+                    Arrivals::Poisson(rate) => -(1.0 - rng.unit()).ln() / rate,
+                    Arrivals::Bursty { size, every } if i > 0 && i % size == 0 => every.as_secs_f64(),
+                    Arrivals::Bursty { .. } => 0.0,
+                    Arrivals::Ramp { from, to } => {
+                        let progress = if count > 1 { i as f64 / (count - 1) as f64 } else { 0.0 };
+                        if i == 0 { 0.0 } else { 1.0 / (from + (to - from) * progress) }
+                    },
+                };
+                at += gap;
+                Duration::from_secs_f64(at)
+            })
+            .collect()
+    }
+}
+
+// How long generated tasks take
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Durations {
+    // Anywhere from min to max, evenly
+    Uniform { min: Duration, max: Duration },
+    // Mostly around the median, with a long tail for larger sigma
+    LogNormal { median: Duration, sigma: f64 },
+    // Pareto: at least min, and the smaller alpha the more very long ones
+    HeavyTailed { min: Duration, alpha: f64 },
+}
+
+// The longest a heavy-tailed task may take, in multiples of its minimum,
+// so one unlucky draw can't stall a run
+const HEAVY_TAIL_CAP: f64 = 100.0;
+
+impl Durations {
+    // uniform:MIN-MAX, lognormal:MEDIAN,SIGMA or pareto:MIN,ALPHA, times in
+    // milliseconds
+    pub fn parse(spec: &str) -> Option<Durations> {
+        let (name, params) = spec.split_once(':')?;
+        let millis = |ms: &str| ms.trim().parse().ok().map(Duration::from_millis);
+        let durations = match name {
+            "uniform" => {
+                let (min, max) = params.split_once('-')?;
+                Durations::Uniform { min: millis(min)?, max: millis(max)? }
+            },
+            "lognormal" => {
+                let (median, sigma) = params.split_once(',')?;
+                Durations::LogNormal { median: millis(median)?, sigma: sigma.trim().parse().ok()? }
+            },
+            "pareto" | "heavy-tailed" => {
+                let (min, alpha) = params.split_once(',')?;
+                Durations::HeavyTailed { min: millis(min)?, alpha: alpha.trim().parse().ok()? }
+            },
+            _ => return None,
+        };
+        let valid = match durations {
+            Durations::Uniform { min, max } => min <= max,
+            Durations::LogNormal { sigma, .. } => sigma >= 0.0,
+            Durations::HeavyTailed { alpha, .. } => alpha > 0.0,
+        };
+        valid.then_some(durations)
+    }
+
+    pub fn sample(&self, rng: &mut Rng) -> Duration {
+        match *self {
+            Durations::Uniform { min, max } => min + (max - min).mul_f64(rng.unit()),
+            Durations::LogNormal { median, sigma } => {
+                // Box-Muller for a standard normal draw
+                let normal = (-2.0 * (1.0 - rng.unit()).ln()).sqrt() * (2.0 * std::f64::consts::PI * rng.unit()).cos();
+                median.mul_f64((sigma * normal).exp())
+            },
+            Durations::HeavyTailed { min, alpha } => min.mul_f64((1.0 - rng.unit()).powf(-1.0 / alpha).min(HEAVY_TAIL_CAP)),
+        }
+    }
+}
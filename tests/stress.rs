@@ -0,0 +1,501 @@
+// The pool's racier paths run over and over with threads yielding at
+// shuffled points, checking after every round that nothing was lost,
+// duplicated or left hanging: queues closing while producers push and
+// workers drain, shutdown while tasks are still submitting work, and stats
+// read while workers update them. A model checker like loom would try
+// every interleaving; this only tries a lot of them, but needs nothing
+// beyond std. Then random workloads, each checked against what should hold
+// for any of them, the way a property-based test would, and a big one in
+// simulated time that has to come out the same every time. A failure names
+// its seed, and STRESS_SEED=N reruns just that round
+use rust_concurrent_processor::{
+    self as rcp, BoundedQueue, FairQueue, FifoQueue, JobInfo, Pop, PriorityQueue, RetryPolicy, Scheduler, ShutdownMode, Simulation, TaskContext, TaskError, TaskListener, TaskOutput, TaskQueue,
+    TaskResult, ThreadPool,
+};
+use std::env;
+use std::panic;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Once, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const ROUNDS: u64 = 200;
+// Random workloads and simulations take longer a round
+const SLOW_ROUNDS: u64 = 50;
+// Items each producer pushes in the queue rounds
+const ITEMS: u32 = 100;
+const PRODUCERS: u32 = 2;
+const CONSUMERS: usize = 2;
+// Tasks submitted in the pool rounds
+const TASKS: u32 = 50;
+// Most tasks in a random workload
+const MAX_TASKS: u64 = 60;
+// Timeout for random workloads
+const TIMEOUT: Duration = Duration::from_millis(15);
+const PANIC_MESSAGE: &str = "scripted panic";
+// Tasks in a simulated run
+const SIMULATED: u32 = 1_000;
+// A round that takes this long is stuck
+const DEADLOCK: Duration = Duration::from_secs(5);
+
+// One round of a check, given a seed for where its threads yield
+type Check = fn(u64) -> Result<(), String>;
+
+// Runs `rounds` rounds of a check, each with a seed of its own, or only the
+// round STRESS_SEED names
+fn check(rounds: u64, check: Check) {
+    // Panics the workloads ask for are expected; any other still gets
+    // reported as usual
+    static QUIET_SCRIPTED_PANICS: Once = Once::new();
+    QUIET_SCRIPTED_PANICS.call_once(|| {
+        let report_panic = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if info.payload().downcast_ref::<&str>() != Some(&PANIC_MESSAGE) {
+                report_panic(info);
+            }
+        }));
+    });
+    let seeds = match env::var("STRESS_SEED") {
+        Ok(seed) => {
+            let seed = seed.parse().expect("STRESS_SEED should be a number");
+            seed..seed + 1
+        },
+        Err(_) => 1..rounds + 1,
+    };
+    for seed in seeds {
+        if let Err(err) = within_deadline(check, seed) {
+            panic!("seed {}: {}", seed, err);
+        }
+    }
+}
+
+#[test]
+fn fifo_queue_close_while_draining() {
+    check(ROUNDS, |seed| drain_round(FifoQueue::new(), seed));
+}
+
+#[test]
+fn priority_queue_close_while_draining() {
+    check(ROUNDS, |seed| drain_round(PriorityQueue::new(), seed));
+}
+
+#[test]
+fn fair_queue_close_while_draining() {
+    check(ROUNDS, |seed| drain_round(FairQueue::new(), seed));
+}
+
+#[test]
+fn bounded_queue_close_while_draining() {
+    check(ROUNDS, |seed| drain_round(BoundedQueue::new(FifoQueue::new(), 4), seed));
+}
+
+#[test]
+fn drain_shutdown_while_submitting() {
+    check(ROUNDS, |seed| shutdown_round(ShutdownMode::Drain, seed));
+}
+
+#[test]
+fn immediate_shutdown_while_submitting() {
+    check(ROUNDS, |seed| shutdown_round(ShutdownMode::Immediate, seed));
+}
+
+#[test]
+fn stats_while_updating() {
+    check(ROUNDS, stats_round);
+}
+
+#[test]
+fn random_workloads() {
+    check(SLOW_ROUNDS, workload_round);
+}
+
+#[test]
+fn simulated_runs() {
+    check(SLOW_ROUNDS, simulation_round);
+}
+
+// Runs one round on a thread of its own so a deadlock shows up as a
+// failure rather than a hang. A stuck round's threads are left behind
+fn within_deadline(check: Check, seed: u64) -> Result<(), String> {
+    let (done_tx, done) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = done_tx.send(check(seed));
+    });
+    match done.recv_timeout(DEADLOCK) {
+        Ok(result) => result,
+        Err(mpsc::RecvTimeoutError::Timeout) => Err(format!("deadlocked, no result in {}s", DEADLOCK.as_secs())),
+        Err(mpsc::RecvTimeoutError::Disconnected) => Err("panicked".to_string()),
+    }
+}
+
+// Producers push while consumers pop and a third thread closes the queue
+// part way. Everything pushed before the close has to come out exactly
+// once, nothing is accepted after it, and nobody is left waiting
+fn drain_round<Q: TaskQueue<u32>>(queue: Q, seed: u64) -> Result<(), String> {
+    let (mut pushed, mut popped) = (Vec::new(), Vec::new());
+    let mut errors = Vec::new();
+    thread::scope(|scope| {
+        let producers: Vec<_> = (0..PRODUCERS)
+            .map(|producer| {
+                let queue = &queue;
+                scope.spawn(move || {
+                    let mut jitter = Jitter::new(seed * 31 + producer as u64);
+                    let (mut accepted, mut refused) = (Vec::new(), false);
+                    for item in producer * ITEMS..(producer + 1) * ITEMS {
+                        jitter.step();
+                        let info = JobInfo { task_type: Some(if item.is_multiple_of(2) { "even" } else { "odd" }.into()), ..JobInfo::default() };
+                        match queue.push(item, info) {
+                            Ok(()) if refused => return Err(format!("item {} accepted after the queue refused one", item)),
+                            Ok(()) => accepted.push(item),
+                            Err(_) => refused = true,
+                        }
+                    }
+                    Ok(accepted)
+                })
+            })
+            .collect();
+        let consumers: Vec<_> = (0..CONSUMERS)
+            .map(|worker| {
+                let queue = &queue;
+                scope.spawn(move || {
+                    let mut jitter = Jitter::new(seed * 17 + worker as u64);
+                    let mut items = Vec::new();
+                    loop {
+                        jitter.step();
+                        match queue.pop(worker, Some(Duration::from_millis(10))) {
+                            Pop::Item(item) => items.push(item),
+                            Pop::TimedOut => {},
+                            Pop::Closed => return items,
+                        }
+                    }
+                })
+            })
+            .collect();
+        let mut jitter = Jitter::new(seed);
+        for _ in 0..jitter.below(ITEMS as u64 * 2) {
+            thread::yield_now();
+        }
+        queue.close();
+        for producer in producers {
+            match producer.join().unwrap() {
+                Ok(accepted) => pushed.extend(accepted),
+                Err(err) => errors.push(err),
+            }
+        }
+        for consumer in consumers {
+            popped.extend(consumer.join().unwrap());
+        }
+    });
+    if let Some(err) = errors.pop() {
+        return Err(err);
+    }
+    pushed.sort_unstable();
+    popped.sort_unstable();
+    if pushed != popped {
+        return Err(format!("{} items pushed but {} popped", pushed.len(), popped.len()));
+    }
+    if !queue.is_empty() {
+        return Err(format!("{} items left in the queue", queue.len()));
+    }
+    Ok(())
+}
+
+// A task that hands a follow-up to the pool it runs on and waits for it,
+// so work is still being submitted when the pool shuts down
+struct Spawner {
+    id: u32,
+    seed: u64,
+    follow_ups: Arc<AtomicU32>,
+}
+
+impl rcp::Task for Spawner {
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn execute(&self, _ctx: &TaskContext) -> Result<TaskOutput, TaskError> {
+        Jitter::new(self.seed + self.id as u64).step();
+        let follow_ups = Arc::clone(&self.follow_ups);
+        rcp::spawn(move || follow_ups.fetch_add(1, Ordering::Relaxed)).wait();
+        Ok(TaskOutput::new("spawned"))
+    }
+}
+
+// Every task has to end up with exactly one result, or be counted as
+// discarded if the shutdown was immediate, and the stats have to agree
+fn shutdown_round(mode: ShutdownMode, seed: u64) -> Result<(), String> {
+    let pool = ThreadPool::new(2);
+    let follow_ups = Arc::new(AtomicU32::new(0));
+    let handles: Vec<_> = (0..TASKS)
+        .map(|id| pool.submit(Spawner { id, seed, follow_ups: Arc::clone(&follow_ups) }))
+        .collect();
+    let mut jitter = Jitter::new(seed);
+    for _ in 0..jitter.below(TASKS as u64) {
+        thread::yield_now();
+    }
+    let stats = pool.shutdown(mode);
+    let finished = handles.into_iter().filter_map(|handle| handle.wait()).count() as u32;
+    let follow_ups = follow_ups.load(Ordering::Relaxed);
+    // Follow-ups that went through the queue count as completed closures,
+    // and as discarded ones if the queue was cleared with them in it
+    let completed = stats.latency.get("task").map_or(0, |latency| latency.count()) as u32;
+    let accounted = match mode {
+        ShutdownMode::Drain => finished == TASKS,
+        ShutdownMode::Immediate => stats.tasks_discarded >= TASKS - finished,
+    };
+    if !accounted || completed != finished {
+        return Err(format!("{} tasks, {} results, {} completed, {} discarded", TASKS, finished, completed, stats.tasks_discarded));
+    }
+    // An immediate shutdown may throw away a follow-up still queued
+    if follow_ups > finished || (mode == ShutdownMode::Drain && follow_ups != finished) {
+        return Err(format!("{} tasks finished but {} follow-ups ran", finished, follow_ups));
+    }
+    Ok(())
+}
+
+// Fails every third time, so both counters move
+struct Counted {
+    id: u32,
+}
+
+impl rcp::Task for Counted {
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn execute(&self, _ctx: &TaskContext) -> Result<TaskOutput, TaskError> {
+        if self.id.is_multiple_of(3) {
+            return Err(TaskError::new("every third fails"));
+        }
+        Ok(TaskOutput::new("counted"))
+    }
+}
+
+// A reader keeps taking snapshots while workers record results. No count
+// may go backwards or past what was submitted, and the final numbers have
+// to add up
+fn stats_round(seed: u64) -> Result<(), String> {
+    let pool = ThreadPool::new(3);
+    let done = AtomicBool::new(false);
+    let read = thread::scope(|scope| {
+        let reader = scope.spawn(|| {
+            let mut jitter = Jitter::new(seed);
+            let (mut completed, mut failed) = (0, 0);
+            while !done.load(Ordering::Acquire) {
+                jitter.step();
+                let stats = pool.stats();
+                if stats.tasks_completed < completed || stats.tasks_failed < failed {
+                    return Err(format!("counts went back from {}/{} to {}/{}", completed, failed, stats.tasks_completed, stats.tasks_failed));
+                }
+                if stats.tasks_completed + stats.tasks_failed > TASKS {
+                    return Err(format!("{} results counted for {} tasks", stats.tasks_completed + stats.tasks_failed, TASKS));
+                }
+                (completed, failed) = (stats.tasks_completed, stats.tasks_failed);
+            }
+            Ok(())
+        });
+        pool.submit_batch((0..TASKS).map(|id| Counted { id })).wait_all();
+        done.store(true, Ordering::Release);
+        reader.join().unwrap()
+    });
+    read?;
+    let stats = pool.shutdown(ShutdownMode::Drain);
+    let failures = TASKS.div_ceil(3);
+    let latencies: u64 = stats.latency.values().map(|latency| latency.count()).sum();
+    if stats.tasks_completed != TASKS - failures || stats.tasks_failed != failures || latencies != stats.tasks_completed as u64 {
+        return Err(format!(
+            "{} completed, {} failed and {} latencies recorded for {} tasks",
+            stats.tasks_completed, stats.tasks_failed, latencies, TASKS
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Outcome {
+    Succeed,
+    Fail,
+    Panic,
+    // Runs until its timeout calls it off
+    Slow,
+}
+
+struct Scripted {
+    id: u32,
+    outcome: Outcome,
+}
+
+impl rcp::Task for Scripted {
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn execute(&self, ctx: &TaskContext) -> Result<TaskOutput, TaskError> {
+        match self.outcome {
+            Outcome::Succeed => Ok(TaskOutput::new("done")),
+            Outcome::Fail => Err(TaskError::new("scripted failure")),
+            Outcome::Panic => panic::panic_any(PANIC_MESSAGE),
+            Outcome::Slow => {
+                loop {
+                    ctx.check_cancelled()?;
+                    thread::sleep(Duration::from_millis(1));
+                }
+            }
+        }
+    }
+}
+
+// Counts results handed to listeners
+#[derive(Default)]
+struct Announced(AtomicU32);
+
+impl TaskListener for Announced {
+    fn on_complete(&self, _result: &TaskResult) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_failure(&self, _result: &TaskResult) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// A workload made up from the seed: how many tasks, how each one ends,
+// the scheduler, the workers, and whether results come back in order.
+// Whatever the mix, every task ends exactly once, the stats add up to what
+// was submitted, ordered results keep submission order, and nothing is
+// reported once shutdown has returned
+fn workload_round(seed: u64) -> Result<(), String> {
+    let mut random = Jitter::new(seed);
+    let tasks = random.below(MAX_TASKS + 1) as u32;
+    let outcomes: Vec<Outcome> = (0..tasks)
+        .map(|_| match random.below(20) {
+            0 | 1 => Outcome::Fail,
+            2 | 3 => Outcome::Panic,
+            4 => Outcome::Slow,
+            _ => Outcome::Succeed,
+        })
+        .collect();
+    let schedulers = [Scheduler::SharedQueue, Scheduler::WorkStealing, Scheduler::Channel, Scheduler::Fifo, Scheduler::ShortestJobFirst, Scheduler::WeightedFair];
+    let scheduler = schedulers[random.below(schedulers.len() as u64) as usize];
+    let workers = 1 + random.below(4) as usize;
+    let ordered = random.below(2) == 0;
+    let workload = format!("{} tasks, {:?}, {} workers{}", tasks, scheduler, workers, if ordered { ", ordered" } else { "" });
+
+    let announced = Arc::new(Announced::default());
+    let pool = ThreadPool::builder()
+        .workers(workers)
+        .scheduler(scheduler)
+        .default_timeout(TIMEOUT)
+        .listener(Arc::clone(&announced))
+        .build();
+    let mut results = if ordered { pool.ordered_results() } else { pool.results() };
+    for (id, outcome) in outcomes.iter().enumerate() {
+        results.submit(Scripted { id: id as u32, outcome: *outcome });
+    }
+    let ids: Vec<u32> = results.map(|result| result.id()).collect();
+    let stats = pool.shutdown(ShutdownMode::Drain);
+    let after_shutdown = announced.0.load(Ordering::Relaxed);
+
+    if ordered && !ids.iter().copied().eq(0..tasks) {
+        return Err(format!("{}: results out of order: {:?}", workload, ids));
+    }
+    let mut sorted = ids.clone();
+    sorted.sort_unstable();
+    if !sorted.iter().copied().eq(0..tasks) {
+        return Err(format!("{}: {} results for {} tasks", workload, ids.len(), tasks));
+    }
+    let count = |wanted: fn(&Outcome) -> bool| outcomes.iter().filter(|outcome| wanted(outcome)).count() as u32;
+    let expected = [
+        count(|outcome| matches!(outcome, Outcome::Succeed)),
+        count(|outcome| matches!(outcome, Outcome::Fail)),
+        count(|outcome| matches!(outcome, Outcome::Panic)),
+        count(|outcome| matches!(outcome, Outcome::Slow)),
+    ];
+    let counted = [stats.tasks_completed, stats.tasks_failed, stats.worker_panics, stats.tasks_timed_out];
+    if counted != expected {
+        return Err(format!("{}: expected completed/failed/panicked/timed out {:?}, stats say {:?}", workload, expected, counted));
+    }
+    thread::sleep(Duration::from_millis(5));
+    let announced = announced.0.load(Ordering::Relaxed);
+    if after_shutdown != tasks || announced != after_shutdown {
+        return Err(format!("{}: {} results announced by shutdown, {} a moment later", workload, after_shutdown, announced));
+    }
+    Ok(())
+}
+
+// Takes a made-up amount of virtual time, failing now and then
+struct Virtual {
+    id: u32,
+    millis: u64,
+    fails: bool,
+}
+
+impl rcp::Task for Virtual {
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn execute(&self, ctx: &TaskContext) -> Result<TaskOutput, TaskError> {
+        ctx.sleep(Duration::from_millis(self.millis));
+        if self.fails {
+            return Err(TaskError::new("simulated failure"));
+        }
+        Ok(TaskOutput::new(format!("slept {}ms", self.millis)))
+    }
+}
+
+// A thousand tasks with retries and timeouts on a virtual clock, twice.
+// Both runs have to give the same results in the same order at the same
+// virtual times, without taking any real time to speak of
+fn simulation_round(seed: u64) -> Result<(), String> {
+    let simulate = || {
+        let mut random = Jitter::new(seed);
+        let mut simulation = Simulation::new(1 + random.below(8) as usize)
+            .default_timeout(Duration::from_millis(180))
+            .retry_policy(RetryPolicy::exponential(2, Duration::from_millis(10)));
+        for id in 0..SIMULATED {
+            simulation.submit(Virtual { id, millis: 1 + random.below(200), fails: random.below(20) == 0 });
+        }
+        let results: Vec<String> = simulation.run().iter().map(|result| format!("{:?}", result)).collect();
+        (results, simulation.elapsed(), simulation.stats())
+    };
+    let start = Instant::now();
+    let (first, second) = (simulate(), simulate());
+    if first != second {
+        return Err("two runs of the same simulation differed".to_string());
+    }
+    let (results, elapsed, stats) = first;
+    if results.len() != SIMULATED as usize || stats.tasks_completed + stats.tasks_failed + stats.tasks_timed_out != SIMULATED {
+        return Err(format!("{} results and {:?} for {} tasks", results.len(), stats, SIMULATED));
+    }
+    // Far quicker than the virtual time it covered
+    if start.elapsed() * 100 > elapsed {
+        return Err(format!("{}ms of virtual time took {}ms", elapsed.as_millis(), start.elapsed().as_millis()));
+    }
+    Ok(())
+}
+
+// Yields at pseudo-random points, the same ones for the same seed, to
+// shake up how threads interleave from one round to the next. Draws come
+// from SplitMix64, as with the demos' --seed
+struct Jitter(u64);
+
+impl Jitter {
+    fn new(seed: u64) -> Jitter {
+        Jitter(seed)
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        (z ^ (z >> 31)) % bound.max(1)
+    }
+
+    fn step(&mut self) {
+        if self.below(4) == 0 {
+            thread::yield_now();
+        }
+    }
+}